@@ -5,10 +5,12 @@ export MIN_QEMU_VERSION:= 9.0.50		# Older QEMU Versions has FDT bugs on loongarc
 
 build:
 	@echo Bulding "$(BUILD_NAME)"...
-	# make -C user build
-	# mkdir -p $(RUNTIME)/user
-	# cp $(CUR)/$(USER_DIR)/* $(RUNTIME)/user
 	make -f mk-cfg/$(BUILD_NAME).mak pre_build
+	make -C user build
+	mkdir -p $(RUNTIME)/user
+	cp $(CUR)/$(USER_DIR)/* $(RUNTIME)/user
+	@echo Packing initrd
+	bash scripts/mkinitrd.sh $(RUNTIME)/user $(INITRD_FILE)
 	make -C os all	
 
 run:
@@ -0,0 +1,379 @@
+//! Reader of `newc` cpio archives, the format of Linux initramfs images.
+//!
+//! [CpioArchive::entries] walks an archive held in memory without copying it,
+//! so it runs on the host as well as in the kernel.
+#![no_std]
+
+/// Magic of `newc` headers.
+const MAGIC_NEWC: &[u8] = b"070701";
+/// Magic of `newc` headers with checksums, which are not verified.
+const MAGIC_CRC: &[u8] = b"070702";
+/// Name of the entry closing the archive.
+const TRAILER: &str = "TRAILER!!!";
+
+/// Size of a header: the magic and 13 fields of 8 hexadecimal digits.
+pub const HEADER_SIZE: usize = 110;
+const FIELD_LEN: usize = 8;
+
+/// File types (`mode & S_IFMT`).
+pub const S_IFMT: u32 = 0o170000;
+pub const S_IFDIR: u32 = 0o040000;
+pub const S_IFREG: u32 = 0o100000;
+pub const S_IFLNK: u32 = 0o120000;
+
+/// Errors found while reading an archive. `offset` is that of the faulty header.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CpioError {
+    /// The archive ends inside an entry, or before its trailer.
+    Truncated { offset: usize },
+    /// Not a `newc` header.
+    InvalidMagic { offset: usize },
+    /// A field is not a hexadecimal number.
+    InvalidHeader { offset: usize },
+    /// The name is empty, not NUL-terminated or not valid UTF-8.
+    InvalidName { offset: usize },
+}
+
+/// An entry of an archive, borrowing its data from the archive.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Entry<'a> {
+    /// Path as stored, usually relative and without a leading `/`.
+    pub name: &'a str,
+    pub ino: u32,
+    pub mode: u32,
+    pub nlink: u32,
+    pub mtime: u32,
+    pub data: &'a [u8],
+}
+
+impl Entry<'_> {
+    pub fn is_file(&self) -> bool {
+        self.mode & S_IFMT == S_IFREG
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.mode & S_IFMT == S_IFDIR
+    }
+
+    /// The target of a symlink is stored as its data.
+    pub fn is_symlink(&self) -> bool {
+        self.mode & S_IFMT == S_IFLNK
+    }
+}
+
+/// An archive held in memory.
+#[derive(Debug, Clone, Copy)]
+pub struct CpioArchive<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> CpioArchive<'a> {
+    pub fn new(data: &'a [u8]) -> CpioArchive<'a> {
+        CpioArchive { data }
+    }
+
+    /// Iterate over the entries up to the trailer. The iteration stops after the first error.
+    pub fn entries(&self) -> Entries<'a> {
+        Entries {
+            data: self.data,
+            offset: 0,
+            done: false,
+        }
+    }
+
+    /// Find the entry named `name`.
+    pub fn find(&self, name: &str) -> Result<Option<Entry<'a>>, CpioError> {
+        for entry in self.entries() {
+            let entry = entry?;
+            if entry.name == name {
+                return Ok(Some(entry));
+            }
+        }
+        Ok(None)
+    }
+}
+
+const fn align4(value: usize) -> usize {
+    (value + 3) & !3
+}
+
+fn parse_field(header: &[u8], index: usize) -> Option<u32> {
+    let start = MAGIC_NEWC.len() + index * FIELD_LEN;
+    let digits = core::str::from_utf8(&header[start..start + FIELD_LEN]).ok()?;
+    u32::from_str_radix(digits, 16).ok()
+}
+
+/// Iterator returned by [CpioArchive::entries].
+#[derive(Debug)]
+pub struct Entries<'a> {
+    data: &'a [u8],
+    offset: usize,
+    done: bool,
+}
+
+impl<'a> Entries<'a> {
+    fn read_entry(&mut self) -> Result<Option<Entry<'a>>, CpioError> {
+        let offset = self.offset;
+        let header = offset
+            .checked_add(HEADER_SIZE)
+            .and_then(|end| self.data.get(offset..end))
+            .ok_or(CpioError::Truncated { offset })?;
+        let magic = &header[..MAGIC_NEWC.len()];
+        if magic != MAGIC_NEWC && magic != MAGIC_CRC {
+            return Err(CpioError::InvalidMagic { offset });
+        }
+        let field = |index| parse_field(header, index).ok_or(CpioError::InvalidHeader { offset });
+        let ino = field(0)?;
+        let mode = field(1)?;
+        let nlink = field(4)?;
+        let mtime = field(5)?;
+        let file_size = field(6)? as usize;
+        let name_size = field(11)? as usize;
+
+        let name_start = offset + HEADER_SIZE;
+        let name_bytes = name_start
+            .checked_add(name_size)
+            .and_then(|end| self.data.get(name_start..end))
+            .ok_or(CpioError::Truncated { offset })?;
+        let name = match name_bytes.split_last() {
+            Some((0, name)) if !name.is_empty() => {
+                core::str::from_utf8(name).map_err(|_| CpioError::InvalidName { offset })?
+            }
+            _ => return Err(CpioError::InvalidName { offset }),
+        };
+        if name == TRAILER {
+            return Ok(None);
+        }
+
+        let data_start = align4(name_start + name_size);
+        let data = data_start
+            .checked_add(file_size)
+            .and_then(|end| self.data.get(data_start..end))
+            .ok_or(CpioError::Truncated { offset })?;
+        self.offset = align4(data_start + file_size);
+        Ok(Some(Entry {
+            name,
+            ino,
+            mode,
+            nlink,
+            mtime,
+            data,
+        }))
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = Result<Entry<'a>, CpioError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let res = self.read_entry();
+        if !matches!(res, Ok(Some(_))) {
+            self.done = true;
+        }
+        res.transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    extern crate alloc;
+
+    use super::*;
+    use alloc::{format, vec::Vec};
+
+    const S_IFCHR: u32 = 0o020000;
+
+    /// Append a `newc` entry to `archive`, with its name and data padded to 4 bytes.
+    fn push_entry(archive: &mut Vec<u8>, ino: u32, mode: u32, name: &str, data: &[u8]) {
+        let fields = [
+            ino,
+            mode,
+            0,
+            0,
+            1,
+            0x6000_0000,
+            data.len() as u32,
+            0,
+            0,
+            0,
+            0,
+            name.len() as u32 + 1,
+            0,
+        ];
+        archive.extend_from_slice(MAGIC_NEWC);
+        for field in fields {
+            archive.extend_from_slice(format!("{:08X}", field).as_bytes());
+        }
+        archive.extend_from_slice(name.as_bytes());
+        archive.push(0);
+        archive.resize(align4(archive.len()), 0);
+        archive.extend_from_slice(data);
+        archive.resize(align4(archive.len()), 0);
+    }
+
+    fn push_trailer(archive: &mut Vec<u8>) {
+        push_entry(archive, 0, 0, TRAILER, &[]);
+    }
+
+    fn sample() -> Vec<u8> {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, 1, S_IFDIR | 0o755, "bin", &[]);
+        push_entry(&mut archive, 2, S_IFREG | 0o755, "bin/init", b"\x7fELF!");
+        push_entry(&mut archive, 3, S_IFLNK | 0o777, "init", b"bin/init");
+        push_trailer(&mut archive);
+        archive
+    }
+
+    fn collect(data: &[u8]) -> Vec<Result<Entry<'_>, CpioError>> {
+        CpioArchive::new(data).entries().collect()
+    }
+
+    #[test]
+    fn entries() {
+        let mut archive = sample();
+        // Anything after the trailer is ignored.
+        archive.extend_from_slice(&[0; 512]);
+        let entries: Vec<_> = collect(&archive).into_iter().map(Result::unwrap).collect();
+        assert_eq!(entries.len(), 3);
+        let names: Vec<_> = entries.iter().map(|entry| entry.name).collect();
+        assert_eq!(names, ["bin", "bin/init", "init"]);
+        assert!(entries[0].is_dir() && entries[0].data.is_empty());
+        assert!(entries[1].is_file());
+        assert_eq!(entries[1].data, b"\x7fELF!");
+        assert_eq!((entries[1].ino, entries[1].mode & !S_IFMT), (2, 0o755));
+        assert_eq!((entries[1].nlink, entries[1].mtime), (1, 0x6000_0000));
+        assert!(entries[2].is_symlink());
+        assert_eq!(entries[2].data, b"bin/init");
+
+        let archive = CpioArchive::new(&archive);
+        assert_eq!(archive.find("bin/init").unwrap().unwrap().ino, 2);
+        assert!(archive.find("sbin/init").unwrap().is_none());
+    }
+
+    #[test]
+    fn checksummed_header() {
+        let mut archive = sample();
+        archive[..MAGIC_CRC.len()].copy_from_slice(MAGIC_CRC);
+        assert_eq!(collect(&archive).len(), 3);
+    }
+
+    #[test]
+    fn padding() {
+        let names = ["a", "ab", "abc", "abcd", "abcde"];
+        let data = [&b""[..], b"1", b"12", b"123", b"1234", b"12345"];
+        let mut archive = Vec::new();
+        let mut expected = Vec::new();
+        for name in names {
+            for data in data {
+                let offset = archive.len();
+                push_entry(&mut archive, expected.len() as u32, S_IFREG, name, data);
+                expected.push((offset, name, data));
+            }
+        }
+        push_trailer(&mut archive);
+        let entries = collect(&archive);
+        assert_eq!(entries.len(), expected.len());
+        for (entry, (offset, name, data)) in entries.into_iter().zip(expected) {
+            let entry = entry.unwrap();
+            assert_eq!(offset % 4, 0);
+            assert_eq!((entry.name, entry.data), (name, data));
+            // The data starts at the first multiple of 4 after the name.
+            let data_start = entry.data.as_ptr() as usize - archive.as_ptr() as usize;
+            assert_eq!(data_start, align4(offset + HEADER_SIZE + name.len() + 1));
+        }
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut archive = sample();
+        assert_eq!(
+            collect(&archive[..0]),
+            [Err(CpioError::Truncated { offset: 0 })]
+        );
+        archive[5] = b'7';
+        assert_eq!(
+            collect(&archive),
+            [Err(CpioError::InvalidMagic { offset: 0 })]
+        );
+
+        let mut archive = sample();
+        let offset = align4(HEADER_SIZE + "bin".len() + 1);
+        archive[offset] = b'1';
+        let entries = collect(&archive);
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_ok());
+        assert_eq!(entries[1], Err(CpioError::InvalidMagic { offset }));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, 1, S_IFREG, "file", b"content");
+        push_trailer(&mut archive);
+        let header_end = HEADER_SIZE;
+        let name_end = HEADER_SIZE + "file".len() + 1;
+        let data_end = align4(name_end) + "content".len();
+        for len in [
+            1,
+            header_end - 1,
+            header_end + 2,
+            name_end - 1,
+            data_end - 1,
+        ] {
+            assert_eq!(
+                collect(&archive[..len]),
+                [Err(CpioError::Truncated { offset: 0 })]
+            );
+        }
+    }
+
+    #[test]
+    fn missing_trailer() {
+        let mut archive = Vec::new();
+        push_entry(&mut archive, 1, S_IFCHR, "console", &[]);
+        let entries = collect(&archive);
+        assert_eq!(entries.len(), 2);
+        assert!(entries[0].is_ok());
+        assert_eq!(
+            entries[1],
+            Err(CpioError::Truncated {
+                offset: archive.len()
+            })
+        );
+        assert_eq!(
+            CpioArchive::new(&archive).find("init").unwrap_err(),
+            CpioError::Truncated {
+                offset: archive.len()
+            }
+        );
+    }
+
+    #[test]
+    fn invalid_fields() {
+        let mut archive = sample();
+        archive[MAGIC_NEWC.len() + FIELD_LEN * 6] = b'g';
+        assert_eq!(
+            collect(&archive),
+            [Err(CpioError::InvalidHeader { offset: 0 })]
+        );
+
+        // The name lacks its NUL.
+        let mut archive = sample();
+        archive[HEADER_SIZE + "bin".len()] = b'/';
+        assert_eq!(
+            collect(&archive),
+            [Err(CpioError::InvalidName { offset: 0 })]
+        );
+
+        let mut archive = Vec::new();
+        push_entry(&mut archive, 1, S_IFREG, "", &[]);
+        push_trailer(&mut archive);
+        assert_eq!(
+            collect(&archive),
+            [Err(CpioError::InvalidName { offset: 0 })]
+        );
+    }
+}
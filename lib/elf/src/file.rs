@@ -0,0 +1,405 @@
+//! ELF64 header and program header parsing.
+
+use crate::{ET_EXEC, ElfError, PT_INTERP, PT_LOAD, PT_NOTE};
+
+const ELF_MAGIC: [u8; 4] = [0x7f, b'E', b'L', b'F'];
+const ELFCLASS64: u8 = 2;
+const ELFDATA2LSB: u8 = 1;
+const EV_CURRENT: u32 = 1;
+
+/// Size of the ELF64 file header.
+pub const EHDR_SIZE: usize = 64;
+/// Size of an ELF64 program header.
+pub const PHDR_SIZE: usize = 56;
+
+fn read_bytes<const N: usize>(data: &[u8], offset: usize) -> Result<[u8; N], ElfError> {
+    offset
+        .checked_add(N)
+        .and_then(|end| data.get(offset..end))
+        .map(|bytes| bytes.try_into().unwrap())
+        .ok_or(ElfError::Truncated { offset })
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    Ok(u16::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    Ok(u32::from_le_bytes(read_bytes(data, offset)?))
+}
+
+fn read_u64(data: &[u8], offset: usize) -> Result<u64, ElfError> {
+    Ok(u64::from_le_bytes(read_bytes(data, offset)?))
+}
+
+/// A program header (segment descriptor).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ProgramHeader {
+    pub p_type: u32,
+    /// Permissions, a combination of [crate::PF_R], [crate::PF_W] and [crate::PF_X].
+    pub flags: u32,
+    pub offset: u64,
+    pub vaddr: u64,
+    pub filesz: u64,
+    pub memsz: u64,
+    pub align: u64,
+}
+
+impl ProgramHeader {
+    fn parse(data: &[u8], offset: usize) -> Result<ProgramHeader, ElfError> {
+        Ok(ProgramHeader {
+            p_type: read_u32(data, offset)?,
+            flags: read_u32(data, offset + 4)?,
+            offset: read_u64(data, offset + 8)?,
+            vaddr: read_u64(data, offset + 16)?,
+            filesz: read_u64(data, offset + 32)?,
+            memsz: read_u64(data, offset + 40)?,
+            align: read_u64(data, offset + 48)?,
+        })
+    }
+
+    /// Whether the segment fits in the file and in the address space.
+    fn is_valid(&self, file_len: usize) -> bool {
+        let in_file = self
+            .offset
+            .checked_add(self.filesz)
+            .is_some_and(|end| end <= file_len as u64);
+        let in_space = self.vaddr.checked_add(self.memsz).is_some();
+        let aligned = self.align <= 1
+            || (self.align.is_power_of_two()
+                && self.vaddr % self.align == self.offset % self.align);
+        in_file && in_space && aligned && self.filesz <= self.memsz
+    }
+}
+
+/// A note of a `PT_NOTE` segment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note<'a> {
+    /// Owner of the note, without its terminating NUL.
+    pub name: &'a [u8],
+    pub n_type: u32,
+    pub desc: &'a [u8],
+}
+
+/// Parse the notes of `data`, whose fields are padded to `align` bytes.
+/// The walk stops at the first truncated note.
+fn parse_notes(data: &[u8], align: usize) -> impl Iterator<Item = Note<'_>> {
+    let pad = move |len: usize| len.checked_next_multiple_of(align);
+    let mut offset = 0;
+    core::iter::from_fn(move || {
+        let namesz = read_u32(data, offset).ok()? as usize;
+        let descsz = read_u32(data, offset + 4).ok()? as usize;
+        let n_type = read_u32(data, offset + 8).ok()?;
+        let name_start = offset + 12;
+        let desc_start = name_start.checked_add(pad(namesz)?)?;
+        let desc_end = desc_start.checked_add(descsz)?;
+        let name = data.get(name_start..name_start + namesz)?;
+        let desc = data.get(desc_start..desc_end)?;
+        offset = desc_start.checked_add(pad(descsz)?)?;
+        Some(Note {
+            name: name.strip_suffix(&[0]).unwrap_or(name),
+            n_type,
+            desc,
+        })
+    })
+}
+
+/// A validated ELF64 executable.
+///
+/// Every loadable segment lies within the file, so [ElfFile::segment_data] never fails.
+#[derive(Debug)]
+pub struct ElfFile<'a> {
+    data: &'a [u8],
+    pub entry: u64,
+    phoff: usize,
+    phnum: usize,
+}
+
+impl<'a> ElfFile<'a> {
+    /// Validate `data` as a statically linked little-endian ELF64 executable for `machine`.
+    pub fn parse(data: &'a [u8], machine: u16) -> Result<ElfFile<'a>, ElfError> {
+        let ident: [u8; 16] = read_bytes(data, 0)?;
+        if ident[0..4] != ELF_MAGIC {
+            return Err(ElfError::InvalidMagic);
+        }
+        if ident[4] != ELFCLASS64 {
+            return Err(ElfError::UnsupportedClass { class: ident[4] });
+        }
+        if ident[5] != ELFDATA2LSB {
+            return Err(ElfError::UnsupportedEncoding { encoding: ident[5] });
+        }
+        let elf_type = read_u16(data, 16)?;
+        if elf_type != ET_EXEC {
+            return Err(ElfError::UnsupportedType { elf_type });
+        }
+        let elf_machine = read_u16(data, 18)?;
+        if elf_machine != machine {
+            return Err(ElfError::WrongMachine {
+                machine: elf_machine,
+            });
+        }
+        let version = read_u32(data, 20)?;
+        if version != EV_CURRENT {
+            return Err(ElfError::UnsupportedVersion { version });
+        }
+        let entry = read_u64(data, 24)?;
+        let phoff = read_u64(data, 32)?;
+        let phentsize = read_u16(data, 54)?;
+        let phnum = read_u16(data, 56)? as usize;
+        if phnum != 0 && phentsize as usize != PHDR_SIZE {
+            return Err(ElfError::InvalidProgramHeaderSize { size: phentsize });
+        }
+        let phoff =
+            usize::try_from(phoff).map_err(|_| ElfError::Truncated { offset: usize::MAX })?;
+        let phend = phnum
+            .checked_mul(PHDR_SIZE)
+            .and_then(|size| phoff.checked_add(size))
+            .ok_or(ElfError::Truncated { offset: phoff })?;
+        if phend > data.len() {
+            return Err(ElfError::Truncated { offset: phoff });
+        }
+        let file = ElfFile {
+            data,
+            entry,
+            phoff,
+            phnum,
+        };
+        let mut loadable = false;
+        for (index, ph) in file.program_headers().enumerate() {
+            match ph.p_type {
+                PT_INTERP => return Err(ElfError::DynamicallyLinked),
+                PT_LOAD if !ph.is_valid(data.len()) => {
+                    return Err(ElfError::InvalidSegment { index });
+                }
+                PT_LOAD => loadable |= ph.memsz != 0,
+                _ => {}
+            }
+        }
+        if !loadable {
+            return Err(ElfError::NoLoadableSegment);
+        }
+        Ok(file)
+    }
+
+    pub fn program_headers(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        // The table was checked to be within the file.
+        (0..self.phnum).map(move |index| {
+            ProgramHeader::parse(self.data, self.phoff + index * PHDR_SIZE).unwrap()
+        })
+    }
+
+    pub fn load_segments(&self) -> impl Iterator<Item = ProgramHeader> + '_ {
+        self.program_headers()
+            .filter(|ph| ph.p_type == PT_LOAD && ph.memsz != 0)
+    }
+
+    /// The bytes of a loadable segment found in the file. The rest of the segment is zero-filled.
+    pub fn segment_data(&self, ph: &ProgramHeader) -> &'a [u8] {
+        let start = ph.offset as usize;
+        &self.data[start..start + ph.filesz as usize]
+    }
+
+    /// The notes of the `PT_NOTE` segments. Notes outside of the file are skipped.
+    pub fn notes(&self) -> impl Iterator<Item = Note<'a>> + '_ {
+        let data = self.data;
+        self.program_headers()
+            .filter(|ph| ph.p_type == PT_NOTE)
+            .filter_map(move |ph| {
+                let start = usize::try_from(ph.offset).ok()?;
+                let end = start.checked_add(usize::try_from(ph.filesz).ok()?)?;
+                // 64-bit notes are usually aligned to 4 bytes, but may be aligned to 8.
+                let align = if ph.align == 8 { 8 } else { 4 };
+                Some(parse_notes(data.get(start..end)?, align))
+            })
+            .flatten()
+    }
+
+    /// Number of program headers, for `AT_PHNUM`.
+    pub fn phnum(&self) -> usize {
+        self.phnum
+    }
+
+    /// Address of the program headers once loaded, for `AT_PHDR`,
+    /// or [None] if no loadable segment contains them.
+    pub fn phdr_vaddr(&self) -> Option<u64> {
+        let phoff = self.phoff as u64;
+        self.load_segments()
+            .find(|ph| {
+                ph.offset <= phoff
+                    && phoff + (self.phnum * PHDR_SIZE) as u64 <= ph.offset + ph.filesz
+            })
+            .map(|ph| ph.vaddr + (phoff - ph.offset))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{EM_LOONGARCH, EM_RISCV, ET_DYN, PF_R, PF_W, PF_X};
+    use alloc::vec::Vec;
+
+    fn phdr(
+        p_type: u32,
+        flags: u32,
+        offset: u64,
+        vaddr: u64,
+        filesz: u64,
+        memsz: u64,
+    ) -> ProgramHeader {
+        ProgramHeader {
+            p_type,
+            flags,
+            offset,
+            vaddr,
+            filesz,
+            memsz,
+            align: 0x1000,
+        }
+    }
+
+    /// An executable with the program headers right after the file header, padded to `len` bytes.
+    fn image(phdrs: &[ProgramHeader], len: usize) -> Vec<u8> {
+        let mut data = Vec::from(ELF_MAGIC);
+        data.extend_from_slice(&[ELFCLASS64, ELFDATA2LSB, 1]);
+        data.resize(16, 0);
+        data.extend_from_slice(&ET_EXEC.to_le_bytes());
+        data.extend_from_slice(&EM_RISCV.to_le_bytes());
+        data.extend_from_slice(&EV_CURRENT.to_le_bytes());
+        data.extend_from_slice(&0x10078u64.to_le_bytes());
+        data.extend_from_slice(&(EHDR_SIZE as u64).to_le_bytes());
+        data.resize(52, 0);
+        data.extend_from_slice(&(EHDR_SIZE as u16).to_le_bytes());
+        data.extend_from_slice(&(PHDR_SIZE as u16).to_le_bytes());
+        data.extend_from_slice(&(phdrs.len() as u16).to_le_bytes());
+        data.resize(EHDR_SIZE, 0);
+        for ph in phdrs {
+            data.extend_from_slice(&ph.p_type.to_le_bytes());
+            data.extend_from_slice(&ph.flags.to_le_bytes());
+            data.extend_from_slice(&ph.offset.to_le_bytes());
+            data.extend_from_slice(&ph.vaddr.to_le_bytes());
+            data.extend_from_slice(&ph.vaddr.to_le_bytes());
+            data.extend_from_slice(&ph.filesz.to_le_bytes());
+            data.extend_from_slice(&ph.memsz.to_le_bytes());
+            data.extend_from_slice(&ph.align.to_le_bytes());
+        }
+        data.resize(len.max(data.len()), 0);
+        data
+    }
+
+    fn text() -> ProgramHeader {
+        phdr(PT_LOAD, PF_R | PF_X, 0, 0x10000, 0x200, 0x200)
+    }
+
+    fn parse(data: &[u8]) -> Result<ElfFile<'_>, ElfError> {
+        ElfFile::parse(data, EM_RISCV)
+    }
+
+    #[test]
+    fn load_segments() {
+        let bss = phdr(PT_LOAD, PF_R | PF_W, 0x200, 0x11200, 0x80, 0x1000);
+        let empty = phdr(PT_LOAD, PF_R, 0x280, 0x12280, 0, 0);
+        let note = phdr(PT_NOTE, PF_R, 0x280, 0, 0, 0);
+        let mut data = image(&[text(), note, bss, empty], 0x280);
+        data[0x200] = 0xaa;
+        let file = parse(&data).unwrap();
+        assert_eq!(file.entry, 0x10078);
+        assert_eq!(file.phnum(), 4);
+        assert_eq!(
+            file.program_headers().collect::<Vec<_>>(),
+            [text(), note, bss, empty]
+        );
+        assert_eq!(file.load_segments().collect::<Vec<_>>(), [text(), bss]);
+        assert_eq!(file.segment_data(&text()), &data[..0x200]);
+        assert_eq!(file.segment_data(&bss).len(), 0x80);
+        assert_eq!(file.segment_data(&bss)[0], 0xaa);
+        assert_eq!(file.phdr_vaddr(), Some(0x10000 + EHDR_SIZE as u64));
+    }
+
+    #[test]
+    fn truncated_header() {
+        let data = image(&[text()], 0x200);
+        assert_eq!(
+            parse(&data[..8]).unwrap_err(),
+            ElfError::Truncated { offset: 0 }
+        );
+        assert_eq!(
+            parse(&data[..40]).unwrap_err(),
+            ElfError::Truncated { offset: 54 }
+        );
+    }
+
+    #[test]
+    fn invalid_magic() {
+        let mut data = image(&[text()], 0x200);
+        data[1] = b'e';
+        assert_eq!(parse(&data).unwrap_err(), ElfError::InvalidMagic);
+    }
+
+    #[test]
+    fn unsupported_header() {
+        let data = image(&[text()], 0x200);
+        let patched = |offset: usize, bytes: &[u8]| {
+            let mut data = data.clone();
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+            parse(&data).unwrap_err()
+        };
+        assert_eq!(patched(4, &[1]), ElfError::UnsupportedClass { class: 1 });
+        assert_eq!(
+            patched(5, &[2]),
+            ElfError::UnsupportedEncoding { encoding: 2 }
+        );
+        assert_eq!(
+            patched(16, &ET_DYN.to_le_bytes()),
+            ElfError::UnsupportedType { elf_type: ET_DYN }
+        );
+        assert_eq!(
+            patched(18, &EM_LOONGARCH.to_le_bytes()),
+            ElfError::WrongMachine {
+                machine: EM_LOONGARCH
+            }
+        );
+        assert_eq!(
+            patched(20, &[0]),
+            ElfError::UnsupportedVersion { version: 0 }
+        );
+        assert_eq!(
+            patched(54, &[32]),
+            ElfError::InvalidProgramHeaderSize { size: 32 }
+        );
+    }
+
+    #[test]
+    fn program_headers_out_of_file() {
+        let data = image(&[text(), text()], 0);
+        let end = EHDR_SIZE + PHDR_SIZE * 2;
+        assert_eq!(
+            parse(&data[..end - 1]).unwrap_err(),
+            ElfError::Truncated { offset: EHDR_SIZE }
+        );
+    }
+
+    #[test]
+    fn invalid_segments() {
+        let beyond_file = phdr(PT_LOAD, PF_R, 0x100, 0x10100, 0x200, 0x200);
+        let larger_in_file = phdr(PT_LOAD, PF_R, 0, 0x10000, 0x200, 0x100);
+        let misaligned = phdr(PT_LOAD, PF_R, 0, 0x10010, 0x200, 0x200);
+        let overflowing = phdr(PT_LOAD, PF_R, 0, u64::MAX - 0xfff, 0x200, 0x2000);
+        for ph in [beyond_file, larger_in_file, misaligned, overflowing] {
+            let data = image(&[text(), ph], 0x200);
+            assert_eq!(
+                parse(&data).unwrap_err(),
+                ElfError::InvalidSegment { index: 1 }
+            );
+        }
+    }
+
+    #[test]
+    fn unloadable() {
+        let interp = phdr(PT_INTERP, PF_R, 0x100, 0, 0x10, 0x10);
+        let data = image(&[text(), interp], 0x200);
+        assert_eq!(parse(&data).unwrap_err(), ElfError::DynamicallyLinked);
+        let empty = phdr(PT_LOAD, PF_R, 0, 0x10000, 0, 0);
+        let data = image(&[empty], 0x200);
+        assert_eq!(parse(&data).unwrap_err(), ElfError::NoLoadableSegment);
+    }
+}
@@ -0,0 +1,74 @@
+//! ELF64 parsing for the program loader.
+//!
+//! [ElfFile::parse] validates an executable image held in memory, and [stack::InitialStack]
+//! lays out the initial user stack (argv, envp and auxv). Neither touches memory outside of
+//! the buffers passed in, so both run on the host as well as in the kernel.
+#![no_std]
+extern crate alloc;
+
+pub mod file;
+pub mod stack;
+
+pub use file::{ElfFile, Note, ProgramHeader};
+
+/// Machine of RISC-V executables (`e_machine`).
+pub const EM_RISCV: u16 = 243;
+/// Machine of LoongArch executables (`e_machine`).
+pub const EM_LOONGARCH: u16 = 258;
+
+/// Statically linked executable (`e_type`).
+pub const ET_EXEC: u16 = 2;
+/// Position-independent executable or shared object (`e_type`).
+pub const ET_DYN: u16 = 3;
+
+/// Segment types (`p_type`).
+pub const PT_LOAD: u32 = 1;
+pub const PT_INTERP: u32 = 3;
+pub const PT_NOTE: u32 = 4;
+pub const PT_PHDR: u32 = 6;
+
+/// Segment permissions (`p_flags`).
+pub const PF_X: u32 = 1 << 0;
+pub const PF_W: u32 = 1 << 1;
+pub const PF_R: u32 = 1 << 2;
+
+/// Errors found while validating an ELF file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    /// The file ends before a structure it declares.
+    Truncated {
+        offset: usize,
+    },
+    InvalidMagic,
+    /// Not a 64-bit file.
+    UnsupportedClass {
+        class: u8,
+    },
+    /// Not a little-endian file.
+    UnsupportedEncoding {
+        encoding: u8,
+    },
+    UnsupportedVersion {
+        version: u32,
+    },
+    /// Only statically linked executables are loaded.
+    UnsupportedType {
+        elf_type: u16,
+    },
+    /// Built for another architecture.
+    WrongMachine {
+        machine: u16,
+    },
+    /// The program headers don't have the size of an ELF64 program header.
+    InvalidProgramHeaderSize {
+        size: u16,
+    },
+    /// The executable asks for a dynamic linker.
+    DynamicallyLinked,
+    /// A segment is larger in the file than in memory, overflows the address space,
+    /// or its address and offset disagree modulo its alignment.
+    InvalidSegment {
+        index: usize,
+    },
+    NoLoadableSegment,
+}
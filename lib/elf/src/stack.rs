@@ -0,0 +1,207 @@
+//! Initial user stack layout.
+//!
+//! At the entry point, `sp` points to the following words, as expected by the System V ABI
+//! and laid out by Linux:
+//!
+//! ```text
+//! sp ->  argc
+//!        argv[0], ..., argv[argc - 1], NULL
+//!        envp[0], ..., NULL
+//!        auxv key, value pairs, AT_NULL, 0
+//!        (padding)
+//!        data pointed to by auxv values (AT_RANDOM bytes, AT_EXECFN string)
+//!        strings of argv and envp
+//!        NULL end marker
+//!        (padding up to the stack top)
+//! ```
+
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Auxiliary vector keys.
+pub const AT_NULL: usize = 0;
+pub const AT_PHDR: usize = 3;
+pub const AT_PHENT: usize = 4;
+pub const AT_PHNUM: usize = 5;
+pub const AT_PAGESZ: usize = 6;
+pub const AT_BASE: usize = 7;
+pub const AT_FLAGS: usize = 8;
+pub const AT_ENTRY: usize = 9;
+pub const AT_UID: usize = 11;
+pub const AT_EUID: usize = 12;
+pub const AT_GID: usize = 13;
+pub const AT_EGID: usize = 14;
+pub const AT_HWCAP: usize = 16;
+pub const AT_CLKTCK: usize = 17;
+pub const AT_SECURE: usize = 23;
+pub const AT_RANDOM: usize = 25;
+pub const AT_EXECFN: usize = 31;
+
+/// Alignment of `sp` at the entry point.
+pub const STACK_ALIGN: usize = 16;
+
+const WORD: usize = size_of::<u64>();
+
+fn push_word(image: &mut Vec<u8>, word: usize) {
+    image.extend_from_slice(&(word as u64).to_le_bytes());
+}
+
+/// The content of the initial stack, to be copied to `[sp, stack_top)` in the user memspace.
+#[derive(Debug)]
+pub struct InitialStack {
+    pub sp: usize,
+    pub image: Vec<u8>,
+}
+
+impl InitialStack {
+    /// Lay out `argv`, `envp` and the `(key, value)` pairs of `auxv` below `stack_top`.
+    /// Each `(key, data)` of `aux_data` is copied to the stack and appended to `auxv`
+    /// with the address of the copy as its value. `AT_NULL` is appended last.
+    ///
+    /// Return [None] if the layout doesn't fit in `max_size` bytes.
+    pub fn build(
+        stack_top: usize,
+        max_size: usize,
+        argv: &[&str],
+        envp: &[&str],
+        auxv: &[(usize, usize)],
+        aux_data: &[(usize, &[u8])],
+    ) -> Option<InitialStack> {
+        let top = stack_top & !(WORD - 1);
+        let strings_size: usize = argv.iter().chain(envp).map(|s| s.len() + 1).sum();
+        let data_size: usize = aux_data
+            .iter()
+            .map(|(_, data)| data.len().next_multiple_of(WORD))
+            .sum();
+        let strings_start = top.checked_sub(WORD + strings_size)?;
+        let data_start = (strings_start & !(WORD - 1)).checked_sub(data_size)?;
+        let words = 1 + (argv.len() + 1) + (envp.len() + 1) + 2 * (auxv.len() + aux_data.len() + 1);
+        let sp = data_start.checked_sub(words * WORD)? & !(STACK_ALIGN - 1);
+        if stack_top - sp > max_size {
+            return None;
+        }
+
+        let mut image = Vec::with_capacity(stack_top - sp);
+        push_word(&mut image, argv.len());
+        let mut string_addr = strings_start;
+        for s in argv {
+            push_word(&mut image, string_addr);
+            string_addr += s.len() + 1;
+        }
+        push_word(&mut image, 0);
+        for s in envp {
+            push_word(&mut image, string_addr);
+            string_addr += s.len() + 1;
+        }
+        push_word(&mut image, 0);
+        for &(key, value) in auxv {
+            push_word(&mut image, key);
+            push_word(&mut image, value);
+        }
+        let mut data_addr = data_start;
+        for (key, data) in aux_data {
+            push_word(&mut image, *key);
+            push_word(&mut image, data_addr);
+            data_addr += data.len().next_multiple_of(WORD);
+        }
+        push_word(&mut image, AT_NULL);
+        push_word(&mut image, 0);
+        image.resize(data_start - sp, 0);
+        for (_, data) in aux_data {
+            image.extend_from_slice(data);
+            image.resize(image.len().next_multiple_of(WORD), 0);
+        }
+        image.resize(strings_start - sp, 0);
+        for s in argv.iter().chain(envp) {
+            image.extend_from_slice(s.as_bytes());
+            image.push(0);
+        }
+        // The end marker and the padding up to the stack top.
+        image.resize(stack_top - sp, 0);
+        Some(InitialStack { sp, image })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STACK_TOP: usize = 0x4000_0000;
+
+    fn word(stack: &InitialStack, addr: usize) -> usize {
+        let offset = addr - stack.sp;
+        u64::from_le_bytes(stack.image[offset..offset + WORD].try_into().unwrap()) as usize
+    }
+
+    fn string(stack: &InitialStack, addr: usize) -> &str {
+        let bytes = &stack.image[addr - stack.sp..];
+        let len = bytes.iter().position(|&b| b == 0).unwrap();
+        core::str::from_utf8(&bytes[..len]).unwrap()
+    }
+
+    #[test]
+    fn layout() {
+        let random = [0x5a; 16];
+        let stack = InitialStack::build(
+            STACK_TOP - 3,
+            0x1000,
+            &["/bin/sh", "-c", "ls"],
+            &["PATH=/bin"],
+            &[(AT_PAGESZ, 0x1000)],
+            &[(AT_RANDOM, &random), (AT_EXECFN, b"/bin/sh\0")],
+        )
+        .unwrap();
+        assert_eq!(stack.sp % STACK_ALIGN, 0);
+        assert_eq!(stack.image.len(), STACK_TOP - 3 - stack.sp);
+
+        let mut addr = stack.sp;
+        let mut next = || {
+            let value = word(&stack, addr);
+            addr += WORD;
+            value
+        };
+        assert_eq!(next(), 3);
+        let argv = [next(), next(), next()];
+        assert_eq!(next(), 0);
+        let envp = next();
+        assert_eq!(next(), 0);
+        assert_eq!([next(), next()], [AT_PAGESZ, 0x1000]);
+        assert_eq!(next(), AT_RANDOM);
+        let random_addr = next();
+        assert_eq!(next(), AT_EXECFN);
+        let execfn_addr = next();
+        assert_eq!([next(), next()], [AT_NULL, 0]);
+
+        assert_eq!(
+            argv.map(|addr| string(&stack, addr)),
+            ["/bin/sh", "-c", "ls"]
+        );
+        assert_eq!(string(&stack, envp), "PATH=/bin");
+        assert_eq!(random_addr % WORD, 0);
+        let offset = random_addr - stack.sp;
+        assert_eq!(stack.image[offset..offset + random.len()], random);
+        assert_eq!(string(&stack, execfn_addr), "/bin/sh");
+        // The strings are followed by the end marker.
+        let end = envp + "PATH=/bin".len() + 1;
+        assert!(end + WORD <= STACK_TOP - 3);
+        assert_eq!(word(&stack, end), 0);
+    }
+
+    #[test]
+    fn alignment() {
+        for args in 0..4 {
+            let argv = ["x"; 4];
+            let stack =
+                InitialStack::build(STACK_TOP, 0x1000, &argv[..args], &[], &[], &[]).unwrap();
+            assert_eq!(stack.sp % STACK_ALIGN, 0);
+            assert_eq!(word(&stack, stack.sp), args);
+        }
+    }
+
+    #[test]
+    fn too_large() {
+        let arg = "x".repeat(0x1000);
+        assert!(InitialStack::build(STACK_TOP, 0x1000, &[&arg], &[], &[], &[]).is_none());
+        assert!(InitialStack::build(0x10, 0x1000, &["arg"], &[], &[], &[]).is_none());
+    }
+}
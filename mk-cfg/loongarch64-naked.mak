@@ -1,6 +1,6 @@
 export QEMU			:= qemu-system-loongarch64
 export TARGET		:= loongarch64-unknown-none
-export FEATURES 	:= naked
+export FEATURES 	:= naked,embed-initrd
 export DTB_FILE		:= $(RUNTIME)/qemu-loongarch64.dtb
 export DTS_FILE		:= $(RUNTIME)/qemu-loongarch64.dts
 
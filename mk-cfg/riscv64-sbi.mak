@@ -12,6 +12,7 @@ export QEMU_ARGS	:= 	-machine $(MACHINE),memory-backend=mem0 \
 						-smp $(CPU_INFO) \
 						-m $(MEM_SIZE) \
 						-bios $(BIOS) \
+						-initrd "$(INITRD_FILE)" \
 						-display gtk -monitor stdio \
 						-object memory-backend-ram,id=mem0,size=$(MEM_SIZE),share=on,prealloc=off \
 						$(EXTRA_QEMU_ARGS)
@@ -1,4 +1,5 @@
 export RUNTIME		:= $(CUR)/runtime
+export INITRD_FILE	:= $(RUNTIME)/initrd.cpio
 
 export CPU_INFO		:= 8,sockets=2,dies=1,cores=2,threads=2
 export MEM_SIZE		:= 5G
@@ -25,6 +25,7 @@ fn main() {
     println!("cargo:rerun-if-changed=build.rs");
     println!("cargo:rerun-if-changed=linker.cross.ld");
     println!("cargo:rerun-if-changed=../runtime/qemu-loongarch64.dtb");
+    println!("cargo:rerun-if-changed=../runtime/initrd.cpio");
     println!("cargo:rerun-if-changed=link_flags.json");
 }
 
@@ -0,0 +1,199 @@
+//! Lazy switching of the floating-point and LSX registers
+//!
+//! The units stay disabled in `EUEN` while the kernel runs, so that it traps on any use of the registers.
+//! A task turns them on with its first floating-point or LSX instruction, see [enable_on_first_use].
+//!
+//! Unlike RISC-V, LoongArch doesn't track changes to the registers: those of a task are saved
+//! when it's switched out if it returned to user mode with a unit on, see [save], and only loaded back
+//! on its way to user mode if another task used them on the hart meanwhile, see [return_to_user].
+//! LASX, 256-bit vectors, is not supported.
+//!
+//! The port has no trap path yet. Once it does, [init] runs on each hart and on each trap from user mode,
+//! [save] when a task is switched out, [return_to_user] on the way back to user mode,
+//! and [enable_on_first_use] on the `FPD` and `SXD` exceptions.
+
+use crate::{arch::reg::CR_EUEN, percpu};
+use core::{
+    arch::asm,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+/// `EUEN.FPE`, enabling the floating-point unit.
+pub const EUEN_FPE: usize = 1 << 0;
+/// `EUEN.SXE`, enabling the 128-bit LSX unit. Requires [EUEN_FPE].
+pub const EUEN_SXE: usize = 1 << 1;
+
+/// Exception code of a floating-point instruction with the unit disabled.
+pub const ECODE_FPD: usize = 0xf;
+/// Exception code of an LSX instruction with the unit disabled.
+pub const ECODE_SXD: usize = 0x10;
+
+percpu! {
+    /// Address of the [FpuContext] the registers of the hart hold, 0 if none.
+    static FPU_OWNER: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Floating-point and LSX registers. The floating-point ones are the low halves of the LSX ones.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct FpRegs {
+    vr: [[u64; 2]; 32],
+    /// `fcc0`-`fcc7`.
+    fcc: [u8; 8],
+    fcsr: u32,
+}
+
+/// Floating-point and LSX state of a task, while the registers of a hart don't hold it.
+#[derive(Debug, Default)]
+pub struct FpuContext {
+    regs: FpRegs,
+    /// `EUEN` bits of the units the task turned on.
+    units: usize,
+    /// Whether the task returned to user mode with a unit on since the registers were saved.
+    dirty: bool,
+    /// The hart whose registers hold the state, if any. They do as long as it's also the [FPU_OWNER] there.
+    hart_id: Option<usize>,
+}
+
+impl FpuContext {
+    /// A copy for a new thread or process, which must be up to date, see [save].
+    pub fn fork(&self) -> FpuContext {
+        FpuContext {
+            regs: self.regs,
+            units: self.units,
+            dirty: false,
+            hart_id: None,
+        }
+    }
+
+    fn address(&self) -> usize {
+        self as *const FpuContext as usize
+    }
+}
+
+fn write_euen(value: usize) {
+    unsafe { asm!("csrwr {}, {csr}", inout(reg) value => _, csr = const CR_EUEN) };
+}
+
+/// Disable the units in the kernel. Call it on each hart, and on each trap from user mode.
+pub fn init() {
+    write_euen(0);
+}
+
+/// Forget the registers of hart `hart_id`, going offline: they are lost once it's stopped.
+pub fn hart_offline(hart_id: usize) {
+    FPU_OWNER.remote(hart_id).store(0, Ordering::Relaxed);
+}
+
+#[target_feature(enable = "lsx")]
+unsafe fn save_regs(regs: &mut FpRegs, lsx: bool) {
+    let base = regs as *mut FpRegs;
+    unsafe {
+        if lsx {
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "vst $vr\\n, {base}, \\n * 16",
+                ".endr",
+                base = in(reg) base,
+            );
+        } else {
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "fst.d $f\\n, {base}, \\n * 16",
+                ".endr",
+                base = in(reg) base,
+            );
+        }
+        asm!(
+            ".irp n, 0,1,2,3,4,5,6,7",
+            "movcf2gr {tmp}, $fcc\\n",
+            "st.b {tmp}, {base}, 512 + \\n",
+            ".endr",
+            "movfcsr2gr {tmp}, $fcsr0",
+            "st.w {tmp}, {base}, 520",
+            base = in(reg) base,
+            tmp = out(reg) _,
+        );
+    }
+}
+
+#[target_feature(enable = "lsx")]
+unsafe fn load_regs(regs: &FpRegs, lsx: bool) {
+    let base = regs as *const FpRegs;
+    unsafe {
+        if lsx {
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "vld $vr\\n, {base}, \\n * 16",
+                ".endr",
+                base = in(reg) base,
+            );
+        } else {
+            asm!(
+                ".irp n, 0,1,2,3,4,5,6,7,8,9,10,11,12,13,14,15,16,17,18,19,20,21,22,23,24,25,26,27,28,29,30,31",
+                "fld.d $f\\n, {base}, \\n * 16",
+                ".endr",
+                base = in(reg) base,
+            );
+        }
+        asm!(
+            ".irp n, 0,1,2,3,4,5,6,7",
+            "ld.bu {tmp}, {base}, 512 + \\n",
+            "movgr2cf $fcc\\n, {tmp}",
+            ".endr",
+            "ld.wu {tmp}, {base}, 520",
+            "movgr2fcsr $fcsr0, {tmp}",
+            base = in(reg) base,
+            tmp = out(reg) _,
+        );
+    }
+}
+
+/// Save the registers of the task switched out of hart `hart_id` if it may have changed them,
+/// and disable the units. **The task must be the last to run on the hart, with interrupts disabled.**
+pub fn save(fpu: &mut FpuContext, hart_id: usize) {
+    if fpu.dirty && fpu.hart_id == Some(hart_id) {
+        write_euen(fpu.units);
+        unsafe { save_regs(&mut fpu.regs, fpu.units & EUEN_SXE != 0) };
+        fpu.dirty = false;
+    }
+    write_euen(0);
+}
+
+/// Load the registers of the task returning to user mode on hart `hart_id`,
+/// unless the hart still holds them, and enable the units it turned on.
+/// **Interrupts must be disabled until the task is in user mode.**
+pub fn return_to_user(fpu: &mut FpuContext, hart_id: usize) {
+    if fpu.units == 0 {
+        return;
+    }
+    let owner = FPU_OWNER.remote(hart_id);
+    write_euen(fpu.units);
+    if fpu.hart_id != Some(hart_id) || owner.load(Ordering::Relaxed) != fpu.address() {
+        unsafe { load_regs(&fpu.regs, fpu.units & EUEN_SXE != 0) };
+        fpu.hart_id = Some(hart_id);
+        owner.store(fpu.address(), Ordering::Relaxed);
+    }
+    fpu.dirty = true;
+}
+
+/// Turn on the unit whose use raised the exception `ecode` in user mode, so that the instruction
+/// runs again once [return_to_user] enables it. Return whether it's one of those units.
+pub fn enable_on_first_use(fpu: &mut FpuContext, ecode: usize) -> bool {
+    let units = match ecode {
+        ECODE_FPD => EUEN_FPE,
+        ECODE_SXD => EUEN_FPE | EUEN_SXE,
+        _ => return false,
+    };
+    if fpu.units & units == units {
+        return false;
+    }
+    // The registers of the units already on must be saved, or they would be reloaded from older ones.
+    if let (true, Some(hart_id)) = (fpu.dirty, fpu.hart_id) {
+        save(fpu, hart_id);
+    }
+    fpu.units |= units;
+    // The new unit starts zeroed, the others as saved.
+    fpu.hart_id = None;
+    true
+}
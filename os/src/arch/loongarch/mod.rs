@@ -2,6 +2,7 @@
 
 use bitflags::bitflags;
 
+pub mod fpu;
 pub mod mm;
 pub mod reg;
 mod sbi;
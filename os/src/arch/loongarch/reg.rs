@@ -8,6 +8,9 @@ pub const CR_CRMD: u16 = 0x0;
 /// Previous Mode CSR
 pub const CR_PRMD: u16 = 0x1;
 
+/// Extended Component Unit Enable CSR
+pub const CR_EUEN: u16 = 0x2;
+
 /// CPUID CSR
 pub const CR_CPUID: u16 = 0x20;
 
@@ -15,3 +15,21 @@ pub const MAX_ASID: usize = sv::MAX_ASID;
 pub const KERNEL_ASID: usize = sv::KERNEL_ASID;
 
 pub const MAX_HARTS: usize = 16;
+
+/// Machine of the executables the loader accepts (`e_machine`).
+pub const ELF_MACHINE: u16 = elf::EM_RISCV;
+
+/// Machine name reported by `uname`.
+pub const MACHINE_NAME: &str = "riscv64";
+
+/// ISA extensions reported in `AT_HWCAP`, one bit per extension letter as on Linux: IMAFDC.
+pub const ELF_HWCAP: usize = {
+    let mut res = 0;
+    let letters = b"imafdc";
+    let mut i = 0;
+    while i < letters.len() {
+        res |= 1 << (letters[i] - b'a');
+        i += 1;
+    }
+    res
+};
@@ -1,6 +1,6 @@
 use core::arch::asm;
 
-use crate::{arch::SbiTable, panic_init};
+use crate::arch::{SbiError, SbiTable};
 
 pub fn store_hart_id(hart_id: usize) {
     unsafe {
@@ -17,9 +17,14 @@ pub fn get_current_hart_id() -> usize {
     tp_value
 }
 
-/// Call from the main hart and wake slave harts.
-/// **It's available only after the task module is initialized.**
-pub fn wake_slave_harts(hart_id: usize, entry: usize) {
-    SbiTable::hart_start(hart_id, entry, 0)
-        .unwrap_or_else(|err| panic_init!("Unable to start slave hart {:}: {:?}", hart_id, err));
+/// Start a stopped hart, at boot or when it comes back online.
+/// It starts at the physical address `entry` with its hart id in `a0` and `opaque` in `a1`.
+pub fn start_hart(hart_id: usize, entry: usize, opaque: usize) -> Result<(), SbiError> {
+    SbiTable::hart_start(hart_id, entry, opaque)
+}
+
+/// Send a software interrupt to the given hart.
+pub fn send_ipi(hart_id: usize) {
+    SbiTable::send_ipi(1 << hart_id, 0)
+        .unwrap_or_else(|err| log::warn!("Unable to send IPI to hart {:}: {:?}", hart_id, err));
 }
@@ -6,8 +6,16 @@ pub mod sv;
 mod types;
 pub use types::*;
 
-use crate::{arch::MAX_HARTS, mm::stack::RawKernelStack};
+use crate::{mm::stack::RawKernelStack, percpu};
+use alloc::boxed::Box;
+use spin::Once;
 
-/// The kernel stack
+/// The kernel stack of the boot hart
 #[unsafe(link_section = ".bss.stack")]
-pub static BOOT_STACK: [RawKernelStack; MAX_HARTS] = [RawKernelStack::new(); MAX_HARTS];
+pub static BOOT_STACK: RawKernelStack = RawKernelStack::new();
+
+percpu! {
+    /// The kernel stacks of the other harts, allocated by the boot hart before starting them.
+    /// The harts keep running their scheduling loop on them.
+    pub static SLAVE_STACK: Once<Box<RawKernelStack>> = Once::new();
+}
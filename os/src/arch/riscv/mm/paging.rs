@@ -1,6 +1,6 @@
 use crate::{
     arch::{
-        KERNEL_ASID, KERNEL_OFFSET, MAX_USPACE_ADDR, PAGE_WIDTH,
+        KERNEL_ASID, KERNEL_OFFSET, MAX_ASID, MAX_USPACE_ADDR, PAGE_WIDTH,
         mm::{PageNum, sv::SATP_MODE},
         symbols::{_ebss, _edata, _erodata, _etext, _sbss, _sdata, _srodata, _stext},
     },
@@ -11,11 +11,15 @@ use crate::{
         paging::{PageDirTrait, PageTable, PagingError},
         space::MemSpace,
     },
+    mutex::SpinLock,
     phys_addr_from_symbol,
+    smp::smp_call_all,
+    task::tid::TaskIdAllocator,
 };
 use alloc::boxed::Box;
 use bitflags::bitflags;
 use core::{
+    arch::asm,
     array,
     fmt::Debug,
     ops::{Deref, Range},
@@ -206,6 +210,20 @@ impl PageDir {
     pub fn ppn(&self) -> PageNum {
         self.frame.ppn()
     }
+
+    pub fn get_entry(&self, index: usize) -> PageTableEntry {
+        unsafe { self.as_data_ref() }.get_value(index)
+    }
+
+    /// Copy the entries `range` of `other` without taking its subdirs, so that both dirs share the mappings.
+    /// **The shared mappings must outlive this dir, and `other` must not replace the entries.**
+    pub unsafe fn share_entries(&mut self, other: &PageDir, range: Range<usize>) {
+        let src = unsafe { other.as_data_ref() };
+        let dst = unsafe { self.as_data_mut() };
+        for i in range {
+            dst.set_value(i, src.get_value(i));
+        }
+    }
 }
 
 impl PageDirTrait for PageDir {
@@ -345,9 +363,25 @@ pub const fn create_boot_ptable() -> RawPageDir {
 // region: Page Table Management
 pub unsafe fn set_memspace(memspace: impl Deref<Target = MemSpace>) {
     unsafe {
-        satp::set(SATP_MODE, memspace.asid, memspace.page_table.ppn().into());
+        satp::set(SATP_MODE, memspace.asid, memspace.get_root_ppn().into());
     }
 }
+
+/// Switch to the memspace of a user task before switching to it.
+/// **The memspace must stay alive until [deactivate_user_memspace].**
+pub unsafe fn activate_user_memspace(memspace: &MemSpace) {
+    unsafe { set_memspace(memspace) };
+    // Without an ASID of its own, the mappings of the previous user memspace may still be cached.
+    if memspace.asid == KERNEL_ASID {
+        unsafe { asm!("sfence.vma") };
+    }
+}
+
+/// Switch back to the kernel memspace once a user task is switched out,
+/// so that the user memspace can be freed.
+pub fn deactivate_user_memspace() {
+    unsafe { set_memspace(&KERNEL_MEMSPACE as &MemSpace) };
+}
 // endregion
 
 // region: Kernel MemSpace
@@ -418,10 +452,78 @@ fn create_kernel_ptable() -> Result<PageTable, PagingError> {
 
 // endregion
 
+// region: User MemSpace
+
+/// Number of ASID bits implemented by every hart, found by [init].
+static ASID_BITS: AtomicUsize = AtomicUsize::new(usize::BITS as usize);
+
+static ASID_ALLOC: SpinLock<TaskIdAllocator> =
+    SpinLock::new(TaskIdAllocator::starting_from(KERNEL_ASID + 1));
+
+/// Number of ASID bits the current hart implements: the bits of an all-ones ASID which stick in `satp`.
+fn detect_asid_bits() -> usize {
+    let old = satp::read();
+    unsafe {
+        satp::set(SATP_MODE, MAX_ASID, old.ppn());
+    }
+    let bits = satp::read().asid().count_ones() as usize;
+    unsafe {
+        satp::set(SATP_MODE, old.asid(), old.ppn());
+    }
+    bits
+}
+
+/// Allocate an ASID for a user memspace, or share [KERNEL_ASID] once the harts run out of them.
+///
+/// A recycled ASID may still be cached by any hart, so every new one is flushed on all harts:
+/// **the other harts must be running.**
+pub fn alloc_asid() -> usize {
+    let mut alloc = ASID_ALLOC.lock_no_preempt();
+    let asid = unsafe { alloc.alloc() };
+    if asid >> ASID_BITS.load(Ordering::Relaxed) != 0 {
+        unsafe { alloc.free(asid) };
+        return KERNEL_ASID;
+    }
+    drop(alloc);
+    smp_call_all(move || flush_asid(asid), true);
+    flush_asid(asid);
+    asid
+}
+
+pub fn free_asid(asid: usize) {
+    if asid != KERNEL_ASID {
+        unsafe { ASID_ALLOC.lock_no_preempt().free(asid) };
+    }
+}
+
+fn flush_asid(asid: usize) {
+    unsafe { asm!("sfence.vma zero, {}", in(reg) asid) };
+}
+
+/// Flush the user translations of `asid` on all harts, after mappings are removed or restricted.
+/// **The other harts must be running.**
+pub fn flush_user_tlb(asid: usize) {
+    smp_call_all(move || flush_asid(asid), true);
+    flush_asid(asid);
+}
+
+/// Create a page table for a user memspace. The kernel half is shared with [KERNEL_MEMSPACE],
+/// whose root entries there never change after boot.
+pub fn create_user_ptable() -> Result<PageTable, PagingError> {
+    let mut table = PageTable::new()?;
+    KERNEL_MEMSPACE.with_page_table(|kernel_table| unsafe {
+        table.share_root_entries(kernel_table, PTABLE_ENTRY_COUNT / 2..PTABLE_ENTRY_COUNT);
+    });
+    Ok(table)
+}
+
+// endregion
+
 pub fn init() {
     debug_ex!("Initializing paging module in RISC-V.");
     unsafe {
         set_memspace(&KERNEL_MEMSPACE as &MemSpace);
     }
+    ASID_BITS.fetch_min(detect_asid_bits(), Ordering::Relaxed);
     debug_ex!("Paging module in RISC-V initialized.");
 }
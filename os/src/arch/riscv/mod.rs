@@ -6,7 +6,9 @@ pub use config::*;
 pub mod hart;
 pub mod mm;
 mod sbi;
+pub use sbi::{HartStatus, SbiError};
 pub mod task;
+pub mod timer;
 pub mod trap;
 
 // TODO:Temporarily Used
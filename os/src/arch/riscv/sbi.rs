@@ -4,18 +4,40 @@ use num_enum::FromPrimitive;
 pub struct SbiTable;
 
 pub const SBI_CON_PUTCHR: (usize, usize) = (0x01, 0);
-//pub const CON_GETCHR: (usize, usize) = (0x02, 0);
+pub const SBI_CON_GETCHR: (usize, usize) = (0x02, 0);
 pub const SBI_SET_TIMER: (usize, usize) = (0x54494D45, 0);
 pub const SBI_HART_START: (usize, usize) = (0x48534D, 0);
 pub const SBI_HART_STOP: (usize, usize) = (0x48534D, 1);
 pub const SBI_GET_STATUS: (usize, usize) = (0x48534D, 2);
 pub const SBI_SEND_IPI: (usize, usize) = (0x735049, 0);
+pub const SBI_SYSTEM_RESET: (usize, usize) = (0x53525354, 0);
+
+/// Reset type of [SBI_SYSTEM_RESET] powering the system off.
+const RESET_TYPE_SHUTDOWN: usize = 0;
+/// Reset reason of [SBI_SYSTEM_RESET] for a normal shutdown.
+const RESET_REASON_NONE: usize = 0;
 
 impl SbiTable {
     pub fn console_putchr(chr: char) -> Result<(), SbiError> {
         sbi_call(SBI_CON_PUTCHR, chr as usize, 0, 0)?;
         Ok(())
     }
+    /// Read a byte from the console, or [None] if there is no input.
+    /// The legacy call returns the byte itself in `a0`, so it doesn't go through [sbi_call].
+    pub fn console_getchr() -> Option<u8> {
+        let (eid, fid) = SBI_CON_GETCHR;
+        let ret: isize;
+        unsafe {
+            asm!(
+                "ecall",
+                inlateout("a0") 0usize => ret,
+                lateout("a1") _,
+                in("a6") fid,
+                in("a7") eid,
+            );
+        }
+        (ret >= 0).then_some(ret as u8)
+    }
     pub fn set_timer(time: usize) -> Result<(), SbiError> {
         sbi_call(SBI_SET_TIMER, time, 0, 0)?;
         Ok(())
@@ -26,6 +48,12 @@ impl SbiTable {
         Ok(())
     }
 
+    /// Power the system off. It only returns on failure.
+    pub fn shutdown() -> Result<(), SbiError> {
+        sbi_call(SBI_SYSTEM_RESET, RESET_TYPE_SHUTDOWN, RESET_REASON_NONE, 0)?;
+        Ok(())
+    }
+
     /// The `a1` register of the given hart will be filled with `opaque`.
     pub fn hart_start(hart_id: usize, start_addr: usize, opaque: usize) -> Result<(), SbiError> {
         sbi_call(SBI_HART_START, hart_id, start_addr, opaque)?;
@@ -0,0 +1,272 @@
+//! Lazy switching of the floating-point and vector registers
+//!
+//! The kernel runs with `sstatus.FS` and `sstatus.VS` off, so that it traps on any use of the registers.
+//! User tasks start with them off too, and turn them on with their first floating-point
+//! or vector instruction, see [enable_on_first_use].
+//!
+//! The hardware marks the registers dirty in `sstatus` when a task changes them.
+//! Dirty registers are saved when the task is switched out, see [save], and only loaded back
+//! on its way to user mode if another task used them on the hart meanwhile, see [restore].
+
+use crate::{
+    arch::{
+        hart::get_current_hart_id,
+        trap::{
+            context::TrapContext,
+            intr::{disable_intr, restore_intr},
+        },
+    },
+    percpu,
+};
+use alloc::{boxed::Box, vec};
+use core::{
+    arch::{asm, global_asm},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+global_asm!(include_str!("fpu.S"));
+unsafe extern "C" {
+    unsafe fn __save_fp_regs(regs: *mut FpRegs);
+    unsafe fn __load_fp_regs(regs: *const FpRegs);
+    unsafe fn __save_vector_regs(csrs: *mut VectorCsrs, regs: *mut u8);
+    unsafe fn __load_vector_regs(csrs: *const VectorCsrs, regs: *const u8);
+}
+
+/// `sstatus.FS`.
+pub const SSTATUS_FS: usize = 0x6000;
+/// `sstatus.VS`.
+pub const SSTATUS_VS: usize = 0x600;
+
+const CSR_VLENB: usize = 0xc22;
+
+/// Length of the vector registers in bytes, or 0 without the V extension. Set by [init].
+static VLENB: AtomicUsize = AtomicUsize::new(0);
+
+percpu! {
+    /// Address of the [FpuContext] the registers of the hart hold, 0 if none.
+    static FPU_OWNER: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// State of the floating-point or vector registers, as in `sstatus.FS` and `sstatus.VS`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExtState {
+    /// Unusable: any instruction using them traps.
+    Off,
+    /// Zeroed.
+    Initial,
+    /// Unchanged since saved.
+    Clean,
+    /// Changed since saved.
+    Dirty,
+}
+
+impl ExtState {
+    pub const fn from_bits(bits: usize) -> ExtState {
+        match bits & 0b11 {
+            0 => ExtState::Off,
+            1 => ExtState::Initial,
+            2 => ExtState::Clean,
+            _ => ExtState::Dirty,
+        }
+    }
+
+    pub const fn bits(self) -> usize {
+        self as usize
+    }
+}
+
+/// Floating-point registers of the D extension, laid out as `struct __riscv_d_ext_state` of Linux.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FpRegs {
+    pub f: [u64; 32],
+    pub fcsr: u32,
+}
+
+/// Vector CSRs, referenced in `fpu.S`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+struct VectorCsrs {
+    vstart: usize,
+    vtype: usize,
+    vl: usize,
+    vcsr: usize,
+}
+
+#[derive(Debug, Clone)]
+struct VectorContext {
+    csrs: VectorCsrs,
+    /// `v0`-`v31`, of [vlenb] bytes each.
+    regs: Box<[u8]>,
+}
+
+/// Floating-point and vector state of a task, while the registers of a hart don't hold it.
+#[derive(Debug, Default)]
+pub struct FpuContext {
+    fp: FpRegs,
+    /// Allocated when the task turns the vector registers on.
+    vector: Option<VectorContext>,
+    /// The hart whose registers hold the state, if any. They do as long as it's also the [FPU_OWNER] there.
+    hart_id: Option<usize>,
+}
+
+impl FpuContext {
+    /// A copy for a new thread or process, which must be up to date, see [save].
+    pub fn fork(&self) -> FpuContext {
+        FpuContext {
+            fp: self.fp,
+            vector: self.vector.clone(),
+            hart_id: None,
+        }
+    }
+
+    pub fn get_fp_regs(&self) -> &FpRegs {
+        &self.fp
+    }
+
+    /// Replace the floating-point registers of the task returning with `context`,
+    /// which get loaded on its way to user mode. Nothing changes while they are off.
+    pub fn set_fp_regs(&mut self, context: &mut TrapContext, regs: &FpRegs) {
+        if context.get_fp_state() == ExtState::Off {
+            return;
+        }
+        // A task switch must not save the old registers over these.
+        let intr = disable_intr();
+        self.fp = *regs;
+        self.hart_id = None;
+        context.set_fp_state(ExtState::Clean);
+        restore_intr(intr);
+    }
+
+    fn address(&self) -> usize {
+        self as *const FpuContext as usize
+    }
+}
+
+/// Length of the vector registers in bytes, or 0 without the V extension.
+pub fn vlenb() -> usize {
+    VLENB.load(Ordering::Relaxed)
+}
+
+/// Turn the floating-point and vector registers off in the kernel, and detect the V extension.
+/// Call it on each hart.
+pub fn init() {
+    let status: usize;
+    unsafe {
+        // `sstatus.VS` is read-only zero without the V extension.
+        asm!("csrs sstatus, {}", in(reg) SSTATUS_VS);
+        asm!("csrr {}, sstatus", out(reg) status);
+    }
+    if status & SSTATUS_VS != 0 {
+        let vlenb: usize;
+        unsafe { asm!("csrr {}, {csr}", out(reg) vlenb, csr = const CSR_VLENB) };
+        VLENB.store(vlenb, Ordering::Relaxed);
+    }
+    unsafe { asm!("csrc sstatus, {}", in(reg) SSTATUS_FS | SSTATUS_VS) };
+}
+
+/// Forget the registers of hart `hart_id`, going offline: they are lost once it's stopped.
+pub fn hart_offline(hart_id: usize) {
+    FPU_OWNER.remote(hart_id).store(0, Ordering::Relaxed);
+}
+
+/// Run `f` with the registers of `mask` usable in the kernel.
+fn with_enabled(mask: usize, f: impl FnOnce()) {
+    let intr = disable_intr();
+    unsafe { asm!("csrs sstatus, {}", in(reg) mask) };
+    f();
+    unsafe { asm!("csrc sstatus, {}", in(reg) mask) };
+    restore_intr(intr);
+}
+
+/// Save the registers the task running with `context` changed since they were last saved,
+/// and mark them clean. **The task must be the last to run on the current hart.**
+pub fn save(fpu: &mut FpuContext, context: &mut TrapContext) {
+    let fp_dirty = context.get_fp_state() == ExtState::Dirty;
+    let vector_dirty = context.get_vector_state() == ExtState::Dirty;
+    if !fp_dirty && !vector_dirty {
+        return;
+    }
+    with_enabled(SSTATUS_FS | SSTATUS_VS, || {
+        if fp_dirty {
+            unsafe { __save_fp_regs(&mut fpu.fp) };
+        }
+        if let Some(vector) = fpu.vector.as_mut().filter(|_| vector_dirty) {
+            unsafe { __save_vector_regs(&mut vector.csrs, vector.regs.as_mut_ptr()) };
+        }
+    });
+    if fp_dirty {
+        context.set_fp_state(ExtState::Clean);
+    }
+    if vector_dirty {
+        context.set_vector_state(ExtState::Clean);
+    }
+}
+
+/// Load the registers of the task returning to user mode with `context`,
+/// unless the current hart still holds them.
+pub fn restore(fpu: &mut FpuContext, context: &TrapContext) {
+    let load_fp = context.get_fp_state() != ExtState::Off;
+    let load_vector = context.get_vector_state() != ExtState::Off;
+    if !load_fp && !load_vector {
+        return;
+    }
+    let hart_id = get_current_hart_id();
+    let owner = FPU_OWNER.remote(hart_id);
+    if fpu.hart_id == Some(hart_id) && owner.load(Ordering::Relaxed) == fpu.address() {
+        return;
+    }
+    with_enabled(SSTATUS_FS | SSTATUS_VS, || {
+        if load_fp {
+            unsafe { __load_fp_regs(&fpu.fp) };
+        }
+        if let Some(vector) = fpu.vector.as_ref().filter(|_| load_vector) {
+            unsafe { __load_vector_regs(&vector.csrs, vector.regs.as_ptr()) };
+        }
+    });
+    fpu.hart_id = Some(hart_id);
+    owner.store(fpu.address(), Ordering::Relaxed);
+}
+
+/// Turn on the registers of the user task which raised an illegal instruction exception with `context`,
+/// if they are off, so that the instruction runs again. The floating-point registers go first,
+/// so a vector instruction traps once more. Return whether any got turned on:
+/// otherwise, the instruction is illegal indeed.
+pub fn enable_on_first_use(fpu: &mut FpuContext, context: &mut TrapContext) -> bool {
+    if context.get_fp_state() == ExtState::Off {
+        fpu.fp = FpRegs::default();
+        context.set_fp_state(ExtState::Initial);
+    } else if vlenb() != 0 && context.get_vector_state() == ExtState::Off {
+        fpu.vector = Some(VectorContext {
+            csrs: VectorCsrs::default(),
+            regs: vec![0; 32 * vlenb()].into_boxed_slice(),
+        });
+        context.set_vector_state(ExtState::Initial);
+    } else {
+        return false;
+    }
+    // The hart holds the registers of whoever used them last.
+    fpu.hart_id = None;
+    true
+}
+
+/// Whether `instr`, as reported in `stval`, is a floating-point or vector instruction,
+/// to tell why the kernel raised an illegal instruction exception.
+pub fn is_fpu_instr(instr: usize) -> bool {
+    const OPCODES: [usize; 8] = [0x07, 0x27, 0x43, 0x47, 0x4b, 0x4f, 0x53, 0x57];
+    const OPCODE_SYSTEM: usize = 0x73;
+    // fflags, frm, fcsr, vstart, vxsat, vxrm, vcsr, vl, vtype, vlenb
+    const CSRS: [usize; 10] = [
+        0x001, 0x002, 0x003, 0x008, 0x009, 0x00a, 0x00f, 0xc20, 0xc21, 0xc22,
+    ];
+    if instr & 0b11 != 0b11 {
+        // c.fld, c.fsd, c.fldsp and c.fsdsp.
+        let funct3 = (instr >> 13) & 0b111;
+        let quadrant = instr & 0b11;
+        return (quadrant == 0b00 || quadrant == 0b10) && (funct3 == 0b001 || funct3 == 0b101);
+    }
+    let opcode = instr & 0x7f;
+    let funct3 = (instr >> 12) & 0b111;
+    OPCODES.contains(&opcode)
+        || (opcode == OPCODE_SYSTEM && funct3 != 0 && CSRS.contains(&((instr >> 20) & 0xfff)))
+}
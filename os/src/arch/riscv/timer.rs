@@ -0,0 +1,24 @@
+use crate::arch::SbiTable;
+use riscv::register::time;
+
+/// Interval between two scheduling ticks, in timebase units.
+pub const TIMER_TICK: usize = 0x4000;
+
+/// Timebase frequency assumed when the device tree does not provide one (QEMU `virt`).
+pub const DEFAULT_TIMEBASE_FREQ: usize = 10_000_000;
+
+/// Read the `time` CSR of the current hart.
+pub fn get_time() -> usize {
+    time::read()
+}
+
+/// Program the timer interrupt of the current hart to fire at `deadline`.
+pub fn set_next_event(deadline: usize) {
+    SbiTable::set_timer(deadline)
+        .unwrap_or_else(|value| panic!("Unexpected timer error:{:?}", value));
+}
+
+/// Disarm the timer of the current hart. The pending timer interrupt is cleared as well.
+pub fn clear_event() {
+    set_next_event(usize::MAX);
+}
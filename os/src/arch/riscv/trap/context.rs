@@ -1,3 +1,4 @@
+use crate::arch::task::fpu::{ExtState, SSTATUS_FS, SSTATUS_VS};
 use core::fmt::Debug;
 use riscv::register::sstatus::{self, SPP, Sstatus};
 
@@ -39,6 +40,59 @@ impl TrapContext {
             kstack_top: kstack_top,
         }
     }
+
+    /// Update the hart the context returns to. Kernel contexts also carry the hart id in `tp`.
+    pub fn set_hart_id(&mut self, hart_id: usize) {
+        self.hart_id = hart_id;
+        if self.sstatus.spp() == SPP::Supervisor {
+            self.x[4] = hart_id;
+        }
+    }
+
+    /// The system call number, in `a7`.
+    pub fn get_syscall_id(&self) -> usize {
+        self.x[17]
+    }
+
+    /// The system call arguments, in `a0`-`a5`.
+    pub fn get_syscall_args(&self) -> [usize; 6] {
+        self.x[10..16].try_into().unwrap()
+    }
+
+    /// Return `value` to user mode in `a0`.
+    pub fn set_syscall_ret(&mut self, value: usize) {
+        self.x[10] = value;
+    }
+
+    /// Resume after the `ecall` instruction rather than executing it again.
+    pub fn skip_syscall_instr(&mut self) {
+        self.sepc += 4;
+    }
+
+    /// State of the floating-point registers, in `sstatus.FS`.
+    pub fn get_fp_state(&self) -> ExtState {
+        ExtState::from_bits(self.sstatus.bits() >> SSTATUS_FS.trailing_zeros())
+    }
+
+    pub fn set_fp_state(&mut self, state: ExtState) {
+        self.set_sstatus_field(SSTATUS_FS, state.bits());
+    }
+
+    /// State of the vector registers, in `sstatus.VS`.
+    pub fn get_vector_state(&self) -> ExtState {
+        ExtState::from_bits(self.sstatus.bits() >> SSTATUS_VS.trailing_zeros())
+    }
+
+    pub fn set_vector_state(&mut self, state: ExtState) {
+        self.set_sstatus_field(SSTATUS_VS, state.bits());
+    }
+
+    /// Set the field `mask` of `sstatus` to `value`, which [Sstatus] has no setter for.
+    fn set_sstatus_field(&mut self, mask: usize, value: usize) {
+        // [Sstatus] only wraps the CSR value, which the trap handler stores as is.
+        let bits = unsafe { &mut *(&raw mut self.sstatus).cast::<usize>() };
+        *bits = (*bits & !mask) | ((value << mask.trailing_zeros()) & mask);
+    }
 }
 
 impl Debug for TrapContext {
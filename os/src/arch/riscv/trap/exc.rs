@@ -1,4 +1,27 @@
-use crate::arch::trap::context::TrapContext;
+use crate::{
+    arch::{
+        task::fpu,
+        trap::{
+            context::TrapContext,
+            intr::{disable_intr, enable_intr},
+        },
+    },
+    syscall,
+    task::{
+        get_current_task,
+        signal::{SIGBUS, SIGILL, SIGSEGV, SIGTRAP, force_signal},
+    },
+};
+use log::info;
+use riscv::register::sstatus::SPP;
+
+const EXC_INSTR_MISALIGNED: usize = 0;
+const EXC_ILLEGAL_INSTR: usize = 2;
+const EXC_BREAKPOINT: usize = 3;
+const EXC_LOAD_MISALIGNED: usize = 4;
+const EXC_STORE_MISALIGNED: usize = 6;
+const EXC_USER_ECALL: usize = 8;
+const EXC_STORE_PAGE_FAULT: usize = 15;
 
 pub const EXCEPTION_DESC: [&'static str; 16] = {
     let mut res = ["Reserved or Designated for Custom Use"; 16];
@@ -25,7 +48,51 @@ pub fn get_exception_desc(code: usize) -> &'static str {
         EXCEPTION_DESC[code]
     }
 }
-pub fn exception_handler(exception_code: usize, context: &mut TrapContext, _stval: usize) {
+
+/// The signal sent to a thread for an exception it raised.
+fn get_exception_signal(code: usize) -> usize {
+    match code {
+        EXC_ILLEGAL_INSTR => SIGILL,
+        EXC_BREAKPOINT => SIGTRAP,
+        EXC_INSTR_MISALIGNED | EXC_LOAD_MISALIGNED | EXC_STORE_MISALIGNED => SIGBUS,
+        // Access and page faults.
+        _ => SIGSEGV,
+    }
+}
+
+/// Turn on the floating-point or vector registers of the current task on first use, see [fpu::enable_on_first_use].
+fn enable_fpu(context: &mut TrapContext) -> bool {
+    let task = get_current_task();
+    unsafe { fpu::enable_on_first_use(&mut *task.get_fpu_context_mut_ptr(), context) }
+}
+
+/// Resolve a write to a copy-on-write page of the current memspace at `stval`.
+fn handle_write_fault(stval: usize) -> bool {
+    get_current_task()
+        .memsp
+        .as_ref()
+        .is_some_and(|memsp| memsp.handle_write_fault(stval))
+}
+
+pub fn exception_handler(exception_code: usize, context: &mut TrapContext, stval: usize) {
+    if context.sstatus.spp() == SPP::User {
+        user_exception_handler(exception_code, context, stval);
+        return;
+    }
+    // User copies keep the memspace locked, so its pages can't change under them:
+    // resolving the fault would deadlock on the lock.
+    if exception_code == EXC_STORE_PAGE_FAULT {
+        panic!(
+            "Kernel wrote to unprepared page {:#x} at {:#x}, user memory must go through uaccess",
+            stval, context.sepc
+        );
+    }
+    if exception_code == EXC_ILLEGAL_INSTR && fpu::is_fpu_instr(stval) {
+        panic!(
+            "Kernel used floating-point or vector instruction {:#x} at {:#x}, which it runs without",
+            stval, context.sepc
+        );
+    }
     panic!(
         "Unexcepted Exception {:#x}({:}) Occurred in kernel at {:#x}",
         exception_code,
@@ -33,3 +100,30 @@ pub fn exception_handler(exception_code: usize, context: &mut TrapContext, _stva
         context.sepc
     );
 }
+
+/// Exceptions raised in user mode only concern the task: a faulting thread gets a signal.
+fn user_exception_handler(exception_code: usize, context: &mut TrapContext, stval: usize) {
+    match exception_code {
+        EXC_USER_ECALL => {
+            // System calls may block, and long ones must not hold off interrupts.
+            enable_intr();
+            syscall::dispatch(context);
+            disable_intr();
+        }
+        EXC_STORE_PAGE_FAULT if handle_write_fault(stval) => {}
+        EXC_ILLEGAL_INSTR if enable_fpu(context) => {}
+        _ => {
+            let sig = get_exception_signal(exception_code);
+            info!(
+                "Task #{:} gets signal {:} for exception {:#x}({:}) at {:#x}, stval {:#x}.",
+                get_current_task().get_tid(),
+                sig,
+                exception_code,
+                get_exception_desc(exception_code),
+                context.sepc,
+                stval
+            );
+            force_signal(sig);
+        }
+    }
+}
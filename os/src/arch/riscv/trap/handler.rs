@@ -1,6 +1,13 @@
-use crate::arch::trap::{context::TrapContext, exc::exception_handler, intr::intr_handler};
-use core::arch::global_asm;
-use riscv::register::scause::Interrupt;
+use crate::{
+    arch::{
+        task::fpu,
+        trap::{context::TrapContext, exc::exception_handler, intr::intr_handler},
+    },
+    defer::softirq::do_softirq,
+    task::{get_current_task, process::exit_thread, signal::handle_signals},
+};
+use core::{arch::global_asm, sync::atomic::Ordering};
+use riscv::register::{scause::Interrupt, sstatus::SPP};
 
 unsafe extern "C" {
     pub unsafe fn __trap_from_kernel_handler();
@@ -10,14 +17,44 @@ unsafe extern "C" {
 
 global_asm!(include_str!("handler.S"));
 
+/// Exit code of a killed thread. Its process exits with the code of the thread that killed it.
+const KILLED_EXIT_CODE: i32 = -1;
+
 #[unsafe(no_mangle)]
 pub extern "C" fn __handler(context: &mut TrapContext, scause: usize, stval: usize) {
     const CODE_MASK: usize = usize::MAX >> 1;
     let is_intr = (scause & !CODE_MASK) != 0;
     let code = scause & CODE_MASK;
+    // Time between the return to user mode and this trap is user time.
+    if context.sstatus.spp() == SPP::User {
+        get_current_task().stats.leave_user();
+    }
     if is_intr {
         intr_handler(Interrupt::from(code), context);
+        do_softirq();
     } else {
         exception_handler(code, context, stval);
     }
+    if context.sstatus.spp() == SPP::User {
+        // Whatever interrupted a blocking call is taken below.
+        get_current_task()
+            .interrupt_pending
+            .store(false, Ordering::Relaxed);
+        handle_signals(context);
+        let task = get_current_task();
+        if task.is_killed() {
+            drop(task);
+            exit_thread(KILLED_EXIT_CODE);
+        }
+        task.stats.enter_user();
+    }
+}
+
+/// Load the floating-point and vector registers of the current task before it returns to user mode with `context`.
+#[unsafe(no_mangle)]
+pub extern "C" fn __load_fpu_context(context: &mut TrapContext) {
+    if context.sstatus.spp() == SPP::User {
+        let task = get_current_task();
+        unsafe { fpu::restore(&mut *task.get_fpu_context_mut_ptr(), context) };
+    }
 }
@@ -1,26 +1,29 @@
-use crate::{
-    arch::{SbiTable, trap::context::TrapContext},
-    task::scheduler::schedule,
-};
+use crate::{arch::trap::context::TrapContext, smp, timer};
+use core::arch::asm;
 use riscv::{
     asm::wfi,
-    register::{scause::Interrupt, sie, sstatus, time},
+    register::{scause::Interrupt, sie, sstatus},
 };
 
-pub const TIMER_TICK: usize = 0x4000;
-
 pub fn intr_handler(intr_type: Interrupt, _context: &mut TrapContext) {
     match intr_type {
-        Interrupt::SupervisorTimer => timer_tick(),
+        Interrupt::SupervisorTimer => timer::handle_timer_intr(),
+        Interrupt::SupervisorSoft => soft_intr(),
         _ => {}
     }
 }
 
-fn timer_tick() {
-    let time = time::read();
-    SbiTable::set_timer(time + TIMER_TICK)
-        .unwrap_or_else(|value| panic!("Unexpected timer error:{:?}", value));
-    schedule();
+/// Software interrupts are IPIs sent by other harts. Acknowledge it and handle the requests.
+fn soft_intr() {
+    clear_soft_intr();
+    smp::handle_ipi();
+}
+
+/// Clear the pending supervisor software interrupt bit (`sip.SSIP`).
+fn clear_soft_intr() {
+    unsafe {
+        asm!("csrc sip, {}", in(reg) 1usize << 1);
+    }
 }
 
 fn set_sie_masks() {
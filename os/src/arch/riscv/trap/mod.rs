@@ -1,14 +1,17 @@
 use riscv::register::stvec::{self, TrapMode};
 
-use crate::arch::trap::handler::__trap_from_kernel_handler;
+use crate::arch::{task::fpu, trap::handler::__trap_from_kernel_handler};
 
 pub mod context;
 pub mod exc;
 pub mod handler;
 pub mod intr;
+pub mod signal;
 
 pub fn init() {
     set_trap_handler();
+    // Floating-point and vector instructions trap in the kernel.
+    fpu::init();
     intr::init();
 }
 
@@ -0,0 +1,143 @@
+//! Signal frames
+//!
+//! A handler runs on the user stack below a frame holding the `siginfo_t` and `ucontext_t`
+//! of the Linux riscv64 ABI. It returns to the signal trampoline, which calls `sigreturn`
+//! to restore the interrupted context from the frame.
+//!
+//! The frame holds the floating-point registers, but not the vector ones, which handlers must leave alone.
+
+use crate::{
+    arch::{
+        task::fpu::{self, FpRegs, FpuContext},
+        trap::context::TrapContext,
+    },
+    mm::uaccess::{UserAccessError, UserPtr},
+    task::signal::SigSet,
+};
+use core::mem::offset_of;
+
+/// `siginfo_t`, of which only the signal number is filled.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    _fields: [i32; 29],
+}
+
+/// `stack_t`, for the alternate signal stack, which is never used.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SigStack {
+    sp: usize,
+    flags: i32,
+    size: usize,
+}
+
+/// `struct sigcontext`. The floating-point registers read as zero while the task has them off.
+#[repr(C, align(16))]
+#[derive(Clone, Copy)]
+struct SigContext {
+    /// The pc, then `x1`-`x31`.
+    regs: [usize; 32],
+    /// `union __riscv_fp_state`, of which the D extension takes the start.
+    fp: FpRegs,
+    _fp_reserved: [u8; 528 - size_of::<FpRegs>()],
+}
+
+/// `ucontext_t`.
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct UContext {
+    flags: usize,
+    link: usize,
+    stack: SigStack,
+    sigmask: SigSet,
+    _reserved: [u8; 120],
+    mcontext: SigContext,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct SignalFrame {
+    info: SigInfo,
+    ucontext: UContext,
+}
+
+/// Code of the signal trampoline, calling the `sigreturn` system call `syscall_id`.
+pub fn trampoline_code(syscall_id: usize) -> [u8; 8] {
+    debug_assert!(syscall_id < 1 << 11);
+    // li a7, syscall_id
+    let li = (syscall_id as u32) << 20 | 17 << 7 | 0x13;
+    let ecall: u32 = 0x73;
+    let mut res = [0; 8];
+    res[..4].copy_from_slice(&li.to_le_bytes());
+    res[4..].copy_from_slice(&ecall.to_le_bytes());
+    res
+}
+
+/// Push a signal frame saving `context`, along with the registers of `fpu`, and the signal mask `blocked`
+/// on the user stack, and make `context` enter `handler(sig, info, ucontext)`, returning to `trampoline`.
+pub fn setup_signal_frame(
+    context: &mut TrapContext,
+    fpu: &mut FpuContext,
+    sig: usize,
+    handler: usize,
+    trampoline: usize,
+    blocked: SigSet,
+) -> Result<(), UserAccessError> {
+    let sp = context.x[2]
+        .checked_sub(size_of::<SignalFrame>())
+        .ok_or(UserAccessError::BadAddress)?
+        & !0xf;
+    let mut regs = context.x;
+    regs[0] = context.sepc;
+    fpu::save(fpu, context);
+    let frame = SignalFrame {
+        info: SigInfo {
+            signo: sig as i32,
+            errno: 0,
+            code: 0,
+            _fields: [0; 29],
+        },
+        ucontext: UContext {
+            flags: 0,
+            link: 0,
+            stack: SigStack {
+                sp: 0,
+                flags: 0,
+                size: 0,
+            },
+            sigmask: blocked,
+            _reserved: [0; 120],
+            mcontext: SigContext {
+                regs,
+                fp: *fpu.get_fp_regs(),
+                _fp_reserved: [0; 528 - size_of::<FpRegs>()],
+            },
+        },
+    };
+    UserPtr::<SignalFrame>::new(sp).write(&frame)?;
+    context.x[1] = trampoline; // ra
+    context.x[2] = sp; // sp
+    context.x[10] = sig; // a0
+    context.x[11] = sp + offset_of!(SignalFrame, info); // a1
+    context.x[12] = sp + offset_of!(SignalFrame, ucontext); // a2
+    context.sepc = handler;
+    Ok(())
+}
+
+/// Restore `context` and the registers of `fpu` from the signal frame at its stack pointer,
+/// as left by [setup_signal_frame], and return the signal mask saved there.
+pub fn restore_signal_frame(
+    context: &mut TrapContext,
+    fpu: &mut FpuContext,
+) -> Result<SigSet, UserAccessError> {
+    let frame = UserPtr::<SignalFrame>::new(context.x[2]).read()?;
+    let mcontext = &frame.ucontext.mcontext;
+    context.sepc = mcontext.regs[0];
+    context.x[1..].copy_from_slice(&mcontext.regs[1..]);
+    fpu.set_fp_regs(context, &mcontext.fp);
+    Ok(frame.ucontext.sigmask)
+}
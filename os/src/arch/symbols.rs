@@ -9,6 +9,8 @@ unsafe extern "C" {
     pub unsafe fn _erodata();
     pub unsafe fn _sdata();
     pub unsafe fn _edata();
+    pub unsafe fn _spercpu();
+    pub unsafe fn _epercpu();
     pub unsafe fn _sbss();
     pub unsafe fn _kbss();
     pub unsafe fn _ebss();
@@ -1,7 +1,11 @@
 // TODO: Temporarily Used
 #![allow(missing_docs)]
 
-use crate::{arch::SbiTable, mutex::SpinLock};
+use crate::{
+    arch::SbiTable,
+    mutex::SpinLock,
+    task::resource::{File, FileError},
+};
 use core::fmt::{Arguments, Error, Write};
 
 static CON_LOCK: SpinLock<()> = SpinLock::new(());
@@ -23,6 +27,25 @@ pub fn serial_print(args: Arguments) {
     drop(guard);
 }
 
+/// The serial console as an open file, for the standard streams of user processes.
+/// Input goes to the debug console, so it's write-only.
+pub struct ConsoleFile;
+
+impl File for ConsoleFile {
+    fn read(&self, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::NotReadable)
+    }
+
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError> {
+        let guard = CON_LOCK.lock_no_preempt();
+        for byte in buf {
+            SbiTable::console_putchr(*byte as char).map_err(|_| FileError::Io)?;
+        }
+        drop(guard);
+        Ok(buf.len())
+    }
+}
+
 pub unsafe fn serial_print_unsafe(args: Arguments) {
     SerialOut.write_fmt(args).unwrap();
 }
@@ -0,0 +1,84 @@
+//! Debug Console
+//!
+//! A kernel thread polls the SBI console for input and runs the diagnostic commands typed there,
+//! one per line.
+
+use crate::{
+    arch::SbiTable,
+    hotplug::{hart_offline, hart_online},
+    kserial_print, kserial_println, panic_init, shutdown,
+    task::{kthread, registry::dump_tasks, stats::dump_hart_times},
+    timer::sleep_ns,
+};
+use alloc::string::String;
+use log::Level;
+
+/// Interval between two reads of an empty console.
+const POLL_INTERVAL_NS: usize = 20_000_000;
+
+const MAX_LINE_LEN: usize = 64;
+
+/// Take a hart offline or bring it back online, as asked by `offline <hart>` or `online <hart>`.
+fn run_hotplug(cmd: &str, hart: &str) {
+    let Ok(hart_id) = hart.parse::<usize>() else {
+        kserial_println!("Usage: {} <hart>", cmd);
+        return;
+    };
+    let res = match cmd {
+        "offline" => hart_offline(hart_id),
+        _ => hart_online(hart_id),
+    };
+    if let Err(err) = res {
+        kserial_println!("Unable to take hart #{} {}: {:?}", hart_id, cmd, err);
+    }
+}
+
+fn run_command(line: &str) {
+    let mut words = line.split_whitespace();
+    match (words.next(), words.next()) {
+        (None, _) => {}
+        (Some("ps"), None) => dump_tasks(Level::Info),
+        (Some("harts"), None) => dump_hart_times(Level::Info),
+        (Some(cmd @ ("offline" | "online")), hart) => run_hotplug(cmd, hart.unwrap_or("")),
+        (Some("shutdown"), None) => shutdown(),
+        (Some("help"), None) => {
+            kserial_println!("Commands: ps, harts, offline <hart>, online <hart>, shutdown, help")
+        }
+        _ => kserial_println!(
+            "Unknown command '{}', type 'help' for the commands.",
+            line.trim()
+        ),
+    }
+}
+
+fn run_console() {
+    let mut line = String::new();
+    loop {
+        match SbiTable::console_getchr() {
+            Some(b'\r' | b'\n') => {
+                kserial_println!("");
+                run_command(&line);
+                line.clear();
+            }
+            // Backspace or delete
+            Some(0x08 | 0x7f) => {
+                if line.pop().is_some() {
+                    kserial_print!("\x08 \x08");
+                }
+            }
+            Some(chr) if chr.is_ascii_graphic() || chr == b' ' => {
+                if line.len() < MAX_LINE_LEN {
+                    line.push(chr as char);
+                    kserial_print!("{}", chr as char);
+                }
+            }
+            Some(_) => {}
+            None => sleep_ns(POLL_INTERVAL_NS),
+        }
+    }
+}
+
+pub fn init() {
+    kthread::spawn("kconsole", run_console)
+        .unwrap_or_else(|err| panic_init!("Unable to start the debug console: {:?}", err));
+}
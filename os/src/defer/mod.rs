@@ -0,0 +1,14 @@
+//! Deferred Work
+//!
+//! Interrupt handlers should only acknowledge the device and defer the heavy processing:
+//! - to a softirq or a [softirq::Tasklet], which runs on the same hart as soon as the interrupt returns,
+//!   still in interrupt context;
+//! - to a [workqueue::WorkQueue], whose workers are kernel threads, if the processing may block.
+
+pub mod softirq;
+pub mod workqueue;
+
+pub fn init() {
+    softirq::init();
+    workqueue::init();
+}
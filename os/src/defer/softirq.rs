@@ -0,0 +1,177 @@
+//! Softirqs and Tasklets
+//!
+//! A softirq is a vector of work raised by an interrupt handler with [raise_softirq],
+//! and run by [do_softirq] on the same hart when the interrupt returns,
+//! with interrupts enabled and preemption disabled.
+//!
+//! Tasklets are queued on the [SoftIrq::Tasklet] vector. A tasklet never runs on two harts at once.
+//!
+//! A hart going offline hands its pending softirqs and tasklets over to a working hart, see [hart_offline].
+
+use crate::{
+    arch::{
+        hart::get_current_hart_id,
+        trap::intr::{disable_intr, enable_intr},
+    },
+    dev::get_working_hart_ids,
+    mutex::SpinLock,
+    percpu,
+    smp::send_reschedule,
+    task::preempt::{disable_preempt, restore_preempt},
+};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Once;
+
+/// Softirq vectors, run in this order.
+#[derive(Debug, Clone, Copy)]
+#[repr(usize)]
+pub enum SoftIrq {
+    NetRx,
+    Block,
+    Tasklet,
+}
+
+pub const NR_SOFTIRQS: usize = 3;
+
+/// Rounds of [do_softirq] before the remaining vectors are left to the next interrupt.
+const MAX_SOFTIRQ_RESTART: usize = 10;
+
+static HANDLERS: [Once<fn()>; NR_SOFTIRQS] = {
+    const NONE: Once<fn()> = Once::new();
+    [NONE; NR_SOFTIRQS]
+};
+
+percpu! {
+    /// Vectors raised on the hart and not run yet.
+    static SOFTIRQ_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Whether the hart is running softirqs, so that nested interrupts don't run them again.
+    static IN_SOFTIRQ: AtomicBool = AtomicBool::new(false);
+}
+
+/// Set the handler of `vec`. A vector gets only one handler.
+pub fn register_softirq(vec: SoftIrq, handler: fn()) {
+    HANDLERS[vec as usize].call_once(|| handler);
+}
+
+/// Mark `vec` pending on the current hart. It runs when the current interrupt returns,
+/// or at the next interrupt of the hart if raised from a task, **which must not be preemptible**.
+pub fn raise_softirq(vec: SoftIrq) {
+    SOFTIRQ_PENDING
+        .remote(get_current_hart_id())
+        .fetch_or(1 << vec as usize, Ordering::AcqRel);
+}
+
+/// Run the pending softirqs of the current hart. Called with interrupts disabled when an interrupt returns.
+pub fn do_softirq() {
+    let hart_id = get_current_hart_id();
+    let pending = SOFTIRQ_PENDING.remote(hart_id);
+    let in_softirq = IN_SOFTIRQ.remote(hart_id);
+    if pending.load(Ordering::Acquire) == 0 || in_softirq.swap(true, Ordering::AcqRel) {
+        return;
+    }
+    // The task must stay on this hart, since the flags above belong to it.
+    disable_preempt();
+    for _ in 0..MAX_SOFTIRQ_RESTART {
+        let vecs = pending.swap(0, Ordering::AcqRel);
+        if vecs == 0 {
+            break;
+        }
+        enable_intr();
+        for (vec, handler) in HANDLERS.iter().enumerate() {
+            if vecs & (1 << vec) == 0 {
+                continue;
+            }
+            if let Some(handler) = handler.get() {
+                handler();
+            }
+        }
+        disable_intr();
+    }
+    in_softirq.store(false, Ordering::Release);
+    // Raised faster than they run: come back with another interrupt instead of starving the tasks.
+    if pending.load(Ordering::Acquire) != 0 {
+        send_reschedule(hart_id);
+    }
+    restore_preempt();
+}
+
+// region: Tasklet
+
+/// A function deferred from an interrupt handler with [Tasklet::schedule].
+pub struct Tasklet {
+    func: Box<dyn Fn() + Send + Sync>,
+    /// Whether the tasklet is queued, so that it's queued only once.
+    scheduled: AtomicBool,
+    /// Whether the tasklet is running on some hart.
+    running: AtomicBool,
+}
+
+percpu! {
+    static TASKLETS: SpinLock<VecDeque<Arc<Tasklet>>> = SpinLock::new(VecDeque::new());
+}
+
+impl Tasklet {
+    pub fn new(func: impl Fn() + Send + Sync + 'static) -> Arc<Tasklet> {
+        Arc::new(Tasklet {
+            func: Box::new(func),
+            scheduled: AtomicBool::new(false),
+            running: AtomicBool::new(false),
+        })
+    }
+
+    /// Queue the tasklet on the current hart. Return `false` if it was already queued.
+    pub fn schedule(self: &Arc<Self>) -> bool {
+        if self.scheduled.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        TASKLETS
+            .remote(get_current_hart_id())
+            .lock_no_irq()
+            .push_back(self.clone());
+        raise_softirq(SoftIrq::Tasklet);
+        true
+    }
+}
+
+fn tasklet_action() {
+    let hart_id = get_current_hart_id();
+    let tasklets = core::mem::take(&mut *TASKLETS.remote(hart_id).lock_no_irq());
+    for tasklet in tasklets {
+        if tasklet.running.swap(true, Ordering::AcqRel) {
+            // Running on another hart: try again in the next round.
+            TASKLETS.remote(hart_id).lock_no_irq().push_back(tasklet);
+            raise_softirq(SoftIrq::Tasklet);
+            continue;
+        }
+        // Cleared before running, so that the tasklet can be scheduled again meanwhile.
+        tasklet.scheduled.store(false, Ordering::Release);
+        (tasklet.func)();
+        tasklet.running.store(false, Ordering::Release);
+    }
+}
+
+// endregion
+
+/// Move the pending softirqs and the tasklets of hart `hart_id`, going offline, to a working hart,
+/// where they run when the IPI sent there returns. Called on the hart itself by [crate::hotplug].
+pub fn hart_offline(hart_id: usize) {
+    let Some(target) = get_working_hart_ids().first().copied() else {
+        return;
+    };
+    let pending = SOFTIRQ_PENDING.remote(hart_id).swap(0, Ordering::AcqRel);
+    let tasklets = core::mem::take(&mut *TASKLETS.remote(hart_id).lock_no_irq());
+    if pending == 0 && tasklets.is_empty() {
+        return;
+    }
+    TASKLETS.remote(target).lock_no_irq().extend(tasklets);
+    SOFTIRQ_PENDING
+        .remote(target)
+        .fetch_or(pending, Ordering::AcqRel);
+    send_reschedule(target);
+}
+
+pub fn init() {
+    register_softirq(SoftIrq::Tasklet, tasklet_action);
+}
@@ -0,0 +1,156 @@
+//! Workqueues
+//!
+//! A [WorkQueue] runs [Work] items in its worker kernel threads, so the items may block.
+//! Items can be queued from interrupt context, and [DelayedWork] queues an item once a timer expires.
+//!
+//! [SYSTEM_WQ] has one worker per working hart; use [schedule_work] to queue on it.
+
+use crate::{
+    dev::get_working_harts,
+    mutex::SpinLock,
+    panic_init,
+    task::{kthread, wait_queue::WaitQueue},
+    timer::{TimerHandle, add_timer, get_time, ns_to_time},
+};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// A function to run in a worker of a [WorkQueue].
+pub struct Work {
+    func: Box<dyn Fn() + Send + Sync>,
+    /// Whether the item is queued, so that it's queued only once.
+    pending: AtomicBool,
+}
+
+impl Work {
+    pub fn new(func: impl Fn() + Send + Sync + 'static) -> Arc<Work> {
+        Arc::new(Work {
+            func: Box::new(func),
+            pending: AtomicBool::new(false),
+        })
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.pending.load(Ordering::Acquire)
+    }
+}
+
+pub struct WorkQueue {
+    items: SpinLock<VecDeque<Arc<Work>>>,
+    /// Idle workers wait here.
+    workers: WaitQueue,
+}
+
+impl WorkQueue {
+    pub const fn new() -> WorkQueue {
+        WorkQueue {
+            items: SpinLock::new(VecDeque::new()),
+            workers: WaitQueue::new(),
+        }
+    }
+
+    /// Start `count` workers named `name`.
+    pub fn start(&'static self, name: &str, count: usize) {
+        for _ in 0..count {
+            kthread::spawn(name, move || self.run_worker())
+                .unwrap_or_else(|err| panic_init!("Unable to start worker '{}': {:?}", name, err));
+        }
+    }
+
+    /// Queue `work`. Return `false` if it was already queued.
+    ///
+    /// It can be called from interrupt context.
+    pub fn queue(&self, work: &Arc<Work>) -> bool {
+        if work.pending.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        self.items.lock_no_irq().push_back(work.clone());
+        self.workers.wake_one();
+        true
+    }
+
+    /// Take `work` out of the queue if it has not started yet. Return `false` if it was not queued.
+    pub fn cancel(&self, work: &Arc<Work>) -> bool {
+        let mut items = self.items.lock_no_irq();
+        let len = items.len();
+        items.retain(|item| !Arc::ptr_eq(item, work));
+        if items.len() == len {
+            return false;
+        }
+        work.pending.store(false, Ordering::Release);
+        true
+    }
+
+    fn run_worker(&self) {
+        loop {
+            self.workers
+                .wait_until(|| !self.items.lock_no_irq().is_empty());
+            let work = self.items.lock_no_irq().pop_front();
+            let Some(work) = work else {
+                continue;
+            };
+            // Cleared before running, so that the item can be queued again meanwhile.
+            work.pending.store(false, Ordering::Release);
+            (work.func)();
+        }
+    }
+}
+
+/// A [Work] queued on a [WorkQueue] after a delay.
+pub struct DelayedWork {
+    work: Arc<Work>,
+    timer: SpinLock<Option<TimerHandle>>,
+}
+
+impl DelayedWork {
+    pub fn new(func: impl Fn() + Send + Sync + 'static) -> Arc<DelayedWork> {
+        Arc::new(DelayedWork {
+            work: Work::new(func),
+            timer: SpinLock::new(None),
+        })
+    }
+
+    /// Queue the item on `wq` in `delay_ns` nanoseconds. Return `false` if it's already waiting or queued.
+    ///
+    /// It can be called from interrupt context.
+    pub fn queue(self: &Arc<Self>, wq: &'static WorkQueue, delay_ns: usize) -> bool {
+        let mut timer = self.timer.lock_no_irq();
+        if timer.is_some() || self.work.is_pending() {
+            return false;
+        }
+        let dwork = self.clone();
+        let deadline = get_time() + ns_to_time(delay_ns);
+        *timer = Some(add_timer(deadline, move || {
+            dwork.timer.lock_no_irq().take();
+            wq.queue(&dwork.work);
+        }));
+        true
+    }
+
+    /// Cancel the timer, or take the item out of `wq` if it has already expired.
+    /// Return `false` if the item was neither waiting nor queued.
+    pub fn cancel(&self, wq: &WorkQueue) -> bool {
+        let timer = self.timer.lock_no_irq().take();
+        match timer {
+            Some(timer) if timer.cancel() => true,
+            _ => wq.cancel(&self.work),
+        }
+    }
+}
+
+/// The workqueue shared by the whole kernel.
+pub static SYSTEM_WQ: WorkQueue = WorkQueue::new();
+
+/// Queue `work` on [SYSTEM_WQ].
+pub fn schedule_work(work: &Arc<Work>) -> bool {
+    SYSTEM_WQ.queue(work)
+}
+
+/// Queue `dwork` on [SYSTEM_WQ] in `delay_ns` nanoseconds.
+pub fn schedule_delayed_work(dwork: &Arc<DelayedWork>, delay_ns: usize) -> bool {
+    dwork.queue(&SYSTEM_WQ, delay_ns)
+}
+
+pub fn init() {
+    SYSTEM_WQ.start("kworker", get_working_harts().len());
+}
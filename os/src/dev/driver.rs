@@ -9,16 +9,17 @@
 //! - Allow concurrent lookups via [find_drivers] and synchronized updates via [register_driver].
 //!
 //! Ownership and concurrency notes:
-//! - [COMP_MAP] is protected by an [RwLock] for concurrent reader-heavy access patterns.
+//! - [COMP_MAP] is an [RcuCell]: lookups take no lock, registrations publish an updated copy.
 //! - [DRIVER_REG] owns boxed driver instances and yields `&'static dyn Driver` references.
 //! - **Drivers returned by [find_drivers] are `&'static` references originating from [DRIVER_REG].**
 use crate::{
     debug_ex,
     dev::{Device, handle::Handle, serial},
+    rcu::{RcuCell, rcu_read_lock},
 };
 use alloc::{boxed::Box, collections::btree_map::BTreeMap, vec, vec::Vec};
 use core::fmt::Debug;
-use spin::RwLock;
+use lazy_static::lazy_static;
 use utils::vec::LockedVecStatic;
 
 /// Trait implemented by drivers.
@@ -39,9 +40,11 @@ pub trait Driver: Sync + Debug {
     fn on_registered(&self);
 }
 
-/// Registry mapping from compatible string to candidate driver references.
-static COMP_MAP: RwLock<BTreeMap<&'static str, Vec<&'static dyn Driver>>> =
-    RwLock::new(BTreeMap::new());
+lazy_static! {
+    /// Registry mapping from compatible string to candidate driver references.
+    static ref COMP_MAP: RcuCell<BTreeMap<&'static str, Vec<&'static dyn Driver>>> =
+        RcuCell::new(BTreeMap::new());
+}
 
 /// Global storage that owns driver instances.
 static DRIVER_REG: LockedVecStatic<dyn Driver> = LockedVecStatic::new();
@@ -51,8 +54,8 @@ static DRIVER_REG: LockedVecStatic<dyn Driver> = LockedVecStatic::new();
 /// Return a vector of `&'static dyn Driver` candidate references. The caller receives owned clones
 /// of the internal vector to avoid holding locks while probing. If no drivers match, return an empty vec.
 pub fn find_drivers(comp_str: &str) -> Vec<&'static dyn Driver> {
-    let guard = COMP_MAP.read();
-    if let Some(drv) = guard.get(comp_str) {
+    let rcu = rcu_read_lock();
+    if let Some(drv) = COMP_MAP.read(&rcu).get(comp_str) {
         drv.clone()
     } else {
         vec![]
@@ -75,15 +78,16 @@ pub fn register_driver<T: 'static + Driver>(driver: Box<T>) {
     driver.on_registered();
     let (driver, _) = DRIVER_REG.push_boxed(driver);
 
-    let mut guard = COMP_MAP.write();
-    for comp in driver.get_comp_strs() {
-        let key = *comp;
-        if !guard.contains_key(key) {
-            guard.insert(key, vec![]);
+    COMP_MAP.update(|map| {
+        for comp in driver.get_comp_strs() {
+            let key = *comp;
+            if !map.contains_key(key) {
+                map.insert(key, vec![]);
+            }
+            let vec = map.get_mut(key).unwrap();
+            vec.push(driver);
         }
-        let vec = guard.get_mut(key).unwrap();
-        vec.push(driver);
-    }
+    });
 }
 
 /// Initialize driver registry by calling platform-specific registrations.
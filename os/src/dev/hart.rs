@@ -1,10 +1,12 @@
 use alloc::vec::Vec;
-use spin::{Once};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use spin::Once;
 use utils::vec::LockedVecStatic;
 
 use crate::{
     arch::{MAX_HARTS, hart::get_current_hart_id},
     dev::{handle::Handle, intc::Intc},
+    percpu,
     task::preempt::PreemptCounter,
 };
 
@@ -24,38 +26,84 @@ impl HartInfo {
     }
 }
 
-static WORKING_HARTS: LockedVecStatic<&'static HartInfo> = LockedVecStatic::new();
+/// Harts found in the device tree.
+static PRESENT_HARTS: LockedVecStatic<usize> = LockedVecStatic::new();
 
-static HARTS: [HartInfo; MAX_HARTS] = {
-    const NONE: HartInfo = HartInfo::new(0);
-    let mut res = [NONE; MAX_HARTS];
-    let mut i = 0;
-    while i < MAX_HARTS {
-        res[i].hart_id = i;
-        i += 1;
-    }
-    res
-};
+/// Mask of the working harts, one bit per hart: the present harts which are online.
+/// Harts are taken offline and back online by [crate::hotplug].
+static WORKING_MASK: AtomicUsize = AtomicUsize::new(0);
+
+percpu! {
+    static HART_INFO: HartInfo = HartInfo::new(0);
+}
 
 pub fn register_hart(hart_id: usize) {
-    if hart_id > MAX_HARTS {
+    if hart_id >= MAX_HARTS {
         log::warn!(
             "Unsupported hart id: #{:}, exceeding max hart count '{:}'.",
             hart_id,
             MAX_HARTS
         );
+        return;
+    }
+    PRESENT_HARTS.push(hart_id);
+    WORKING_MASK.fetch_or(1 << hart_id, Ordering::SeqCst);
+}
+
+/// Fill in the hart ids of the per-hart [HartInfo]s.
+/// **Call it right after [crate::mm::percpu::init].**
+pub fn init_harts() {
+    for hart_id in get_present_hart_ids() {
+        unsafe { (*HART_INFO.as_ptr_on(hart_id)).hart_id = hart_id };
     }
-    WORKING_HARTS.push(&HARTS[hart_id]);
 }
 
-pub fn get_working_harts() -> Vec<&'static &'static HartInfo> {
-    WORKING_HARTS.clone()
+pub fn get_present_hart_ids() -> Vec<usize> {
+    PRESENT_HARTS.clone().into_iter().copied().collect()
+}
+
+pub fn is_present_hart(hart_id: usize) -> bool {
+    get_present_hart_ids().contains(&hart_id)
+}
+
+pub fn get_present_harts() -> Vec<&'static HartInfo> {
+    get_present_hart_ids()
+        .into_iter()
+        .map(|hart_id| HART_INFO.remote(hart_id))
+        .collect()
+}
+
+pub fn get_working_hart_ids() -> Vec<usize> {
+    get_present_hart_ids()
+        .into_iter()
+        .filter(|hart_id| is_working_hart(*hart_id))
+        .collect()
+}
+
+pub fn is_working_hart(hart_id: usize) -> bool {
+    hart_id < MAX_HARTS && WORKING_MASK.load(Ordering::SeqCst) & (1 << hart_id) != 0
+}
+
+pub fn get_working_harts() -> Vec<&'static HartInfo> {
+    get_working_hart_ids()
+        .into_iter()
+        .map(|hart_id| HART_INFO.remote(hart_id))
+        .collect()
+}
+
+/// Mark hart `hart_id` online or offline. Only for [crate::hotplug].
+pub fn set_hart_working(hart_id: usize, working: bool) {
+    if working {
+        WORKING_MASK.fetch_or(1 << hart_id, Ordering::SeqCst);
+    } else {
+        WORKING_MASK.fetch_and(!(1 << hart_id), Ordering::SeqCst);
+    }
 }
 
 pub fn get_current_hart() -> &'static HartInfo {
-    &HARTS[get_current_hart_id()]
+    HART_INFO.remote(get_current_hart_id())
 }
 
 pub fn get_hart(hart_id: usize) -> &'static HartInfo {
-    &HARTS[hart_id]
+    HART_INFO.remote(hart_id)
 }
@@ -10,12 +10,17 @@ use crate::{
         mmio::IoRange,
         register_hart,
     },
+    initrd::set_initrd_range,
+    mm::config::PAGE_SIZE,
     panic_init, phys_addr_from_symbol,
+    timer::set_timebase_freq,
 };
 use alloc::{boxed::Box, vec};
+use core::ops::Range;
 use dt::node::{DeviceTree, Node, NodeType};
 use log::warn;
 use spin::RwLock;
+use utils::num::AlignableTo;
 
 pub fn register_all(dev_tree: DeviceTree) {
     register_mem(&dev_tree);
@@ -50,10 +55,43 @@ fn register_mem(dev_tree: &DeviceTree) {
     }
     let self_range = phys_addr_from_symbol!(_skernel)..phys_addr_from_symbol!(_ekernel);
     mem.sub(self_range);
+    if let Some(range) = get_initrd_range(dev_tree) {
+        debug_ex!("\tInitrd at [{:#x}, {:#x}).", range.start, range.end);
+        mem.sub(range.start.align_down(PAGE_SIZE)..range.end.align_up(PAGE_SIZE));
+        set_initrd_range(range);
+    }
     GENERAL_MEM.call_once(|| mem);
     debug_ex!("Memory info registered.");
 }
 
+/// The initrd range given by the bootloader in `/chosen`. Its bounds are 32-bit or 64-bit cells.
+fn get_initrd_range(dev_tree: &DeviceTree) -> Option<Range<usize>> {
+    let chosen = dev_tree.get_node("/chosen")?;
+    let read_addr = |name: &str| {
+        let prop = dev_tree.get_property(chosen, name)?;
+        let value = if prop.data.len() >= size_of::<u64>() {
+            prop.value_as_u64()
+        } else {
+            prop.value_as_u32().map(u64::from)
+        };
+        value
+            .inspect_err(|err| {
+                warn!(
+                    "Error loading '{:}' value of node '/chosen': {:?}.",
+                    name, err
+                )
+            })
+            .ok()
+    };
+    let start = read_addr("linux,initrd-start")? as usize;
+    let end = read_addr("linux,initrd-end")? as usize;
+    if start >= end {
+        warn!("Ignoring empty initrd at {:#x}.", start);
+        return None;
+    }
+    Some(start..end)
+}
+
 fn register_harts(dev_tree: &DeviceTree) {
     debug_ex!("Registering hart info...");
     let cpu_nodes = dev_tree.get_nodes("/cpus/cpu");
@@ -95,6 +133,17 @@ fn register_harts(dev_tree: &DeviceTree) {
             );
         }
     }
+    let freq = dev_tree
+        .get_node("/cpus")
+        .and_then(|node| dev_tree.get_property(node, "timebase-frequency"));
+    match freq.map(|prop| prop.value_as_u32()) {
+        Some(Ok(freq)) => {
+            debug_ex!("\tTimebase frequency {} Hz.", freq);
+            set_timebase_freq(freq as usize);
+        }
+        Some(Err(err)) => warn!("Error loading timebase frequency: {:?}.", err),
+        None => warn!("Timebase frequency not found in device tree."),
+    }
     debug_ex!("Hart info registered.");
 }
 
@@ -7,8 +7,10 @@ use crate::{
         driver::IntcError,
         handle::{Handle, HandleRef},
     },
+    rcu::{RcuCell, rcu_read_lock},
 };
 use alloc::{boxed::Box, collections::btree_map::BTreeMap};
+use lazy_static::lazy_static;
 use spin::RwLock;
 
 pub trait IntcDev: Sync + Debug {
@@ -24,37 +26,41 @@ pub struct Intc {
 }
 
 impl Intc {
-    /// None meaning that the intc_id is occupied
-    fn new(intc_id: usize, ctl: Box<dyn IntcDev + Send>) -> Option<Handle<Intc>> {
-        let mut guard = INTC_MAP.write();
-        if guard.contains_key(&intc_id) {
-            return None;
-        }
-        let intc = Intc {
-            intc_id,
-            ctl,
-            devs: RwLock::new(BTreeMap::new()),
-        };
-        let handle = Handle::from(intc);
-        guard.insert(intc_id, handle.create_ref());
-        Some(handle)
+    /// The map is only published if `intc_id` is free.
+    fn new(intc_id: usize, ctl: Box<dyn IntcDev + Send>) -> Result<Handle<Intc>, IntcError> {
+        INTC_MAP.try_update(|map| {
+            if map.contains_key(&intc_id) {
+                return Err(IntcError::DuplicatedId);
+            }
+            let intc = Intc {
+                intc_id,
+                ctl,
+                devs: RwLock::new(BTreeMap::new()),
+            };
+            let handle = Handle::from(intc);
+            map.insert(intc_id, handle.create_ref());
+            Ok(handle)
+        })
     }
 }
 
 impl Drop for Intc {
     fn drop(&mut self) {
-        INTC_MAP.write().remove(&self.intc_id);
+        INTC_MAP.update(|map| map.remove(&self.intc_id));
     }
 }
 
-static INTC_MAP: RwLock<BTreeMap<usize, HandleRef<Intc>>> = RwLock::new(BTreeMap::new());
+lazy_static! {
+    static ref INTC_MAP: RcuCell<BTreeMap<usize, HandleRef<Intc>>> = RcuCell::new(BTreeMap::new());
+}
 
 pub fn get_intc(id: usize) -> Option<HandleRef<Intc>> {
-    INTC_MAP.read().get(&id).cloned()
+    let rcu = rcu_read_lock();
+    INTC_MAP.read(&rcu).get(&id).cloned()
 }
 
 pub fn register_intc(id: usize, ctl: Box<dyn IntcDev + Send>) -> Result<Handle<Intc>, IntcError> {
-    let res = Intc::new(id, ctl).ok_or(IntcError::DuplicatedId);
+    let res = Intc::new(id, ctl)?;
     debug_ex!("Registered interrupt controller #{}.", id);
-    res
+    Ok(res)
 }
@@ -13,7 +13,8 @@
 //! Since different architectures may use different types to describe the device info,
 //!     the entry should choose a proper implementation of [crate::devices::device_info::DeviceInfo] and send it to [crate::rust_main]
 //!
-//! All the members in this module is **private**, because the entry should **never** be used after the pre-initialization.
+//! All the members in this module is **private**, because the entry should **never** be used after the pre-initialization,
+//! except for the slave entry, which restarts the harts brought back online by [crate::hotplug].
 //!
 
 #[cfg(all(target_arch = "riscv64", feature = "naked"))]
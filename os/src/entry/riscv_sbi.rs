@@ -6,21 +6,26 @@
 //!     avoid memory holes and virtual address overflow
 //! 3. jumping to [setup]: set up a boot page table and the kernel stack.
 //! 4. jumping to [start]: clear the bss and create a fdt tree instance (uninitialized).
-//! 5. jumping to [crate::rust_main]
+//! 5. allocating the per-CPU areas and the stacks of the slave harts, and waking them.
+//! 6. jumping to [crate::kernel_main]
+//!
+//! Slave harts start from [_start_slave] on the stack allocated for them,
+//! set up the boot page table and jump to [crate::kernel_slave].
+//! They start there again when they come back online, see [start_slave_hart].
 
 use crate::{
     arch::{
-        KERNEL_OFFSET, PAGE_WIDTH,
-        hart::{store_hart_id, wake_slave_harts},
-        mm::{BOOT_STACK, paging::BOOT_PTABLE},
+        KERNEL_OFFSET, PAGE_WIDTH, SbiError,
+        hart::{start_hart, store_hart_id},
+        mm::{BOOT_STACK, SLAVE_STACK, paging::BOOT_PTABLE},
         symbols::_ekernel,
     },
     debug_ex,
-    dev::{get_working_harts, info::dt::register_all},
+    dev::{get_working_harts, info::dt::register_all, init_harts},
     early_init_main,
     entry::shared::clear_bss,
     kernel_main, kernel_slave,
-    mm::config::KERNEL_STACK_SHIFT,
+    mm::{config::KERNEL_STACK_SIZE, percpu, stack::RawKernelStack},
     panic_init, phys_addr_from_symbol,
 };
 use core::{arch::naked_asm, ptr::copy};
@@ -43,12 +48,8 @@ unsafe extern "C" fn _start(hart_id: usize, dtb_addr: usize) {
     naked_asm!(
         // init stack top
         "   la      a2, {boot_stack_p}          // sp = boot_stack_p
-            li      t0, {boot_stack_shift}      // t0 = boot_stack_shift
-            addi    a0, a0, 1                   // hart_id += 1
-            sll     t1, a0, t0                  // t1 = hart_id << t0
-            addi    a2, a2, 0                   // sp -= 0
-            add     a2, a2, t1                  // sp += t1
-            addi    a0, a0, -1                  // hart_id -= 1
+            li      t0, {boot_stack_size}       // t0 = boot_stack_size
+            add     a2, a2, t0                  // sp += t0
             mv      sp, a2
             ",            
         // jump
@@ -56,32 +57,22 @@ unsafe extern "C" fn _start(hart_id: usize, dtb_addr: usize) {
             jr      a3",
         // The hart id and dtb addr args are passed in reg a0 & a1
         boot_stack_p = sym BOOT_STACK,
-        boot_stack_shift = const KERNEL_STACK_SHIFT,
+        boot_stack_size = const KERNEL_STACK_SIZE,
         copy_dtb = sym adjust_dtb,
     )
 }
 
 /// Move the dtb to the place right behind the kernel.
-///
-/// Do nothing if starting from a slave hart.
 #[inline(always)]
 fn adjust_dtb(hart_id: usize, dtb_addr: usize) -> ! {
-    if dtb_addr != 0 {
-        // Main Hart
-        let reader = FdtReader::new(dtb_addr as *const u8);
-        let len = reader.get_header().totalsize.value() as usize;
+    let reader = FdtReader::new(dtb_addr as *const u8);
+    let len = reader.get_header().totalsize.value() as usize;
 
-        let src = dtb_addr as *const u8;
-        let dst = _ekernel as *const u8 as *mut u8;
-        unsafe {
-            copy(src, dst, len);
-            setup(hart_id, dst as usize);
-        }
-    } else {
-        // Slave Hart
-        unsafe {
-            setup(hart_id, 0);
-        }
+    let src = dtb_addr as *const u8;
+    let dst = _ekernel as *const u8 as *mut u8;
+    unsafe {
+        copy(src, dst, len);
+        setup(hart_id, dst as usize);
     }
 }
 
@@ -104,12 +95,8 @@ unsafe extern "C" fn setup(hart_id: usize, dtb_addr: usize) -> ! {
         "   la      t2, {offset}",
         // init stack top
         "   la      a2, {boot_stack_p}          // sp = boot_stack_p
-            li      t0, {boot_stack_shift}      // t0 = boot_stack_shift
-            addi    a0, a0, 1                   // hart_id += 1
-            sll     t1, a0, t0                  // t1 = hart_id << t0
-            addi    a2, a2, 0                   // sp -= 0
-            add     a2, a2, t1                  // sp += t1
-            addi    a0, a0, -1                  // hart_id -= 1
+            li      t0, {boot_stack_size}       // t0 = boot_stack_size
+            add     a2, a2, t0                  // sp += t0
             or      a2, a2, t2
             mv      sp, a2
             ",            
@@ -119,8 +106,7 @@ unsafe extern "C" fn setup(hart_id: usize, dtb_addr: usize) -> ! {
             jr      a3",
         // The hart id and dtb addr args are passed in reg a0 & a1
         boot_stack_p = sym BOOT_STACK,
-        boot_stack_shift = const KERNEL_STACK_SHIFT,
-        //boot_stack_size  = const KERNEL_STACK_SIZE,
+        boot_stack_size = const KERNEL_STACK_SIZE,
         boot_satp = const BOOT_SATP,
         boot_table_addr = sym BOOT_PTABLE,
         page_width = const PAGE_WIDTH,
@@ -129,17 +115,61 @@ unsafe extern "C" fn setup(hart_id: usize, dtb_addr: usize) -> ! {
     )
 }
 
+/// The entry point of the slave harts, started by [start_slave_hart] with the top of their stack in `a1`.
+///
+/// Set up the boot page table and the stack, and jump to [start_slave].
+#[unsafe(naked)]
+#[unsafe(no_mangle)]
+unsafe extern "C" fn _start_slave(hart_id: usize, stack_top: usize) -> ! {
+    naked_asm!(
+        // init boot ptable
+        "
+            la      t0, {boot_table_addr}       // > t0 = boot_table_addr << page_width
+            srli    t0, t0, {page_width}        // |
+            li      t1, {boot_satp}             // > t1 = boot_satp
+            or      t0, t0, t1                  // > t0 = t0 | t1
+            csrw    satp, t0                    // > satp = t0
+            sfence.vma                          // > refresh
+            
+        ",
+        // the stack is allocated in the kernel space
+        "   mv      sp, a1",
+        // jump
+        "   li      t2, {offset}
+            la      a3, {start}
+            or      a3, a3, t2
+            jr      a3",
+        boot_satp = const BOOT_SATP,
+        boot_table_addr = sym BOOT_PTABLE,
+        page_width = const PAGE_WIDTH,
+        offset = const KERNEL_OFFSET,
+        start = sym start_slave,
+    )
+}
+
 /// Write the hart info to `tp` register and jump to kernel main.
 fn start(hart_id: usize, dtb_addr: usize) -> ! {
     store_hart_id(hart_id);
-    if dtb_addr != 0 {
-        // Main Hart
-        start_main(hart_id, dtb_addr);
-    } else {
-        // Slave Hart
-        debug_ex!("karox RISC-V slave entry(hart: #{}).", hart_id);
-        kernel_slave();
-    }
+    start_main(hart_id, dtb_addr);
+}
+
+fn start_slave(hart_id: usize) -> ! {
+    store_hart_id(hart_id);
+    debug_ex!("karox RISC-V slave entry(hart: #{}).", hart_id);
+    kernel_slave();
+}
+
+/// Start hart `hart_id` at [_start_slave], on the stack allocated on its first start.
+/// Also used by [crate::hotplug] to bring a stopped hart back online.
+pub fn start_slave_hart(hart_id: usize) -> Result<(), SbiError> {
+    let stack = SLAVE_STACK
+        .remote(hart_id)
+        .call_once(RawKernelStack::new_boxed);
+    start_hart(
+        hart_id,
+        phys_addr_from_symbol!(_start_slave),
+        stack.get_stack_top() as usize,
+    )
 }
 
 fn start_main(hart_id: usize, dtb_addr: usize) -> ! {
@@ -157,12 +187,16 @@ fn start_main(hart_id: usize, dtb_addr: usize) -> ! {
         .unwrap_or_else(|err| panic_init!("Error loading FDT: {:?}", err));
 
     register_all(dev_tree);
+    percpu::init();
+    init_harts();
 
     for hart in get_working_harts() {
         if hart.hart_id == hart_id {
             continue;
         }
-        wake_slave_harts(hart.hart_id, phys_addr_from_symbol!(_start));
+        start_slave_hart(hart.hart_id).unwrap_or_else(|err| {
+            panic_init!("Unable to start slave hart {:}: {:?}", hart.hart_id, err)
+        });
     }
 
     kernel_main();
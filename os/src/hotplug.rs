@@ -0,0 +1,238 @@
+//! Hart Hotplug
+//!
+//! A working hart is taken offline with [hart_offline], and brought back online with [hart_online],
+//! through the Hart State Management extension of SBI.
+//!
+//! Going offline, the hart first leaves the working harts, so that no task or call gets queued there any more,
+//! then is asked to stop. Its scheduler loop stops running tasks and runs the offline callbacks
+//! of the subsystems keeping per-hart state, which hand that state over to the working harts:
+//! queued tasks, timer events, softirqs and tasklets, cross-hart calls. The hart then stops itself.
+//!
+//! Coming back online, the hart starts again from the slave entry, which initializes its paging, trap
+//! and timer state again. It runs the online callbacks, then joins the working harts and runs tasks.
+//!
+//! Subsystems register their callbacks with [register_hotplug_notifier]. The callbacks run on the hart
+//! going offline or coming online, with interrupts disabled: **they must not block.**
+//!
+//! Device interrupts are routed to the boot hart, which therefore stays online.
+
+use crate::{
+    arch::{
+        HartStatus, SbiError, SbiTable,
+        hart::get_current_hart_id,
+        task::fpu,
+        trap::intr::{disable_intr, restore_intr},
+    },
+    defer::softirq,
+    dev::{get_working_hart_ids, is_present_hart, is_working_hart, set_hart_working},
+    entry::riscv_sbi::start_slave_hart,
+    mutex::{SleepMutex, SpinLock},
+    percpu,
+    smp::{self, halt, send_reschedule, smp_call_all},
+    task::{get_current_task, scheduler::migrate_queued},
+    timer::{self, sleep_ns},
+};
+use alloc::vec::Vec;
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::{error, info, trace};
+
+/// Interval between two checks of a hart changing state.
+const POLL_INTERVAL_NS: usize = 1_000_000;
+
+/// Callbacks of a subsystem keeping per-hart state, called with the id of the hart.
+#[derive(Debug, Clone, Copy)]
+pub struct HotplugNotifier {
+    pub name: &'static str,
+    /// Run on a hart coming online, before it joins the working harts.
+    pub online: Option<fn(usize)>,
+    /// Run on a hart going offline, once it stopped running tasks.
+    pub offline: Option<fn(usize)>,
+}
+
+/// Errors returned by [hart_offline] and [hart_online].
+#[derive(Debug)]
+pub enum HotplugError {
+    /// The hart is not present.
+    InvalidHart,
+    /// The boot hart, which handles the device interrupts, stays online.
+    BootHart,
+    /// The hart is the last working one.
+    LastHart,
+    /// The calling task can't run on any other working hart.
+    Pinned,
+    AlreadyOnline,
+    AlreadyOffline,
+    /// The hart is offline but not stopped yet.
+    NotStopped,
+    Sbi(SbiError),
+}
+
+/// Online callbacks run in this order, offline ones in the reverse order.
+static NOTIFIERS: SpinLock<Vec<HotplugNotifier>> = SpinLock::new(Vec::new());
+
+/// Serializes the harts going offline and coming online.
+static HOTPLUG_LOCK: SleepMutex<()> = SleepMutex::new(());
+
+static BOOT_HART: AtomicUsize = AtomicUsize::new(0);
+
+percpu! {
+    /// Whether the hart was asked to go offline.
+    static STOP_REQUESTED: AtomicBool = AtomicBool::new(false);
+
+    /// Whether the hart was last started by [hart_online] rather than at boot.
+    static HOTPLUGGED: AtomicBool = AtomicBool::new(false);
+}
+
+/// Register the callbacks of a subsystem, which run for the harts going offline or coming online from now on.
+pub fn register_hotplug_notifier(notifier: HotplugNotifier) {
+    NOTIFIERS.lock().push(notifier);
+}
+
+fn get_notifiers() -> Vec<HotplugNotifier> {
+    NOTIFIERS.lock().clone()
+}
+
+/// Whether hart `hart_id` was asked to go offline. Checked by its scheduler loop.
+pub fn is_stop_requested(hart_id: usize) -> bool {
+    STOP_REQUESTED.remote(hart_id).load(Ordering::SeqCst)
+}
+
+/// Whether hart `hart_id` was started by [hart_online], so that it doesn't wait for the others to boot.
+pub fn is_hotplugged(hart_id: usize) -> bool {
+    HOTPLUGGED.remote(hart_id).load(Ordering::SeqCst)
+}
+
+fn wait_for_status(hart_id: usize, status: HartStatus) -> Result<(), HotplugError> {
+    loop {
+        match SbiTable::hart_get_status(hart_id) {
+            Ok(cur) if cur == status => return Ok(()),
+            Ok(_) => sleep_ns(POLL_INTERVAL_NS),
+            Err(err) => return Err(HotplugError::Sbi(err)),
+        }
+    }
+}
+
+/// Take working hart `hart_id` offline, and block until it's stopped.
+pub fn hart_offline(hart_id: usize) -> Result<(), HotplugError> {
+    if !is_present_hart(hart_id) {
+        return Err(HotplugError::InvalidHart);
+    }
+    if hart_id == BOOT_HART.load(Ordering::Relaxed) {
+        return Err(HotplugError::BootHart);
+    }
+    let _guard = HOTPLUG_LOCK.lock();
+    if !is_working_hart(hart_id) {
+        return Err(HotplugError::AlreadyOffline);
+    }
+    let others: Vec<usize> = get_working_hart_ids()
+        .into_iter()
+        .filter(|id| *id != hart_id)
+        .collect();
+    if others.is_empty() {
+        return Err(HotplugError::LastHart);
+    }
+    // It would wait on the hart for the hart to stop.
+    let task = get_current_task();
+    if !others.iter().any(|id| task.sched.is_allowed(*id)) {
+        return Err(HotplugError::Pinned);
+    }
+    drop(task);
+    set_hart_working(hart_id, false);
+    // Harts queue tasks and calls with interrupts disabled:
+    // once they have run this, none of them still queues anything there.
+    smp_call_all(|| {}, true);
+    STOP_REQUESTED.remote(hart_id).store(true, Ordering::SeqCst);
+    send_reschedule(hart_id);
+    wait_for_status(hart_id, HartStatus::Stopped)?;
+    info!("Hart #{:} is offline.", hart_id);
+    Ok(())
+}
+
+/// Bring hart `hart_id`, taken offline by [hart_offline], back online, and block until it runs tasks.
+pub fn hart_online(hart_id: usize) -> Result<(), HotplugError> {
+    if !is_present_hart(hart_id) {
+        return Err(HotplugError::InvalidHart);
+    }
+    let _guard = HOTPLUG_LOCK.lock();
+    if is_working_hart(hart_id) {
+        return Err(HotplugError::AlreadyOnline);
+    }
+    if SbiTable::hart_get_status(hart_id).map_err(HotplugError::Sbi)? != HartStatus::Stopped {
+        return Err(HotplugError::NotStopped);
+    }
+    STOP_REQUESTED
+        .remote(hart_id)
+        .store(false, Ordering::SeqCst);
+    HOTPLUGGED.remote(hart_id).store(true, Ordering::SeqCst);
+    start_slave_hart(hart_id).map_err(HotplugError::Sbi)?;
+    while !is_working_hart(hart_id) {
+        sleep_ns(POLL_INTERVAL_NS);
+    }
+    info!("Hart #{:} is online.", hart_id);
+    Ok(())
+}
+
+/// Stop hart `hart_id`, the current one, asked to go offline.
+/// **Call it from its scheduler loop, once it stopped running tasks.**
+pub fn stop_current(hart_id: usize) -> ! {
+    disable_intr();
+    for notifier in get_notifiers().iter().rev() {
+        if let Some(offline) = notifier.offline {
+            trace!("Hart #{:} going offline: {:}.", hart_id, notifier.name);
+            offline(hart_id);
+        }
+    }
+    if let Err(err) = SbiTable::hart_stop() {
+        error!("Unable to stop hart #{:}: {:?}", hart_id, err);
+    }
+    halt()
+}
+
+/// Run the online callbacks for hart `hart_id`, the current one started by [hart_online],
+/// and make it a working hart. **Call it right before it runs tasks.**
+pub fn start_current(hart_id: usize) {
+    let intr = disable_intr();
+    for notifier in get_notifiers() {
+        if let Some(online) = notifier.online {
+            trace!("Hart #{:} coming online: {:}.", hart_id, notifier.name);
+            online(hart_id);
+        }
+    }
+    set_hart_working(hart_id, true);
+    restore_intr(intr);
+}
+
+/// Record the boot hart, and register the callbacks of the core subsystems. Call it on the boot hart.
+pub fn init() {
+    BOOT_HART.store(get_current_hart_id(), Ordering::Relaxed);
+    let notifiers = [
+        HotplugNotifier {
+            name: "fpu",
+            online: None,
+            offline: Some(fpu::hart_offline),
+        },
+        HotplugNotifier {
+            name: "smp",
+            online: Some(smp::hart_online),
+            offline: Some(smp::hart_offline),
+        },
+        HotplugNotifier {
+            name: "softirq",
+            online: None,
+            offline: Some(softirq::hart_offline),
+        },
+        HotplugNotifier {
+            name: "timer",
+            online: None,
+            offline: Some(timer::hart_offline),
+        },
+        HotplugNotifier {
+            name: "sched",
+            online: None,
+            offline: Some(migrate_queued),
+        },
+    ];
+    for notifier in notifiers {
+        register_hotplug_notifier(notifier);
+    }
+}
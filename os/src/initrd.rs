@@ -0,0 +1,151 @@
+//! Initial Ramdisk
+//!
+//! The initrd is a `newc` cpio archive holding the first user programs. It's either:
+//! - linked into the kernel image with the `embed-initrd` feature, from `runtime/initrd.cpio`;
+//! - or loaded by the bootloader at the range given by `linux,initrd-start` and `linux,initrd-end`
+//!   in `/chosen`, whose pages are then kept out of [crate::dev::GENERAL_MEM].
+//!
+//! The archive is never copied: its regular files are indexed by absolute path
+//! and handed out as static slices, or opened read-only as [InitrdFile].
+
+use crate::{
+    debug_ex,
+    mutex::SpinLock,
+    task::resource::{File, FileError},
+};
+use alloc::{collections::btree_map::BTreeMap, format, string::String};
+use core::{ops::Range, slice};
+use cpio::CpioArchive;
+use log::{info, warn};
+use spin::Once;
+
+#[cfg(feature = "embed-initrd")]
+unsafe extern "C" {
+    unsafe fn _initrd_start();
+    unsafe fn _initrd_end();
+}
+
+#[cfg(feature = "embed-initrd")]
+core::arch::global_asm! {
+    "
+        .section .rodata
+        .balign 8
+        .globl _initrd_start
+        .globl _initrd_end
+        _initrd_start:
+        .incbin \"runtime/initrd.cpio\"
+        _initrd_end:
+    "
+}
+
+/// Physical range of the initrd loaded by the bootloader.
+static INITRD_RANGE: Once<Range<usize>> = Once::new();
+
+/// Regular files of the initrd by absolute path.
+static FILES: Once<BTreeMap<String, &'static [u8]>> = Once::new();
+
+/// Record the physical range of the initrd loaded by the bootloader, found in the device tree.
+pub fn set_initrd_range(range: Range<usize>) {
+    INITRD_RANGE.call_once(|| range);
+}
+
+fn get_image() -> Option<&'static [u8]> {
+    #[cfg(feature = "embed-initrd")]
+    {
+        let start = _initrd_start as *const u8;
+        let len = _initrd_end as usize - start as usize;
+        Some(unsafe { slice::from_raw_parts(start, len) })
+    }
+    #[cfg(not(feature = "embed-initrd"))]
+    {
+        // The physical memory is mapped at `KERNEL_OFFSET` in the kernel memspace.
+        INITRD_RANGE.get().map(|range| unsafe {
+            slice::from_raw_parts(
+                (range.start + crate::arch::KERNEL_OFFSET) as *const u8,
+                range.len(),
+            )
+        })
+    }
+}
+
+/// `./bin/sh`, `/bin/sh` and `bin/sh` all name `/bin/sh`.
+fn normalize_path(name: &str) -> String {
+    let name = name.trim_start_matches("./").trim_start_matches('/');
+    format!("/{}", name)
+}
+
+/// Index the files of the initrd. A malformed archive is used up to the faulty entry.
+///
+/// **The kernel memspace must be active.**
+pub fn init() {
+    let mut files = BTreeMap::new();
+    match get_image() {
+        Some(image) => {
+            debug_ex!("Reading initrd ({:} bytes)...", image.len());
+            for entry in CpioArchive::new(image).entries() {
+                match entry {
+                    Ok(entry) if entry.is_file() => {
+                        files.insert(normalize_path(entry.name), entry.data);
+                    }
+                    Ok(_) => {}
+                    Err(err) => {
+                        warn!("Malformed initrd: {:?}.", err);
+                        break;
+                    }
+                }
+            }
+            info!("Initrd loaded with {:} files.", files.len());
+        }
+        None => info!("No initrd found."),
+    }
+    FILES.call_once(|| files);
+}
+
+/// The content of the regular file `path` in the initrd.
+pub fn get_file(path: &str) -> Option<&'static [u8]> {
+    FILES.get()?.get(&normalize_path(path)).copied()
+}
+
+/// Paths of the regular files in the initrd.
+pub fn get_file_names() -> impl Iterator<Item = &'static str> {
+    FILES
+        .get()
+        .into_iter()
+        .flat_map(|files| files.keys().map(String::as_str))
+}
+
+/// A regular file of the initrd, opened for reading.
+pub struct InitrdFile {
+    data: &'static [u8],
+    pos: SpinLock<usize>,
+}
+
+impl InitrdFile {
+    /// Open the regular file `path` of the initrd.
+    pub fn open(path: &str) -> Option<InitrdFile> {
+        get_file(path).map(|data| InitrdFile {
+            data,
+            pos: SpinLock::new(0),
+        })
+    }
+}
+
+impl File for InitrdFile {
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError> {
+        let mut pos = self.pos.lock_no_irq();
+        let count = self.read_at(*pos, buf)?;
+        *pos += count;
+        Ok(count)
+    }
+
+    fn write(&self, _buf: &[u8]) -> Result<usize, FileError> {
+        Err(FileError::NotWritable)
+    }
+
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> Result<usize, FileError> {
+        let rest = self.data.get(offset..).unwrap_or(&[]);
+        let count = rest.len().min(buf.len());
+        buf[..count].copy_from_slice(&rest[..count]);
+        Ok(count)
+    }
+}
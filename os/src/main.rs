@@ -6,23 +6,39 @@
 #![allow(long_running_const_eval)]
 
 use crate::{
-    arch::{hart::get_current_hart_id, trap},
+    arch::{
+        SbiTable,
+        hart::get_current_hart_id,
+        trap::{self, intr::disable_intr},
+    },
     dev::get_working_harts,
-    task::scheduler::run_tasks,
+    smp::{halt, stop_others},
+    task::{
+        loader::start_init, registry::dump_tasks, scheduler::run_tasks, stats::dump_hart_times,
+    },
 };
 use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use log::{Level, error, info, warn};
 
 extern crate alloc;
 
 #[macro_use]
 pub mod arch;
+pub mod debug_console;
+pub mod defer;
 pub mod dev;
 pub mod entry;
+pub mod hotplug;
+pub mod initrd;
 pub mod mm;
 pub mod mutex;
 mod panic;
+pub mod rcu;
 pub mod sched;
+pub mod smp;
+pub mod syscall;
 pub mod task;
+pub mod timer;
 #[macro_use]
 pub mod console;
 #[macro_use]
@@ -59,28 +75,57 @@ pub fn early_init_main() {
 pub fn kernel_main() -> ! {
     debug_ex!("karox running on hart #{:}.", get_current_hart_id());
     mm::init();
+    initrd::init();
     trap::init();
     dev::init();
+    timer::init();
+    task::executor::init();
+    defer::init();
+    hotplug::init();
+    debug_console::init();
     debug_ex!("Main hart initialized (#{:}).", get_current_hart_id());
 
     mark_init();
     wait_for_slave();
+    // Loading init allocates an ASID, which calls every working hart: the slaves must be running.
+    if let Err(err) = start_init() {
+        warn!("Unable to start init: {:?}.", err);
+    }
 
     run_tasks();
 }
 
-/// The main function of the operating system
+/// The main function of the slave harts, also entered by the harts coming back online.
 pub fn kernel_slave() -> ! {
     wait_for_main();
 
-    debug_ex!("karox running on slave hart #{:}.", get_current_hart_id());
+    let hart_id = get_current_hart_id();
+    debug_ex!("karox running on slave hart #{:}.", hart_id);
 
     mm::init_slave();
     trap::init();
-    debug_ex!("Slave hart initialized (#{:}).", get_current_hart_id());
+    timer::init();
+    debug_ex!("Slave hart initialized (#{:}).", hart_id);
 
-    wait_for_slave();
+    if hotplug::is_hotplugged(hart_id) {
+        hotplug::start_current(hart_id);
+    } else {
+        wait_for_slave();
+    }
 
     run_tasks();
     //loop {}
 }
+
+/// Stop every hart, log the scheduling statistics and power the system off.
+pub fn shutdown() -> ! {
+    disable_intr();
+    stop_others();
+    info!("Shutting down on hart #{:}.", get_current_hart_id());
+    dump_tasks(Level::Info);
+    dump_hart_times(Level::Info);
+    if let Err(err) = SbiTable::shutdown() {
+        error!("Unable to power off: {:?}", err);
+    }
+    halt()
+}
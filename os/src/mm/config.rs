@@ -1,5 +1,5 @@
 use crate::{
-    arch::{PAGE_WIDTH, mm::paging::PageDir},
+    arch::{MAX_USPACE_ADDR, PAGE_WIDTH, mm::paging::PageDir},
     mm::paging::PageDirTrait,
 };
 
@@ -12,3 +12,10 @@ pub const KERNEL_STACK_SHIFT: usize = 17; // 128KiB
 pub const PAGE_SIZE: usize = 1 << PAGE_WIDTH;
 
 pub const PTABLE_ENTRY_COUNT: usize = 1 << PageDir::LEVEL_WIDTH;
+
+pub const USER_STACK_PAGES: usize = 32; // 128KiB
+pub const USER_STACK_SIZE: usize = USER_STACK_PAGES * PAGE_SIZE; // 128KiB
+/// Top of the initial user stack. The page above it holds the signal trampoline, read-only.
+pub const USER_STACK_TOP: usize = MAX_USPACE_ADDR - PAGE_SIZE;
+/// Address of the signal trampoline, where signal handlers return to call `sigreturn`.
+pub const SIGNAL_TRAMPOLINE: usize = USER_STACK_TOP;
@@ -4,8 +4,10 @@ pub mod config;
 pub mod frame;
 pub mod heap;
 pub mod paging;
+pub mod percpu;
 pub mod space;
 pub mod stack;
+pub mod uaccess;
 
 /// Initializes the memory management module.
 pub fn init() {
@@ -13,14 +13,14 @@ use crate::{
         self, PTABLE_MAX_LEVEL,
         mm::{
             PageNum,
-            paging::{PageDir, PageTableFlags},
+            paging::{PageDir, PageTableEntry, PageTableFlags},
         },
     }, debug_ex, mm::{
         config::PTABLE_ENTRY_COUNT,
         frame::{FRAME_ALLOC, FrameAllocatorError},
     }
 };
-use core::fmt::Debug;
+use core::{fmt::Debug, ops::Range};
 
 // region: PageDirTrait
 pub trait PageDirTrait: Sized {
@@ -128,6 +128,31 @@ impl PageTable {
     pub fn ppn(&self) -> PageNum {
         self.root.ppn()
     }
+
+    /// Share the root entries `range` of `other`, mapping the same pages as `other` there.
+    /// **The mappings must outlive this table, and `other` must not replace the entries.**
+    pub unsafe fn share_root_entries(&mut self, other: &PageTable, range: Range<usize>) {
+        unsafe { self.root.share_entries(&other.root, range) }
+    }
+
+    /// The leaf entry mapping `vpn`, which may be a huge page, or [None] if `vpn` is not mapped.
+    pub fn lookup(&self, vpn: PageNum) -> Option<PageTableEntry> {
+        let vpn: usize = vpn.into();
+        let mut dir = &self.root;
+        for level in (0..=PTABLE_MAX_LEVEL).rev() {
+            let level_width = PageDir::LEVEL_WIDTH;
+            let index = calc_index(vpn, level * level_width, level_width, false);
+            let entry = dir.get_entry(index);
+            if !entry.is_valid() {
+                return None;
+            }
+            if !entry.is_dir() {
+                return Some(entry);
+            }
+            dir = dir.get_or_none(index)?;
+        }
+        None
+    }
 }
 
 fn calc_index(vpn: usize, level_offset: usize, level_width: usize, non_zero: bool) -> usize {
@@ -0,0 +1,139 @@
+//! Per-CPU Variables
+//!
+//! Variables declared with [crate::percpu] live in the `.percpu` section, which is only a template:
+//! [init] gives every hart found in the device tree its own copy of the section.
+//! Before that, only the boot hart runs, and it uses the template itself.
+//!
+//! The copy of the current hart is borrowed with [PerCpu::get], which disables preemption
+//! for the borrow, so that the task can't move to another hart meanwhile.
+//! The owner of a variable reaches the copy of any hart with [PerCpu::remote],
+//! as long as the data can be shared between harts.
+
+use crate::{
+    arch::{
+        hart::get_current_hart_id,
+        symbols::{_epercpu, _spercpu},
+    },
+    debug_ex,
+    dev::get_present_hart_ids,
+    mm::config::PAGE_SIZE,
+    panic_init,
+    task::preempt::{disable_preempt, restore_preempt},
+};
+use alloc::{alloc::alloc, vec, vec::Vec};
+use core::{alloc::Layout, marker::PhantomData, ops::Deref, ptr::copy_nonoverlapping};
+use spin::Once;
+
+/// Declare per-CPU variables. The initial values must be constant.
+///
+/// ```ignore
+/// percpu! {
+///     /// Ticks handled by the hart.
+///     pub static TICKS: AtomicUsize = AtomicUsize::new(0);
+/// }
+/// ```
+#[macro_export]
+macro_rules! percpu {
+    ($($(#[$attr:meta])* $vis:vis static $name:ident: $ty:ty = $init:expr;)+) => {
+        $(
+            $(#[$attr])*
+            #[unsafe(link_section = ".percpu")]
+            $vis static $name: $crate::mm::percpu::PerCpu<$ty> =
+                $crate::mm::percpu::PerCpu::new($init);
+        )+
+    };
+}
+
+/// Base address of the per-CPU area of each hart, indexed by hart id.
+static AREAS: Once<Vec<Option<usize>>> = Once::new();
+
+fn area_base(hart_id: usize) -> usize {
+    match AREAS.get() {
+        Some(areas) => areas
+            .get(hart_id)
+            .copied()
+            .flatten()
+            .unwrap_or_else(|| panic!("Hart #{:} has no per-CPU area.", hart_id)),
+        None => _spercpu as usize,
+    }
+}
+
+/// A per-CPU variable, declared with [crate::percpu].
+pub struct PerCpu<T> {
+    template: T,
+}
+
+unsafe impl<T: Send> Sync for PerCpu<T> {}
+
+impl<T> PerCpu<T> {
+    #[doc(hidden)]
+    pub const fn new(value: T) -> PerCpu<T> {
+        PerCpu { template: value }
+    }
+
+    /// Pointer to the copy of hart `hart_id`.
+    pub fn as_ptr_on(&self, hart_id: usize) -> *mut T {
+        let offset = &self.template as *const T as usize - _spercpu as usize;
+        (area_base(hart_id) + offset) as *mut T
+    }
+
+    /// Borrow the copy of the current hart. Preemption is disabled until the guard is dropped.
+    ///
+    /// Interrupt handlers of the hart may borrow it too, so mutable data needs interior mutability.
+    pub fn get(&self) -> PerCpuGuard<'_, T> {
+        disable_preempt();
+        PerCpuGuard {
+            value: unsafe { &*self.as_ptr_on(get_current_hart_id()) },
+            _not_send: PhantomData,
+        }
+    }
+}
+
+impl<T: Sync> PerCpu<T> {
+    /// The copy of hart `hart_id`.
+    pub fn remote(&self, hart_id: usize) -> &T {
+        unsafe { &*self.as_ptr_on(hart_id) }
+    }
+}
+
+/// Borrow of the copy of a per-CPU variable on the current hart, returned by [PerCpu::get].
+pub struct PerCpuGuard<'a, T> {
+    value: &'a T,
+    /// Preemption is restored on the hart that disabled it.
+    _not_send: PhantomData<*const ()>,
+}
+
+impl<T> Deref for PerCpuGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &T {
+        self.value
+    }
+}
+
+impl<T> Drop for PerCpuGuard<'_, T> {
+    fn drop(&mut self) {
+        restore_preempt();
+    }
+}
+
+/// Allocate the per-CPU areas of the working harts, as copies of the template.
+///
+/// **Call it on the boot hart once the harts are registered, and before the other harts start.**
+/// The areas inherit the state of the template, so the per-CPU variables must not be used before.
+pub fn init() {
+    let size = _epercpu as usize - _spercpu as usize;
+    let hart_ids = get_present_hart_ids();
+    let len = hart_ids.iter().max().map_or(0, |max| max + 1);
+    let mut areas = vec![None; len];
+    let layout = Layout::from_size_align(size.max(PAGE_SIZE), PAGE_SIZE).unwrap();
+    for hart_id in hart_ids {
+        let area = unsafe { alloc(layout) };
+        if area.is_null() {
+            panic_init!("Unable to allocate the per-CPU area of hart #{:}.", hart_id);
+        }
+        unsafe { copy_nonoverlapping(_spercpu as *const u8, area, size) };
+        areas[hart_id] = Some(area as usize);
+    }
+    AREAS.call_once(|| areas);
+    debug_ex!("Per-CPU areas allocated ({:#x} bytes each).", size);
+}
@@ -1,16 +1,654 @@
-use crate::mm::paging::PageTable;
+use crate::{
+    arch::{
+        MAX_USPACE_ADDR, PAGE_WIDTH,
+        mm::{
+            PageNum,
+            paging::{
+                PageTableEntry, PageTableFlags, alloc_asid, create_user_ptable, flush_user_tlb,
+                free_asid,
+            },
+        },
+    },
+    mm::{
+        config::PAGE_SIZE,
+        frame::{FRAME_ALLOC, Frame},
+        paging::{PageTable, PagingError},
+    },
+    mutex::SpinLock,
+};
+use alloc::{
+    collections::{btree_map::BTreeMap, btree_set::BTreeSet},
+    sync::Arc,
+    vec::Vec,
+};
+use core::{
+    fmt::{Debug, Formatter},
+    ops::RangeInclusive,
+    ptr::{copy_nonoverlapping, write_bytes},
+};
+use utils::num::AlignableTo;
 
+/// Errors returned by the user page operations of [MemSpace].
 #[derive(Debug)]
+pub enum MemSpaceError {
+    /// The range is not in user space.
+    BadAddress,
+    /// A page of the range has no user frame.
+    NotMapped,
+    /// A page of the range can't be written from user mode.
+    NotWritable,
+    /// No free range of user space is large enough.
+    NoSpace,
+    /// The range overlaps a mapping that must not be replaced.
+    Overlap,
+    PagingError {
+        error: PagingError,
+    },
+}
+
+impl From<PagingError> for MemSpaceError {
+    fn from(error: PagingError) -> Self {
+        MemSpaceError::PagingError { error }
+    }
+}
+
+/// Where [MemSpace::map_area] places an area.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AreaPlacement {
+    /// In the highest free range below the top of the mmap region.
+    Anywhere,
+    /// At this vpn, replacing whatever is mapped there.
+    Fixed(usize),
+    /// At this vpn, failing if anything is mapped there.
+    FixedNoReplace(usize),
+}
+
 pub struct MemSpace {
     pub asid: usize,
-    pub page_table: PageTable,
+    /// Root of the page table, which never changes.
+    root_ppn: PageNum,
+    inner: SpinLock<MemSpaceInner>,
+}
+
+struct MemSpaceInner {
+    page_table: PageTable,
+    /// Frames backing the user pages, by vpn. They are freed with the last memspace using them.
+    user_frames: BTreeMap<usize, Arc<Frame>>,
+    /// Writable pages whose frame may be shared with another memspace. They are mapped
+    /// read-only and get a frame of their own on the first write.
+    cow: BTreeSet<usize>,
+    /// The heap spans `[heap_start, brk)`, and its pages are mapped up to `brk` rounded up.
+    heap_start: usize,
+    brk: usize,
+    /// Mmap areas are placed down from `mmap_top`, the heap grows up until it meets them.
+    mmap_top: usize,
+    /// Mmap areas, as end vpns by start vpn. Their pages may lack frames if they are not accessible.
+    areas: BTreeMap<usize, usize>,
 }
 
 impl MemSpace {
     pub fn new(asid: usize, ptable: PageTable) -> MemSpace {
         MemSpace {
             asid,
-            page_table: ptable,
+            root_ppn: ptable.ppn(),
+            inner: SpinLock::new(MemSpaceInner {
+                page_table: ptable,
+                user_frames: BTreeMap::new(),
+                cow: BTreeSet::new(),
+                heap_start: 0,
+                brk: 0,
+                mmap_top: 0,
+                areas: BTreeMap::new(),
+            }),
+        }
+    }
+
+    /// Create an empty memspace for user tasks. The kernel is mapped in its upper half.
+    pub fn new_user() -> Result<MemSpace, PagingError> {
+        let ptable = create_user_ptable()?;
+        Ok(MemSpace::new(alloc_asid(), ptable))
+    }
+
+    pub fn get_root_ppn(&self) -> PageNum {
+        self.root_ppn
+    }
+
+    /// Run `f` on the page table. Mappings must only be changed through the other methods.
+    pub fn with_page_table<R>(&self, f: impl FnOnce(&PageTable) -> R) -> R {
+        f(&self.inner.lock_no_irq().page_table)
+    }
+
+    /// The leaf entry mapping `vpn`, see [PageTable::lookup].
+    pub fn lookup(&self, vpn: PageNum) -> Option<PageTableEntry> {
+        self.inner.lock_no_irq().page_table.lookup(vpn)
+    }
+
+    /// Run `f` if every page of `vpns` is mapped with the flags `required`, and return [None] otherwise.
+    /// The memspace stays locked until `f` returns, so that no thread unmaps the pages
+    /// or changes their permissions meanwhile.
+    ///
+    /// **`f` must neither block nor use the memspace**, it runs with interrupts disabled.
+    pub fn with_user_pages<R>(
+        &self,
+        vpns: RangeInclusive<usize>,
+        required: PageTableFlags,
+        f: impl FnOnce() -> R,
+    ) -> Option<R> {
+        let inner = self.inner.lock_no_irq();
+        let mapped = vpns.into_iter().all(|vpn| {
+            inner
+                .page_table
+                .lookup(PageNum::from_const(vpn))
+                .is_some_and(|entry| entry.get_flags().contains(required))
+        });
+        mapped.then(f)
+    }
+
+    /// Map `count` zero-filled user pages from `vpn` with the permissions `perm`.
+    ///
+    /// Pages already mapped keep their content and gain `perm`,
+    /// as segments may share their boundary pages.
+    /// **The memspace must not be active if pages gain permissions**, since their translations are not flushed.
+    pub fn map_user(
+        &self,
+        vpn: usize,
+        count: usize,
+        perm: PageTableFlags,
+    ) -> Result<(), MemSpaceError> {
+        self.inner.lock_no_irq().map_user(vpn, count, perm)
+    }
+
+    /// Unmap `count` user pages from `vpn` and free their frames. Pages not mapped are skipped,
+    /// and the mmap areas are cut out of the range.
+    ///
+    /// **The other harts must be running**, as their cached translations are flushed.
+    pub fn unmap_user(&self, vpn: usize, count: usize) -> Result<(), MemSpaceError> {
+        let mut inner = self.inner.lock_no_irq();
+        let frames = inner.unmap_user(vpn, count)?;
+        inner.remove_areas(vpn, vpn + count);
+        drop(inner);
+        // No hart may reach the frames any more once they are freed.
+        flush_user_tlb(self.asid);
+        drop(frames);
+        Ok(())
+    }
+
+    /// Copy `data` to the user pages at `vaddr` through the kernel mapping of their frames,
+    /// regardless of their permissions. Shared copy-on-write frames are copied first.
+    ///
+    /// **The other harts must be running**, as copied pages are flushed from their TLBs.
+    pub fn write_user(&self, vaddr: usize, data: &[u8]) -> Result<(), MemSpaceError> {
+        self.write_frames(vaddr, data, false)
+    }
+
+    /// Like [MemSpace::write_user], but only to pages writable from user mode,
+    /// for the stores the kernel makes on behalf of a task whose memspace is not active.
+    /// Nothing is written if a page is not.
+    pub fn write_user_checked(&self, vaddr: usize, data: &[u8]) -> Result<(), MemSpaceError> {
+        self.write_frames(vaddr, data, true)
+    }
+
+    fn write_frames(&self, vaddr: usize, data: &[u8], checked: bool) -> Result<(), MemSpaceError> {
+        let end = vaddr
+            .checked_add(data.len())
+            .filter(|end| *end <= MAX_USPACE_ADDR)
+            .ok_or(MemSpaceError::BadAddress)?;
+        let mut inner = self.inner.lock_no_irq();
+        if checked && !data.is_empty() {
+            let writable = ((vaddr >> PAGE_WIDTH)..=((end - 1) >> PAGE_WIDTH)).all(|vpn| {
+                inner
+                    .page_perm(vpn)
+                    .is_some_and(|perm| perm.contains(PageTableFlags::W))
+            });
+            if !writable {
+                return Err(MemSpaceError::NotWritable);
+            }
+        }
+        let mut flush = false;
+        let mut addr = vaddr;
+        let mut rest = data;
+        let res = loop {
+            if rest.is_empty() {
+                break Ok(());
+            }
+            let vpn = addr >> PAGE_WIDTH;
+            if inner.cow.contains(&vpn) {
+                if let Err(error) = inner.break_cow(vpn) {
+                    break Err(error);
+                }
+                flush = true;
+            }
+            let Some(frame) = inner.user_frames.get(&vpn) else {
+                break Err(MemSpaceError::NotMapped);
+            };
+            let offset = addr & (PAGE_SIZE - 1);
+            let len = rest.len().min(PAGE_SIZE - offset);
+            unsafe {
+                copy_nonoverlapping(rest.as_ptr(), frame.as_ptr_mut::<u8>().add(offset), len)
+            };
+            addr += len;
+            rest = &rest[len..];
+        };
+        drop(inner);
+        if flush {
+            flush_user_tlb(self.asid);
+        }
+        res
+    }
+
+    /// Resolve a write fault at `vaddr` by giving its copy-on-write page a frame of its own.
+    /// Return `false` if the page is not writable, or memory runs out.
+    ///
+    /// **The other harts must be running**, as the old translation is flushed from their TLBs.
+    pub fn handle_write_fault(&self, vaddr: usize) -> bool {
+        if vaddr >= MAX_USPACE_ADDR {
+            return false;
+        }
+        let vpn = vaddr >> PAGE_WIDTH;
+        let mut inner = self.inner.lock_no_irq();
+        if !inner.cow.contains(&vpn) {
+            // Another thread may have resolved the fault already.
+            return inner
+                .page_perm(vpn)
+                .is_some_and(|perm| perm.contains(PageTableFlags::W));
+        }
+        let res = inner.break_cow(vpn).is_ok();
+        drop(inner);
+        if res {
+            flush_user_tlb(self.asid);
+        }
+        res
+    }
+
+    /// Start an empty heap at `heap_start`, and place mmap areas down from `mmap_top`.
+    pub fn init_layout(&self, heap_start: usize, mmap_top: usize) {
+        let mut inner = self.inner.lock_no_irq();
+        inner.heap_start = heap_start;
+        inner.brk = heap_start;
+        inner.mmap_top = mmap_top;
+    }
+
+    /// Move the program break to `brk`, mapping or unmapping heap pages, and return the new break.
+    /// The break is left unchanged if `brk` is below the heap start or would reach an mmap area,
+    /// or if memory runs out.
+    ///
+    /// **The other harts must be running**, as shrinking the heap flushes their cached translations.
+    pub fn set_brk(&self, brk: usize) -> usize {
+        let mut inner = self.inner.lock_no_irq();
+        let old = inner.brk;
+        if brk < inner.heap_start || brk.align_up(PAGE_SIZE) > inner.heap_limit() {
+            return old;
+        }
+        let old_end = old.align_up(PAGE_SIZE) >> PAGE_WIDTH;
+        let new_end = brk.align_up(PAGE_SIZE) >> PAGE_WIDTH;
+        let (res, freed) = if new_end > old_end {
+            match inner.map_user(old_end, new_end - old_end, PageTableFlags::RW) {
+                Ok(()) => (brk, Vec::new()),
+                // Drop the pages mapped before the failure.
+                Err(_) => (
+                    old,
+                    inner
+                        .unmap_user(old_end, new_end - old_end)
+                        .unwrap_or_default(),
+                ),
+            }
+        } else {
+            (
+                brk,
+                inner
+                    .unmap_user(new_end, old_end - new_end)
+                    .unwrap_or_default(),
+            )
+        };
+        inner.brk = res;
+        drop(inner);
+        if !freed.is_empty() {
+            flush_user_tlb(self.asid);
+        }
+        res
+    }
+
+    /// Map an mmap area of `count` pages with the permissions `perm`, and return its start vpn.
+    /// The pages are zero-filled; with no permission they get no frame until they are made accessible.
+    ///
+    /// **The other harts must be running**, as replaced pages are flushed from their TLBs.
+    pub fn map_area(
+        &self,
+        placement: AreaPlacement,
+        count: usize,
+        perm: PageTableFlags,
+    ) -> Result<usize, MemSpaceError> {
+        if count == 0 {
+            return Err(MemSpaceError::BadAddress);
+        }
+        let mut inner = self.inner.lock_no_irq();
+        let (vpn, mut frames) = match placement {
+            AreaPlacement::Anywhere => (
+                inner.find_free_area(count).ok_or(MemSpaceError::NoSpace)?,
+                Vec::new(),
+            ),
+            AreaPlacement::Fixed(vpn) => {
+                let frames = inner.unmap_user(vpn, count)?;
+                inner.remove_areas(vpn, vpn + count);
+                (vpn, frames)
+            }
+            AreaPlacement::FixedNoReplace(vpn) => {
+                check_user_pages(vpn, count)?;
+                if !inner.is_range_free(vpn, count) {
+                    return Err(MemSpaceError::Overlap);
+                }
+                (vpn, Vec::new())
+            }
+        };
+        let res = inner.protect_user(vpn, count, perm);
+        match res {
+            Ok(_) => {
+                inner.areas.insert(vpn, vpn + count);
+            }
+            // Drop the pages mapped before the failure.
+            Err(_) => frames.extend(inner.unmap_user(vpn, count).unwrap_or_default()),
+        }
+        drop(inner);
+        // No hart may reach the frames any more once they are freed.
+        if !frames.is_empty() {
+            flush_user_tlb(self.asid);
+        }
+        drop(frames);
+        res.map(|_| vpn)
+    }
+
+    /// Set the permissions of `count` pages from `vpn` to `perm`, allocating zero-filled frames
+    /// for the pages that become accessible. Every page must be mapped or in an mmap area.
+    ///
+    /// **The other harts must be running**, as the old translations are flushed from their TLBs.
+    pub fn protect_user(
+        &self,
+        vpn: usize,
+        count: usize,
+        perm: PageTableFlags,
+    ) -> Result<(), MemSpaceError> {
+        let mut inner = self.inner.lock_no_irq();
+        check_user_pages(vpn, count)?;
+        let covered = (vpn..vpn + count).all(|vpn| {
+            inner.user_frames.contains_key(&vpn)
+                || inner
+                    .areas
+                    .range(..=vpn)
+                    .next_back()
+                    .is_some_and(|(_, end)| vpn < *end)
+        });
+        if !covered {
+            return Err(MemSpaceError::NotMapped);
+        }
+        let flush = inner.protect_user(vpn, count, perm)?;
+        drop(inner);
+        if flush {
+            flush_user_tlb(self.asid);
         }
+        Ok(())
+    }
+
+    /// Create a user memspace with a copy of the user pages, heap and mmap areas.
+    ///
+    /// The frames are shared, and writable pages become copy-on-write in both memspaces.
+    /// **The other harts must be running**, as the pages made read-only are flushed from their TLBs.
+    pub fn duplicate(&self) -> Result<MemSpace, MemSpaceError> {
+        let res = MemSpace::new_user()?;
+        let mut inner = self.inner.lock_no_irq();
+        let mut res_inner = res.inner.lock_no_irq();
+        let mut flush = false;
+        let shared = inner.share_pages(&mut res_inner, &mut flush);
+        res_inner.heap_start = inner.heap_start;
+        res_inner.brk = inner.brk;
+        res_inner.mmap_top = inner.mmap_top;
+        res_inner.areas = inner.areas.clone();
+        drop(res_inner);
+        drop(inner);
+        // Pages may have been made copy-on-write before a failure.
+        if flush {
+            flush_user_tlb(self.asid);
+        }
+        shared.map(|_| res)
+    }
+}
+
+impl MemSpaceInner {
+    /// The permissions of the user page `vpn`, or [None] if it's not accessible.
+    fn page_perm(&self, vpn: usize) -> Option<PageTableFlags> {
+        let entry = self.page_table.lookup(PageNum::from_const(vpn))?;
+        let mut perm = entry.get_flags() & PageTableFlags::RWX;
+        if self.cow.contains(&vpn) {
+            perm |= PageTableFlags::W;
+        }
+        Some(perm)
+    }
+
+    /// Map the unmapped page `vpn` to its frame with the permissions `perm`.
+    /// It's made copy-on-write if it's writable and its frame is shared.
+    fn map_page(&mut self, vpn: usize, perm: PageTableFlags) -> Result<(), MemSpaceError> {
+        let frame = &self.user_frames[&vpn];
+        let ppn = frame.ppn();
+        let perm = if perm.contains(PageTableFlags::W) && Arc::strong_count(frame) > 1 {
+            self.cow.insert(vpn);
+            perm - PageTableFlags::W
+        } else {
+            self.cow.remove(&vpn);
+            perm
+        };
+        self.page_table
+            .map(PageNum::from_const(vpn), ppn, 1, user_page_flags(perm))?;
+        Ok(())
+    }
+
+    /// Give the copy-on-write page `vpn` a frame of its own, copied unless the frame is no
+    /// longer shared, and make it writable. Its old translation must then be flushed.
+    fn break_cow(&mut self, vpn: usize) -> Result<(), MemSpaceError> {
+        let frame = &self.user_frames[&vpn];
+        if Arc::strong_count(frame) > 1 {
+            let copy = FRAME_ALLOC
+                .alloc_managed()
+                .map_err(|error| PagingError::FrameAllocatorError { error })?;
+            unsafe {
+                copy_nonoverlapping(frame.as_ptr::<u8>(), copy.as_ptr_mut::<u8>(), PAGE_SIZE)
+            };
+            self.user_frames.insert(vpn, Arc::new(copy));
+        }
+        let perm = self.page_perm(vpn).unwrap_or(PageTableFlags::RW);
+        self.page_table.clear(PageNum::from_const(vpn), 1)?;
+        self.cow.remove(&vpn);
+        self.map_page(vpn, perm)
+    }
+
+    /// Share the user pages with the empty memspace `child`, making the writable ones copy-on-write.
+    /// `flush` is set once translations of this memspace are restricted.
+    fn share_pages(
+        &mut self,
+        child: &mut MemSpaceInner,
+        flush: &mut bool,
+    ) -> Result<(), MemSpaceError> {
+        let vpns: Vec<usize> = self.user_frames.keys().copied().collect();
+        for vpn in vpns {
+            child
+                .user_frames
+                .insert(vpn, self.user_frames[&vpn].clone());
+            // Inaccessible pages keep their frame unmapped.
+            let Some(perm) = self.page_perm(vpn) else {
+                continue;
+            };
+            if perm.contains(PageTableFlags::W) && !self.cow.contains(&vpn) {
+                self.page_table.clear(PageNum::from_const(vpn), 1)?;
+                *flush = true;
+                self.map_page(vpn, perm)?;
+            }
+            child.map_page(vpn, perm)?;
+        }
+        Ok(())
+    }
+
+    /// The end of the heap: the lowest mmap area above its start, or the top of the mmap region.
+    fn heap_limit(&self) -> usize {
+        self.areas
+            .range((self.heap_start >> PAGE_WIDTH)..)
+            .next()
+            .map_or(self.mmap_top, |(start, _)| start << PAGE_WIDTH)
+    }
+
+    /// The start vpn of the highest free range of `count` pages between the heap and the top of the mmap region.
+    fn find_free_area(&self, count: usize) -> Option<usize> {
+        let bottom = self.brk.align_up(PAGE_SIZE) >> PAGE_WIDTH;
+        let mut end = self.mmap_top >> PAGE_WIDTH;
+        for (&start, &area_end) in self.areas.iter().rev() {
+            let gap_start = area_end.max(bottom);
+            if end >= gap_start + count {
+                return Some(end - count);
+            }
+            end = end.min(start);
+            if end <= bottom {
+                return None;
+            }
+        }
+        (end >= bottom + count).then(|| end - count)
+    }
+
+    /// Whether no page of the range has a frame or belongs to an mmap area.
+    fn is_range_free(&self, vpn: usize, count: usize) -> bool {
+        let end = vpn + count;
+        self.user_frames.range(vpn..end).next().is_none()
+            && self
+                .areas
+                .range(..end)
+                .next_back()
+                .is_none_or(|(_, area_end)| *area_end <= vpn)
+    }
+
+    /// Cut `[start, end)` out of the mmap areas.
+    fn remove_areas(&mut self, start: usize, end: usize) {
+        let overlapping: Vec<(usize, usize)> = self
+            .areas
+            .range(..end)
+            .filter(|(_, area_end)| **area_end > start)
+            .map(|(area_start, area_end)| (*area_start, *area_end))
+            .collect();
+        for (area_start, area_end) in overlapping {
+            self.areas.remove(&area_start);
+            if area_start < start {
+                self.areas.insert(area_start, start);
+            }
+            if area_end > end {
+                self.areas.insert(end, area_end);
+            }
+        }
+    }
+
+    /// The frame of the user page `vpn`, allocated and zero-filled if it has none.
+    fn get_or_alloc_frame(&mut self, vpn: usize) -> Result<PageNum, MemSpaceError> {
+        if let Some(frame) = self.user_frames.get(&vpn) {
+            return Ok(frame.ppn());
+        }
+        let frame = FRAME_ALLOC
+            .alloc_managed()
+            .map_err(|error| PagingError::FrameAllocatorError { error })?;
+        unsafe { write_bytes(frame.as_ptr_mut::<u8>(), 0, PAGE_SIZE) };
+        let ppn = frame.ppn();
+        self.user_frames.insert(vpn, Arc::new(frame));
+        Ok(ppn)
+    }
+
+    /// Map the pages with exactly the permissions `perm`, or leave them unmapped if it's empty.
+    /// Return whether translations were removed or changed, and must be flushed.
+    fn protect_user(
+        &mut self,
+        vpn: usize,
+        count: usize,
+        perm: PageTableFlags,
+    ) -> Result<bool, MemSpaceError> {
+        check_user_pages(vpn, count)?;
+        let perm = perm & PageTableFlags::RWX;
+        let mut flush = false;
+        for vpn in vpn..vpn + count {
+            let page = PageNum::from_const(vpn);
+            if self.page_table.lookup(page).is_some() {
+                self.page_table.clear(page, 1)?;
+                flush = true;
+            }
+            self.cow.remove(&vpn);
+            if perm.is_empty() {
+                continue;
+            }
+            self.get_or_alloc_frame(vpn)?;
+            self.map_page(vpn, perm)?;
+        }
+        Ok(flush)
+    }
+
+    fn map_user(
+        &mut self,
+        vpn: usize,
+        count: usize,
+        perm: PageTableFlags,
+    ) -> Result<(), MemSpaceError> {
+        check_user_pages(vpn, count)?;
+        let perm = perm & PageTableFlags::RWX;
+        for vpn in vpn..vpn + count {
+            let perm = match self.page_perm(vpn) {
+                Some(old) => {
+                    if old.contains(perm) {
+                        continue;
+                    }
+                    self.page_table.clear(PageNum::from_const(vpn), 1)?;
+                    old | perm
+                }
+                None => perm,
+            };
+            self.get_or_alloc_frame(vpn)?;
+            self.map_page(vpn, perm)?;
+        }
+        Ok(())
+    }
+
+    /// Unmap the pages and return their frames, to be freed once no hart caches their translations.
+    fn unmap_user(&mut self, vpn: usize, count: usize) -> Result<Vec<Arc<Frame>>, MemSpaceError> {
+        check_user_pages(vpn, count)?;
+        let mut frames = Vec::new();
+        for vpn in vpn..vpn + count {
+            if let Some(frame) = self.user_frames.remove(&vpn) {
+                self.cow.remove(&vpn);
+                self.page_table.clear(PageNum::from_const(vpn), 1)?;
+                frames.push(frame);
+            }
+        }
+        Ok(frames)
+    }
+}
+
+/// Flags of a user page with the permissions `perm`.
+fn user_page_flags(perm: PageTableFlags) -> PageTableFlags {
+    PageTableFlags::VALID
+        | PageTableFlags::USER
+        | PageTableFlags::ACCESSED
+        | PageTableFlags::DIRTY
+        | perm
+}
+
+/// Check that `count` pages from `vpn` are in user space.
+fn check_user_pages(vpn: usize, count: usize) -> Result<(), MemSpaceError> {
+    vpn.checked_add(count)
+        .filter(|end| *end <= MAX_USPACE_ADDR >> PAGE_WIDTH)
+        .map(|_| ())
+        .ok_or(MemSpaceError::BadAddress)
+}
+
+impl Debug for MemSpace {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("MemSpace")
+            .field("asid", &self.asid)
+            .field("root_ppn", &self.root_ppn)
+            .finish()
+    }
+}
+
+impl Drop for MemSpace {
+    fn drop(&mut self) {
+        free_asid(self.asid);
     }
 }
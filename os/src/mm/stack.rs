@@ -1,13 +1,20 @@
+use alloc::{
+    alloc::{alloc_zeroed, handle_alloc_error},
+    boxed::Box,
+};
+use core::alloc::Layout;
 use utils::define_struct;
 
-use crate::{
-    mm::{
-        config::{KERNEL_STACK_PAGES, KERNEL_STACK_SIZE},
-        frame::{FRAME_ALLOC, FrameAllocatorError, FrameRange},
-    },
+use crate::mm::{
+    config::{KERNEL_STACK_PAGES, KERNEL_STACK_SIZE},
+    frame::{FRAME_ALLOC, FrameAllocatorError, FrameRange},
 };
 
 // region: KernelStack
+
+/// Pattern filling new kernel stacks, so that their usage can be measured.
+const STACK_PAINT: u64 = 0x5aa5_5aa5_5aa5_5aa5;
+
 define_struct!(copy_aligned, RawKernelStack, [u8; KERNEL_STACK_SIZE], 4096);
 impl RawKernelStack {
     pub const fn new() -> RawKernelStack {
@@ -16,6 +23,19 @@ impl RawKernelStack {
     pub fn get_stack_top(&self) -> *const u8 {
         unsafe { self.as_ptr().add(KERNEL_STACK_SIZE) }
     }
+
+    /// Allocate a stack on the heap, which is available before the frame allocator.
+    /// It's zeroed in place, since it wouldn't fit on the stack of the caller.
+    pub fn new_boxed() -> Box<RawKernelStack> {
+        let layout = Layout::new::<RawKernelStack>();
+        unsafe {
+            let ptr = alloc_zeroed(layout) as *mut RawKernelStack;
+            if ptr.is_null() {
+                handle_alloc_error(layout);
+            }
+            Box::from_raw(ptr)
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -27,7 +47,10 @@ impl KernelStack {
     /// Create a kernel stack and set the stack top
     pub fn new() -> Result<KernelStack, FrameAllocatorError> {
         let frames = FRAME_ALLOC.alloc_range_managed(KERNEL_STACK_PAGES)?;
-        let res = KernelStack { frames: frames };
+        let mut res = KernelStack { frames: frames };
+        for word in res.as_data_mut().chunks_exact_mut(8) {
+            word.copy_from_slice(&STACK_PAINT.to_ne_bytes());
+        }
         Ok(res)
     }
 
@@ -47,5 +70,16 @@ impl KernelStack {
     pub fn get_stack_top(&self) -> usize {
         self.frames.start_ppn().physical_to_kernel().get_base_addr() + KERNEL_STACK_SIZE
     }
+
+    /// Maximum number of bytes ever used, found from the bottom of the stack
+    /// as the first word overwritten since [KernelStack::new].
+    pub fn get_max_usage(&self) -> usize {
+        let untouched = self
+            .as_data_ref()
+            .chunks_exact(8)
+            .take_while(|word| *word == STACK_PAINT.to_ne_bytes())
+            .count();
+        KERNEL_STACK_SIZE - untouched * 8
+    }
 }
 // endregion
@@ -0,0 +1,255 @@
+//! User Memory Access
+//!
+//! The kernel reaches user memory only through these functions. They check that every page of the
+//! range is mapped for user mode with the required permission in the memspace of the current task,
+//! and allow supervisor access to user pages (`sstatus.SUM`) only for the copy itself.
+//! The memspace stays locked during the copy, so that no other thread unmaps the pages under it.
+
+use crate::{
+    arch::{
+        MAX_USPACE_ADDR, PAGE_WIDTH,
+        mm::{PageNum, paging::PageTableFlags},
+    },
+    mm::{config::PAGE_SIZE, space::MemSpace},
+    task::get_current_task,
+};
+use alloc::{string::String, vec, vec::Vec};
+use core::{
+    fmt::{Debug, Formatter},
+    marker::PhantomData,
+    mem::size_of,
+    ops::RangeInclusive,
+    ptr::copy_nonoverlapping,
+};
+use riscv::register::sstatus;
+
+/// Errors returned by the user memory accessors.
+#[derive(Debug)]
+pub enum UserAccessError {
+    /// The range is not in user space.
+    BadAddress,
+    /// A page of the range is not mapped for user mode with the required permission.
+    NotMapped,
+    /// The current task has no user memspace.
+    NoMemSpace,
+    /// No NUL byte in the maximum length of the string.
+    TooLong,
+    /// The string is not valid UTF-8.
+    InvalidString,
+}
+
+/// Allow supervisor access to user pages until dropped.
+struct SumGuard {
+    /// Whether the access was allowed before, by an outer guard.
+    prev: bool,
+}
+
+impl SumGuard {
+    fn new() -> SumGuard {
+        let prev = sstatus::read().sum();
+        unsafe { sstatus::set_sum() };
+        SumGuard { prev }
+    }
+}
+
+impl Drop for SumGuard {
+    fn drop(&mut self) {
+        if !self.prev {
+            unsafe { sstatus::clear_sum() };
+        }
+    }
+}
+
+/// Rounds of making copy-on-write pages writable and locking them, which a concurrent fork may undo.
+const MAX_WRITE_ATTEMPTS: usize = 3;
+
+/// Check that `[addr, addr + len)` is mapped for user mode in `memsp`, with the flags `required`.
+/// Copy-on-write pages are made writable then if `write` is set.
+fn prepare_user_range(
+    memsp: &MemSpace,
+    vpns: RangeInclusive<usize>,
+    required: PageTableFlags,
+    write: bool,
+) -> Result<(), UserAccessError> {
+    for vpn in vpns {
+        match memsp.lookup(PageNum::from_const(vpn)) {
+            Some(entry) if entry.get_flags().contains(required) => {}
+            // Copy-on-write pages get their own frame before the kernel writes to them.
+            Some(entry)
+                if write
+                    && entry.get_flags().contains(PageTableFlags::USER)
+                    && memsp.handle_write_fault(vpn << PAGE_WIDTH) => {}
+            _ => return Err(UserAccessError::NotMapped),
+        }
+    }
+    Ok(())
+}
+
+/// Run `f` with supervisor access to user pages, once `[addr, addr + len)` is known to be mapped
+/// for user mode in the current memspace, writable if `write` is set.
+///
+/// The memspace stays locked until `f` returns: another thread unmapping the range
+/// or changing its permissions meanwhile would make the access fault in the kernel.
+/// **`f` must neither block nor use the memspace.**
+fn with_user_range<R>(
+    addr: usize,
+    len: usize,
+    write: bool,
+    f: impl FnOnce() -> R,
+) -> Result<R, UserAccessError> {
+    if len == 0 {
+        return Ok(f());
+    }
+    let end = addr
+        .checked_add(len)
+        .filter(|end| *end <= MAX_USPACE_ADDR)
+        .ok_or(UserAccessError::BadAddress)?;
+    let task = get_current_task();
+    let memsp = task.memsp.as_ref().ok_or(UserAccessError::NoMemSpace)?;
+    let required = PageTableFlags::USER
+        | if write {
+            PageTableFlags::W
+        } else {
+            PageTableFlags::R
+        };
+    let vpns = (addr >> PAGE_WIDTH)..=((end - 1) >> PAGE_WIDTH);
+    let mut f = Some(f);
+    let attempts = if write { MAX_WRITE_ATTEMPTS } else { 1 };
+    for _ in 0..attempts {
+        prepare_user_range(memsp, vpns.clone(), required, write)?;
+        let res = memsp.with_user_pages(vpns.clone(), required, || {
+            let _sum = SumGuard::new();
+            f.take().map(|f| f())
+        });
+        if let Some(Some(res)) = res {
+            return Ok(res);
+        }
+    }
+    Err(UserAccessError::NotMapped)
+}
+
+pub fn copy_from_user(dst: &mut [u8], src: usize) -> Result<(), UserAccessError> {
+    with_user_range(src, dst.len(), false, || unsafe {
+        copy_nonoverlapping(src as *const u8, dst.as_mut_ptr(), dst.len())
+    })
+}
+
+pub fn copy_to_user(dst: usize, src: &[u8]) -> Result<(), UserAccessError> {
+    with_user_range(dst, src.len(), true, || unsafe {
+        copy_nonoverlapping(src.as_ptr(), dst as *mut u8, src.len())
+    })
+}
+
+/// Read a value of plain data from user address `src`, which need not be aligned.
+pub fn read_user<T: Copy>(src: usize) -> Result<T, UserAccessError> {
+    with_user_range(src, size_of::<T>(), false, || unsafe {
+        (src as *const T).read_unaligned()
+    })
+}
+
+/// Write a value of plain data to user address `dst`, which need not be aligned.
+pub fn write_user<T: Copy>(dst: usize, value: &T) -> Result<(), UserAccessError> {
+    with_user_range(dst, size_of::<T>(), true, || unsafe {
+        (dst as *mut T).write_unaligned(*value)
+    })
+}
+
+/// Read a NUL-terminated string of at most `max_len` bytes, NUL excluded, from user address `src`.
+pub fn read_user_str(src: usize, max_len: usize) -> Result<String, UserAccessError> {
+    let mut bytes = Vec::new();
+    let mut addr = src;
+    // Page by page, since the string may end right before an unmapped page.
+    loop {
+        let page_end = (addr & !(PAGE_SIZE - 1)) + PAGE_SIZE;
+        // The bytes are only gathered with the memspace locked, and checked once it's released.
+        let mut page = [0u8; PAGE_SIZE];
+        let start = addr;
+        let len = with_user_range(start, page_end - start, false, || {
+            for (i, byte) in page[..page_end - start].iter_mut().enumerate() {
+                *byte = unsafe { ((start + i) as *const u8).read() };
+                if *byte == 0 {
+                    return i;
+                }
+            }
+            page_end - start
+        })?;
+        if bytes.len() + len > max_len {
+            return Err(UserAccessError::TooLong);
+        }
+        bytes.extend_from_slice(&page[..len]);
+        if len < page_end - start {
+            return String::from_utf8(bytes).map_err(|_| UserAccessError::InvalidString);
+        }
+        addr = page_end;
+    }
+}
+
+/// A user address holding a `T`, as passed to system calls.
+/// It's only dereferenced through the checked accessors above.
+pub struct UserPtr<T> {
+    addr: usize,
+    _marker: PhantomData<fn() -> T>,
+}
+
+impl<T> Clone for UserPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for UserPtr<T> {}
+
+impl<T> Debug for UserPtr<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.write_fmt(format_args!("UserPtr({:#x})", self.addr))
+    }
+}
+
+impl<T: Copy> UserPtr<T> {
+    pub const fn new(addr: usize) -> UserPtr<T> {
+        UserPtr {
+            addr,
+            _marker: PhantomData,
+        }
+    }
+
+    pub fn addr(&self) -> usize {
+        self.addr
+    }
+
+    pub fn is_null(&self) -> bool {
+        self.addr == 0
+    }
+
+    /// The address of the `count`-th `T` after this one. Overflows are caught on access.
+    pub fn add(&self, count: usize) -> UserPtr<T> {
+        UserPtr::new(self.addr.wrapping_add(count.wrapping_mul(size_of::<T>())))
+    }
+
+    pub fn read(&self) -> Result<T, UserAccessError> {
+        read_user(self.addr)
+    }
+
+    pub fn write(&self, value: &T) -> Result<(), UserAccessError> {
+        write_user(self.addr, value)
+    }
+}
+
+impl UserPtr<u8> {
+    /// Read `len` bytes from the address.
+    pub fn read_bytes(&self, len: usize) -> Result<Vec<u8>, UserAccessError> {
+        let mut buf = vec![0; len];
+        copy_from_user(&mut buf, self.addr)?;
+        Ok(buf)
+    }
+
+    /// Write `data` at the address.
+    pub fn write_bytes(&self, data: &[u8]) -> Result<(), UserAccessError> {
+        copy_to_user(self.addr, data)
+    }
+
+    /// Read a NUL-terminated string of at most `max_len` bytes from the address.
+    pub fn read_str(&self, max_len: usize) -> Result<String, UserAccessError> {
+        read_user_str(self.addr, max_len)
+    }
+}
@@ -1,2 +1,4 @@
 pub mod spin;
 pub type SpinLock<T> = spin::SpinLock<T>;
+pub mod sleep;
+pub type SleepMutex<T> = sleep::SleepMutex<T>;
@@ -0,0 +1,222 @@
+use core::{
+    cell::UnsafeCell,
+    cmp::Reverse,
+    ops::{Deref, DerefMut},
+    ptr,
+};
+
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+
+use crate::{
+    arch::trap::intr::{disable_intr, restore_intr},
+    mutex::SpinLock,
+    task::{
+        get_current_task,
+        scheduler::{block_current, requeue, schedule, wake_up},
+        task::Task,
+    },
+};
+
+/// A mutex that blocks the waiting tasks instead of spinning.
+///
+/// It implements priority inheritance: while a task waits, the owner runs with at least
+/// the priority of the waiter, so that a low priority owner preempted by medium priority tasks
+/// cannot keep a high priority waiter waiting. The boost goes down the chain of owners
+/// blocked on other mutexes, and is recomputed from the remaining waiters once a mutex is released.
+///
+/// **It can only be locked with preemption enabled, and never in interrupt context.**
+pub struct SleepMutex<T: ?Sized> {
+    inner: SpinLock<SleepMutexInner>,
+    data: UnsafeCell<T>,
+}
+
+struct SleepMutexInner {
+    owner: Option<Arc<Task>>,
+    waiters: VecDeque<Arc<Task>>,
+}
+
+impl SleepMutexInner {
+    /// Highest effective priority of the waiters, 0 if none.
+    fn waiter_priority(&self) -> usize {
+        self.waiters
+            .iter()
+            .map(|waiter| waiter.sched.get_priority())
+            .max()
+            .unwrap_or(0)
+    }
+}
+
+/// Serializes priority inheritance: the owners and waiters of every mutex, and the [PiState] of every task,
+/// so that boosts can be walked along chains of owners. Locked before the mutexes.
+static PI_LOCK: SpinLock<()> = SpinLock::new(());
+
+/// Bound on the chains of owners walked, in case of a deadlock between them.
+const MAX_CHAIN_DEPTH: usize = 64;
+
+/// Mutexes a task holds, and the one it waits for, kept in its scheduling entity.
+/// **Only used with [PI_LOCK] held.**
+#[derive(Debug)]
+pub struct PiState {
+    held: Vec<*const SpinLock<SleepMutexInner>>,
+    blocked_on: Option<*const SpinLock<SleepMutexInner>>,
+}
+
+/// A task holds or waits for a mutex only while borrowing it, so the pointers are valid.
+unsafe impl Send for PiState {}
+
+impl PiState {
+    pub const fn new() -> PiState {
+        PiState {
+            held: Vec::new(),
+            blocked_on: None,
+        }
+    }
+}
+
+/// Recompute the inherited priority of `task` from the waiters of the mutexes it holds, and go on
+/// with the owner of the mutex it waits for as long as priorities change. **Hold [PI_LOCK].**
+fn update_chain(mut task: Arc<Task>) {
+    for _ in 0..MAX_CHAIN_DEPTH {
+        let pi = task.sched.pi.lock();
+        let priority = pi
+            .held
+            .iter()
+            .map(|inner| unsafe { &**inner }.lock().waiter_priority())
+            .max()
+            .unwrap_or(0);
+        let blocked_on = pi.blocked_on;
+        drop(pi);
+        if !task.sched.set_inherited_priority(priority) {
+            return;
+        }
+        requeue(&task);
+        let Some(owner) = blocked_on.and_then(|inner| unsafe { &*inner }.lock().owner.clone())
+        else {
+            return;
+        };
+        task = owner;
+    }
+}
+
+unsafe impl<T: ?Sized + Send> Send for SleepMutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for SleepMutex<T> {}
+
+impl<T> SleepMutex<T> {
+    pub const fn new(value: T) -> SleepMutex<T> {
+        SleepMutex {
+            inner: SpinLock::new(SleepMutexInner {
+                owner: None,
+                waiters: VecDeque::new(),
+            }),
+            data: UnsafeCell::new(value),
+        }
+    }
+}
+
+impl<T: ?Sized> SleepMutex<T> {
+    pub fn lock(&self) -> SleepMutexGuard<'_, T> {
+        let task = get_current_task();
+        let this = &self.inner as *const SpinLock<SleepMutexInner>;
+        loop {
+            // Interrupts stay disabled until the task is switched out,
+            // so that the unlocker always finds it blocked.
+            let intr = disable_intr();
+            let pi = PI_LOCK.lock();
+            task.sched.pi.lock().blocked_on = None;
+            let mut inner = self.inner.lock();
+            // A waiter woken by a kill or a signal is still queued.
+            if let Some(idx) = inner
+                .waiters
+                .iter()
+                .position(|waiter| Arc::ptr_eq(waiter, &task))
+            {
+                inner.waiters.remove(idx);
+            }
+            match inner.owner.clone() {
+                None => {
+                    inner.owner = Some(task.clone());
+                    drop(inner);
+                    task.sched.pi.lock().held.push(this);
+                    // Inherit from the tasks which were already waiting.
+                    update_chain(task.clone());
+                    drop(pi);
+                    restore_intr(intr);
+                    return SleepMutexGuard { mutex: self };
+                }
+                Some(owner) => {
+                    inner.waiters.push_back(task.clone());
+                    drop(inner);
+                    task.sched.pi.lock().blocked_on = Some(this);
+                    update_chain(owner);
+                    block_current(move |_| drop(pi));
+                    restore_intr(intr);
+                }
+            }
+        }
+    }
+
+    /// Release the mutex and wake up the waiter with the highest priority.
+    /// The owner gives up the priority it inherited from the waiters of this mutex,
+    /// and yields at once if that lowered its priority, rather than at the next tick.
+    fn unlock(&self) {
+        let intr = disable_intr();
+        let pi = PI_LOCK.lock();
+        let mut inner = self.inner.lock();
+        let owner = inner.owner.take();
+        let next = inner
+            .waiters
+            .iter()
+            .enumerate()
+            .max_by_key(|(idx, waiter)| (waiter.sched.get_priority(), Reverse(*idx)))
+            .map(|(idx, _)| idx);
+        let next = next.and_then(|idx| inner.waiters.remove(idx));
+        drop(inner);
+        let mut deboosted = false;
+        if let Some(owner) = owner {
+            let this = &self.inner as *const SpinLock<SleepMutexInner>;
+            owner
+                .sched
+                .pi
+                .lock()
+                .held
+                .retain(|inner| !ptr::eq(*inner, this));
+            let old = owner.sched.get_priority();
+            update_chain(owner.clone());
+            deboosted = owner.sched.get_priority() < old;
+        }
+        if let Some(next) = &next {
+            next.sched.pi.lock().blocked_on = None;
+        }
+        drop(pi);
+        if let Some(next) = next {
+            wake_up(next);
+        }
+        if deboosted {
+            schedule();
+        }
+        restore_intr(intr);
+    }
+}
+
+pub struct SleepMutexGuard<'a, T: ?Sized> {
+    mutex: &'a SleepMutex<T>,
+}
+
+impl<T: ?Sized> Drop for SleepMutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+impl<T: ?Sized> Deref for SleepMutexGuard<'_, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for SleepMutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
@@ -1,8 +1,14 @@
-use core::ops::{Deref, DerefMut};
+use core::{
+    fmt::{Debug, Formatter},
+    ops::{Deref, DerefMut},
+};
 
 use spin::{MutexGuard, Spin, mutex::Mutex};
 
-use crate::task::preempt::{disable_preempt, restore_preempt};
+use crate::{
+    arch::trap::intr::{disable_intr, restore_intr},
+    task::preempt::{disable_preempt, restore_preempt},
+};
 
 pub struct SpinLock<T: ?Sized> {
     inner: Mutex<T, Spin>,
@@ -15,6 +21,15 @@ impl<T: ?Sized> SpinLock<T> {
     pub fn lock_no_preempt(&self) -> NoPreemptSpinLockGuard<'_, T> {
         NoPreemptSpinLockGuard::new(&self.inner)
     }
+    /// Lock with local interrupts disabled.
+    /// Use it for data that is also touched by interrupt handlers on the same hart.
+    pub fn lock_no_irq(&self) -> NoIrqSpinLockGuard<'_, T> {
+        NoIrqSpinLockGuard::new(&self.inner)
+    }
+    /// Like [SpinLock::lock_no_irq], but give up instead of spinning if the lock is held.
+    pub fn try_lock_no_irq(&self) -> Option<NoIrqSpinLockGuard<'_, T>> {
+        NoIrqSpinLockGuard::try_new(&self.inner)
+    }
 }
 
 impl<T> SpinLock<T> {
@@ -25,6 +40,13 @@ impl<T> SpinLock<T> {
     }
 }
 
+/// The value is only shown if the lock is free.
+impl<T: ?Sized + Debug> Debug for SpinLock<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
 // region: NoPreemptGuard
 
 pub struct NoPreemptSpinLockGuard<'a, T: ?Sized> {
@@ -59,3 +81,53 @@ impl<'a, T> DerefMut for NoPreemptSpinLockGuard<'a, T> {
 }
 
 // endregion
+
+// region: NoIrqGuard
+
+pub struct NoIrqSpinLockGuard<'a, T: ?Sized> {
+    inner: Option<MutexGuard<'a, T>>,
+    intr: bool,
+}
+
+impl<T: ?Sized> NoIrqSpinLockGuard<'_, T> {
+    pub fn new(mutex: &Mutex<T>) -> NoIrqSpinLockGuard<'_, T> {
+        let intr = disable_intr();
+        NoIrqSpinLockGuard {
+            inner: Some(mutex.lock()),
+            intr,
+        }
+    }
+    pub fn try_new(mutex: &Mutex<T>) -> Option<NoIrqSpinLockGuard<'_, T>> {
+        let intr = disable_intr();
+        match mutex.try_lock() {
+            Some(guard) => Some(NoIrqSpinLockGuard {
+                inner: Some(guard),
+                intr,
+            }),
+            None => {
+                restore_intr(intr);
+                None
+            }
+        }
+    }
+}
+impl<T: ?Sized> Drop for NoIrqSpinLockGuard<'_, T> {
+    fn drop(&mut self) {
+        self.inner = None; // drop first
+        restore_intr(self.intr);
+    }
+}
+impl<'a, T> Deref for NoIrqSpinLockGuard<'a, T> {
+    type Target = T;
+    fn deref(&self) -> &Self::Target {
+        self.inner.as_ref().unwrap().deref()
+    }
+}
+
+impl<'a, T> DerefMut for NoIrqSpinLockGuard<'a, T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.inner.as_mut().unwrap().deref_mut()
+    }
+}
+
+// endregion
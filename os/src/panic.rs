@@ -1,12 +1,30 @@
-use crate::arch::hart::get_current_hart_id;
-use core::panic::PanicInfo;
-use log::error;
+use crate::{
+    arch::hart::get_current_hart_id,
+    smp::{halt, stop_others},
+    task::registry::dump_tasks,
+};
+use core::{
+    panic::PanicInfo,
+    sync::atomic::{AtomicBool, Ordering},
+};
+use log::{Level, error};
+
+static PANICKED: AtomicBool = AtomicBool::new(false);
 
 #[panic_handler]
 pub fn panic_handler(pinfo: &PanicInfo) -> ! {
+    // Stop the other harts once, even if they panic at the same time or the report panics again.
+    let first = !PANICKED.swap(true, Ordering::AcqRel);
+    if first {
+        stop_others();
+    }
     error!("{:}", pinfo);
     error!("Panic on hart #{:}.", get_current_hart_id());
-    loop {}
+    // Only for the first panic, in case the dump itself panics.
+    if first {
+        dump_tasks(Level::Error);
+    }
+    halt()
 }
 
 #[macro_export]
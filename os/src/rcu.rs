@@ -0,0 +1,216 @@
+//! Read-Copy-Update
+//!
+//! Readers of RCU-protected data take no lock: they only disable preemption with [rcu_read_lock].
+//! Writers publish a new version of the data, and free the old one once every reader which may still
+//! see it is gone, i.e. after a grace period: every hart has passed through a quiescent state.
+//!
+//! Quiescent states are reported by the scheduler: a hart switching tasks, or interrupted by its tick
+//! with preemption enabled, is outside any read-side critical section. Idle harts, and harts which
+//! don't run tasks yet, hold no reader.
+//!
+//! [synchronize_rcu] blocks until a grace period elapses; [call_rcu] defers a callback past it.
+//! [RcuCell] is a pointer whose value is replaced with copy-on-write updates.
+
+use crate::{
+    arch::hart::get_current_hart_id,
+    defer::workqueue::{Work, schedule_work},
+    dev::{get_current_hart, get_working_hart_ids},
+    mutex::SpinLock,
+    percpu,
+    task::{
+        preempt::{disable_preempt, restore_preempt},
+        scheduler::{is_hart_idle, is_scheduler_running, is_scheduler_running_on},
+        wait_queue::WaitQueue,
+    },
+};
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::{
+    hint::spin_loop,
+    marker::PhantomData,
+    ptr::null_mut,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+use spin::Once;
+
+/// Number of the latest grace period started.
+static GP_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+percpu! {
+    /// Value of [GP_SEQ] at the last quiescent state of the hart.
+    static QS_SEQ: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Tasks blocked in [synchronize_rcu].
+static GP_WAITERS: WaitQueue = WaitQueue::new();
+
+/// Report a quiescent state of hart `hart_id`. Called by the scheduler.
+///
+/// The waiters are woken the first time the hart reports one after a grace period started,
+/// i.e. at most once per hart and grace period.
+pub fn note_quiescent_state(hart_id: usize) {
+    let seq = GP_SEQ.load(Ordering::SeqCst);
+    if QS_SEQ.remote(hart_id).swap(seq, Ordering::SeqCst) != seq {
+        GP_WAITERS.wake_all();
+    }
+}
+
+/// Whether every hart has passed through a quiescent state since grace period `target` started.
+fn gp_completed(target: usize) -> bool {
+    let cur_hart = get_current_hart_id();
+    get_working_hart_ids().into_iter().all(|hart_id| {
+        // The caller is outside any read-side critical section, and so is its hart.
+        hart_id == cur_hart
+            || QS_SEQ.remote(hart_id).load(Ordering::SeqCst) >= target
+            || is_hart_idle(hart_id)
+            || !is_scheduler_running_on(hart_id)
+    })
+}
+
+// region: Readers
+
+/// A read-side critical section, entered with [rcu_read_lock] and left when dropped.
+/// The task must not block inside.
+pub struct RcuReadGuard {
+    _not_send: PhantomData<*const ()>,
+}
+
+pub fn rcu_read_lock() -> RcuReadGuard {
+    disable_preempt();
+    RcuReadGuard {
+        _not_send: PhantomData,
+    }
+}
+
+impl Drop for RcuReadGuard {
+    fn drop(&mut self) {
+        restore_preempt();
+    }
+}
+
+// endregion
+
+// region: Grace Periods
+
+/// Block until every read-side critical section running at the call has been left.
+///
+/// **Preemption must be enabled**, which also means the caller is not a reader.
+/// Before the hart runs tasks, it spins instead of blocking.
+pub fn synchronize_rcu() {
+    debug_assert!(
+        get_current_hart().preempt.is_preempt_allowed(),
+        "Trying to wait for a grace period with preemption disabled."
+    );
+    let target = GP_SEQ.fetch_add(1, Ordering::SeqCst) + 1;
+    if is_scheduler_running() {
+        GP_WAITERS.wait_until(|| gp_completed(target));
+    } else {
+        while !gp_completed(target) {
+            spin_loop();
+        }
+    }
+}
+
+type RcuCallback = Box<dyn FnOnce() + Send>;
+
+/// Callbacks waiting for a grace period.
+static CALLBACKS: SpinLock<Vec<RcuCallback>> = SpinLock::new(Vec::new());
+
+/// The item of the system workqueue running the callbacks.
+static CALLBACK_WORK: Once<Arc<Work>> = Once::new();
+
+/// Run `callback` after a grace period, in a worker of the system workqueue.
+///
+/// It never blocks, and can be called from interrupt context.
+pub fn call_rcu(callback: impl FnOnce() + Send + 'static) {
+    CALLBACKS.lock_no_irq().push(Box::new(callback));
+    schedule_work(CALLBACK_WORK.call_once(|| Work::new(run_callbacks)));
+}
+
+fn run_callbacks() {
+    loop {
+        let callbacks = core::mem::take(&mut *CALLBACKS.lock_no_irq());
+        if callbacks.is_empty() {
+            break;
+        }
+        // The grace period starts after the callbacks were queued, so it covers all of them.
+        synchronize_rcu();
+        for callback in callbacks {
+            callback();
+        }
+    }
+}
+
+// endregion
+
+// region: RcuCell
+
+/// A pointer to RCU-protected data.
+///
+/// Readers borrow the current value for their critical section.
+/// Writers are serialized; they publish a new value, and the old one is freed after a grace period.
+pub struct RcuCell<T: Send + Sync + 'static> {
+    ptr: AtomicPtr<T>,
+    writer: SpinLock<()>,
+}
+
+impl<T: Send + Sync + 'static> RcuCell<T> {
+    pub fn new(value: T) -> RcuCell<T> {
+        RcuCell {
+            ptr: AtomicPtr::new(Box::into_raw(Box::new(value))),
+            writer: SpinLock::new(()),
+        }
+    }
+
+    /// The current value. It stays valid until `guard` is dropped.
+    pub fn read<'a>(&'a self, _guard: &'a RcuReadGuard) -> &'a T {
+        unsafe { &*self.ptr.load(Ordering::Acquire) }
+    }
+
+    /// Publish `value` and free the old value after a grace period.
+    pub fn replace(&self, value: T) {
+        let _writer = self.writer.lock_no_irq();
+        self.publish(value);
+    }
+
+    /// Publish a copy of the current value modified by `f`, and free the old value after a grace period.
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R
+    where
+        T: Clone,
+    {
+        let _writer = self.writer.lock_no_irq();
+        // Writers are serialized, so the value can't be freed under us.
+        let mut value = unsafe { &*self.ptr.load(Ordering::Acquire) }.clone();
+        let res = f(&mut value);
+        self.publish(value);
+        res
+    }
+
+    /// Like [RcuCell::update], but the copy is only published if `f` succeeds.
+    pub fn try_update<R, E>(&self, f: impl FnOnce(&mut T) -> Result<R, E>) -> Result<R, E>
+    where
+        T: Clone,
+    {
+        let _writer = self.writer.lock_no_irq();
+        let mut value = unsafe { &*self.ptr.load(Ordering::Acquire) }.clone();
+        let res = f(&mut value)?;
+        self.publish(value);
+        Ok(res)
+    }
+
+    fn publish(&self, value: T) {
+        let old = self
+            .ptr
+            .swap(Box::into_raw(Box::new(value)), Ordering::AcqRel) as usize;
+        call_rcu(move || drop(unsafe { Box::from_raw(old as *mut T) }));
+    }
+}
+
+impl<T: Send + Sync + 'static> Drop for RcuCell<T> {
+    fn drop(&mut self) {
+        // Readers may still use it.
+        let ptr = self.ptr.swap(null_mut(), Ordering::AcqRel) as usize;
+        call_rcu(move || drop(unsafe { Box::from_raw(ptr as *mut T) }));
+    }
+}
+
+// endregion
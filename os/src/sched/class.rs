@@ -0,0 +1,110 @@
+//! Scheduling classes.
+//!
+//! [ClassScheduler] consults the classes in order: the real-time class first, then the normal class.
+//! A task belongs to the real-time class while its effective priority is not 0,
+//! so a task boosted by priority inheritance moves there until the boost is dropped.
+
+use crate::{
+    sched::{
+        Scheduler,
+        rt::{RR_TIMESLICE_NS, RtScheduler},
+    },
+    task::task::Task,
+};
+use alloc::sync::Arc;
+
+pub struct ClassScheduler<N: Scheduler> {
+    rt: RtScheduler,
+    normal: N,
+}
+
+impl<N: Scheduler> ClassScheduler<N> {
+    pub fn new(normal: N) -> ClassScheduler<N> {
+        ClassScheduler {
+            rt: RtScheduler::new(),
+            normal,
+        }
+    }
+
+    /// The highest real-time priority ready on this hart. 0 if none.
+    pub fn highest_priority(&self) -> usize {
+        self.rt.highest_priority()
+    }
+}
+
+impl<N: Scheduler> Scheduler for ClassScheduler<N> {
+    fn add_to_ready(&mut self, task: Arc<Task>) {
+        if task.sched.is_rt() {
+            self.rt.enqueue(task, false);
+        } else {
+            self.normal.add_to_ready(task);
+        }
+    }
+
+    /// Pick the next task from the first class which has one.
+    fn fetch_new(&mut self) -> Arc<Task> {
+        match self.rt.pick() {
+            Some(task) => task,
+            None => self.normal.fetch_new(),
+        }
+    }
+
+    fn remove(&mut self, task: &Arc<Task>) -> bool {
+        // Look into both classes, the task may have changed its class while queued.
+        self.rt.remove(task) || self.normal.remove(task)
+    }
+
+    fn nr_ready(&self) -> usize {
+        self.rt.nr_ready() + self.normal.nr_ready()
+    }
+
+    fn detach(&mut self, task: &Arc<Task>) -> bool {
+        self.rt.remove(task) || self.normal.detach(task)
+    }
+
+    fn steal(&mut self, hart_id: usize) -> Option<Arc<Task>> {
+        // Real-time tasks first, they suffer the most from waiting.
+        match self.rt.steal(hart_id) {
+            Some(task) => Some(task),
+            None => self.normal.steal(hart_id),
+        }
+    }
+
+    fn attach(&mut self, task: Arc<Task>) {
+        if task.sched.is_rt() {
+            self.rt.enqueue(task, false);
+        } else {
+            self.normal.attach(task);
+        }
+    }
+
+    fn wake_up(&mut self, task: Arc<Task>) {
+        if task.sched.is_rt() {
+            self.rt.enqueue(task, false);
+        } else {
+            self.normal.wake_up(task);
+        }
+    }
+
+    fn put_prev(&mut self, task: Arc<Task>, runtime_ns: usize, blocked: bool) {
+        if !task.sched.is_rt() {
+            self.normal.put_prev(task, runtime_ns, blocked);
+        } else if !blocked {
+            // Only a round-robin task which used up its slice goes behind its peers.
+            let expired = task.sched.is_round_robin() && runtime_ns >= RR_TIMESLICE_NS;
+            self.rt.enqueue(task, !expired);
+        }
+    }
+
+    fn need_resched(&self, current: &Arc<Task>, runtime_ns: usize) -> bool {
+        let priority = current.sched.get_priority();
+        let highest = self.rt.highest_priority();
+        if priority == 0 {
+            return highest > 0 || self.normal.need_resched(current, runtime_ns);
+        }
+        highest > priority
+            || (highest == priority
+                && current.sched.is_round_robin()
+                && runtime_ns >= RR_TIMESLICE_NS)
+    }
+}
@@ -0,0 +1,169 @@
+//! Fair-share scheduler.
+//!
+//! Every task accumulates a virtual runtime: its real runtime scaled by `NICE_0_WEIGHT / weight`,
+//! where the weight comes from its nice value. The task with the smallest virtual runtime runs next,
+//! so CPU time is shared in proportion to the weights.
+//!
+//! Virtual runtimes are only comparable within a run queue. A task leaving the run queue
+//! (blocking or being stolen) keeps its lag to the queue's `min_vruntime`,
+//! and is placed relative to the `min_vruntime` of the queue it joins. New tasks join with no lag.
+
+use crate::{
+    sched::{MIN_NICE, Scheduler, idle::get_idle_task},
+    task::task::Task,
+};
+use alloc::{collections::btree_map::BTreeMap, sync::Arc};
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+/// Weight of a task with nice value 0.
+pub const NICE_0_WEIGHT: usize = 1024;
+
+/// Weights of nice values -20..=19. Each step changes the CPU share by about 10%.
+const NICE_TO_WEIGHT: [usize; 40] = [
+    88761, 71755, 56483, 46273, 36291, // -20 .. -16
+    29154, 23254, 18705, 14949, 11916, // -15 .. -11
+    9548, 7620, 6100, 4904, 3906, // -10 .. -6
+    3121, 2501, 1991, 1586, 1277, // -5 .. -1
+    1024, 820, 655, 526, 423, // 0 .. 4
+    335, 272, 215, 172, 137, // 5 .. 9
+    110, 87, 70, 56, 45, // 10 .. 14
+    36, 29, 23, 18, 15, // 15 .. 19
+];
+
+/// Period in which every runnable task should get to run once.
+/// Woken up tasks are credited at most half of it.
+pub const SCHED_LATENCY_NS: usize = 6_000_000;
+
+static MIN_GRANULARITY_NS: AtomicUsize = AtomicUsize::new(750_000);
+
+/// Set the minimum time a task runs before the tick can preempt it.
+pub fn set_min_granularity(ns: usize) {
+    MIN_GRANULARITY_NS.store(ns, Ordering::Relaxed);
+}
+
+pub fn get_min_granularity() -> usize {
+    MIN_GRANULARITY_NS.load(Ordering::Relaxed)
+}
+
+fn get_weight(task: &Task) -> usize {
+    NICE_TO_WEIGHT[(task.sched.get_nice() - MIN_NICE) as usize]
+}
+
+/// Scale real runtime to virtual runtime by the weight of `task`.
+fn calc_vruntime(task: &Task, runtime_ns: usize) -> isize {
+    (runtime_ns as u128 * NICE_0_WEIGHT as u128 / get_weight(task) as u128) as isize
+}
+
+pub struct FairScheduler {
+    hart_id: usize,
+    /// Ready tasks ordered by `(vruntime, tid)`.
+    tree: BTreeMap<(isize, usize), Arc<Task>>,
+    /// Monotonic lower bound of the virtual runtimes in the queue.
+    min_vruntime: isize,
+}
+
+impl FairScheduler {
+    pub fn new(hart_id: usize) -> FairScheduler {
+        FairScheduler {
+            hart_id,
+            tree: BTreeMap::new(),
+            min_vruntime: 0,
+        }
+    }
+
+    /// Turn the virtual runtime of a task leaving the queue into its lag to `min_vruntime`.
+    fn vruntime_to_lag(&self, task: &Task) {
+        task.sched
+            .set_vruntime(task.sched.get_vruntime() - self.min_vruntime);
+    }
+
+    /// Turn the lag of a task joining the queue back into a virtual runtime.
+    fn lag_to_vruntime(&self, task: &Task) {
+        task.sched
+            .set_vruntime(task.sched.get_vruntime() + self.min_vruntime);
+    }
+}
+
+impl Scheduler for FairScheduler {
+    fn add_to_ready(&mut self, task: Arc<Task>) {
+        self.tree
+            .insert((task.sched.get_vruntime(), task.get_tid()), task);
+    }
+
+    fn fetch_new(&mut self) -> Arc<Task> {
+        match self.tree.pop_first() {
+            Some(((vruntime, _), task)) => {
+                self.min_vruntime = self.min_vruntime.max(vruntime);
+                task
+            }
+            None => get_idle_task(self.hart_id).clone(),
+        }
+    }
+
+    fn remove(&mut self, task: &Arc<Task>) -> bool {
+        let key = (task.sched.get_vruntime(), task.get_tid());
+        match self.tree.get(&key) {
+            Some(queued) if Arc::ptr_eq(queued, task) => self.tree.remove(&key).is_some(),
+            _ => false,
+        }
+    }
+
+    fn nr_ready(&self) -> usize {
+        self.tree.len()
+    }
+
+    fn detach(&mut self, task: &Arc<Task>) -> bool {
+        if !self.remove(task) {
+            return false;
+        }
+        self.vruntime_to_lag(task);
+        true
+    }
+
+    fn steal(&mut self, hart_id: usize) -> Option<Arc<Task>> {
+        // The task with the largest virtual runtime is the last to run here.
+        let key = self
+            .tree
+            .iter()
+            .rev()
+            .find(|(_, task)| task.sched.is_allowed(hart_id))
+            .map(|(key, _)| *key)?;
+        let task = self.tree.remove(&key)?;
+        self.vruntime_to_lag(&task);
+        Some(task)
+    }
+
+    fn attach(&mut self, task: Arc<Task>) {
+        self.lag_to_vruntime(&task);
+        self.add_to_ready(task);
+    }
+
+    fn wake_up(&mut self, task: Arc<Task>) {
+        self.lag_to_vruntime(&task);
+        // Sleeper fairness: a task that slept for long gets ahead of the queue, but only by a bounded credit.
+        let floor = self.min_vruntime - (SCHED_LATENCY_NS / 2) as isize;
+        task.sched
+            .set_vruntime(task.sched.get_vruntime().max(floor));
+        self.add_to_ready(task);
+    }
+
+    fn put_prev(&mut self, task: Arc<Task>, runtime_ns: usize, blocked: bool) {
+        task.sched
+            .set_vruntime(task.sched.get_vruntime() + calc_vruntime(&task, runtime_ns));
+        if blocked {
+            self.vruntime_to_lag(&task);
+        } else {
+            self.add_to_ready(task);
+        }
+    }
+
+    fn need_resched(&self, current: &Arc<Task>, runtime_ns: usize) -> bool {
+        if runtime_ns < get_min_granularity() {
+            return false;
+        }
+        let vruntime = current.sched.get_vruntime() + calc_vruntime(current, runtime_ns);
+        self.tree
+            .first_key_value()
+            .is_some_and(|((next, _), _)| *next < vruntime)
+    }
+}
@@ -1,21 +1,17 @@
-use core::mem::swap;
-
 use crate::{
-    sched::{Scheduler, idle::IDLE_TASKS},
+    sched::{Scheduler, idle::get_idle_task},
     task::task::Task,
 };
 use alloc::{collections::vec_deque::VecDeque, sync::Arc};
 
 pub struct FifoScheduler {
     hart_id: usize,
-    running: Option<Arc<Task>>,
     queue: VecDeque<Arc<Task>>,
 }
 impl FifoScheduler {
     pub fn new(hart_id: usize) -> FifoScheduler {
         FifoScheduler {
             hart_id,
-            running: None,
             queue: VecDeque::new(),
         }
     }
@@ -26,18 +22,33 @@ impl Scheduler for FifoScheduler {
     }
 
     fn fetch_new(&mut self) -> Arc<Task> {
-        // Add
-        let mut last_running: Option<Arc<Task>> = None;
-        swap(&mut last_running, &mut self.running);
-        if let Some(task) = last_running {
-            self.add_to_ready(task);
-        }
-        // Fetch
-        let res = self.queue.pop_front();
-        self.running = res.clone();
-        match res {
+        match self.queue.pop_front() {
             Some(task) => task,
-            None => IDLE_TASKS[self.hart_id].clone(),
+            None => get_idle_task(self.hart_id).clone(),
+        }
+    }
+
+    fn remove(&mut self, task: &Arc<Task>) -> bool {
+        match self
+            .queue
+            .iter()
+            .position(|queued| Arc::ptr_eq(queued, task))
+        {
+            Some(idx) => self.queue.remove(idx).is_some(),
+            None => false,
         }
     }
+
+    fn nr_ready(&self) -> usize {
+        self.queue.len()
+    }
+
+    fn steal(&mut self, hart_id: usize) -> Option<Arc<Task>> {
+        // The back of the queue is the last to run here.
+        let idx = self
+            .queue
+            .iter()
+            .rposition(|task| task.sched.is_allowed(hart_id))?;
+        self.queue.remove(idx)
+    }
 }
@@ -1,10 +1,6 @@
-use crate::{
-    arch::{MAX_HARTS, trap::intr::wait_for_intr},
-    task::task::Task,
-};
+use crate::{arch::trap::intr::wait_for_intr, percpu, task::task::Task};
 use alloc::sync::Arc;
-use core::array;
-use lazy_static::lazy_static;
+use spin::Once;
 
 pub fn idle_main() -> ! {
     loop {
@@ -13,14 +9,30 @@ pub fn idle_main() -> ! {
 }
 
 pub fn create_idle_task(hart_id: usize) -> Arc<Task> {
-    Task::new_kernel_from_entry(idle_main as *const (), hart_id).unwrap_or_else(|err| {
-        panic!(
-            "Could not create idle tasks for scheduler #{:}:{:?}",
-            hart_id, err
-        )
-    })
+    let task = Task::new_kernel_from_entry("idle", idle_main as *const (), hart_id).unwrap_or_else(
+        |err| {
+            panic!(
+                "Could not create idle tasks for scheduler #{:}:{:?}",
+                hart_id, err
+            )
+        },
+    );
+    task.sched.pin(hart_id);
+    task
 }
 
-lazy_static! {
-    pub static ref IDLE_TASKS: [Arc<Task>; MAX_HARTS] = array::from_fn(|idx| create_idle_task(idx));
+/// Whether `task` is the idle task of hart `hart_id`.
+pub fn is_idle_task(task: &Arc<Task>, hart_id: usize) -> bool {
+    Arc::ptr_eq(task, get_idle_task(hart_id))
+}
+
+percpu! {
+    static IDLE_TASK: Once<Arc<Task>> = Once::new();
+}
+
+/// The idle task of hart `hart_id`, created on first use.
+pub fn get_idle_task(hart_id: usize) -> &'static Arc<Task> {
+    IDLE_TASK
+        .remote(hart_id)
+        .call_once(|| create_idle_task(hart_id))
 }
@@ -1,12 +1,214 @@
-use crate::task::task::Task;
+use crate::{
+    arch::MAX_HARTS,
+    mutex::{SpinLock, sleep::PiState},
+    task::task::Task,
+};
 use alloc::sync::Arc;
+use core::sync::atomic::{AtomicBool, AtomicIsize, AtomicUsize, Ordering};
+use num_enum::FromPrimitive;
 
 pub mod idle;
 
+pub mod class;
+pub type DefaultScheduler = class::ClassScheduler<NormalScheduler>;
+
 mod fifo;
-pub type DefaultScheduler = fifo::FifoScheduler;
+#[cfg(not(feature = "sched-fair"))]
+pub type NormalScheduler = fifo::FifoScheduler;
+
+pub mod fair;
+#[cfg(feature = "sched-fair")]
+pub type NormalScheduler = fair::FairScheduler;
+
+pub mod rt;
+
+pub const MIN_NICE: isize = -20;
+pub const MAX_NICE: isize = 19;
+
+/// Affinity mask allowing all the harts.
+pub const ALL_HARTS: usize = (1 << MAX_HARTS) - 1;
+
+/// Highest real-time priority. Real-time priorities range from 1 to it, and a larger one runs first.
+pub const MAX_RT_PRIORITY: usize = 99;
+
+/// Scheduling class of a task.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, FromPrimitive)]
+#[repr(usize)]
+pub enum SchedPolicy {
+    /// Time-shared by [NormalScheduler]; always preempted by real-time tasks.
+    #[default]
+    Normal = 0,
+    /// Real-time, runs until it blocks, yields or a higher priority task is ready.
+    Fifo = 1,
+    /// Real-time like [SchedPolicy::Fifo], but shares a time slice with tasks of the same priority.
+    RoundRobin = 2,
+}
+
+/// Scheduling state of a task. It travels with the task between the run queues of harts.
+#[derive(Debug)]
+pub struct SchedEntity {
+    nice: AtomicIsize,
+    /// Virtual runtime in nanoseconds, used by [fair::FairScheduler].
+    vruntime: AtomicIsize,
+    /// [SchedPolicy] and real-time priority, packed as `policy << 8 | priority` to be updated together.
+    policy: AtomicUsize,
+    /// Real-time priority inherited from the waiters of the locks the task holds. 0 if none.
+    inherited: AtomicUsize,
+    /// Priority inheriting locks the task holds, and the one it waits for.
+    pub pi: SpinLock<PiState>,
+    /// Harts the task may run on, one bit per hart.
+    affinity: AtomicUsize,
+    /// Whether the task is bound to a hart for good, see [SchedEntity::pin].
+    pinned: AtomicBool,
+    /// Hart the task runs or last ran on.
+    hart: AtomicUsize,
+}
+
+impl SchedEntity {
+    pub const fn new() -> SchedEntity {
+        SchedEntity {
+            nice: AtomicIsize::new(0),
+            vruntime: AtomicIsize::new(0),
+            policy: AtomicUsize::new(0),
+            inherited: AtomicUsize::new(0),
+            pi: SpinLock::new(PiState::new()),
+            affinity: AtomicUsize::new(ALL_HARTS),
+            pinned: AtomicBool::new(false),
+            hart: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get_nice(&self) -> isize {
+        self.nice.load(Ordering::Relaxed)
+    }
+
+    /// Set the nice value, clamped to [MIN_NICE]..=[MAX_NICE]. It takes effect from the next accounting.
+    pub fn set_nice(&self, nice: isize) {
+        self.nice
+            .store(nice.clamp(MIN_NICE, MAX_NICE), Ordering::Relaxed);
+    }
+
+    pub fn get_vruntime(&self) -> isize {
+        self.vruntime.load(Ordering::Relaxed)
+    }
+
+    pub fn set_vruntime(&self, vruntime: isize) {
+        self.vruntime.store(vruntime, Ordering::Relaxed);
+    }
+
+    pub fn get_policy(&self) -> (SchedPolicy, usize) {
+        let packed = self.policy.load(Ordering::Relaxed);
+        (SchedPolicy::from_primitive(packed >> 8), packed & 0xff)
+    }
+
+    /// Set the policy and real-time priority. The priority is clamped to 1..=[MAX_RT_PRIORITY]
+    /// for real-time policies, and ignored for [SchedPolicy::Normal].
+    ///
+    /// **Use [crate::task::scheduler::set_priority] on tasks which may be queued**,
+    /// so that they are moved to the right run queue.
+    pub fn set_policy(&self, policy: SchedPolicy, priority: usize) {
+        let priority = match policy {
+            SchedPolicy::Normal => 0,
+            _ => priority.clamp(1, MAX_RT_PRIORITY),
+        };
+        self.policy
+            .store((policy as usize) << 8 | priority, Ordering::Relaxed);
+    }
+
+    /// Effective real-time priority, including the inherited one. 0 for normal tasks.
+    pub fn get_priority(&self) -> usize {
+        let (_, priority) = self.get_policy();
+        priority.max(self.inherited.load(Ordering::Relaxed))
+    }
+
+    /// Whether the task is scheduled by the real-time class, by its policy or by inheritance.
+    pub fn is_rt(&self) -> bool {
+        self.get_priority() > 0
+    }
+
+    /// Whether the task round-robins with the tasks of the same priority.
+    /// A task boosted by inheritance runs as [SchedPolicy::Fifo].
+    pub fn is_round_robin(&self) -> bool {
+        let (policy, priority) = self.get_policy();
+        policy == SchedPolicy::RoundRobin && priority >= self.inherited.load(Ordering::Relaxed)
+    }
+
+    /// Set the inherited priority to `priority`. Return whether the effective priority changed.
+    pub fn set_inherited_priority(&self, priority: usize) -> bool {
+        let old = self.get_priority();
+        self.inherited.store(priority, Ordering::Relaxed);
+        self.get_priority() != old
+    }
+
+    pub fn get_affinity(&self) -> usize {
+        self.affinity.load(Ordering::Relaxed)
+    }
+
+    /// **Use [crate::task::scheduler::set_affinity] on tasks which may be queued or running**,
+    /// so that they are moved to an allowed hart.
+    pub fn set_affinity(&self, mask: usize) {
+        self.affinity.store(mask & ALL_HARTS, Ordering::Relaxed);
+    }
+
+    pub fn is_allowed(&self, hart_id: usize) -> bool {
+        self.get_affinity() & (1 << hart_id) != 0
+    }
+
+    /// Bind the task to hart `hart_id` for good, as for per-hart kernel threads.
+    /// **Call it before the task is queued.**
+    pub fn pin(&self, hart_id: usize) {
+        self.set_affinity(1 << hart_id);
+        self.pinned.store(true, Ordering::Relaxed);
+        self.set_hart(hart_id);
+    }
+
+    pub fn is_pinned(&self) -> bool {
+        self.pinned.load(Ordering::Relaxed)
+    }
+
+    pub fn get_hart(&self) -> usize {
+        self.hart.load(Ordering::Relaxed)
+    }
+
+    pub fn set_hart(&self, hart_id: usize) {
+        self.hart.store(hart_id, Ordering::Relaxed);
+    }
+}
 
 pub trait Scheduler {
     fn add_to_ready(&mut self, task: Arc<Task>);
+    /// Take the next task to run. Return the idle task of the hart if nothing is ready.
     fn fetch_new(&mut self) -> Arc<Task>;
+    /// Take `task` out of the run queue. Return `false` if it's not queued here.
+    fn remove(&mut self, task: &Arc<Task>) -> bool;
+    /// Take `task` out of the run queue to be queued on another hart with [Scheduler::attach].
+    /// Return `false` if it's not queued here.
+    fn detach(&mut self, task: &Arc<Task>) -> bool {
+        self.remove(task)
+    }
+    /// Number of tasks waiting in the run queue.
+    fn nr_ready(&self) -> usize;
+    /// Take a ready task allowed on hart `hart_id` out of the run queue, so that the hart can run it.
+    /// The task is queued there with [Scheduler::attach].
+    fn steal(&mut self, hart_id: usize) -> Option<Arc<Task>>;
+    /// Queue a task coming from outside of the run queues: a new task,
+    /// or a task taken from another hart with [Scheduler::steal].
+    fn attach(&mut self, task: Arc<Task>) {
+        self.add_to_ready(task);
+    }
+    /// Queue a task woken up after blocking.
+    fn wake_up(&mut self, task: Arc<Task>) {
+        self.add_to_ready(task);
+    }
+    /// Called when `task` is switched out after running for `runtime_ns` nanoseconds.
+    /// Queue it again unless it `blocked`.
+    fn put_prev(&mut self, task: Arc<Task>, _runtime_ns: usize, blocked: bool) {
+        if !blocked {
+            self.add_to_ready(task);
+        }
+    }
+    /// Called on each tick: whether `current`, running for `runtime_ns` nanoseconds, should be preempted.
+    fn need_resched(&self, _current: &Arc<Task>, _runtime_ns: usize) -> bool {
+        true
+    }
 }
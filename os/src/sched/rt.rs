@@ -0,0 +1,110 @@
+//! Real-time scheduling class.
+//!
+//! Tasks are kept in one FIFO queue per priority, and the highest non-empty priority always runs first.
+//! [SchedPolicy::Fifo](super::SchedPolicy::Fifo) tasks run until they block or are preempted by a higher priority;
+//! [SchedPolicy::RoundRobin](super::SchedPolicy::RoundRobin) tasks also give way to tasks of the same priority
+//! after [RR_TIMESLICE_NS].
+
+use crate::{sched::MAX_RT_PRIORITY, task::task::Task};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use core::array;
+
+/// Time slice of round-robin tasks.
+pub const RR_TIMESLICE_NS: usize = 100_000_000;
+
+pub struct RtScheduler {
+    /// Ready queues, indexed by priority. Index 0 is unused.
+    queues: [VecDeque<Arc<Task>>; MAX_RT_PRIORITY + 1],
+    /// Bit `n` is set if `queues[n]` is not empty.
+    bitmap: u128,
+    nr_ready: usize,
+}
+
+impl RtScheduler {
+    pub fn new() -> RtScheduler {
+        RtScheduler {
+            queues: array::from_fn(|_| VecDeque::new()),
+            bitmap: 0,
+            nr_ready: 0,
+        }
+    }
+
+    /// The highest priority with a ready task. 0 if none.
+    pub fn highest_priority(&self) -> usize {
+        match self.bitmap {
+            0 => 0,
+            bitmap => 127 - bitmap.leading_zeros() as usize,
+        }
+    }
+
+    pub fn nr_ready(&self) -> usize {
+        self.nr_ready
+    }
+
+    /// Queue a task at the tail of its priority, or at the head if it was preempted before its turn ended.
+    pub fn enqueue(&mut self, task: Arc<Task>, head: bool) {
+        let priority = task.sched.get_priority();
+        debug_assert!(
+            priority > 0,
+            "Queueing a normal task in the real-time class."
+        );
+        if head {
+            self.queues[priority].push_front(task);
+        } else {
+            self.queues[priority].push_back(task);
+        }
+        self.bitmap |= 1 << priority;
+        self.nr_ready += 1;
+    }
+
+    /// Take the first task of the highest priority.
+    pub fn pick(&mut self) -> Option<Arc<Task>> {
+        let priority = self.highest_priority();
+        let task = self.queues[priority].pop_front()?;
+        self.dequeued(priority);
+        Some(task)
+    }
+
+    /// Take the task allowed on hart `hart_id` which would wait here the longest:
+    /// the last one of the lowest priority.
+    pub fn steal(&mut self, hart_id: usize) -> Option<Arc<Task>> {
+        for priority in 1..=MAX_RT_PRIORITY {
+            if self.bitmap & (1 << priority) == 0 {
+                continue;
+            }
+            let queue = &mut self.queues[priority];
+            if let Some(idx) = queue
+                .iter()
+                .rposition(|task| task.sched.is_allowed(hart_id))
+            {
+                let task = queue.remove(idx);
+                self.dequeued(priority);
+                return task;
+            }
+        }
+        None
+    }
+
+    pub fn remove(&mut self, task: &Arc<Task>) -> bool {
+        // The priority may have changed since the task was queued.
+        for priority in 1..=MAX_RT_PRIORITY {
+            if self.bitmap & (1 << priority) == 0 {
+                continue;
+            }
+            let queue = &mut self.queues[priority];
+            if let Some(idx) = queue.iter().position(|queued| Arc::ptr_eq(queued, task)) {
+                queue.remove(idx);
+                self.dequeued(priority);
+                return true;
+            }
+        }
+        false
+    }
+
+    fn dequeued(&mut self, priority: usize) {
+        if self.queues[priority].is_empty() {
+            self.bitmap &= !(1 << priority);
+        }
+        self.nr_ready -= 1;
+    }
+}
@@ -0,0 +1,190 @@
+//! Inter-Processor Interrupts
+//!
+//! Harts interrupt each other with software interrupts, and leave the reasons in the
+//! pending mask of the target hart:
+//! - a reschedule request, sent by [send_reschedule] when a task is queued on a remote hart;
+//! - function calls queued on the hart by [smp_call_on] and [smp_call_all];
+//! - a stop request, sent by [stop_others] on panic.
+//!
+//! The requested functions run in interrupt context on the target hart: **they must not block.**
+//!
+//! Only working harts are called. A hart going offline runs the calls queued before, see [hart_offline].
+
+use crate::{
+    arch::{
+        hart::{get_current_hart_id, send_ipi},
+        trap::intr::{disable_intr, restore_intr, wait_for_intr},
+    },
+    dev::{get_working_hart_ids, is_working_hart},
+    mutex::SpinLock,
+    percpu,
+    task::scheduler::schedule,
+};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::{
+    hint::spin_loop,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const IPI_RESCHEDULE: usize = 1 << 0;
+const IPI_CALL: usize = 1 << 1;
+const IPI_STOP: usize = 1 << 2;
+
+/// A function to run on one or more harts.
+struct CallRequest {
+    func: Box<dyn Fn() + Send + Sync>,
+    /// Number of harts which have not run the function yet.
+    pending: AtomicUsize,
+}
+
+percpu! {
+    /// Reasons of the IPIs sent to the hart and not handled yet.
+    static IPI_PENDING: AtomicUsize = AtomicUsize::new(0);
+
+    /// Function calls queued on the hart.
+    static CALL_QUEUE: SpinLock<VecDeque<Arc<CallRequest>>> = SpinLock::new(VecDeque::new());
+}
+
+/// Errors returned by the cross-hart call APIs.
+#[derive(Debug)]
+pub enum IpiError {
+    /// The hart is not working.
+    InvalidHart,
+}
+
+/// Record `reason` for hart `hart_id` and interrupt it, unless an IPI is already on its way.
+fn raise(hart_id: usize, reason: usize) {
+    let pending = IPI_PENDING
+        .remote(hart_id)
+        .fetch_or(reason, Ordering::AcqRel);
+    if pending == 0 {
+        send_ipi(hart_id);
+    }
+}
+
+/// Ask hart `hart_id` to reschedule, e.g. after queueing a task there.
+pub fn send_reschedule(hart_id: usize) {
+    raise(hart_id, IPI_RESCHEDULE);
+}
+
+fn queue_call(hart_id: usize, request: Arc<CallRequest>) {
+    CALL_QUEUE.remote(hart_id).lock_no_irq().push_back(request);
+    raise(hart_id, IPI_CALL);
+}
+
+/// Run the calls queued on the current hart.
+fn handle_calls(hart_id: usize) {
+    loop {
+        let request = CALL_QUEUE.remote(hart_id).lock_no_irq().pop_front();
+        let Some(request) = request else {
+            break;
+        };
+        (request.func)();
+        request.pending.fetch_sub(1, Ordering::Release);
+    }
+}
+
+/// Wait until every target of `request` has run it.
+/// The calls queued on this hart meanwhile are run, so that two harts calling each other can't deadlock.
+fn wait_for(request: &CallRequest, hart_id: usize) {
+    while request.pending.load(Ordering::Acquire) != 0 {
+        handle_calls(hart_id);
+        spin_loop();
+    }
+}
+
+/// Run `f` on hart `hart_id`, and wait until it returns if `wait` is set.
+/// On the current hart, `f` runs right away with interrupts disabled.
+pub fn smp_call_on(
+    hart_id: usize,
+    f: impl Fn() + Send + Sync + 'static,
+    wait: bool,
+) -> Result<(), IpiError> {
+    if !is_working_hart(hart_id) {
+        return Err(IpiError::InvalidHart);
+    }
+    // Interrupts stay disabled, so that the task can't move to another hart meanwhile.
+    let intr = disable_intr();
+    let cur_hart = get_current_hart_id();
+    if hart_id == cur_hart {
+        f();
+    } else {
+        let request = Arc::new(CallRequest {
+            func: Box::new(f),
+            pending: AtomicUsize::new(1),
+        });
+        queue_call(hart_id, request.clone());
+        if wait {
+            wait_for(&request, cur_hart);
+        }
+    }
+    restore_intr(intr);
+    Ok(())
+}
+
+/// Run `f` on every working hart other than the current one,
+/// and wait until all of them have returned if `wait` is set.
+pub fn smp_call_all(f: impl Fn() + Send + Sync + 'static, wait: bool) {
+    let intr = disable_intr();
+    let cur_hart = get_current_hart_id();
+    let harts: Vec<usize> = get_working_hart_ids()
+        .into_iter()
+        .filter(|hart_id| *hart_id != cur_hart)
+        .collect();
+    let request = Arc::new(CallRequest {
+        func: Box::new(f),
+        pending: AtomicUsize::new(harts.len()),
+    });
+    for hart_id in harts {
+        queue_call(hart_id, request.clone());
+    }
+    if wait {
+        wait_for(&request, cur_hart);
+    }
+    restore_intr(intr);
+}
+
+/// Halt every working hart other than the current one.
+pub fn stop_others() {
+    let cur_hart = get_current_hart_id();
+    for hart_id in get_working_hart_ids() {
+        if hart_id != cur_hart {
+            raise(hart_id, IPI_STOP);
+        }
+    }
+}
+
+/// Halt the current hart for good.
+pub fn halt() -> ! {
+    disable_intr();
+    loop {
+        wait_for_intr();
+    }
+}
+
+/// Run the calls queued on hart `hart_id`, going offline. Called on the hart itself by [crate::hotplug].
+pub fn hart_offline(hart_id: usize) {
+    handle_calls(hart_id);
+}
+
+/// Forget the IPIs sent to hart `hart_id` while it was offline, which would keep new ones from being sent.
+/// Called on the hart itself by [crate::hotplug].
+pub fn hart_online(hart_id: usize) {
+    IPI_PENDING.remote(hart_id).store(0, Ordering::Release);
+}
+
+/// Handle the IPIs received by the current hart.
+/// **The software interrupt must be acknowledged before**, so that no IPI gets lost.
+pub fn handle_ipi() {
+    let hart_id = get_current_hart_id();
+    let pending = IPI_PENDING.remote(hart_id).swap(0, Ordering::AcqRel);
+    if pending & IPI_STOP != 0 {
+        halt();
+    }
+    if pending & IPI_CALL != 0 {
+        handle_calls(hart_id);
+    }
+    if pending & IPI_RESCHEDULE != 0 {
+        schedule();
+    }
+}
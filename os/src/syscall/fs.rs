@@ -0,0 +1,35 @@
+//! File system calls.
+
+use crate::{
+    mm::{config::PAGE_SIZE, uaccess::UserPtr},
+    syscall::{SyscallError, SyscallResult, get_caller_process},
+};
+
+/// Bytes copied from user space at a time, so that large writes don't need large buffers.
+const WRITE_CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// Write `len` bytes at `buf` to the file `fd`, and return the number of bytes written.
+/// A short count is returned if the file accepts less, or if an error follows a partial write.
+pub fn sys_write(fd: usize, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    let file = get_caller_process()?.fds.lock_no_irq().get(fd)?;
+    let mut written = 0;
+    while written < len {
+        let chunk_len = (len - written).min(WRITE_CHUNK_SIZE);
+        let res = buf
+            .add(written)
+            .read_bytes(chunk_len)
+            .map_err(SyscallError::from)
+            .and_then(|chunk| file.write(&chunk).map_err(SyscallError::from));
+        match res {
+            Ok(count) => {
+                written += count;
+                if count < chunk_len {
+                    break;
+                }
+            }
+            Err(error) if written == 0 => return Err(error),
+            Err(_) => break,
+        }
+    }
+    Ok(written)
+}
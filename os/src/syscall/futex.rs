@@ -0,0 +1,92 @@
+//! Futex system calls.
+//!
+//! Both personalities use the Linux operations and flags: futexes are shared by physical address
+//! unless the operation has [FUTEX_PRIVATE_FLAG], which keys them by memspace and is faster.
+
+use crate::{
+    mm::uaccess::UserPtr,
+    syscall::{SyscallError, SyscallResult},
+    task::{
+        futex::{FutexKey, futex_requeue, futex_wait, futex_wake},
+        get_current_task,
+    },
+    timer::{get_time, ns_to_time},
+};
+
+/// Operations.
+pub const FUTEX_WAIT: u32 = 0;
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_REQUEUE: u32 = 3;
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+/// Flags of the operations.
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+/// The timeout is measured against the real-time clock, which is the monotonic one here.
+pub const FUTEX_CLOCK_REALTIME: u32 = 256;
+
+/// Timeout of karox waits that never expire.
+pub const FUTEX_NO_TIMEOUT: usize = usize::MAX;
+
+/// The futex at `uaddr` in the current memspace, which must be aligned.
+fn get_key(uaddr: UserPtr<u32>, private: bool) -> Result<FutexKey, SyscallError> {
+    if uaddr.addr() % 4 != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let task = get_current_task();
+    let memsp = task.memsp.as_ref().ok_or(SyscallError::NoProcess)?;
+    if private {
+        Ok(FutexKey::private(memsp, uaddr.addr()))
+    } else {
+        Ok(FutexKey::shared(memsp, uaddr.addr())?)
+    }
+}
+
+/// Run the futex operation `op` on the word at `uaddr`:
+/// - `FUTEX_WAIT` blocks if the word holds `val`, for at most the relative timeout in nanoseconds
+///   returned by `get_timeout`, which is only called then;
+/// - `FUTEX_WAKE` wakes up to `val` waiters;
+/// - `FUTEX_REQUEUE` wakes up to `val` waiters, and moves up to `val2` others to the futex at `uaddr2`;
+/// - `FUTEX_CMP_REQUEUE` does it only if the word holds `val3`.
+///
+/// The wake and requeue operations return the number of waiters woken or moved.
+pub fn do_futex(
+    uaddr: UserPtr<u32>,
+    op: u32,
+    val: u32,
+    get_timeout: impl FnOnce() -> Result<Option<usize>, SyscallError>,
+    val2: usize,
+    uaddr2: UserPtr<u32>,
+    val3: u32,
+) -> SyscallResult {
+    let private = op & FUTEX_PRIVATE_FLAG != 0;
+    match op & !(FUTEX_PRIVATE_FLAG | FUTEX_CLOCK_REALTIME) {
+        FUTEX_WAIT => {
+            let key = get_key(uaddr, private)?;
+            let deadline = get_timeout()?.map(|ns| get_time().saturating_add(ns_to_time(ns)));
+            futex_wait(key, uaddr, val, deadline)?;
+            Ok(0)
+        }
+        FUTEX_WAKE => Ok(futex_wake(get_key(uaddr, private)?, val as usize)),
+        cmd @ (FUTEX_REQUEUE | FUTEX_CMP_REQUEUE) => {
+            let key = get_key(uaddr, private)?;
+            let target = get_key(uaddr2, private)?;
+            let expected = (cmd == FUTEX_CMP_REQUEUE).then_some((uaddr, val3));
+            Ok(futex_requeue(key, target, val as usize, val2, expected)?)
+        }
+        _ => Err(SyscallError::NoSyscall),
+    }
+}
+
+/// Futex operation `op` on the word at `uaddr`, see [do_futex].
+/// `arg` is the relative timeout in nanoseconds of waits, [FUTEX_NO_TIMEOUT] for none,
+/// or the maximum number of waiters to requeue.
+pub fn sys_futex(
+    uaddr: UserPtr<u32>,
+    op: u32,
+    val: u32,
+    arg: usize,
+    uaddr2: UserPtr<u32>,
+    val3: u32,
+) -> SyscallResult {
+    let get_timeout = || Ok((arg != FUTEX_NO_TIMEOUT).then_some(arg));
+    do_futex(uaddr, op, val, get_timeout, arg, uaddr2, val3)
+}
@@ -0,0 +1,101 @@
+//! Linux file system calls.
+
+use crate::{
+    initrd::InitrdFile,
+    mm::{config::PAGE_SIZE, uaccess::UserPtr},
+    syscall::{SyscallError, SyscallResult, get_caller_process, linux::IoVec},
+    task::resource::File,
+};
+use alloc::{sync::Arc, vec};
+
+pub use crate::syscall::fs::sys_write;
+
+/// `dirfd` of paths relative to the working directory, which is always `/`.
+pub const AT_FDCWD: i32 = -100;
+
+/// Open flags.
+pub const O_ACCMODE: u32 = 0o3;
+pub const O_RDONLY: u32 = 0o0;
+pub const O_CREAT: u32 = 0o100;
+pub const O_TRUNC: u32 = 0o1000;
+
+/// Maximum length of a path, NUL excluded.
+pub const PATH_MAX: usize = 4095;
+/// Maximum number of buffers of a vectored write.
+pub const IOV_MAX: usize = 1024;
+
+/// Bytes copied to user space at a time, so that large reads don't need large buffers.
+const READ_CHUNK_SIZE: usize = PAGE_SIZE;
+
+/// Open the file `path` of the initrd read-only, and return its descriptor.
+/// Relative paths are only supported from the working directory.
+pub fn sys_openat(dirfd: i32, path: UserPtr<u8>, flags: u32, _mode: u32) -> SyscallResult {
+    let path = path.read_str(PATH_MAX)?;
+    if !path.starts_with('/') && dirfd != AT_FDCWD {
+        return Err(SyscallError::BadFd);
+    }
+    if flags & O_ACCMODE != O_RDONLY || flags & (O_CREAT | O_TRUNC) != 0 {
+        return Err(SyscallError::ReadOnly);
+    }
+    let file = InitrdFile::open(&path).ok_or(SyscallError::NotFound)?;
+    let file: Arc<dyn File> = Arc::new(file);
+    Ok(get_caller_process()?.fds.lock_no_irq().insert(file)?)
+}
+
+pub fn sys_close(fd: usize) -> SyscallResult {
+    get_caller_process()?.fds.lock_no_irq().remove(fd)?;
+    Ok(0)
+}
+
+/// Read up to `len` bytes from the file `fd` to `buf`, and return the number of bytes read.
+/// A short count is returned at the end of the file, or if an error follows a partial read.
+pub fn sys_read(fd: usize, buf: UserPtr<u8>, len: usize) -> SyscallResult {
+    let file = get_caller_process()?.fds.lock_no_irq().get(fd)?;
+    let mut chunk = vec![0; len.min(READ_CHUNK_SIZE)];
+    let mut read = 0;
+    while read < len {
+        let chunk_len = (len - read).min(READ_CHUNK_SIZE);
+        let res = file
+            .read(&mut chunk[..chunk_len])
+            .map_err(SyscallError::from)
+            .and_then(|count| {
+                buf.add(read).write_bytes(&chunk[..count])?;
+                Ok(count)
+            });
+        match res {
+            Ok(count) => {
+                read += count;
+                if count < chunk_len {
+                    break;
+                }
+            }
+            Err(error) if read == 0 => return Err(error),
+            Err(_) => break,
+        }
+    }
+    Ok(read)
+}
+
+/// Write the `count` buffers described at `iov` to the file `fd` in order,
+/// and return the number of bytes written. It stops at the first short write.
+pub fn sys_writev(fd: usize, iov: UserPtr<IoVec>, count: usize) -> SyscallResult {
+    if count > IOV_MAX {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let mut written = 0;
+    for index in 0..count {
+        let vec = iov.add(index).read()?;
+        let res = sys_write(fd, vec.base, vec.len);
+        match res {
+            Ok(count) => {
+                written += count;
+                if count < vec.len {
+                    break;
+                }
+            }
+            Err(error) if written == 0 => return Err(error),
+            Err(_) => break,
+        }
+    }
+    Ok(written)
+}
@@ -0,0 +1,150 @@
+//! Linux memory system calls.
+//!
+//! Mappings are private and populated at once. File mappings are copies of the file content.
+
+use crate::{
+    arch::{PAGE_WIDTH, mm::paging::PageTableFlags},
+    mm::{
+        config::PAGE_SIZE,
+        space::{AreaPlacement, MemSpace},
+    },
+    syscall::{SyscallError, SyscallResult, get_caller_process},
+    task::resource::{File, FileError},
+};
+use alloc::vec;
+
+pub use crate::syscall::mm::sys_brk;
+
+/// Protections.
+pub const PROT_READ: u32 = 0x1;
+pub const PROT_WRITE: u32 = 0x2;
+pub const PROT_EXEC: u32 = 0x4;
+
+/// Mapping flags.
+pub const MAP_SHARED: u32 = 0x01;
+pub const MAP_PRIVATE: u32 = 0x02;
+pub const MAP_TYPE: u32 = 0x0f;
+pub const MAP_FIXED: u32 = 0x10;
+pub const MAP_ANONYMOUS: u32 = 0x20;
+pub const MAP_FIXED_NOREPLACE: u32 = 0x100000;
+
+fn prot_to_perm(prot: u32) -> Result<PageTableFlags, SyscallError> {
+    if prot & !(PROT_READ | PROT_WRITE | PROT_EXEC) != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let mut perm = PageTableFlags::NUL;
+    if prot & PROT_READ != 0 {
+        perm |= PageTableFlags::R;
+    }
+    if prot & PROT_WRITE != 0 {
+        // Write-only pages are reserved encodings on RISC-V.
+        perm |= PageTableFlags::RW;
+    }
+    if prot & PROT_EXEC != 0 {
+        perm |= PageTableFlags::X;
+    }
+    Ok(perm)
+}
+
+/// The page range of `len` bytes at `addr`, which must be page-aligned.
+fn page_range(addr: usize, len: usize) -> Result<(usize, usize), SyscallError> {
+    if addr % PAGE_SIZE != 0 || len == 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let count = len
+        .checked_next_multiple_of(PAGE_SIZE)
+        .ok_or(SyscallError::OutOfMemory)?
+        >> PAGE_WIDTH;
+    Ok((addr >> PAGE_WIDTH, count))
+}
+
+/// Map `len` bytes with the protection `prot`, and return the address of the mapping.
+///
+/// Without `MAP_ANONYMOUS`, the mapping is filled from `offset` in the file `fd`.
+/// The address is only a hint, unless `MAP_FIXED` or `MAP_FIXED_NOREPLACE` is set.
+/// Shared mappings are not supported.
+pub fn sys_mmap(
+    addr: usize,
+    len: usize,
+    prot: u32,
+    flags: u32,
+    fd: i32,
+    offset: usize,
+) -> SyscallResult {
+    if flags & MAP_TYPE != MAP_PRIVATE || offset % PAGE_SIZE != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let perm = prot_to_perm(prot)?;
+    let process = get_caller_process()?;
+    let file = if flags & MAP_ANONYMOUS == 0 {
+        Some(process.fds.lock_no_irq().get(fd as usize)?)
+    } else {
+        None
+    };
+    let placement = if flags & (MAP_FIXED | MAP_FIXED_NOREPLACE) != 0 {
+        let (vpn, _) = page_range(addr, len)?;
+        if flags & MAP_FIXED_NOREPLACE != 0 {
+            AreaPlacement::FixedNoReplace(vpn)
+        } else {
+            AreaPlacement::Fixed(vpn)
+        }
+    } else {
+        AreaPlacement::Anywhere
+    };
+    let (_, count) = page_range(0, len)?;
+    let memsp = process.get_memsp();
+    let start = memsp.map_area(placement, count, perm)? << PAGE_WIDTH;
+    // Inaccessible pages have no frame to fill yet.
+    if let Some(file) = file.filter(|_| !perm.is_empty()) {
+        let res = fill_from_file(file.as_ref(), offset, start, len, &memsp);
+        if let Err(error) = res {
+            memsp.unmap_user(start >> PAGE_WIDTH, count)?;
+            return Err(error);
+        }
+    }
+    Ok(start)
+}
+
+/// Copy up to `len` bytes from `offset` in `file` to the pages at `start`, a page at a time.
+fn fill_from_file(
+    file: &dyn File,
+    offset: usize,
+    start: usize,
+    len: usize,
+    memsp: &MemSpace,
+) -> Result<(), SyscallError> {
+    let mut buf = vec![0; PAGE_SIZE];
+    let mut done = 0;
+    while done < len {
+        let chunk_len = (len - done).min(PAGE_SIZE);
+        let count =
+            file.read_at(offset + done, &mut buf[..chunk_len])
+                .map_err(|error| match error {
+                    FileError::NotSeekable => SyscallError::NoDevice,
+                    error => error.into(),
+                })?;
+        if count == 0 {
+            break;
+        }
+        memsp.write_user(start + done, &buf[..count])?;
+        done += count;
+    }
+    Ok(())
+}
+
+/// Unmap the pages of `len` bytes at `addr`. Pages not mapped are skipped.
+pub fn sys_munmap(addr: usize, len: usize) -> SyscallResult {
+    let (vpn, count) = page_range(addr, len)?;
+    get_caller_process()?.get_memsp().unmap_user(vpn, count)?;
+    Ok(0)
+}
+
+/// Change the protection of the pages of `len` bytes at `addr` to `prot`.
+pub fn sys_mprotect(addr: usize, len: usize, prot: u32) -> SyscallResult {
+    let (vpn, count) = page_range(addr, len)?;
+    let perm = prot_to_perm(prot)?;
+    get_caller_process()?
+        .get_memsp()
+        .protect_user(vpn, count, perm)?;
+    Ok(0)
+}
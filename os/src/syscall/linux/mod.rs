@@ -0,0 +1,192 @@
+//! Linux Personality
+//!
+//! The system calls of Linux riscv64 that static musl binaries need, with the Linux numbers,
+//! structures and `errno` values. They share the kernel objects of the karox system calls:
+//! - files are those of the initrd, which is read-only, and the console;
+//! - processes have no credentials or groups, and `clone` copies memspaces on write;
+//! - signal handlers return through a trampoline mapped by the kernel, not the vDSO.
+
+use crate::{
+    mm::uaccess::UserPtr,
+    syscall::{SyscallEntry, SyscallError, syscall_entry},
+    task::signal::{SigAction, SigSet},
+    timer::NSEC_PER_SEC,
+};
+
+mod fs;
+mod mm;
+mod signal;
+mod sys;
+mod task;
+mod time;
+
+// region: Numbers
+
+pub const SYS_OPENAT: usize = 56;
+pub const SYS_CLOSE: usize = 57;
+pub const SYS_READ: usize = 63;
+pub const SYS_WRITE: usize = 64;
+pub const SYS_WRITEV: usize = 66;
+pub const SYS_EXIT: usize = 93;
+pub const SYS_EXIT_GROUP: usize = 94;
+pub const SYS_SET_TID_ADDRESS: usize = 96;
+pub const SYS_FUTEX: usize = 98;
+pub const SYS_NANOSLEEP: usize = 101;
+pub const SYS_CLOCK_GETTIME: usize = 113;
+pub const SYS_SCHED_YIELD: usize = 124;
+pub const SYS_KILL: usize = 129;
+pub const SYS_TKILL: usize = 130;
+pub const SYS_TGKILL: usize = 131;
+pub const SYS_RT_SIGACTION: usize = 134;
+pub const SYS_RT_SIGPROCMASK: usize = 135;
+pub const SYS_RT_SIGRETURN: usize = 139;
+pub const SYS_UNAME: usize = 160;
+pub const SYS_GETPID: usize = 172;
+pub const SYS_GETPPID: usize = 173;
+pub const SYS_GETTID: usize = 178;
+pub const SYS_BRK: usize = 214;
+pub const SYS_MUNMAP: usize = 215;
+pub const SYS_CLONE: usize = 220;
+pub const SYS_EXECVE: usize = 221;
+pub const SYS_MMAP: usize = 222;
+pub const SYS_MPROTECT: usize = 226;
+pub const SYS_WAIT4: usize = 260;
+
+// endregion
+
+// region: Structures
+
+/// `struct timespec`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Timespec {
+    pub tv_sec: i64,
+    pub tv_nsec: i64,
+}
+
+impl Timespec {
+    pub fn from_ns(ns: usize) -> Timespec {
+        Timespec {
+            tv_sec: (ns / NSEC_PER_SEC) as i64,
+            tv_nsec: (ns % NSEC_PER_SEC) as i64,
+        }
+    }
+
+    /// The duration in nanoseconds. Negative or unnormalized values are invalid.
+    pub fn to_ns(&self) -> Result<usize, SyscallError> {
+        if self.tv_sec < 0 || !(0..NSEC_PER_SEC as i64).contains(&self.tv_nsec) {
+            return Err(SyscallError::InvalidArgument);
+        }
+        Ok((self.tv_sec as usize)
+            .saturating_mul(NSEC_PER_SEC)
+            .saturating_add(self.tv_nsec as usize))
+    }
+}
+
+/// `struct timeval`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Timeval {
+    pub tv_sec: i64,
+    pub tv_usec: i64,
+}
+
+/// `struct rusage`. Resource usage is not tracked, it is reported as zero.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Rusage {
+    pub ru_utime: Timeval,
+    pub ru_stime: Timeval,
+    /// Memory, paging, IO and switch counters.
+    pub ru_counters: [i64; 14],
+}
+
+/// Length of the fields of [Utsname], NUL included.
+pub const UTSNAME_LEN: usize = 65;
+
+/// `struct utsname`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct Utsname {
+    pub sysname: [u8; UTSNAME_LEN],
+    pub nodename: [u8; UTSNAME_LEN],
+    pub release: [u8; UTSNAME_LEN],
+    pub version: [u8; UTSNAME_LEN],
+    pub machine: [u8; UTSNAME_LEN],
+    pub domainname: [u8; UTSNAME_LEN],
+}
+
+/// `struct iovec`.
+#[repr(C)]
+#[derive(Debug, Clone, Copy)]
+pub struct IoVec {
+    pub base: UserPtr<u8>,
+    pub len: usize,
+}
+
+// endregion
+
+pub static LINUX_SYSCALL_TABLE: &[SyscallEntry] = &[
+    syscall_entry!(SYS_OPENAT, fs::sys_openat, (i32, UserPtr<u8>, u32, u32)),
+    syscall_entry!(SYS_CLOSE, fs::sys_close, (usize)),
+    syscall_entry!(SYS_READ, fs::sys_read, (usize, UserPtr<u8>, usize)),
+    syscall_entry!(SYS_WRITE, fs::sys_write, (usize, UserPtr<u8>, usize)),
+    syscall_entry!(SYS_WRITEV, fs::sys_writev, (usize, UserPtr<IoVec>, usize)),
+    syscall_entry!(SYS_EXIT, task::sys_exit, (i32)),
+    syscall_entry!(SYS_EXIT_GROUP, task::sys_exit_group, (i32)),
+    syscall_entry!(SYS_SET_TID_ADDRESS, task::sys_set_tid_address, (usize)),
+    syscall_entry!(
+        SYS_FUTEX,
+        task::sys_futex,
+        (UserPtr<u32>, u32, u32, usize, UserPtr<u32>, u32)
+    ),
+    syscall_entry!(
+        SYS_NANOSLEEP,
+        time::sys_nanosleep,
+        (UserPtr<Timespec>, usize)
+    ),
+    syscall_entry!(
+        SYS_CLOCK_GETTIME,
+        time::sys_clock_gettime,
+        (i32, UserPtr<Timespec>)
+    ),
+    syscall_entry!(SYS_SCHED_YIELD, task::sys_sched_yield, ()),
+    syscall_entry!(SYS_KILL, signal::sys_kill, (i32, usize)),
+    syscall_entry!(SYS_TKILL, signal::sys_tkill, (i32, usize)),
+    syscall_entry!(SYS_TGKILL, signal::sys_tgkill, (i32, i32, usize)),
+    syscall_entry!(
+        SYS_RT_SIGACTION,
+        signal::sys_rt_sigaction,
+        (usize, UserPtr<SigAction>, UserPtr<SigAction>, usize)
+    ),
+    syscall_entry!(
+        SYS_RT_SIGPROCMASK,
+        signal::sys_rt_sigprocmask,
+        (u32, UserPtr<SigSet>, UserPtr<SigSet>, usize)
+    ),
+    syscall_entry!(SYS_RT_SIGRETURN, signal::sys_rt_sigreturn, context, ()),
+    syscall_entry!(SYS_UNAME, sys::sys_uname, (UserPtr<Utsname>)),
+    syscall_entry!(SYS_GETPID, task::sys_getpid, ()),
+    syscall_entry!(SYS_GETPPID, task::sys_getppid, ()),
+    syscall_entry!(SYS_GETTID, task::sys_gettid, ()),
+    syscall_entry!(SYS_BRK, mm::sys_brk, (usize)),
+    syscall_entry!(SYS_MUNMAP, mm::sys_munmap, (usize, usize)),
+    syscall_entry!(
+        SYS_CLONE,
+        task::sys_clone,
+        context,
+        (usize, usize, UserPtr<u32>, usize, UserPtr<u32>)
+    ),
+    syscall_entry!(
+        SYS_EXECVE,
+        task::sys_execve,
+        (UserPtr<u8>, UserPtr<usize>, UserPtr<usize>)
+    ),
+    syscall_entry!(SYS_MMAP, mm::sys_mmap, (usize, usize, u32, u32, i32, usize)),
+    syscall_entry!(SYS_MPROTECT, mm::sys_mprotect, (usize, usize, u32)),
+    syscall_entry!(
+        SYS_WAIT4,
+        task::sys_wait4,
+        (i32, UserPtr<i32>, u32, UserPtr<Rusage>)
+    ),
+];
@@ -0,0 +1,80 @@
+//! Linux signal system calls.
+
+use crate::{
+    mm::uaccess::UserPtr,
+    syscall::{SyscallError, SyscallResult, get_caller_process, signal},
+    task::{
+        registry::find_task,
+        signal::{SigAction, SigSet, is_valid_signal, send_signal, send_thread_signal},
+    },
+};
+
+pub use crate::syscall::signal::sys_sigreturn as sys_rt_sigreturn;
+
+/// Send `sig` to the process `pid`. There are no process groups: 0 is the calling process,
+/// and the other negative pids are not supported.
+pub fn sys_kill(pid: i32, sig: usize) -> SyscallResult {
+    if pid != 0 {
+        return signal::sys_kill(pid as isize, sig);
+    }
+    if sig != 0 && !is_valid_signal(sig) {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let process = get_caller_process()?;
+    if sig != 0 {
+        send_signal(&process, sig);
+    }
+    Ok(0)
+}
+
+/// Send `sig` to the thread `tid` of the process `tgid`, or of any process if `tgid` is [None].
+fn kill_thread(tgid: Option<i32>, tid: i32, sig: usize) -> SyscallResult {
+    if tid <= 0 || tgid.is_some_and(|tgid| tgid <= 0) || (sig != 0 && !is_valid_signal(sig)) {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let task = find_task(tid as usize).ok_or(SyscallError::NoProcess)?;
+    let pid = task.process.get().map(|process| process.get_pid());
+    match (pid, tgid) {
+        (None, _) => return Err(SyscallError::NoProcess),
+        (Some(pid), Some(tgid)) if pid != tgid as usize => return Err(SyscallError::NoProcess),
+        _ => {}
+    }
+    if sig != 0 {
+        send_thread_signal(&task, sig);
+    }
+    Ok(0)
+}
+
+pub fn sys_tkill(tid: i32, sig: usize) -> SyscallResult {
+    kill_thread(None, tid, sig)
+}
+
+pub fn sys_tgkill(tgid: i32, tid: i32, sig: usize) -> SyscallResult {
+    kill_thread(Some(tgid), tid, sig)
+}
+
+/// Like [signal::sys_sigaction], with the size of the signal sets, which must be that of [SigSet].
+pub fn sys_rt_sigaction(
+    sig: usize,
+    act: UserPtr<SigAction>,
+    oldact: UserPtr<SigAction>,
+    sigsetsize: usize,
+) -> SyscallResult {
+    if sigsetsize != size_of::<SigSet>() {
+        return Err(SyscallError::InvalidArgument);
+    }
+    signal::sys_sigaction(sig, act, oldact)
+}
+
+/// Like [signal::sys_sigprocmask], with the size of the signal sets, which must be that of [SigSet].
+pub fn sys_rt_sigprocmask(
+    how: u32,
+    set: UserPtr<SigSet>,
+    oldset: UserPtr<SigSet>,
+    sigsetsize: usize,
+) -> SyscallResult {
+    if sigsetsize != size_of::<SigSet>() {
+        return Err(SyscallError::InvalidArgument);
+    }
+    signal::sys_sigprocmask(how, set, oldset)
+}
@@ -0,0 +1,31 @@
+//! Linux system information calls.
+
+use crate::{
+    arch::MACHINE_NAME,
+    mm::uaccess::UserPtr,
+    syscall::{
+        SyscallResult,
+        linux::{UTSNAME_LEN, Utsname},
+    },
+};
+
+/// A `utsname` field holding `value`, truncated and NUL-terminated.
+fn uts_field(value: &str) -> [u8; UTSNAME_LEN] {
+    let mut res = [0; UTSNAME_LEN];
+    let len = value.len().min(UTSNAME_LEN - 1);
+    res[..len].copy_from_slice(&value.as_bytes()[..len]);
+    res
+}
+
+/// Store the name of the system at `buf`.
+pub fn sys_uname(buf: UserPtr<Utsname>) -> SyscallResult {
+    buf.write(&Utsname {
+        sysname: uts_field("karox"),
+        nodename: uts_field("karox"),
+        release: uts_field(env!("CARGO_PKG_VERSION")),
+        version: uts_field("#1"),
+        machine: uts_field(MACHINE_NAME),
+        domainname: uts_field("(none)"),
+    })?;
+    Ok(0)
+}
@@ -0,0 +1,189 @@
+//! Linux process and thread system calls.
+
+use crate::{
+    arch::trap::context::TrapContext,
+    mm::uaccess::UserPtr,
+    syscall::{
+        SyscallError, SyscallResult,
+        futex::do_futex,
+        get_caller_process,
+        linux::{Rusage, Timespec},
+    },
+    task::{
+        get_current_task, process::ExitStatus, scheduler::add_task, signal::inherit_signal_mask,
+        task::Task,
+    },
+};
+use alloc::sync::Arc;
+use core::sync::atomic::Ordering;
+
+pub use crate::syscall::task::{
+    WNOHANG, sys_execve, sys_exit, sys_getpid, sys_gettid, sys_yield as sys_sched_yield,
+};
+
+/// Clone flags. The low byte is the signal sent to the parent on exit, which is always `SIGCHLD`.
+pub const CSIGNAL: usize = 0xff;
+pub const CLONE_VM: usize = 0x100;
+pub const CLONE_FS: usize = 0x200;
+pub const CLONE_FILES: usize = 0x400;
+pub const CLONE_SIGHAND: usize = 0x800;
+pub const CLONE_THREAD: usize = 0x10000;
+pub const CLONE_SYSVSEM: usize = 0x40000;
+pub const CLONE_SETTLS: usize = 0x80000;
+pub const CLONE_PARENT_SETTID: usize = 0x100000;
+pub const CLONE_CHILD_CLEARTID: usize = 0x200000;
+pub const CLONE_DETACHED: usize = 0x400000;
+pub const CLONE_CHILD_SETTID: usize = 0x1000000;
+
+const CLONE_SUPPORTED: usize = CSIGNAL
+    | CLONE_VM
+    | CLONE_FS
+    | CLONE_FILES
+    | CLONE_SIGHAND
+    | CLONE_THREAD
+    | CLONE_SYSVSEM
+    | CLONE_SETTLS
+    | CLONE_PARENT_SETTID
+    | CLONE_CHILD_CLEARTID
+    | CLONE_DETACHED
+    | CLONE_CHILD_SETTID;
+
+/// Exit every thread of the calling process, which exits with `code`.
+pub fn sys_exit_group(code: i32) -> SyscallResult {
+    get_caller_process()?.exit_group(ExitStatus::Exited(code))
+}
+
+pub fn sys_getppid() -> SyscallResult {
+    let parent = get_caller_process()?.get_parent();
+    Ok(parent.map_or(0, |parent| parent.get_pid()))
+}
+
+/// Zero the word at `tidptr` and wake its futex when the calling thread exits. Return its tid.
+pub fn sys_set_tid_address(tidptr: usize) -> SyscallResult {
+    let task = get_current_task();
+    task.clear_child_tid.store(tidptr, Ordering::Relaxed);
+    Ok(task.get_tid())
+}
+
+/// Create a thread, or a child process without `CLONE_THREAD`, resuming after the call
+/// with the registers of the caller, `a0` cleared and the stack pointer `stack` if it's not 0.
+/// Return the tid of the thread, or the pid of the child.
+///
+/// A child shares the memspace of the caller with `CLONE_VM`, and gets a copy of it otherwise.
+/// It always gets a copy of the file descriptor table.
+pub fn sys_clone(
+    context: &mut TrapContext,
+    flags: usize,
+    stack: usize,
+    ptid: UserPtr<u32>,
+    tls: usize,
+    ctid: UserPtr<u32>,
+) -> SyscallResult {
+    let thread = flags & CLONE_THREAD != 0;
+    if flags & !CLONE_SUPPORTED != 0 || (thread && flags & CLONE_VM == 0) {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let process = get_caller_process()?;
+    let mut regs = context.x;
+    regs[10] = 0; // a0
+    if stack != 0 {
+        regs[2] = stack; // sp
+    }
+    if flags & CLONE_SETTLS != 0 {
+        regs[4] = tls; // tp
+    }
+    let memsp = if flags & CLONE_VM != 0 {
+        process.get_memsp()
+    } else {
+        Arc::new(process.get_memsp().duplicate()?)
+    };
+    let task = Task::new_user_with_regs(&process.name, memsp.clone(), context.sepc, &regs)
+        .map_err(|_| SyscallError::OutOfMemory)?;
+    inherit_signal_mask(&task);
+    let id = if thread {
+        process.add_thread(&task);
+        task.get_tid()
+    } else {
+        let child = process.new_child(&process.name, memsp.clone());
+        *child.fds.lock_no_irq() = process.fds.lock_no_irq().clone();
+        child.add_thread(&task);
+        child.get_pid()
+    };
+    // Like Linux, failures to store the id are ignored.
+    if flags & CLONE_PARENT_SETTID != 0 {
+        let _ = ptid.write(&(id as u32));
+    }
+    // Both stores are checked like user ones. With CLONE_VM, the memspace of the child is that of the caller.
+    if flags & CLONE_CHILD_SETTID != 0 {
+        let id = id as u32;
+        if flags & CLONE_VM != 0 {
+            let _ = ctid.write(&id);
+        } else {
+            let _ = memsp.write_user_checked(ctid.addr(), &id.to_le_bytes());
+        }
+    }
+    if flags & CLONE_CHILD_CLEARTID != 0 {
+        task.clear_child_tid.store(ctid.addr(), Ordering::Relaxed);
+    }
+    add_task(task);
+    Ok(id)
+}
+
+/// Wait for a child to exit, `pid` or any child if it's -1, reap it and return its pid.
+/// Its exit status is stored at `wstatus`, and zeroed resource usage at `rusage`, unless they are NULL.
+/// With `WNOHANG`, return 0 at once if no child has exited.
+///
+/// There are no process groups, so 0 and other negative pids wait for any child too.
+pub fn sys_wait4(
+    pid: i32,
+    wstatus: UserPtr<i32>,
+    options: u32,
+    rusage: UserPtr<Rusage>,
+) -> SyscallResult {
+    let process = get_caller_process()?;
+    let pid = (pid > 0).then_some(pid as usize);
+    let res = if options & WNOHANG != 0 {
+        process.try_wait_child(pid)?
+    } else {
+        Some(process.wait_child(pid)?)
+    };
+    let Some((pid, status)) = res else {
+        return Ok(0);
+    };
+    if !wstatus.is_null() {
+        wstatus.write(&status.wait_status())?;
+    }
+    if !rusage.is_null() {
+        rusage.write(&Rusage::default())?;
+    }
+    Ok(pid)
+}
+
+/// Futex operation `op` on the word at `uaddr`, see [do_futex]. `timeout` points to the relative
+/// timeout of waits, or is the maximum number of waiters to requeue, truncated to 32 bits.
+pub fn sys_futex(
+    uaddr: UserPtr<u32>,
+    op: u32,
+    val: u32,
+    timeout: usize,
+    uaddr2: UserPtr<u32>,
+    val3: u32,
+) -> SyscallResult {
+    let get_timeout = || -> Result<Option<usize>, SyscallError> {
+        let timeout = UserPtr::<Timespec>::new(timeout);
+        if timeout.is_null() {
+            Ok(None)
+        } else {
+            timeout.read()?.to_ns().map(Some)
+        }
+    };
+    do_futex(
+        uaddr,
+        op,
+        val,
+        get_timeout,
+        timeout as u32 as usize,
+        uaddr2,
+        val3,
+    )
+}
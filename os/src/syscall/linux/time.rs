@@ -0,0 +1,47 @@
+//! Linux time system calls.
+
+use crate::{
+    mm::uaccess::UserPtr,
+    syscall::{SyscallError, SyscallResult, get_caller_process, linux::Timespec},
+    task::get_current_task,
+    timer::{get_time, sleep_ns, time_to_ns},
+};
+
+/// Clocks of `clock_gettime`.
+pub const CLOCK_REALTIME: i32 = 0;
+pub const CLOCK_MONOTONIC: i32 = 1;
+pub const CLOCK_PROCESS_CPUTIME_ID: i32 = 2;
+pub const CLOCK_THREAD_CPUTIME_ID: i32 = 3;
+pub const CLOCK_MONOTONIC_RAW: i32 = 4;
+pub const CLOCK_REALTIME_COARSE: i32 = 5;
+pub const CLOCK_MONOTONIC_COARSE: i32 = 6;
+pub const CLOCK_BOOTTIME: i32 = 7;
+
+/// Store the time of the clock `clock_id` at `tp`.
+///
+/// There is no wall clock, so the real-time clocks count from boot like the monotonic ones.
+pub fn sys_clock_gettime(clock_id: i32, tp: UserPtr<Timespec>) -> SyscallResult {
+    let ns = match clock_id {
+        CLOCK_REALTIME
+        | CLOCK_MONOTONIC
+        | CLOCK_MONOTONIC_RAW
+        | CLOCK_REALTIME_COARSE
+        | CLOCK_MONOTONIC_COARSE
+        | CLOCK_BOOTTIME => time_to_ns(get_time()),
+        CLOCK_PROCESS_CPUTIME_ID => get_caller_process()?
+            .get_threads()
+            .iter()
+            .map(|task| task.stats.get_cpu_time())
+            .sum(),
+        CLOCK_THREAD_CPUTIME_ID => get_current_task().stats.get_cpu_time(),
+        _ => return Err(SyscallError::InvalidArgument),
+    };
+    tp.write(&Timespec::from_ns(ns))?;
+    Ok(0)
+}
+
+/// Block for the duration at `req`. Sleeps are never interrupted, so `rem` is left untouched.
+pub fn sys_nanosleep(req: UserPtr<Timespec>, _rem: usize) -> SyscallResult {
+    sleep_ns(req.read()?.to_ns()?);
+    Ok(0)
+}
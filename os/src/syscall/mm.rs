@@ -0,0 +1,9 @@
+//! Memory system calls.
+
+use crate::syscall::{SyscallResult, get_caller_process};
+
+/// Move the program break to `brk` and return the new break. The break is left unchanged,
+/// and returned, if `brk` is out of the heap or memory runs out; `brk(0)` queries it.
+pub fn sys_brk(brk: usize) -> SyscallResult {
+    Ok(get_caller_process()?.get_memsp().set_brk(brk))
+}
@@ -0,0 +1,387 @@
+//! System Calls
+//!
+//! User tasks enter the kernel with `ecall`, the system call number in `a7` and up to 6 arguments in `a0`-`a5`.
+//! [dispatch] looks the number up in [SYSCALL_TABLE] and writes the result back to `a0`:
+//! the value returned on success, or the negated [SyscallError] code on failure.
+//!
+//! Handlers take typed arguments, converted from the raw registers with [SyscallArg].
+//! Their signatures are declared once in the table with [syscall_entry].
+//! User pointers come as [UserPtr] and are only accessed through its checked accessors.
+//!
+//! Processes with the Linux [Personality] use [linux::LINUX_SYSCALL_TABLE] instead,
+//! with the Linux numbers and semantics.
+
+use crate::{
+    arch::trap::context::TrapContext,
+    mm::{
+        space::MemSpaceError,
+        uaccess::{UserAccessError, UserPtr},
+    },
+    task::{
+        futex::FutexError,
+        loader::LoadError,
+        process::{Personality, Process, WaitError, get_current_process},
+        resource::{FileError, ResourceError},
+        signal::{SigAction, SigSet, SignalError},
+    },
+};
+use alloc::sync::Arc;
+
+mod fs;
+mod futex;
+pub mod linux;
+mod mm;
+mod signal;
+mod task;
+
+// region: Numbers
+
+pub const SYS_EXIT: usize = 1;
+pub const SYS_YIELD: usize = 2;
+pub const SYS_GETPID: usize = 3;
+pub const SYS_SLEEP: usize = 4;
+pub const SYS_WRITE: usize = 5;
+pub const SYS_BRK: usize = 6;
+pub const SYS_THREAD_CREATE: usize = 7;
+pub const SYS_GETTID: usize = 8;
+pub const SYS_FORK: usize = 9;
+pub const SYS_EXECVE: usize = 10;
+pub const SYS_WAITPID: usize = 11;
+pub const SYS_FUTEX: usize = 12;
+pub const SYS_KILL: usize = 13;
+pub const SYS_SIGACTION: usize = 14;
+pub const SYS_SIGPROCMASK: usize = 15;
+pub const SYS_SIGRETURN: usize = 16;
+pub const SYS_EXIT_GROUP: usize = 17;
+
+// endregion
+
+// region: Errors
+
+/// Errors returned by system calls. Their codes are the Linux `errno` values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SyscallError {
+    /// No such file (`ENOENT`).
+    NotFound,
+    /// The caller belongs to no process (`ESRCH`).
+    NoProcess,
+    /// The caller was killed or got a signal while blocked (`EINTR`).
+    Interrupted,
+    /// The arguments and environment of a program are too long (`E2BIG`).
+    ArgumentListTooLong,
+    /// The file is not a valid executable (`ENOEXEC`).
+    ExecFormat,
+    /// No child matches a wait (`ECHILD`).
+    NoChild,
+    /// The operation would block, or must be retried (`EAGAIN`).
+    WouldBlock,
+    /// The kernel is out of memory, or the user address space is full (`ENOMEM`).
+    OutOfMemory,
+    /// A file descriptor refers to no open file (`EBADF`).
+    BadFd,
+    /// A user pointer is not mapped with the required permission (`EFAULT`).
+    BadAddress,
+    /// The target already exists (`EEXIST`).
+    Exists,
+    /// The file can't be mapped into memory (`ENODEV`).
+    NoDevice,
+    /// An argument is out of range (`EINVAL`).
+    InvalidArgument,
+    /// The file doesn't support the operation (`EINVAL` for reads and writes).
+    NotSupported,
+    /// A string argument is too long (`ENAMETOOLONG`).
+    NameTooLong,
+    /// The resource table is full (`EMFILE`).
+    TooManyFiles,
+    /// The file has no random access (`ESPIPE`).
+    NotSeekable,
+    /// The file system can't be written to (`EROFS`).
+    ReadOnly,
+    IoError,
+    /// No system call has this number (`ENOSYS`).
+    NoSyscall,
+    /// A timed wait expired (`ETIMEDOUT`).
+    TimedOut,
+}
+
+impl SyscallError {
+    /// The positive error number.
+    pub const fn code(self) -> isize {
+        match self {
+            SyscallError::NotFound => 2,
+            SyscallError::NoProcess => 3,
+            SyscallError::Interrupted => 4,
+            SyscallError::IoError => 5,
+            SyscallError::ArgumentListTooLong => 7,
+            SyscallError::ExecFormat => 8,
+            SyscallError::BadFd => 9,
+            SyscallError::NoChild => 10,
+            SyscallError::WouldBlock => 11,
+            SyscallError::OutOfMemory => 12,
+            SyscallError::BadAddress => 14,
+            SyscallError::Exists => 17,
+            SyscallError::NoDevice => 19,
+            SyscallError::InvalidArgument | SyscallError::NotSupported => 22,
+            SyscallError::TooManyFiles => 24,
+            SyscallError::NotSeekable => 29,
+            SyscallError::ReadOnly => 30,
+            SyscallError::NameTooLong => 36,
+            SyscallError::NoSyscall => 38,
+            SyscallError::TimedOut => 110,
+        }
+    }
+}
+
+impl From<UserAccessError> for SyscallError {
+    fn from(error: UserAccessError) -> Self {
+        match error {
+            UserAccessError::TooLong => SyscallError::NameTooLong,
+            UserAccessError::InvalidString => SyscallError::InvalidArgument,
+            _ => SyscallError::BadAddress,
+        }
+    }
+}
+
+impl From<ResourceError> for SyscallError {
+    fn from(error: ResourceError) -> Self {
+        match error {
+            ResourceError::BadIndex => SyscallError::BadFd,
+            ResourceError::TableFull => SyscallError::TooManyFiles,
+        }
+    }
+}
+
+impl From<FileError> for SyscallError {
+    fn from(error: FileError) -> Self {
+        match error {
+            FileError::NotReadable | FileError::NotWritable => SyscallError::NotSupported,
+            FileError::NotSeekable => SyscallError::NotSeekable,
+            FileError::Io => SyscallError::IoError,
+        }
+    }
+}
+
+impl From<MemSpaceError> for SyscallError {
+    fn from(error: MemSpaceError) -> Self {
+        match error {
+            MemSpaceError::BadAddress => SyscallError::InvalidArgument,
+            MemSpaceError::Overlap => SyscallError::Exists,
+            MemSpaceError::NotWritable => SyscallError::BadAddress,
+            MemSpaceError::NotMapped
+            | MemSpaceError::NoSpace
+            | MemSpaceError::PagingError { .. } => SyscallError::OutOfMemory,
+        }
+    }
+}
+
+impl From<LoadError> for SyscallError {
+    fn from(error: LoadError) -> Self {
+        match error {
+            LoadError::FileNotFound => SyscallError::NotFound,
+            LoadError::NoProcess => SyscallError::NoProcess,
+            LoadError::ElfError { .. } | LoadError::BadSegment { .. } => SyscallError::ExecFormat,
+            LoadError::ArgumentsTooLong => SyscallError::ArgumentListTooLong,
+            LoadError::MemSpaceError { .. } | LoadError::FrameAllocatorError { .. } => {
+                SyscallError::OutOfMemory
+            }
+        }
+    }
+}
+
+impl From<WaitError> for SyscallError {
+    fn from(error: WaitError) -> Self {
+        match error {
+            WaitError::NoChild => SyscallError::NoChild,
+            WaitError::Interrupted => SyscallError::Interrupted,
+        }
+    }
+}
+
+impl From<FutexError> for SyscallError {
+    fn from(error: FutexError) -> Self {
+        match error {
+            FutexError::WouldBlock => SyscallError::WouldBlock,
+            FutexError::TimedOut => SyscallError::TimedOut,
+            FutexError::Interrupted => SyscallError::Interrupted,
+            FutexError::UserAccessError { error } => error.into(),
+        }
+    }
+}
+
+impl From<SignalError> for SyscallError {
+    fn from(error: SignalError) -> Self {
+        match error {
+            SignalError::BadSignal | SignalError::BadOperation => SyscallError::InvalidArgument,
+        }
+    }
+}
+
+pub type SyscallResult = Result<usize, SyscallError>;
+
+// endregion
+
+// region: Table
+
+/// Conversion of a raw argument register into a handler parameter.
+pub trait SyscallArg {
+    fn from_arg(raw: usize) -> Self;
+}
+
+impl SyscallArg for usize {
+    fn from_arg(raw: usize) -> Self {
+        raw
+    }
+}
+
+impl SyscallArg for isize {
+    fn from_arg(raw: usize) -> Self {
+        raw as isize
+    }
+}
+
+/// 32-bit arguments only use the lower half of the register.
+impl SyscallArg for i32 {
+    fn from_arg(raw: usize) -> Self {
+        raw as i32
+    }
+}
+
+impl SyscallArg for u32 {
+    fn from_arg(raw: usize) -> Self {
+        raw as u32
+    }
+}
+
+impl<T: Copy> SyscallArg for UserPtr<T> {
+    fn from_arg(raw: usize) -> Self {
+        UserPtr::new(raw)
+    }
+}
+
+/// A handler gets the user context of the caller, with the arguments already read from it.
+pub type SyscallHandler = fn(&mut TrapContext, &[usize; 6]) -> SyscallResult;
+
+pub struct SyscallEntry {
+    pub id: usize,
+    pub name: &'static str,
+    /// Number of arguments, for tracing.
+    pub argc: usize,
+    pub handler: SyscallHandler,
+}
+
+/// Declare a [SyscallEntry] calling `handler` with its arguments converted to the given types, in order.
+/// With `context`, the handler also gets the user context of the caller first, to read or change registers.
+macro_rules! syscall_entry {
+    ($id: expr, $handler: path, ($($arg: ty),* $(,)?)) => {
+        $crate::syscall::SyscallEntry {
+            id: $id,
+            name: stringify!($handler),
+            argc: <[&str]>::len(&[$(stringify!($arg)),*]),
+            handler: |_context, _args| {
+                let mut _raw = _args.iter().copied();
+                $handler($(<$arg as $crate::syscall::SyscallArg>::from_arg(_raw.next().unwrap())),*)
+            },
+        }
+    };
+    ($id: expr, $handler: path, context, ($($arg: ty),* $(,)?)) => {
+        $crate::syscall::SyscallEntry {
+            id: $id,
+            name: stringify!($handler),
+            argc: <[&str]>::len(&[$(stringify!($arg)),*]),
+            handler: |context, _args| {
+                let mut _raw = _args.iter().copied();
+                $handler(
+                    context,
+                    $(<$arg as $crate::syscall::SyscallArg>::from_arg(_raw.next().unwrap())),*
+                )
+            },
+        }
+    };
+}
+
+pub(crate) use syscall_entry;
+
+pub static SYSCALL_TABLE: &[SyscallEntry] = &[
+    syscall_entry!(SYS_EXIT, task::sys_exit, (i32)),
+    syscall_entry!(SYS_YIELD, task::sys_yield, ()),
+    syscall_entry!(SYS_GETPID, task::sys_getpid, ()),
+    syscall_entry!(SYS_SLEEP, task::sys_sleep, (usize)),
+    syscall_entry!(SYS_WRITE, fs::sys_write, (usize, UserPtr<u8>, usize)),
+    syscall_entry!(SYS_BRK, mm::sys_brk, (usize)),
+    syscall_entry!(
+        SYS_THREAD_CREATE,
+        task::sys_thread_create,
+        (usize, usize, usize)
+    ),
+    syscall_entry!(SYS_GETTID, task::sys_gettid, ()),
+    syscall_entry!(SYS_FORK, task::sys_fork, context, ()),
+    syscall_entry!(
+        SYS_EXECVE,
+        task::sys_execve,
+        (UserPtr<u8>, UserPtr<usize>, UserPtr<usize>)
+    ),
+    syscall_entry!(SYS_WAITPID, task::sys_waitpid, (isize, UserPtr<i32>, u32)),
+    syscall_entry!(
+        SYS_FUTEX,
+        futex::sys_futex,
+        (UserPtr<u32>, u32, u32, usize, UserPtr<u32>, u32)
+    ),
+    syscall_entry!(SYS_KILL, signal::sys_kill, (isize, usize)),
+    syscall_entry!(
+        SYS_SIGACTION,
+        signal::sys_sigaction,
+        (usize, UserPtr<SigAction>, UserPtr<SigAction>)
+    ),
+    syscall_entry!(
+        SYS_SIGPROCMASK,
+        signal::sys_sigprocmask,
+        (u32, UserPtr<SigSet>, UserPtr<SigSet>)
+    ),
+    syscall_entry!(SYS_SIGRETURN, signal::sys_sigreturn, context, ()),
+    syscall_entry!(SYS_EXIT_GROUP, task::sys_exit_group, (i32)),
+];
+
+// endregion
+
+/// The process of the calling task.
+fn get_caller_process() -> Result<Arc<Process>, SyscallError> {
+    get_current_process().ok_or(SyscallError::NoProcess)
+}
+
+/// Run the system call requested by the user context `context`, and resume after the `ecall`.
+/// The number is looked up in the table of the personality of the caller.
+///
+/// The handler may block, or never return if the task exits.
+pub fn dispatch(context: &mut TrapContext) {
+    let id = context.get_syscall_id();
+    let args = context.get_syscall_args();
+    context.skip_syscall_instr();
+    let personality = get_current_process().map(|process| process.get_personality());
+    let table = match personality {
+        Some(Personality::Linux) => linux::LINUX_SYSCALL_TABLE,
+        _ => SYSCALL_TABLE,
+    };
+    let entry = table.iter().find(|entry| entry.id == id);
+    let res = match entry {
+        Some(entry) => (entry.handler)(context, &args),
+        None => Err(SyscallError::NoSyscall),
+    };
+    #[cfg(debug_assertions)]
+    {
+        let tid = crate::task::get_current_task().get_tid();
+        match entry {
+            Some(entry) => log::trace!(
+                "[Task #{:}] {:}({:#x?}) = {:?}",
+                tid,
+                entry.name,
+                &args[..entry.argc],
+                res
+            ),
+            None => log::trace!("[Task #{:}] Unknown syscall {:}.", tid, id),
+        }
+    }
+    context.set_syscall_ret(match res {
+        Ok(value) => value,
+        Err(error) => -error.code() as usize,
+    });
+}
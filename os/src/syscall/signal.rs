@@ -0,0 +1,70 @@
+//! Signal system calls.
+
+use crate::{
+    arch::trap::context::TrapContext,
+    mm::uaccess::UserPtr,
+    syscall::{SyscallError, SyscallResult, get_caller_process},
+    task::{
+        process::find_process,
+        signal::{
+            SigAction, SigSet, is_valid_signal, return_from_handler, send_signal, set_action,
+            set_blocked,
+        },
+    },
+};
+
+/// Send `sig` to the process `pid`. Signal 0 only checks that the process exists.
+pub fn sys_kill(pid: isize, sig: usize) -> SyscallResult {
+    if pid <= 0 || (sig != 0 && !is_valid_signal(sig)) {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let process = find_process(pid as usize).ok_or(SyscallError::NoProcess)?;
+    if sig != 0 {
+        send_signal(&process, sig);
+    }
+    Ok(0)
+}
+
+/// Set the action of `sig` to the one at `act`, and store the previous one at `oldact`,
+/// each unless it's NULL.
+pub fn sys_sigaction(
+    sig: usize,
+    act: UserPtr<SigAction>,
+    oldact: UserPtr<SigAction>,
+) -> SyscallResult {
+    if !is_valid_signal(sig) {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let action = if act.is_null() {
+        None
+    } else {
+        Some(act.read()?)
+    };
+    let old = set_action(&get_caller_process()?, sig, action)?;
+    if !oldact.is_null() {
+        oldact.write(&old)?;
+    }
+    Ok(0)
+}
+
+/// Change the signal mask of the calling thread with `how` and the set at `set`,
+/// and store the previous mask at `oldset`, each unless it's NULL.
+pub fn sys_sigprocmask(how: u32, set: UserPtr<SigSet>, oldset: UserPtr<SigSet>) -> SyscallResult {
+    let set = if set.is_null() {
+        None
+    } else {
+        Some(set.read()?)
+    };
+    let old = set_blocked(how, set)?;
+    if !oldset.is_null() {
+        oldset.write(&old)?;
+    }
+    Ok(0)
+}
+
+/// Return from a signal handler to the context it interrupted, saved in the signal frame on the stack.
+pub fn sys_sigreturn(context: &mut TrapContext) -> SyscallResult {
+    return_from_handler(context);
+    // The result goes to a0, which must keep its restored value.
+    Ok(context.x[10])
+}
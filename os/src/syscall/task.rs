@@ -0,0 +1,166 @@
+//! Task system calls.
+
+use crate::{
+    arch::{
+        MAX_USPACE_ADDR,
+        trap::{
+            context::TrapContext,
+            intr::{disable_intr, restore_intr},
+        },
+    },
+    mm::uaccess::{UserAccessError, UserPtr},
+    syscall::{SyscallError, SyscallResult, get_caller_process},
+    task::{
+        get_current_task,
+        loader::exec_current,
+        process::{ExitStatus, exit_thread},
+        scheduler::{add_task, schedule},
+        signal::inherit_signal_mask,
+        task::Task,
+    },
+    timer::sleep_ns,
+};
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+/// Wait options.
+pub const WNOHANG: u32 = 1;
+
+/// Maximum number of arguments or environment strings of a program.
+pub const MAX_ARG_STRINGS: usize = 4096;
+/// Maximum length of an argument or environment string, NUL excluded.
+pub const MAX_ARG_STRLEN: usize = 32 * 4096 - 1;
+/// Maximum length of a path, NUL excluded.
+pub const PATH_MAX: usize = 4095;
+
+/// Exit the calling thread. Its process exits with `code` once no other thread is left.
+pub fn sys_exit(code: i32) -> SyscallResult {
+    exit_thread(code)
+}
+
+/// Exit every thread of the calling process, which exits with `code`.
+pub fn sys_exit_group(code: i32) -> SyscallResult {
+    get_caller_process()?.exit_group(ExitStatus::Exited(code))
+}
+
+/// Give up the rest of the time slice.
+pub fn sys_yield() -> SyscallResult {
+    let intr = disable_intr();
+    schedule();
+    restore_intr(intr);
+    Ok(0)
+}
+
+pub fn sys_getpid() -> SyscallResult {
+    Ok(get_caller_process()?.get_pid())
+}
+
+pub fn sys_gettid() -> SyscallResult {
+    Ok(get_current_task().get_tid())
+}
+
+/// Start a thread of the calling process running `entry(arg)` on the stack `sp`, and return its tid.
+/// The caller allocates the stack and frees it once the thread has exited.
+pub fn sys_thread_create(entry: usize, sp: usize, arg: usize) -> SyscallResult {
+    if entry >= MAX_USPACE_ADDR || sp > MAX_USPACE_ADDR {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let process = get_caller_process()?;
+    let task = Task::new_user(&process.name, process.get_memsp(), entry, sp, arg)
+        .map_err(|_| SyscallError::OutOfMemory)?;
+    inherit_signal_mask(&task);
+    process.add_thread(&task);
+    let tid = task.get_tid();
+    add_task(task);
+    Ok(tid)
+}
+
+/// Block for at least `ns` nanoseconds.
+pub fn sys_sleep(ns: usize) -> SyscallResult {
+    sleep_ns(ns);
+    Ok(0)
+}
+
+/// Create a child process running a copy of the caller, and return its pid.
+/// The child resumes after the call with the same registers, but 0 as the result.
+///
+/// Only the calling thread is copied, with its signal mask. The memspace is copied on write,
+/// the file descriptors are shared and the signal actions are copied.
+pub fn sys_fork(context: &mut TrapContext) -> SyscallResult {
+    let process = get_caller_process()?;
+    let memsp = Arc::new(process.get_memsp().duplicate()?);
+    let mut regs = context.x;
+    regs[10] = 0; // a0
+    let task = Task::new_user_with_regs(&process.name, memsp.clone(), context.sepc, &regs)
+        .map_err(|_| SyscallError::OutOfMemory)?;
+    inherit_signal_mask(&task);
+    let child = process.new_child(&process.name, memsp);
+    *child.fds.lock_no_irq() = process.fds.lock_no_irq().clone();
+    child.add_thread(&task);
+    add_task(task);
+    Ok(child.get_pid())
+}
+
+/// Read the NULL-terminated array of strings at `array`. A NULL array is empty.
+pub fn read_str_array(array: UserPtr<usize>) -> Result<Vec<String>, SyscallError> {
+    let mut res = Vec::new();
+    if array.is_null() {
+        return Ok(res);
+    }
+    loop {
+        let ptr = UserPtr::<u8>::new(array.add(res.len()).read()?);
+        if ptr.is_null() {
+            return Ok(res);
+        }
+        if res.len() == MAX_ARG_STRINGS {
+            return Err(SyscallError::ArgumentListTooLong);
+        }
+        let s = ptr.read_str(MAX_ARG_STRLEN).map_err(|error| match error {
+            UserAccessError::TooLong => SyscallError::ArgumentListTooLong,
+            error => error.into(),
+        })?;
+        res.push(s);
+    }
+}
+
+/// Replace the program of the calling process with the executable `path` of the initrd,
+/// run with the NULL-terminated string arrays `argv` and `envp`. Only return on failure.
+///
+/// The other threads are killed, and the program runs in a new thread with another tid.
+pub fn sys_execve(path: UserPtr<u8>, argv: UserPtr<usize>, envp: UserPtr<usize>) -> SyscallResult {
+    let path = path.read_str(PATH_MAX)?;
+    let argv = read_str_array(argv)?;
+    let envp = read_str_array(envp)?;
+    let argv: Vec<&str> = argv.iter().map(String::as_str).collect();
+    let envp: Vec<&str> = envp.iter().map(String::as_str).collect();
+    match exec_current(&path, &argv, &envp) {
+        Ok(never) => match never {},
+        Err(error) => Err(error.into()),
+    }
+}
+
+/// Wait for a child to exit, `pid` or any child if it's -1, reap it and return its pid.
+/// Its exit code is stored at `status` unless it's NULL, `128 + sig` if a signal terminated it.
+/// With [WNOHANG], return 0 at once if no matching child has exited.
+pub fn sys_waitpid(pid: isize, status: UserPtr<i32>, options: u32) -> SyscallResult {
+    let pid = match pid {
+        -1 => None,
+        pid if pid > 0 => Some(pid as usize),
+        _ => return Err(SyscallError::InvalidArgument),
+    };
+    if options & !WNOHANG != 0 {
+        return Err(SyscallError::InvalidArgument);
+    }
+    let process = get_caller_process()?;
+    let res = if options & WNOHANG != 0 {
+        process.try_wait_child(pid)?
+    } else {
+        Some(process.wait_child(pid)?)
+    };
+    let Some((pid, exit_status)) = res else {
+        return Ok(0);
+    };
+    if !status.is_null() {
+        status.write(&exit_status.code())?;
+    }
+    Ok(pid)
+}
@@ -0,0 +1,270 @@
+//! Async Executor
+//!
+//! Futures spawned with [spawn] are polled by worker kernel threads started in [init].
+//! The workers are ordinary tasks of the preemptive scheduler: they block on a [WaitQueue]
+//! when no future is ready. A future is polled with preemption disabled, so that another worker
+//! picking it up meanwhile only spins briefly: **futures must not block.**
+//!
+//! Wakers are safe to use from interrupt handlers. Futures can wait on a [WaitQueue] with
+//! [WaitQueue::wait_until_async], on an interrupt with [AtomicWaker], or on the timer with [sleep].
+//!
+//! [block_on] runs a future to completion on the current task, or during initialization
+//! before the hart runs tasks.
+
+use crate::{
+    arch::trap::intr::{disable_intr, enable_intr, restore_intr, wait_for_intr},
+    dev::get_working_harts,
+    mutex::SpinLock,
+    panic_init,
+    task::{kthread, scheduler::is_scheduler_running, wait_queue::WaitQueue},
+    timer::{TimerHandle, add_timer, get_time, ns_to_time},
+};
+use alloc::{boxed::Box, collections::vec_deque::VecDeque, sync::Arc, task::Wake};
+use core::{
+    pin::{Pin, pin},
+    sync::atomic::{AtomicBool, Ordering},
+    task::{Context, Poll, Waker},
+};
+
+type BoxedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
+/// A spawned future. It's queued in [READY_QUEUE] when woken up.
+struct AsyncTask {
+    /// [None] once the future has completed.
+    future: SpinLock<Option<BoxedFuture>>,
+    /// Whether the task is in [READY_QUEUE], so that it's queued only once.
+    queued: AtomicBool,
+}
+
+impl Wake for AsyncTask {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        if !self.queued.swap(true, Ordering::AcqRel) {
+            READY_QUEUE.lock_no_irq().push_back(self.clone());
+            WORKERS.wake_one();
+        }
+    }
+}
+
+static READY_QUEUE: SpinLock<VecDeque<Arc<AsyncTask>>> = SpinLock::new(VecDeque::new());
+
+/// Idle workers wait here.
+static WORKERS: WaitQueue = WaitQueue::new();
+
+fn run_worker() {
+    loop {
+        WORKERS.wait_until(|| !READY_QUEUE.lock_no_irq().is_empty());
+        let task = READY_QUEUE.lock_no_irq().pop_front();
+        let Some(task) = task else {
+            continue;
+        };
+        // Cleared before polling, so that a wake up during the poll queues the task again.
+        task.queued.store(false, Ordering::Release);
+        let waker = Waker::from(task.clone());
+        let mut cx = Context::from_waker(&waker);
+        let mut future = task.future.lock_no_preempt();
+        let done = future
+            .as_mut()
+            .is_some_and(|fut| fut.as_mut().poll(&mut cx).is_ready());
+        if done {
+            *future = None;
+        }
+    }
+}
+
+// region: Spawn
+
+/// State shared by a spawned future and its [AsyncJoinHandle].
+struct JoinState<T> {
+    result: SpinLock<Option<T>>,
+    waker: AtomicWaker,
+}
+
+/// Handle to a spawned future. Await it for the output; dropping it detaches the future.
+pub struct AsyncJoinHandle<T> {
+    state: Arc<JoinState<T>>,
+}
+
+impl<T> Future for AsyncJoinHandle<T> {
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        self.state.waker.register(cx.waker());
+        match self.state.result.lock_no_irq().take() {
+            Some(res) => Poll::Ready(res),
+            None => Poll::Pending,
+        }
+    }
+}
+
+/// Run `future` on the executor workers.
+pub fn spawn<F>(future: F) -> AsyncJoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let state = Arc::new(JoinState {
+        result: SpinLock::new(None),
+        waker: AtomicWaker::new(),
+    });
+    let their_state = state.clone();
+    let task = Arc::new(AsyncTask {
+        future: SpinLock::new(Some(Box::pin(async move {
+            let res = future.await;
+            *their_state.result.lock_no_irq() = Some(res);
+            their_state.waker.wake();
+        }))),
+        queued: AtomicBool::new(false),
+    });
+    task.wake();
+    AsyncJoinHandle { state }
+}
+
+// endregion
+
+// region: Wakers
+
+/// Slot for the waker of a future waiting on an event, typically signaled by an interrupt handler.
+///
+/// The future calls [AtomicWaker::register] each time it's polled, then checks the event;
+/// the event source records the event, then calls [AtomicWaker::wake].
+pub struct AtomicWaker {
+    waker: SpinLock<Option<Waker>>,
+}
+
+impl AtomicWaker {
+    pub const fn new() -> AtomicWaker {
+        AtomicWaker {
+            waker: SpinLock::new(None),
+        }
+    }
+
+    pub fn register(&self, waker: &Waker) {
+        let mut slot = self.waker.lock_no_irq();
+        if !slot.as_ref().is_some_and(|old| old.will_wake(waker)) {
+            *slot = Some(waker.clone());
+        }
+    }
+
+    pub fn wake(&self) {
+        let waker = self.waker.lock_no_irq().take();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+// endregion
+
+// region: Timer
+
+/// Future resolving once [get_time] reaches its deadline.
+pub struct Sleep {
+    deadline: usize,
+    waker: Arc<AtomicWaker>,
+    timer: Option<TimerHandle>,
+}
+
+impl Future for Sleep {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        if get_time() >= self.deadline {
+            return Poll::Ready(());
+        }
+        self.waker.register(cx.waker());
+        if self.timer.is_none() {
+            let waker = self.waker.clone();
+            self.timer = Some(add_timer(self.deadline, move || waker.wake()));
+        }
+        Poll::Pending
+    }
+}
+
+impl Drop for Sleep {
+    fn drop(&mut self) {
+        if let Some(timer) = self.timer.take() {
+            timer.cancel();
+        }
+    }
+}
+
+pub fn sleep_until(deadline: usize) -> Sleep {
+    Sleep {
+        deadline,
+        waker: Arc::new(AtomicWaker::new()),
+        timer: None,
+    }
+}
+
+/// Sleep for at least `ns` nanoseconds.
+pub fn sleep(ns: usize) -> Sleep {
+    sleep_until(get_time() + ns_to_time(ns))
+}
+
+// endregion
+
+// region: Block On
+
+/// Waker of [block_on].
+struct BlockOnSignal {
+    woken: AtomicBool,
+    queue: WaitQueue,
+}
+
+impl Wake for BlockOnSignal {
+    fn wake(self: Arc<Self>) {
+        self.wake_by_ref();
+    }
+
+    fn wake_by_ref(self: &Arc<Self>) {
+        self.woken.store(true, Ordering::Release);
+        self.queue.wake_all();
+    }
+}
+
+/// Run `future` to completion on the current task, blocking it while the future is pending.
+///
+/// Before the hart runs tasks, e.g. in init code, the hart waits for interrupts instead.
+/// **Preemption must be enabled once the hart runs tasks.**
+pub fn block_on<F: Future>(future: F) -> F::Output {
+    let mut future = pin!(future);
+    let signal = Arc::new(BlockOnSignal {
+        woken: AtomicBool::new(false),
+        queue: WaitQueue::new(),
+    });
+    let waker = Waker::from(signal.clone());
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        if let Poll::Ready(res) = future.as_mut().poll(&mut cx) {
+            return res;
+        }
+        if is_scheduler_running() {
+            signal
+                .queue
+                .wait_until(|| signal.woken.swap(false, Ordering::AcqRel));
+        } else {
+            // The tick is running, so the hart wakes up at least once per tick.
+            let intr = disable_intr();
+            while !signal.woken.swap(false, Ordering::AcqRel) {
+                enable_intr();
+                wait_for_intr();
+                disable_intr();
+            }
+            restore_intr(intr);
+        }
+    }
+}
+
+// endregion
+
+/// Start one executor worker per working hart.
+pub fn init() {
+    for _ in get_working_harts() {
+        kthread::spawn("executor", run_worker)
+            .unwrap_or_else(|err| panic_init!("Unable to start executor worker: {:?}", err));
+    }
+}
@@ -0,0 +1,272 @@
+//! Futexes
+//!
+//! A futex is a user word that threads block on until another thread wakes them up,
+//! so that user locks only enter the kernel when they are contended.
+//!
+//! Waiters queue up by [FutexKey] in a fixed table of buckets, hashed from the key:
+//! - private futexes are keyed by memspace and user address, and only shared by its threads;
+//! - shared futexes are keyed by physical address, so they work across memspaces mapping the same frame.
+
+use crate::{
+    arch::{
+        PAGE_WIDTH,
+        mm::{PageNum, paging::PageTableFlags},
+        trap::intr::{disable_intr, restore_intr},
+    },
+    mm::{
+        config::PAGE_SIZE,
+        space::MemSpace,
+        uaccess::{UserAccessError, UserPtr},
+    },
+    mutex::SpinLock,
+    task::{
+        get_current_task,
+        scheduler::{block_current, wake_up},
+        task::Task,
+    },
+    timer::{add_timer, get_time},
+};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc, vec::Vec};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+/// Errors returned by futex operations.
+#[derive(Debug)]
+pub enum FutexError {
+    /// The futex word doesn't hold the expected value.
+    WouldBlock,
+    TimedOut,
+    /// The waiter was woken up by something else than a futex operation, e.g. because it got a signal.
+    Interrupted,
+    UserAccessError {
+        error: UserAccessError,
+    },
+}
+
+impl From<UserAccessError> for FutexError {
+    fn from(error: UserAccessError) -> Self {
+        FutexError::UserAccessError { error }
+    }
+}
+
+/// Identity of a futex.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FutexKey {
+    /// A futex of the threads of a memspace, by memspace address and user address.
+    Private { memsp: usize, addr: usize },
+    /// A futex of any memspace mapping the frame, by physical address.
+    Shared { paddr: usize },
+}
+
+impl FutexKey {
+    /// The private futex at user address `addr` of `memsp`.
+    pub fn private(memsp: &MemSpace, addr: usize) -> FutexKey {
+        FutexKey::Private {
+            memsp: memsp as *const MemSpace as usize,
+            addr,
+        }
+    }
+
+    /// The shared futex at user address `addr` of `memsp`, which must be mapped writable.
+    /// A copy-on-write page gets its own frame first, so that the key stays valid.
+    ///
+    /// **The other harts must be running**, as a copied page is flushed from their TLBs.
+    pub fn shared(memsp: &MemSpace, addr: usize) -> Result<FutexKey, FutexError> {
+        let page = PageNum::from_const(addr >> PAGE_WIDTH);
+        let writable = |memsp: &MemSpace| {
+            memsp.lookup(page).filter(|entry| {
+                entry
+                    .get_flags()
+                    .contains(PageTableFlags::USER | PageTableFlags::W)
+            })
+        };
+        let entry = match writable(memsp) {
+            Some(entry) => entry,
+            None if memsp.handle_write_fault(addr) => {
+                writable(memsp).ok_or(UserAccessError::NotMapped)?
+            }
+            None => return Err(UserAccessError::NotMapped.into()),
+        };
+        Ok(FutexKey::Shared {
+            paddr: entry.get_ppn().get_base_addr() + (addr & (PAGE_SIZE - 1)),
+        })
+    }
+
+    /// Index of the bucket of the key, by Fibonacci hashing.
+    fn bucket(&self) -> usize {
+        const MULTIPLIER: u64 = 0x9e37_79b9_7f4a_7c15;
+        let (a, b) = match *self {
+            FutexKey::Private { memsp, addr } => (memsp as u64, addr as u64),
+            FutexKey::Shared { paddr } => (0, paddr as u64),
+        };
+        let hash = (a.wrapping_mul(MULTIPLIER) ^ b).wrapping_mul(MULTIPLIER);
+        (hash >> (u64::BITS as usize - FUTEX_HASH_BITS)) as usize
+    }
+}
+
+struct FutexWaiter {
+    task: Arc<Task>,
+    /// The futex waited on, changed when the waiter is requeued with both buckets locked.
+    key: SpinLock<FutexKey>,
+    /// Set by a futex operation when it takes the waiter off its bucket to wake it up.
+    woken: AtomicBool,
+}
+
+impl FutexWaiter {
+    fn get_key(&self) -> FutexKey {
+        *self.key.lock_no_irq()
+    }
+}
+
+const FUTEX_HASH_BITS: usize = 6;
+const FUTEX_HASH_SIZE: usize = 1 << FUTEX_HASH_BITS;
+
+/// Waiters of the futexes hashing to each bucket, in arrival order.
+type FutexBucket = VecDeque<Arc<FutexWaiter>>;
+
+static FUTEX_BUCKETS: [SpinLock<FutexBucket>; FUTEX_HASH_SIZE] =
+    [const { SpinLock::new(VecDeque::new()) }; FUTEX_HASH_SIZE];
+
+/// Block the current task on the futex `key`, whose word is at `uaddr` in the current memspace,
+/// if the word holds `expected`. It's woken up by [futex_wake] or [futex_requeue],
+/// or once [get_time] reaches `deadline`.
+///
+/// **Preemption must be enabled.**
+pub fn futex_wait(
+    key: FutexKey,
+    uaddr: UserPtr<u32>,
+    expected: u32,
+    deadline: Option<usize>,
+) -> Result<(), FutexError> {
+    let waiter = Arc::new(FutexWaiter {
+        task: get_current_task(),
+        key: SpinLock::new(key),
+        woken: AtomicBool::new(false),
+    });
+    let intr = disable_intr();
+    let mut bucket = FUTEX_BUCKETS[key.bucket()].lock();
+    // The word is read with the bucket locked, so that a waker changing it
+    // and then waking the futex can't slip in before the task is queued.
+    // Killers and signal senders don't lock the bucket: if they slip in after the check,
+    // the task is interrupted and doesn't block, see `scheduler::interrupt`.
+    let res = match uaddr.read() {
+        Ok(value) if value != expected => Err(FutexError::WouldBlock),
+        Ok(_) if waiter.task.is_interrupted() => Err(FutexError::Interrupted),
+        Ok(_) => Ok(()),
+        Err(error) => Err(error.into()),
+    };
+    if let Err(error) = res {
+        drop(bucket);
+        restore_intr(intr);
+        return Err(error);
+    }
+    bucket.push_back(waiter.clone());
+    // The timer fires on this hart, so not before the task is blocked.
+    let timer = deadline.map(|deadline| {
+        let task = waiter.task.clone();
+        add_timer(deadline, move || wake_up(task))
+    });
+    block_current(move |_| drop(bucket));
+    restore_intr(intr);
+
+    if let Some(timer) = timer {
+        timer.cancel();
+    }
+    if waiter.woken.load(Ordering::Acquire) || !remove_waiter(&waiter) {
+        // Taken off its bucket by a waker, maybe after another wake up.
+        return Ok(());
+    }
+    match deadline {
+        Some(deadline) if get_time() >= deadline => Err(FutexError::TimedOut),
+        _ => Err(FutexError::Interrupted),
+    }
+}
+
+/// Take `waiter` off its bucket. Return `false` if it's not there any more.
+fn remove_waiter(waiter: &Arc<FutexWaiter>) -> bool {
+    loop {
+        let key = waiter.get_key();
+        let mut bucket = FUTEX_BUCKETS[key.bucket()].lock_no_irq();
+        // Requeued meanwhile, to a key of another bucket maybe.
+        if waiter.get_key() != key {
+            continue;
+        }
+        let Some(index) = bucket.iter().position(|other| Arc::ptr_eq(other, waiter)) else {
+            return false;
+        };
+        bucket.remove(index);
+        return true;
+    }
+}
+
+/// Take up to `count` waiters of `key` off `bucket`, in arrival order.
+fn take_waiters(bucket: &mut FutexBucket, key: FutexKey, count: usize) -> Vec<Arc<FutexWaiter>> {
+    let mut res = Vec::new();
+    let mut index = 0;
+    while res.len() < count && index < bucket.len() {
+        if bucket[index].get_key() == key {
+            res.extend(bucket.remove(index));
+        } else {
+            index += 1;
+        }
+    }
+    res
+}
+
+fn wake_waiters(waiters: &[Arc<FutexWaiter>]) {
+    for waiter in waiters {
+        waiter.woken.store(true, Ordering::Release);
+        wake_up(waiter.task.clone());
+    }
+}
+
+/// Wake up to `count` tasks waiting on the futex `key`, and return their number.
+pub fn futex_wake(key: FutexKey, count: usize) -> usize {
+    let mut bucket = FUTEX_BUCKETS[key.bucket()].lock_no_irq();
+    let woken = take_waiters(&mut bucket, key, count);
+    drop(bucket);
+    wake_waiters(&woken);
+    woken.len()
+}
+
+/// Wake up to `nr_wake` tasks waiting on the futex `key`, and move up to `nr_requeue` of the others
+/// to the futex `target`, where they wait for its wake ups instead. Return the number of tasks woken or moved.
+///
+/// With `expected`, fail if the futex word doesn't hold the value, checked with the waiters locked.
+pub fn futex_requeue(
+    key: FutexKey,
+    target: FutexKey,
+    nr_wake: usize,
+    nr_requeue: usize,
+    expected: Option<(UserPtr<u32>, u32)>,
+) -> Result<usize, FutexError> {
+    let (from, to) = (key.bucket(), target.bucket());
+    // Buckets are locked in index order, so that concurrent requeues can't deadlock.
+    let mut first = FUTEX_BUCKETS[from.min(to)].lock_no_irq();
+    let mut second = (from != to).then(|| FUTEX_BUCKETS[from.max(to)].lock_no_irq());
+    let matches = match expected {
+        Some((uaddr, expected)) => uaddr.read()? == expected,
+        None => true,
+    };
+    if !matches {
+        return Err(FutexError::WouldBlock);
+    }
+    let (src, dst) = match second.as_mut() {
+        None => (&mut *first, None),
+        Some(second) if from < to => (&mut *first, Some(&mut **second)),
+        Some(second) => (&mut **second, Some(&mut *first)),
+    };
+    let woken = take_waiters(src, key, nr_wake);
+    let moved = take_waiters(src, key, nr_requeue);
+    for waiter in &moved {
+        *waiter.key.lock_no_irq() = target;
+    }
+    let count = woken.len() + moved.len();
+    match dst {
+        Some(dst) => dst.extend(moved),
+        None => src.extend(moved),
+    }
+    drop(second);
+    drop(first);
+    wake_waiters(&woken);
+    Ok(count)
+}
@@ -0,0 +1,125 @@
+//! Kernel Threads
+//!
+//! [spawn] runs a closure in a new kernel task and returns a [JoinHandle] to collect its result.
+//! The closure is boxed and passed to [kthread_entry], which exits the task once the closure returns.
+
+use crate::{
+    arch::hart::get_current_hart_id,
+    mm::frame::FrameAllocatorError,
+    mutex::SpinLock,
+    task::{
+        scheduler::{add_task, exit_current},
+        task::Task,
+        wait_queue::WaitQueue,
+    },
+};
+use alloc::{boxed::Box, sync::Arc};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+type ThreadMain = Box<dyn FnOnce() + Send>;
+
+/// Result of a kernel thread, shared with its [JoinHandle].
+struct Packet<T> {
+    result: SpinLock<Option<T>>,
+    finished: AtomicBool,
+    waiters: WaitQueue,
+}
+
+/// Handle to a spawned kernel thread. Dropping it detaches the thread.
+pub struct JoinHandle<T> {
+    task: Arc<Task>,
+    packet: Arc<Packet<T>>,
+}
+
+impl<T> JoinHandle<T> {
+    /// Block until the thread returns and take its result.
+    pub fn join(self) -> T {
+        self.packet
+            .waiters
+            .wait_until(|| self.packet.finished.load(Ordering::Acquire));
+        self.packet
+            .result
+            .lock_no_irq()
+            .take()
+            .expect("Kernel thread finished without a result.")
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::Acquire)
+    }
+
+    pub fn task(&self) -> &Arc<Task> {
+        &self.task
+    }
+}
+
+/// Spawn a kernel thread running `f` on any hart.
+pub fn spawn<F, T>(name: &str, f: F) -> Result<JoinHandle<T>, FrameAllocatorError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_inner(name, None, f)
+}
+
+/// Spawn a kernel thread pinned to hart `hart_id`, as a per-hart worker.
+pub fn spawn_on<F, T>(
+    name: &str,
+    hart_id: usize,
+    f: F,
+) -> Result<JoinHandle<T>, FrameAllocatorError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    spawn_inner(name, Some(hart_id), f)
+}
+
+fn spawn_inner<F, T>(
+    name: &str,
+    hart_id: Option<usize>,
+    f: F,
+) -> Result<JoinHandle<T>, FrameAllocatorError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet {
+        result: SpinLock::new(None),
+        finished: AtomicBool::new(false),
+        waiters: WaitQueue::new(),
+    });
+    let their_packet = packet.clone();
+    let main: ThreadMain = Box::new(move || {
+        let res = f();
+        *their_packet.result.lock_no_irq() = Some(res);
+        their_packet.finished.store(true, Ordering::Release);
+        their_packet.waiters.wake_all();
+    });
+    // Box again for a thin pointer fitting in a register.
+    let arg = Box::into_raw(Box::new(main)) as usize;
+    let task = match Task::new_kernel(
+        name,
+        kthread_entry,
+        arg,
+        hart_id.unwrap_or_else(get_current_hart_id),
+    ) {
+        Ok(task) => task,
+        Err(err) => {
+            drop(unsafe { Box::from_raw(arg as *mut ThreadMain) });
+            return Err(err);
+        }
+    };
+    if let Some(hart_id) = hart_id {
+        task.sched.pin(hart_id);
+    }
+    add_task(task.clone());
+    Ok(JoinHandle { task, packet })
+}
+
+/// Entry of kernel threads. `arg` is the pointer to the boxed [ThreadMain].
+extern "C" fn kthread_entry(arg: usize) -> ! {
+    let main = unsafe { Box::from_raw(arg as *mut ThreadMain) };
+    main();
+    exit_current()
+}
@@ -0,0 +1,301 @@
+//! Program Loader
+//!
+//! Load a statically linked ELF executable into a new user memspace and lay out its initial stack
+//! as Linux does. Parsing and validation are done by the [elf] crate.
+//!
+//! Programs built with `karox_api` carry a `karox` note and run with the karox personality,
+//! the others are taken for Linux binaries. The page above the stack holds the signal trampoline,
+//! which calls the `sigreturn` of the personality.
+//!
+//! The first user program, init, is loaded from the initrd by [start_init].
+//! Processes replace their program with [exec_current].
+
+use crate::{
+    arch::{
+        ELF_HWCAP, ELF_MACHINE, PAGE_WIDTH, mm::paging::PageTableFlags,
+        trap::signal::trampoline_code,
+    },
+    console::ConsoleFile,
+    initrd,
+    mm::{
+        config::{PAGE_SIZE, SIGNAL_TRAMPOLINE, USER_STACK_PAGES, USER_STACK_SIZE, USER_STACK_TOP},
+        frame::FrameAllocatorError,
+        paging::PagingError,
+        space::{MemSpace, MemSpaceError},
+    },
+    syscall::{SYS_SIGRETURN, linux::SYS_RT_SIGRETURN},
+    task::{
+        process::{Personality, Process, exit_thread, get_current_process},
+        resource::{File, STDERR_FD, STDIN_FD},
+        scheduler::add_task,
+        signal::inherit_signal_mask,
+        task::Task,
+    },
+    timer::get_time,
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    convert::Infallible,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+use elf::{
+    ElfError, ElfFile, PF_R, PF_W, PF_X,
+    file::PHDR_SIZE,
+    stack::{
+        AT_BASE, AT_CLKTCK, AT_EGID, AT_ENTRY, AT_EUID, AT_EXECFN, AT_FLAGS, AT_GID, AT_HWCAP,
+        AT_PAGESZ, AT_PHDR, AT_PHENT, AT_PHNUM, AT_RANDOM, AT_SECURE, AT_UID, InitialStack,
+    },
+};
+use utils::num::AlignableTo;
+
+/// Path of the init program in the initrd.
+pub const INIT_PATH: &str = "/init";
+
+/// Owner and type of the note marking karox programs.
+pub const KAROX_NOTE_NAME: &[u8] = b"karox";
+pub const NT_KAROX_ABI: u32 = 1;
+
+/// Clock ticks per second reported in `AT_CLKTCK`, the Linux `USER_HZ`.
+const CLOCK_TICKS: usize = 100;
+
+/// Errors returned by [load_elf] and [start_init].
+#[derive(Debug)]
+pub enum LoadError {
+    /// No such file in the initrd.
+    FileNotFound,
+    /// The caller of [exec_current] belongs to no process.
+    NoProcess,
+    /// The file is not a valid executable for this machine.
+    ElfError {
+        error: ElfError,
+    },
+    /// A segment overlaps the user stack or the kernel.
+    BadSegment {
+        vaddr: usize,
+    },
+    /// The arguments and environment don't fit in the user stack.
+    ArgumentsTooLong,
+    MemSpaceError {
+        error: MemSpaceError,
+    },
+    FrameAllocatorError {
+        error: FrameAllocatorError,
+    },
+}
+
+impl From<ElfError> for LoadError {
+    fn from(error: ElfError) -> Self {
+        LoadError::ElfError { error }
+    }
+}
+
+impl From<MemSpaceError> for LoadError {
+    fn from(error: MemSpaceError) -> Self {
+        LoadError::MemSpaceError { error }
+    }
+}
+
+impl From<PagingError> for LoadError {
+    fn from(error: PagingError) -> Self {
+        LoadError::MemSpaceError {
+            error: MemSpaceError::PagingError { error },
+        }
+    }
+}
+
+/// A program loaded in its memspace, ready to run with [crate::task::task::Task::new_user].
+#[derive(Debug)]
+pub struct LoadedProgram {
+    pub memsp: Arc<MemSpace>,
+    pub entry: usize,
+    pub user_sp: usize,
+    pub personality: Personality,
+}
+
+fn segment_perm(flags: u32) -> PageTableFlags {
+    let mut perm = PageTableFlags::NUL;
+    if flags & PF_R != 0 {
+        perm |= PageTableFlags::R;
+    }
+    if flags & PF_W != 0 {
+        // Write-only pages are reserved encodings on RISC-V.
+        perm |= PageTableFlags::RW;
+    }
+    if flags & PF_X != 0 {
+        perm |= PageTableFlags::X;
+    }
+    perm
+}
+
+fn get_personality(file: &ElfFile) -> Personality {
+    let karox = file
+        .notes()
+        .any(|note| note.name == KAROX_NOTE_NAME && note.n_type == NT_KAROX_ABI);
+    if karox {
+        Personality::Karox
+    } else {
+        Personality::Linux
+    }
+}
+
+/// Bytes for `AT_RANDOM`, from the timer and a sequence number. User programs seed
+/// stack canaries and hash tables with them, but they are not cryptographically strong.
+fn get_random_bytes() -> [u8; 16] {
+    static SEQ: AtomicUsize = AtomicUsize::new(0);
+    let mut state = (get_time() ^ SEQ.fetch_add(1, Ordering::Relaxed).rotate_left(32)) as u64;
+    // SplitMix64
+    let mut next = || {
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let mut res = [0; 16];
+    res[..8].copy_from_slice(&next().to_le_bytes());
+    res[8..].copy_from_slice(&next().to_le_bytes());
+    res
+}
+
+/// Load the executable `data` found at `path` into a new memspace,
+/// with `argv` and `envp` on its initial stack.
+pub fn load_elf(
+    data: &[u8],
+    path: &str,
+    argv: &[&str],
+    envp: &[&str],
+) -> Result<LoadedProgram, LoadError> {
+    let file = ElfFile::parse(data, ELF_MACHINE)?;
+    let personality = get_personality(&file);
+    let memsp = MemSpace::new_user()?;
+    let stack_bottom = USER_STACK_TOP - USER_STACK_SIZE;
+    let mut image_end = 0;
+
+    for ph in file.load_segments() {
+        let vaddr = ph.vaddr as usize;
+        let end = vaddr + ph.memsz as usize;
+        image_end = image_end.max(end);
+        // The trampoline page above the stack must stay free too.
+        if end > stack_bottom && vaddr < USER_STACK_TOP + PAGE_SIZE {
+            return Err(LoadError::BadSegment { vaddr });
+        }
+        let start_vpn = vaddr >> PAGE_WIDTH;
+        let end_vpn = (end + PAGE_SIZE - 1) >> PAGE_WIDTH;
+        memsp
+            .map_user(start_vpn, end_vpn - start_vpn, segment_perm(ph.flags))
+            .map_err(|error| match error {
+                MemSpaceError::BadAddress => LoadError::BadSegment { vaddr },
+                error => LoadError::MemSpaceError { error },
+            })?;
+        // The rest of the segment (bss) stays zero-filled.
+        memsp.write_user(vaddr, file.segment_data(&ph))?;
+    }
+
+    // The heap starts after the image, and mmap areas below a guard page under the stack.
+    let heap_start = image_end.align_up(PAGE_SIZE);
+    memsp.init_layout(heap_start, (stack_bottom - PAGE_SIZE).max(heap_start));
+
+    memsp.map_user(
+        stack_bottom >> PAGE_WIDTH,
+        USER_STACK_PAGES,
+        PageTableFlags::RW,
+    )?;
+    let sigreturn = match personality {
+        Personality::Karox => SYS_SIGRETURN,
+        Personality::Linux => SYS_RT_SIGRETURN,
+    };
+    memsp.map_user(SIGNAL_TRAMPOLINE >> PAGE_WIDTH, 1, PageTableFlags::RX)?;
+    memsp.write_user(SIGNAL_TRAMPOLINE, &trampoline_code(sigreturn))?;
+    let entry = file.entry as usize;
+    let mut auxv = Vec::new();
+    if let Some(phdr) = file.phdr_vaddr() {
+        auxv.push((AT_PHDR, phdr as usize));
+    }
+    // Processes have no credentials, they all run as root.
+    auxv.extend_from_slice(&[
+        (AT_PHENT, PHDR_SIZE),
+        (AT_PHNUM, file.phnum()),
+        (AT_PAGESZ, PAGE_SIZE),
+        (AT_BASE, 0),
+        (AT_FLAGS, 0),
+        (AT_ENTRY, entry),
+        (AT_UID, 0),
+        (AT_EUID, 0),
+        (AT_GID, 0),
+        (AT_EGID, 0),
+        (AT_HWCAP, ELF_HWCAP),
+        (AT_CLKTCK, CLOCK_TICKS),
+        (AT_SECURE, 0),
+    ]);
+    let random = get_random_bytes();
+    let mut execfn = Vec::from(path.as_bytes());
+    execfn.push(0);
+    let aux_data: [(usize, &[u8]); 2] = [(AT_RANDOM, &random), (AT_EXECFN, &execfn)];
+    let stack = InitialStack::build(
+        USER_STACK_TOP,
+        USER_STACK_SIZE,
+        argv,
+        envp,
+        &auxv,
+        &aux_data,
+    )
+    .ok_or(LoadError::ArgumentsTooLong)?;
+    memsp.write_user(stack.sp, &stack.image)?;
+
+    Ok(LoadedProgram {
+        memsp: Arc::new(memsp),
+        entry,
+        user_sp: stack.sp,
+        personality,
+    })
+}
+
+/// Load [INIT_PATH] from the initrd and start it as the init process, with the console as its standard streams.
+pub fn start_init() -> Result<Arc<Process>, LoadError> {
+    let data = initrd::get_file(INIT_PATH).ok_or(LoadError::FileNotFound)?;
+    let program = load_elf(data, INIT_PATH, &[INIT_PATH], &[])?;
+    let task = Task::new_user(
+        "init",
+        program.memsp.clone(),
+        program.entry,
+        program.user_sp,
+        0,
+    )
+    .map_err(|error| LoadError::FrameAllocatorError { error })?;
+    let process = Process::new_init("init", program.memsp, program.personality);
+    let console: Arc<dyn File> = Arc::new(ConsoleFile);
+    let mut fds = process.fds.lock_no_irq();
+    for fd in STDIN_FD..=STDERR_FD {
+        fds.insert_at(fd, console.clone()).unwrap();
+    }
+    drop(fds);
+    process.add_thread(&task);
+    add_task(task);
+    Ok(process)
+}
+
+/// Replace the program of the current process with the executable `path` of the initrd.
+///
+/// The other threads are killed, and the program starts in a new thread replacing the caller,
+/// so its tid changes but it keeps the signal mask. Only return on failure, leaving the process untouched.
+///
+/// **Preemption must be enabled**, as the caller exits.
+pub fn exec_current(path: &str, argv: &[&str], envp: &[&str]) -> Result<Infallible, LoadError> {
+    let process = get_current_process().ok_or(LoadError::NoProcess)?;
+    let data = initrd::get_file(path).ok_or(LoadError::FileNotFound)?;
+    let program = load_elf(data, path, argv, envp)?;
+    let task = Task::new_user(
+        &process.name,
+        program.memsp.clone(),
+        program.entry,
+        program.user_sp,
+        0,
+    )
+    .map_err(|error| LoadError::FrameAllocatorError { error })?;
+    inherit_signal_mask(&task);
+    process.kill_other_threads();
+    process.replace_image(program.memsp, program.personality);
+    process.add_thread(&task);
+    add_task(task);
+    exit_thread(0)
+}
@@ -20,46 +20,54 @@
 
 use crate::{
     arch::task::context::TaskContext,
-    task::{
-        preempt::{disable_preempt, restore_preempt},
-        processor::get_current_processor_context,
-        task::Task,
-    },
+    task::{processor::get_current_processor_context, task::Task},
 };
 use alloc::sync::Arc;
 
+pub mod executor;
+pub mod futex;
+pub mod kthread;
+pub mod loader;
 pub mod preempt;
+pub mod process;
 pub mod processor;
+pub mod registry;
+pub mod resource;
 pub mod scheduler;
+pub mod signal;
+pub mod stats;
 pub mod task;
 pub mod tid;
+pub mod wait_queue;
 
 /// Get the current running task.
+///
+/// **The hart must run tasks.**
 pub fn get_current_task() -> Arc<Task> {
-    disable_preempt();
-    let inner = unsafe { get_current_processor_context().inner.access() };
-    let res = inner.running_task.as_ref().unwrap().clone();
-    drop(inner);
-    restore_preempt();
+    let processor = get_current_processor_context();
+    let res = processor
+        .inner
+        .borrow()
+        .running_task
+        .as_ref()
+        .unwrap()
+        .clone();
+    drop(processor);
     res
 }
 
 /// Get a pointer to the scheduling context of the current hart.
 pub fn get_current_sched_context() -> *const TaskContext {
-    disable_preempt();
-    let inner = unsafe { get_current_processor_context().inner.access() };
-    let res = &inner.sched_context as *const TaskContext;
-    drop(inner);
-    restore_preempt();
+    let processor = get_current_processor_context();
+    let res = &processor.inner.borrow().sched_context as *const TaskContext;
+    drop(processor);
     res
 }
 
 /// Get a mutable pointer to the scheduling context of the current hart.
 pub fn get_current_sched_context_mut() -> *mut TaskContext {
-    disable_preempt();
-    let mut inner = unsafe { get_current_processor_context().inner.exclusive_access() };
-    let res = &mut inner.sched_context as *mut TaskContext;
-    drop(inner);
-    restore_preempt();
+    let processor = get_current_processor_context();
+    let res = &mut processor.inner.borrow_mut().sched_context as *mut TaskContext;
+    drop(processor);
     res
 }
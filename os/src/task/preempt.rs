@@ -30,43 +30,23 @@ impl PreemptCounter {
         self.need_resched.store(true, Ordering::Relaxed);
     }
 
+    /// **Call it with interrupts disabled**, as [disable_preempt] does.
     pub fn disable(&self) {
         self.counter.fetch_add(1, Ordering::Relaxed);
     }
 
     /// ## Notes:
-    /// Since [PreemptCounter] is only expected to appear as a member inside [super::hart::HartInfo],
-    /// there is no need to consider genuine asynchronous concurrent access from different harts;  
-    /// the use of [AtomicUsize] and similar atomic structures is to
-    /// ensure view consistency in the presence of preemption occurring during the execution of
-    /// disable/restore preemption operations.
+    /// The counter belongs to the hart, not to the task: it must be updated on the hart
+    /// whose counter was read, before the task can move to another one.
+    /// Hence **call it with interrupts disabled**, as [restore_preempt] does.
+    /// Once the counter is nonzero, the task is not preempted, so it stays on the hart.
     ///
-    /// Although preemption is restored, as long as [PreemptCounter::need_resched] is true,
-    /// it indicates that the context is still in the Task Execution Environment, so nested scheduling cannot occur.
-    ///
-    /// (because we assume that [disable_preempt] and [restore_preempt] always appear in matching pairs,
-    /// and the Scheduling Environment itself cannot be preempted).
     /// If [PreemptCounter::need_resched] is true, it means the counter was already nonzero when preemption occurred,
     /// so when the counter is zero, we must have returned to the Task Execution Environment.
     pub fn restore(&self) {
-        let mut need_resched = false;
-        if self.get_count() == 1 {
-            if let Ok(_) = self.need_resched.compare_exchange(
-                true,
-                false,
-                Ordering::Relaxed,
-                Ordering::Relaxed,
-            ) {
-                need_resched = true;
-            }
-        }
-        if need_resched {
-            let token = disable_intr();
-            self.counter.fetch_sub(1, Ordering::Relaxed);
+        let count = self.counter.fetch_sub(1, Ordering::Relaxed);
+        if count == 1 && self.need_resched.swap(false, Ordering::Relaxed) {
             schedule();
-            restore_intr(token);
-        } else {
-            self.counter.fetch_sub(1, Ordering::Relaxed);
         }
     }
 
@@ -75,13 +55,17 @@ impl PreemptCounter {
     }
 }
 
+/// Disable preemption. Interrupts are disabled meanwhile,
+/// so that the task can't move to another hart between the lookup of the hart and the update.
 pub fn disable_preempt() {
-    let hart = get_current_hart();
-    hart.preempt.disable();
+    let intr = disable_intr();
+    get_current_hart().preempt.disable();
+    restore_intr(intr);
 }
 
 /// Restore Preemption
 pub fn restore_preempt() {
-    let hart = get_current_hart();
-    hart.preempt.restore();
+    let intr = disable_intr();
+    get_current_hart().preempt.restore();
+    restore_intr(intr);
 }
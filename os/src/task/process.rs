@@ -0,0 +1,501 @@
+//! Processes
+//!
+//! A [Process] groups the threads sharing a memory space and resource tables.
+//! Processes form a tree rooted at init:
+//! - a process exits when its last thread exits, and stays a zombie until its parent
+//!   collects the exit code with [Process::wait_child];
+//! - the children of an exiting process are handed over to init, which reaps them.
+//!
+//! Kernel threads belong to no process.
+//!
+//! A process runs with a [Personality], the system call interface of its program.
+//! Threads are killed by marking them: they exit the next time they would return to user mode.
+//! Processes also end on signals, see [crate::task::signal].
+
+use crate::{
+    mm::{space::MemSpace, uaccess::UserPtr},
+    mutex::SpinLock,
+    task::{
+        futex::{FutexKey, futex_wake},
+        get_current_task,
+        resource::{FdTable, HandleTable},
+        scheduler::{exit_current, interrupt},
+        signal::{ProcessSignals, SIGCHLD, send_signal},
+        task::Task,
+        tid::TaskIdAllocator,
+        wait_queue::WaitQueue,
+    },
+};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    string::{String, ToString},
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use core::{
+    fmt::{Debug, Formatter},
+    sync::atomic::{AtomicBool, Ordering},
+};
+use spin::Once;
+
+// region: Pid
+
+/// Pid of init, the first process.
+pub const INIT_PID: usize = 1;
+
+static PID_ALLOC: SpinLock<TaskIdAllocator> =
+    SpinLock::new(TaskIdAllocator::starting_from(INIT_PID));
+
+/// Safe wrapper around pids that frees the managed pid on drop.
+#[derive(Debug)]
+pub struct ProcessId {
+    inner: usize,
+}
+
+impl ProcessId {
+    pub fn value(&self) -> usize {
+        self.inner
+    }
+}
+
+impl Drop for ProcessId {
+    fn drop(&mut self) {
+        unsafe {
+            PID_ALLOC.lock_no_preempt().free(self.inner);
+        }
+    }
+}
+
+pub fn alloc_pid() -> ProcessId {
+    ProcessId {
+        inner: unsafe { PID_ALLOC.lock_no_preempt().alloc() },
+    }
+}
+
+// endregion
+
+/// Errors returned by [Process::wait_child].
+#[derive(Debug)]
+pub enum WaitError {
+    /// The process has no child matching the request.
+    NoChild,
+    /// The waiting thread was killed, or got a signal.
+    Interrupted,
+}
+
+/// System call interface of a process, chosen by the loader for its program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Personality {
+    /// The karox system calls.
+    Karox,
+    /// The Linux riscv64 system calls, for existing static binaries.
+    Linux,
+}
+
+/// How a process ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    /// A thread exited with the code.
+    Exited(i32),
+    /// The signal terminated it.
+    Signaled(usize),
+}
+
+impl ExitStatus {
+    /// The exit code, `128 + sig` for a signal as shells report it.
+    pub fn code(self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => code,
+            ExitStatus::Signaled(sig) => 128 + sig as i32,
+        }
+    }
+
+    /// The Linux wait status: the low byte of the code shifted by 8, or the signal.
+    pub fn wait_status(self) -> i32 {
+        match self {
+            ExitStatus::Exited(code) => (code & 0xff) << 8,
+            ExitStatus::Signaled(sig) => sig as i32,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum ProcessState {
+    Running,
+    /// All threads have exited, and the parent has not waited for the process yet.
+    Zombie(ExitStatus),
+}
+
+pub struct Process {
+    pid: ProcessId,
+    /// Name for diagnostics, not necessarily unique.
+    pub name: String,
+    /// Memspace of the threads, replaced when the process executes a new program.
+    memsp: SpinLock<Arc<MemSpace>>,
+    pub fds: SpinLock<FdTable>,
+    pub handles: SpinLock<HandleTable>,
+    pub signals: SpinLock<ProcessSignals>,
+    inner: SpinLock<ProcessInner>,
+    /// Threads of the process waiting for a child to exit.
+    child_exit: WaitQueue,
+    /// Set by a stop signal, and cleared by `SIGCONT`.
+    stopped: AtomicBool,
+    /// Threads of the stopped process waiting to continue.
+    continued: WaitQueue,
+}
+
+struct ProcessInner {
+    state: ProcessState,
+    personality: Personality,
+    /// Status of the whole process, once a thread or a signal has asked for it to exit.
+    group_exit: Option<ExitStatus>,
+    /// Live threads. A thread keeps its process alive, not the other way around.
+    threads: Vec<Weak<Task>>,
+    parent: Weak<Process>,
+    /// Children are kept until they are reaped, so that zombies stay around.
+    children: Vec<Arc<Process>>,
+}
+
+static INIT_PROCESS: Once<Arc<Process>> = Once::new();
+
+/// Live processes by pid.
+static PROCESSES: SpinLock<BTreeMap<usize, Weak<Process>>> = SpinLock::new(BTreeMap::new());
+
+impl Process {
+    fn new(
+        name: &str,
+        memsp: Arc<MemSpace>,
+        personality: Personality,
+        signals: ProcessSignals,
+        parent: Weak<Process>,
+    ) -> Arc<Process> {
+        let res = Arc::new(Process {
+            pid: alloc_pid(),
+            name: name.to_string(),
+            memsp: SpinLock::new(memsp),
+            fds: SpinLock::new(FdTable::new()),
+            handles: SpinLock::new(HandleTable::new()),
+            signals: SpinLock::new(signals),
+            inner: SpinLock::new(ProcessInner {
+                state: ProcessState::Running,
+                personality,
+                group_exit: None,
+                threads: Vec::new(),
+                parent,
+                children: Vec::new(),
+            }),
+            child_exit: WaitQueue::new(),
+            stopped: AtomicBool::new(false),
+            continued: WaitQueue::new(),
+        });
+        PROCESSES
+            .lock_no_irq()
+            .insert(res.get_pid(), Arc::downgrade(&res));
+        res
+    }
+
+    /// Create the init process, which adopts the orphans. It must be the first process, with pid [INIT_PID].
+    pub fn new_init(name: &str, memsp: Arc<MemSpace>, personality: Personality) -> Arc<Process> {
+        assert!(INIT_PROCESS.get().is_none(), "Init process created twice.");
+        let init = INIT_PROCESS
+            .call_once(|| {
+                Process::new(name, memsp, personality, ProcessSignals::new(), Weak::new())
+            })
+            .clone();
+        assert_eq!(
+            init.get_pid(),
+            INIT_PID,
+            "Init process created after others."
+        );
+        init
+    }
+
+    /// Create a child of the process with its personality and signal actions, without threads.
+    pub fn new_child(self: &Arc<Self>, name: &str, memsp: Arc<MemSpace>) -> Arc<Process> {
+        let signals = self.signals.lock_no_irq().fork();
+        let child = Process::new(
+            name,
+            memsp,
+            self.get_personality(),
+            signals,
+            Arc::downgrade(self),
+        );
+        self.inner.lock_no_irq().children.push(child.clone());
+        child
+    }
+
+    pub fn get_pid(&self) -> usize {
+        self.pid.value()
+    }
+
+    pub fn get_memsp(&self) -> Arc<MemSpace> {
+        self.memsp.lock_no_irq().clone()
+    }
+
+    pub fn get_personality(&self) -> Personality {
+        self.inner.lock_no_irq().personality
+    }
+
+    /// Switch the process to a new program, run by threads in `memsp` with `personality`.
+    /// Its signal handlers are gone with the old program.
+    pub fn replace_image(&self, memsp: Arc<MemSpace>, personality: Personality) {
+        *self.memsp.lock_no_irq() = memsp;
+        self.inner.lock_no_irq().personality = personality;
+        self.signals.lock_no_irq().reset_handlers();
+    }
+
+    pub fn get_state(&self) -> ProcessState {
+        self.inner.lock_no_irq().state
+    }
+
+    pub fn get_parent(&self) -> Option<Arc<Process>> {
+        self.inner.lock_no_irq().parent.upgrade()
+    }
+
+    pub fn get_children(&self) -> Vec<Arc<Process>> {
+        self.inner.lock_no_irq().children.clone()
+    }
+
+    /// The live threads of the process.
+    pub fn get_threads(&self) -> Vec<Arc<Task>> {
+        let threads = self.inner.lock_no_irq().threads.clone();
+        threads.iter().filter_map(Weak::upgrade).collect()
+    }
+
+    /// Make `task` a thread of the process. A task joins one process only, before it first runs.
+    pub fn add_thread(self: &Arc<Self>, task: &Arc<Task>) {
+        assert!(
+            task.process.get().is_none(),
+            "Task #{:} already belongs to a process.",
+            task.get_tid()
+        );
+        task.process.call_once(|| self.clone());
+        self.inner.lock_no_irq().threads.push(Arc::downgrade(task));
+    }
+
+    /// Remove the exiting thread `tid`. The process exits with `exit_code` if it was the last one,
+    /// unless its whole group was asked to exit.
+    fn remove_thread(self: &Arc<Self>, tid: usize, exit_code: i32) {
+        let mut inner = self.inner.lock_no_irq();
+        inner.threads.retain(|thread| match thread.upgrade() {
+            Some(thread) => thread.get_tid() != tid,
+            None => false,
+        });
+        let last = inner.threads.is_empty();
+        let status = inner.group_exit.unwrap_or(ExitStatus::Exited(exit_code));
+        drop(inner);
+        if last {
+            self.exit(status);
+        }
+    }
+
+    /// Kill every thread of the process but the current one.
+    pub fn kill_other_threads(&self) {
+        let tid = get_current_task().get_tid();
+        for thread in self.get_threads() {
+            if thread.get_tid() != tid {
+                kill_thread(&thread);
+            }
+        }
+    }
+
+    /// Exit the whole process with `status`: the other threads are killed and the current one exits.
+    /// The status of the first thread asking for it wins.
+    ///
+    /// **Preemption must be enabled**, otherwise the task could not be switched out.
+    pub fn exit_group(self: &Arc<Self>, status: ExitStatus) -> ! {
+        self.inner.lock_no_irq().group_exit.get_or_insert(status);
+        self.kill_other_threads();
+        exit_thread(status.code())
+    }
+
+    /// Kill every thread of the process, which exits with `status` unless it's already exiting.
+    pub fn kill(&self, status: ExitStatus) {
+        self.inner.lock_no_irq().group_exit.get_or_insert(status);
+        for thread in self.get_threads() {
+            kill_thread(&thread);
+        }
+    }
+
+    /// Stop the threads of the process on their way back to user mode, until [Process::resume].
+    pub fn stop(&self) {
+        self.stopped.store(true, Ordering::Release);
+    }
+
+    /// Let the threads of a stopped process continue.
+    pub fn resume(&self) {
+        if self.stopped.swap(false, Ordering::AcqRel) {
+            self.continued.wake_all();
+        }
+    }
+
+    /// Block the current thread while the process is stopped, unless it's killed.
+    ///
+    /// **Preemption must be enabled.**
+    pub fn wait_while_stopped(&self) {
+        if !self.stopped.load(Ordering::Acquire) {
+            return;
+        }
+        let task = get_current_task();
+        self.continued
+            .wait_until(|| !self.stopped.load(Ordering::Acquire) || task.is_killed());
+    }
+
+    /// Turn the process into a zombie, hand its children over to init and notify its parent.
+    fn exit(self: &Arc<Self>, status: ExitStatus) {
+        let init = get_init_process();
+        assert!(
+            !Arc::ptr_eq(self, init),
+            "Init process exited with {:?}.",
+            status
+        );
+        // Resources go away with the process, not with the zombie.
+        self.fds.lock_no_irq().clear();
+        self.handles.lock_no_irq().clear();
+        let mut inner = self.inner.lock_no_irq();
+        inner.state = ProcessState::Zombie(status);
+        let orphans = core::mem::take(&mut inner.children);
+        let parent = inner.parent.upgrade();
+        drop(inner);
+        if !orphans.is_empty() {
+            let mut init_inner = init.inner.lock_no_irq();
+            for orphan in orphans {
+                orphan.inner.lock_no_irq().parent = Arc::downgrade(init);
+                init_inner.children.push(orphan);
+            }
+            drop(init_inner);
+            // Some of them may be zombies already.
+            init.child_exit.wake_all();
+        }
+        if let Some(parent) = parent {
+            parent.child_exit.wake_all();
+            send_signal(&parent, SIGCHLD);
+        }
+    }
+
+    /// Whether `wait_child(pid)` would return at once.
+    fn has_waitable_child(&self, pid: Option<usize>) -> bool {
+        if get_current_task().is_interrupted() {
+            return true;
+        }
+        let inner = self.inner.lock_no_irq();
+        let mut matching = inner
+            .children
+            .iter()
+            .filter(|child| pid.is_none_or(|pid| child.get_pid() == pid))
+            .peekable();
+        matching.peek().is_none()
+            || matching.any(|child| matches!(child.get_state(), ProcessState::Zombie(_)))
+    }
+
+    /// Reap an exited child, `pid` or any child if [None], and return its pid and exit status.
+    /// Return [None] if the matching children are all running.
+    pub fn try_wait_child(
+        &self,
+        pid: Option<usize>,
+    ) -> Result<Option<(usize, ExitStatus)>, WaitError> {
+        let mut inner = self.inner.lock_no_irq();
+        let mut found = false;
+        let mut zombie = None;
+        for (index, child) in inner.children.iter().enumerate() {
+            if pid.is_some_and(|pid| child.get_pid() != pid) {
+                continue;
+            }
+            found = true;
+            if let ProcessState::Zombie(status) = child.get_state() {
+                zombie = Some((index, status));
+                break;
+            }
+        }
+        if !found {
+            return Err(WaitError::NoChild);
+        }
+        Ok(zombie.map(|(index, status)| {
+            let child = inner.children.swap_remove(index);
+            (child.get_pid(), status)
+        }))
+    }
+
+    /// Block until a child, `pid` or any child if [None], exits,
+    /// then reap it and return its pid and exit status.
+    ///
+    /// **Preemption must be enabled.**
+    pub fn wait_child(&self, pid: Option<usize>) -> Result<(usize, ExitStatus), WaitError> {
+        loop {
+            self.child_exit.wait_until(|| self.has_waitable_child(pid));
+            if get_current_task().is_interrupted() {
+                return Err(WaitError::Interrupted);
+            }
+            // Another thread may have reaped the child meanwhile.
+            if let Some(res) = self.try_wait_child(pid)? {
+                return Ok(res);
+            }
+        }
+    }
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        // The pid is freed after this, so no other process can be registered under it yet.
+        PROCESSES.lock_no_irq().remove(&self.get_pid());
+    }
+}
+
+impl Debug for Process {
+    fn fmt(&self, f: &mut Formatter<'_>) -> core::fmt::Result {
+        f.debug_struct("Process")
+            .field("pid", &self.get_pid())
+            .field("name", &self.name)
+            .finish()
+    }
+}
+
+/// The init process. **It must have been created with [Process::new_init].**
+pub fn get_init_process() -> &'static Arc<Process> {
+    INIT_PROCESS.get().expect("Init process not created.")
+}
+
+/// Find the live process with pid `pid`, zombies included.
+pub fn find_process(pid: usize) -> Option<Arc<Process>> {
+    let processes = PROCESSES.lock_no_irq();
+    processes.get(&pid).and_then(Weak::upgrade)
+}
+
+/// The process of the current task, or [None] for kernel threads.
+pub fn get_current_process() -> Option<Arc<Process>> {
+    get_current_task().process.get().cloned()
+}
+
+/// Mark `task` as killed, and interrupt its blocking call so that it notices.
+pub fn kill_thread(task: &Arc<Task>) {
+    task.killed.store(true, Ordering::Release);
+    interrupt(task.clone());
+}
+
+/// Exit the current thread. Its process exits with `exit_code` once no other thread is left.
+///
+/// The `clear_child_tid` word of the thread is zeroed and its futex woken, for threads joining it.
+/// Joiners may wait on it as a private or a shared futex, so both are woken.
+///
+/// **Preemption must be enabled**, otherwise the task could not be switched out.
+pub fn exit_thread(exit_code: i32) -> ! {
+    let task = get_current_task();
+    let tid = task.get_tid();
+    let process = task.process.get().cloned();
+    let clear_child_tid = task.clear_child_tid.load(Ordering::Relaxed);
+    if let Some(memsp) = task.memsp.as_ref().filter(|_| clear_child_tid != 0) {
+        // The thread may have unmapped the word.
+        let res = UserPtr::<u32>::new(clear_child_tid).write(&0);
+        if res.is_ok() {
+            futex_wake(FutexKey::private(memsp, clear_child_tid), 1);
+            if let Ok(key) = FutexKey::shared(memsp, clear_child_tid) {
+                futex_wake(key, 1);
+            }
+        }
+    }
+    drop(task);
+    if let Some(process) = process {
+        process.remove_thread(tid, exit_code);
+    }
+    exit_current()
+}
@@ -0,0 +1,107 @@
+//! Task Registry
+//!
+//! Every task is registered under its tid when created, and unregistered when dropped.
+//! The registry only holds weak references, so it never keeps a task alive.
+//!
+//! [dump_tasks] logs a `ps`-style table of the tasks, for the panic handler and the debug console.
+
+use crate::{mutex::SpinLock, task::task::Task};
+use alloc::{
+    collections::btree_map::BTreeMap,
+    format,
+    string::ToString,
+    sync::{Arc, Weak},
+    vec::Vec,
+};
+use log::{Level, log};
+
+static TASKS: SpinLock<BTreeMap<usize, Weak<Task>>> = SpinLock::new(BTreeMap::new());
+
+pub fn register_task(task: &Arc<Task>) {
+    TASKS
+        .lock_no_irq()
+        .insert(task.get_tid(), Arc::downgrade(task));
+}
+
+/// Called when the task is dropped.
+pub fn unregister_task(tid: usize) {
+    TASKS.lock_no_irq().remove(&tid);
+}
+
+/// Find the live task with tid `tid`.
+pub fn find_task(tid: usize) -> Option<Arc<Task>> {
+    let tasks = TASKS.lock_no_irq();
+    tasks.get(&tid).and_then(Weak::upgrade)
+}
+
+/// The live tasks, ordered by tid.
+///
+/// The tasks are collected before the registry is unlocked,
+/// since dropping the last reference to one of them unregisters it.
+pub fn get_all_tasks() -> Vec<Arc<Task>> {
+    collect(&TASKS.lock_no_irq())
+}
+
+fn collect(tasks: &BTreeMap<usize, Weak<Task>>) -> Vec<Arc<Task>> {
+    tasks.values().filter_map(Weak::upgrade).collect()
+}
+
+pub fn get_task_count() -> usize {
+    TASKS.lock_no_irq().len()
+}
+
+/// Log a table of the live tasks at `level`.
+///
+/// It gives up if the registry is locked, so that it can be called from the panic handler.
+pub fn dump_tasks(level: Level) {
+    let tasks = match TASKS.try_lock_no_irq() {
+        Some(tasks) => collect(&tasks),
+        None => {
+            log!(level, "Task registry is locked, no task to dump.");
+            return;
+        }
+    };
+    log!(
+        level,
+        "{:>5} {:>5} {:<16} {:<8} {:>4} {:>4} {:>10} {:>10} {:>10} {:>6} {:>6} {:>6}",
+        "TID",
+        "PID",
+        "NAME",
+        "STATUS",
+        "HART",
+        "LAST",
+        "USER(us)",
+        "SYS(us)",
+        "WAIT(us)",
+        "VCSW",
+        "IVCSW",
+        "KSTACK"
+    );
+    for task in tasks {
+        let info = task.get_info();
+        let pid = match info.pid {
+            Some(pid) => format!("{}", pid),
+            None => "-".to_string(),
+        };
+        let last_hart = match info.last_hart {
+            Some(hart_id) => format!("{}", hart_id),
+            None => "-".to_string(),
+        };
+        log!(
+            level,
+            "{:>5} {:>5} {:<16} {:<8} {:>4} {:>4} {:>10} {:>10} {:>10} {:>6} {:>6} {:>6}",
+            info.tid,
+            pid,
+            info.name,
+            info.status.name(),
+            info.hart_id,
+            last_hart,
+            info.user_time_ns / 1000,
+            info.system_time_ns / 1000,
+            info.wait_time_ns / 1000,
+            info.nr_voluntary_switches,
+            info.nr_involuntary_switches,
+            info.kstack_used
+        );
+    }
+}
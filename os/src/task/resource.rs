@@ -0,0 +1,137 @@
+//! Resource Tables
+//!
+//! A process refers to its open files and kernel objects by small integers:
+//! file descriptors index its [FdTable], and handles its [HandleTable].
+//! New entries take the lowest free index, as POSIX requires for file descriptors.
+
+use crate::task::{process::Process, task::Task};
+use alloc::{sync::Arc, vec::Vec};
+
+/// Maximum number of entries of a resource table.
+pub const MAX_RESOURCES: usize = 1024;
+
+/// Errors returned by the resource tables.
+#[derive(Debug)]
+pub enum ResourceError {
+    /// The index refers to no entry.
+    BadIndex,
+    /// The table has [MAX_RESOURCES] entries already.
+    TableFull,
+}
+
+/// Entries indexed by small integers.
+pub struct ResourceTable<T: Clone> {
+    slots: Vec<Option<T>>,
+}
+
+impl<T: Clone> ResourceTable<T> {
+    pub const fn new() -> ResourceTable<T> {
+        ResourceTable { slots: Vec::new() }
+    }
+
+    /// Insert `value` at the lowest free index and return the index.
+    pub fn insert(&mut self, value: T) -> Result<usize, ResourceError> {
+        let index = match self.slots.iter().position(Option::is_none) {
+            Some(index) => index,
+            None if self.slots.len() < MAX_RESOURCES => {
+                self.slots.push(None);
+                self.slots.len() - 1
+            }
+            None => return Err(ResourceError::TableFull),
+        };
+        self.slots[index] = Some(value);
+        Ok(index)
+    }
+
+    /// Put `value` at `index`, and return the entry it replaces.
+    pub fn insert_at(&mut self, index: usize, value: T) -> Result<Option<T>, ResourceError> {
+        if index >= MAX_RESOURCES {
+            return Err(ResourceError::BadIndex);
+        }
+        if index >= self.slots.len() {
+            self.slots.resize(index + 1, None);
+        }
+        Ok(self.slots[index].replace(value))
+    }
+
+    pub fn get(&self, index: usize) -> Result<T, ResourceError> {
+        self.slots
+            .get(index)
+            .cloned()
+            .flatten()
+            .ok_or(ResourceError::BadIndex)
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<T, ResourceError> {
+        self.slots
+            .get_mut(index)
+            .and_then(Option::take)
+            .ok_or(ResourceError::BadIndex)
+    }
+
+    /// Remove every entry.
+    pub fn clear(&mut self) {
+        self.slots.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.slots.iter().filter(|slot| slot.is_some()).count()
+    }
+}
+
+impl<T: Clone> Clone for ResourceTable<T> {
+    fn clone(&self) -> Self {
+        ResourceTable {
+            slots: self.slots.clone(),
+        }
+    }
+}
+
+// region: Files
+
+/// Errors returned by file operations.
+#[derive(Debug)]
+pub enum FileError {
+    NotReadable,
+    NotWritable,
+    /// The file has no random access, like a stream.
+    NotSeekable,
+    Io,
+}
+
+/// An open file, shared by every descriptor referring to it.
+pub trait File: Send + Sync {
+    /// Read into `buf`, and return the number of bytes read. 0 means end of file.
+    fn read(&self, buf: &mut [u8]) -> Result<usize, FileError>;
+
+    /// Write `buf`, and return the number of bytes written.
+    fn write(&self, buf: &[u8]) -> Result<usize, FileError>;
+
+    /// Read into `buf` from `offset` without moving the file position, and return the number of bytes read.
+    /// Files are mapped into memory with it.
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> Result<usize, FileError> {
+        Err(FileError::NotSeekable)
+    }
+}
+
+pub type FdTable = ResourceTable<Arc<dyn File>>;
+
+/// Standard streams.
+pub const STDIN_FD: usize = 0;
+pub const STDOUT_FD: usize = 1;
+pub const STDERR_FD: usize = 2;
+
+// endregion
+
+// region: Handles
+
+/// A kernel object a handle refers to.
+#[derive(Clone)]
+pub enum KernelObject {
+    Process(Arc<Process>),
+    Thread(Arc<Task>),
+}
+
+pub type HandleTable = ResourceTable<KernelObject>;
+
+// endregion
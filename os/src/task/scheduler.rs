@@ -1,42 +1,227 @@
 use crate::{
-    arch::{MAX_HARTS, hart::get_current_hart_id, task::switch::__switch},
-    dev::get_current_hart,
-    sched::{DefaultScheduler, Scheduler},
+    arch::{
+        hart::get_current_hart_id,
+        mm::paging::{activate_user_memspace, deactivate_user_memspace},
+        task::{fpu, switch::__switch},
+        trap::intr::{disable_intr, restore_intr},
+    },
+    dev::{get_current_hart, get_present_harts, get_working_harts, is_working_hart},
+    hotplug::{is_stop_requested, stop_current},
+    mutex::SpinLock,
+    percpu,
+    rcu::note_quiescent_state,
+    sched::{DefaultScheduler, NormalScheduler, SchedPolicy, Scheduler, idle::is_idle_task},
+    smp::send_reschedule,
     task::{
         get_current_sched_context, get_current_sched_context_mut, get_current_task,
-        processor::PROCESSORS, scheduler::test::add_test_tasks,
+        processor::PROCESSOR,
+        scheduler::test::add_test_tasks,
+        stats::account_hart_time,
+        task::{Task, TaskStatus},
     },
+    timer::{get_time, ns_to_time, restart_tick, stop_tick, time_to_ns},
+};
+use alloc::{sync::Arc, vec::Vec};
+use core::{
+    cmp::Reverse,
+    hint::spin_loop,
+    sync::atomic::{AtomicBool, AtomicUsize, Ordering},
 };
-use core::array;
-use lazy_static::lazy_static;
 use riscv::register::sscratch;
-use utils::sync::LocalCell;
+use spin::Once;
 
 #[path = "test.rs"]
 pub mod test;
 
-lazy_static! {
-    static ref SCHEDULERS: [LocalCell<DefaultScheduler>; MAX_HARTS] =
-        array::from_fn(|hart_id| unsafe { LocalCell::new(DefaultScheduler::new(hart_id)) });
+percpu! {
+    /// Run queue of the hart, created on first use. It's locked with [SpinLock::lock_no_irq],
+    /// so that other harts can steal from it and wakers can queue on it.
+    static SCHEDULER: Once<SpinLock<DefaultScheduler>> = Once::new();
+
+    /// Time the running task of the hart was switched to.
+    static SWITCH_IN_TIME: AtomicUsize = AtomicUsize::new(0);
+
+    /// Effective real-time priority of the running task of the hart. 0 for normal and idle tasks.
+    static RUNNING_PRIORITY: AtomicUsize = AtomicUsize::new(0);
+
+    /// Whether the hart is running its idle task.
+    static HART_IDLE: AtomicBool = AtomicBool::new(false);
+
+    /// Whether the hart has entered [run_tasks].
+    static SCHEDULER_RUNNING: AtomicBool = AtomicBool::new(false);
+}
+
+fn get_scheduler(hart_id: usize) -> &'static SpinLock<DefaultScheduler> {
+    SCHEDULER
+        .remote(hart_id)
+        .call_once(|| SpinLock::new(DefaultScheduler::new(NormalScheduler::new(hart_id))))
+}
+
+/// Whether the current hart runs tasks. Before that, there is no task to switch out.
+pub fn is_scheduler_running() -> bool {
+    is_scheduler_running_on(get_current_hart_id())
+}
+
+pub fn is_scheduler_running_on(hart_id: usize) -> bool {
+    SCHEDULER_RUNNING.remote(hart_id).load(Ordering::Relaxed)
+}
+
+/// Whether hart `hart_id` is running its idle task.
+pub fn is_hart_idle(hart_id: usize) -> bool {
+    HART_IDLE.remote(hart_id).load(Ordering::SeqCst)
 }
 
 pub fn run_tasks() -> ! {
     let hart_id = get_current_hart_id();
     add_test_tasks();
+    SCHEDULER_RUNNING
+        .remote(hart_id)
+        .store(true, Ordering::Relaxed);
     loop {
+        if is_stop_requested(hart_id) {
+            SCHEDULER_RUNNING
+                .remote(hart_id)
+                .store(false, Ordering::Relaxed);
+            HART_IDLE.remote(hart_id).store(false, Ordering::SeqCst);
+            stop_current(hart_id);
+        }
+        // No task runs here, so no reader either.
+        note_quiescent_state(hart_id);
+        if balance_due(hart_id) {
+            balance(hart_id);
+        }
+        let task = pick_next(hart_id);
+        // The task may have been woken up on another hart before its context got saved there.
+        while task.on_cpu.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        *task.status.write() = TaskStatus::Running;
+        RUNNING_PRIORITY
+            .remote(hart_id)
+            .store(task.sched.get_priority(), Ordering::SeqCst);
+        // The idle task only runs with an empty run queue, so the tick has nothing to preempt.
+        if is_idle_task(&task, hart_id) {
+            stop_tick();
+        } else {
+            restart_tick();
+        }
         unsafe {
-            let task = SCHEDULERS[hart_id].exclusive_access().fetch_new();
-            PROCESSORS[hart_id].inner.exclusive_access().running_task = Some(task.clone());
+            task.set_hart_id(hart_id);
+            task.sched.set_hart(hart_id);
+            PROCESSOR.get().inner.borrow_mut().running_task = Some(task.clone());
             let cur_context = get_current_sched_context_mut();
             let next_context = task.get_task_context_ptr();
             sscratch::write(task.get_trap_context_ptr() as usize); // set sscratch
+            task.on_cpu.store(true, Ordering::Relaxed);
+            let now = get_time();
+            task.stats.switch_in(hart_id, now);
+            SWITCH_IN_TIME.remote(hart_id).store(now, Ordering::Relaxed);
+            if let Some(memsp) = &task.memsp {
+                activate_user_memspace(memsp);
+            }
             __switch(cur_context, next_context);
+            // Another hart may run the task once it's put back, from its saved registers.
+            fpu::save(
+                &mut *task.get_fpu_context_mut_ptr(),
+                &mut *task.get_trap_context_mut_ptr(),
+            );
+        }
+        // The task may exit and free its memspace once it's put back.
+        if task.memsp.is_some() {
+            deactivate_user_memspace();
         }
+        put_prev(hart_id, task);
+    }
+}
+
+/// Take the next task to run on hart `hart_id`, stealing one if its own run queue is empty.
+fn pick_next(hart_id: usize) -> Arc<Task> {
+    // Mark the hart idle before looking at the queue, so that a waker queueing a task
+    // after we have found the queue empty always sees the flag and kicks us.
+    HART_IDLE.remote(hart_id).store(true, Ordering::SeqCst);
+    let mut task = get_scheduler(hart_id).lock_no_irq().fetch_new();
+    if is_idle_task(&task, hart_id) {
+        if let Some(stolen) = steal_task(hart_id) {
+            let mut scheduler = get_scheduler(hart_id).lock_no_irq();
+            scheduler.attach(stolen);
+            task = scheduler.fetch_new();
+        }
+    }
+    if !is_idle_task(&task, hart_id) {
+        HART_IDLE.remote(hart_id).store(false, Ordering::SeqCst);
+    }
+    task
+}
+
+/// Account the runtime of the task switched out of hart `hart_id`,
+/// and queue it again if it was preempted. Blocked tasks are queued by their wakers.
+fn put_prev(hart_id: usize, task: Arc<Task>) {
+    let now = get_time();
+    let runtime = time_to_ns(now - SWITCH_IN_TIME.remote(hart_id).load(Ordering::Relaxed));
+    let idle = is_idle_task(&task, hart_id);
+    account_hart_time(hart_id, runtime, idle);
+    let mut moved = false;
+    if idle {
+        // The idle task gives way to queued tasks, and is ready again at once.
+        task.stats.switch_out(runtime, false);
+        task.stats.mark_ready(now);
+    } else {
+        let mut scheduler = get_scheduler(hart_id).lock_no_irq();
+        let mut status = task.status.write();
+        let blocked = match *status {
+            TaskStatus::Running => {
+                *status = TaskStatus::Ready;
+                false
+            }
+            _ => true,
+        };
+        drop(status);
+        task.stats.switch_out(runtime, blocked);
+        if !blocked {
+            task.stats.mark_ready(now);
+        }
+        // A task no longer allowed here leaves like a blocked one, and joins an allowed hart below.
+        moved = !blocked && !task.sched.is_allowed(hart_id);
+        scheduler.put_prev(task.clone(), runtime, blocked || moved);
+    }
+    // The context is saved, so other harts can run the task now.
+    task.on_cpu.store(false, Ordering::Release);
+    if moved {
+        place_on(&task, select_hart(&task));
+    }
+}
+
+/// Called on every tick of the current hart. Preempt the running task if the scheduler asks to.
+pub fn scheduler_tick() {
+    if !is_scheduler_running() {
+        return;
+    }
+    let hart_id = get_current_hart_id();
+    // Readers disable preemption, so the interrupted code is not one.
+    if get_current_hart().preempt.is_preempt_allowed() {
+        note_quiescent_state(hart_id);
+    }
+    let task = get_current_task();
+    let need_resched = is_idle_task(&task, hart_id) || {
+        let runtime =
+            time_to_ns(get_time() - SWITCH_IN_TIME.remote(hart_id).load(Ordering::Relaxed));
+        get_scheduler(hart_id)
+            .lock_no_irq()
+            .need_resched(&task, runtime)
+    };
+    drop(task);
+    if need_resched {
+        schedule();
     }
 }
 
 /// Schedule. **Make sure interrupt is disabled before you call the scheduler**
+///
+/// Do nothing before the hart runs tasks.
 pub fn schedule() {
+    if !is_scheduler_running() {
+        return;
+    }
     let hart_info = get_current_hart();
     if !hart_info.preempt.is_preempt_allowed() {
         hart_info.preempt.need_reschedule();
@@ -49,3 +234,384 @@ pub fn schedule() {
         __switch(cur, next);
     }
 }
+
+/// Mark the current task as blocked and switch away from it.
+/// The task runs again only after it is passed to [wake_up].
+///
+/// `prepare` runs after the task is marked as blocked and before it is switched out,
+/// with interrupts disabled. Use it to hand the task over to its waker.
+///
+/// It returns at once, without running `prepare`, if the task was passed to [interrupt]
+/// since it last returned to user mode.
+///
+/// **Preemption must be enabled**, otherwise the task could not be switched out.
+pub fn block_current(prepare: impl FnOnce(&Arc<Task>)) {
+    assert!(
+        get_current_hart().preempt.is_preempt_allowed(),
+        "Trying to block with preemption disabled."
+    );
+    let intr = disable_intr();
+    let task = get_current_task();
+    let mut status = task.status.write();
+    if task.interrupt_pending.swap(false, Ordering::Acquire) {
+        drop(status);
+        drop(task);
+        restore_intr(intr);
+        return;
+    }
+    *status = TaskStatus::Blocked;
+    drop(status);
+    prepare(&task);
+    drop(task);
+    schedule();
+    restore_intr(intr);
+}
+
+/// Mark the current task as exited and switch away from it for good.
+///
+/// **Preemption must be enabled**, otherwise the task could not be switched out.
+pub fn exit_current() -> ! {
+    assert!(
+        get_current_hart().preempt.is_preempt_allowed(),
+        "Trying to exit with preemption disabled."
+    );
+    disable_intr();
+    let task = get_current_task();
+    *task.status.write() = TaskStatus::Exited;
+    drop(task);
+    schedule();
+    unreachable!("An exited task is scheduled again.");
+}
+
+/// Queue a new task on the hart chosen by [select_hart].
+pub fn add_task(task: Arc<Task>) {
+    let intr = disable_intr();
+    place_on(&task, select_hart(&task));
+    restore_intr(intr);
+}
+
+/// Make a blocked task ready and queue it on the hart chosen by [select_hart].
+/// The hart is kicked with an IPI if it's idle.
+/// Do nothing if the task is not blocked.
+///
+/// It can be called from interrupt context.
+pub fn wake_up(task: Arc<Task>) {
+    let intr = disable_intr();
+    let mut status = task.status.write();
+    if let TaskStatus::Blocked = *status {
+        *status = TaskStatus::Ready;
+        drop(status);
+        task.stats.mark_ready(get_time());
+        // Its old hart may still be saving its context and accounting its runtime.
+        while task.on_cpu.load(Ordering::Acquire) {
+            spin_loop();
+        }
+        let hart_id = select_hart(&task);
+        let priority = task.sched.get_priority();
+        get_scheduler(hart_id).lock_no_irq().wake_up(task);
+        kick_hart(hart_id, priority);
+    }
+    restore_intr(intr);
+}
+
+/// Wake up `task` after killing it or sending it a signal, so that it gives up blocking calls.
+///
+/// A task checks [Task::is_interrupted] before blocking. If it's not blocked yet,
+/// its next [block_current] returns at once instead, so that the wake up can't slip in between.
+///
+/// It can be called from interrupt context.
+pub fn interrupt(task: Arc<Task>) {
+    let intr = disable_intr();
+    let status = task.status.read();
+    if let TaskStatus::Blocked = *status {
+        drop(status);
+        wake_up(task);
+    } else {
+        task.interrupt_pending.store(true, Ordering::Release);
+    }
+    restore_intr(intr);
+}
+
+/// Make hart `hart_id` reschedule if a task of real-time `priority` was queued there
+/// and should run before its running task.
+///
+/// An IPI is sent even to the current hart: the task is then preempted as soon as
+/// interrupts are enabled, instead of in the middle of the waker.
+fn kick_hart(hart_id: usize, priority: usize) {
+    // An offline hart runs its queue once back online.
+    if !is_working_hart(hart_id) {
+        return;
+    }
+    let idle = HART_IDLE.remote(hart_id).load(Ordering::SeqCst);
+    if idle && hart_id == get_current_hart_id() {
+        // The idle task reschedules after the interrupt being handled.
+        return;
+    }
+    if idle || priority > RUNNING_PRIORITY.remote(hart_id).load(Ordering::SeqCst) {
+        send_reschedule(hart_id);
+    }
+}
+
+/// Change the scheduling policy and real-time priority of `task`.
+/// A queued task is moved to the run queue of its new class, and preempts the running task if it should.
+pub fn set_priority(task: &Arc<Task>, policy: SchedPolicy, priority: usize) {
+    task.sched.set_policy(policy, priority);
+    requeue(task);
+}
+
+/// Queue `task` again after its effective priority changed.
+pub fn requeue(task: &Arc<Task>) {
+    let intr = disable_intr();
+    let priority = task.sched.get_priority();
+    for hart in get_present_harts() {
+        let mut scheduler = get_scheduler(hart.hart_id).lock_no_irq();
+        if scheduler.remove(task) {
+            scheduler.add_to_ready(task.clone());
+            drop(scheduler);
+            kick_hart(hart.hart_id, priority);
+            break;
+        }
+    }
+    restore_intr(intr);
+}
+
+// region: Affinity
+
+/// Errors returned by the affinity and migration APIs.
+#[derive(Debug)]
+pub enum AffinityError {
+    /// The hart is not working.
+    InvalidHart,
+    /// The affinity mask contains no working hart, or excludes the target hart.
+    NotAllowed,
+    /// The task is pinned to its hart.
+    Pinned,
+    /// The task is not waiting in a run queue.
+    NotReady,
+}
+
+/// Take `task` out of the run queue of the first hart matching `filter` holding it.
+/// Offline harts are searched too, their queues may hold the tasks allowed nowhere else.
+fn take_queued(task: &Arc<Task>, filter: impl Fn(usize) -> bool) -> bool {
+    get_present_harts()
+        .iter()
+        .filter(|hart| filter(hart.hart_id))
+        .any(|hart| get_scheduler(hart.hart_id).lock_no_irq().detach(task))
+}
+
+/// Queue a ready task taken out of the run queues on hart `hart_id`,
+/// fixing up its per-hart state first.
+fn place_on(task: &Arc<Task>, hart_id: usize) {
+    while task.on_cpu.load(Ordering::Acquire) {
+        spin_loop();
+    }
+    // Nobody else can reach the task: it's neither running nor queued.
+    unsafe { task.set_hart_id(hart_id) };
+    task.sched.set_hart(hart_id);
+    let priority = task.sched.get_priority();
+    get_scheduler(hart_id).lock_no_irq().attach(task.clone());
+    kick_hart(hart_id, priority);
+}
+
+/// Move a ready task to the run queue of hart `hart_id`.
+pub fn migrate_task(task: &Arc<Task>, hart_id: usize) -> Result<(), AffinityError> {
+    if !is_working_hart(hart_id) {
+        return Err(AffinityError::InvalidHart);
+    }
+    if !task.sched.is_allowed(hart_id) {
+        return Err(AffinityError::NotAllowed);
+    }
+    let intr = disable_intr();
+    let res = if take_queued(task, |_| true) {
+        place_on(task, hart_id);
+        Ok(())
+    } else {
+        Err(AffinityError::NotReady)
+    };
+    restore_intr(intr);
+    res
+}
+
+/// Restrict `task` to the harts in `mask`, one bit per hart.
+///
+/// A task queued on a hart outside of the mask is moved at once;
+/// a running one is moved when its hart switches it out, which is requested with an IPI.
+pub fn set_affinity(task: &Arc<Task>, mask: usize) -> Result<(), AffinityError> {
+    if task.sched.is_pinned() {
+        return Err(AffinityError::Pinned);
+    }
+    if !get_working_harts()
+        .iter()
+        .any(|hart| mask & (1 << hart.hart_id) != 0)
+    {
+        return Err(AffinityError::NotAllowed);
+    }
+    let intr = disable_intr();
+    task.sched.set_affinity(mask);
+    if take_queued(task, |hart_id| !task.sched.is_allowed(hart_id)) {
+        place_on(task, select_hart(task));
+    } else if let TaskStatus::Running = *task.status.read() {
+        let hart_id = task.sched.get_hart();
+        if !task.sched.is_allowed(hart_id) {
+            send_reschedule(hart_id);
+        }
+    }
+    restore_intr(intr);
+    Ok(())
+}
+
+/// Move the tasks queued on hart `hart_id`, going offline, to the working harts with the fewest waiting tasks.
+/// The tasks allowed on no working hart stay there until it's back online.
+pub fn migrate_queued(hart_id: usize) {
+    loop {
+        let mut lengths = queue_lengths();
+        lengths.sort_unstable_by_key(|(_, len)| *len);
+        let moved = lengths.into_iter().find_map(|(target, _)| {
+            let task = get_scheduler(hart_id).lock_no_irq().steal(target);
+            task.map(|task| (task, target))
+        });
+        match moved {
+            Some((task, target)) => place_on(&task, target),
+            None => break,
+        }
+    }
+}
+
+// endregion
+
+// region: Load Balancing
+
+/// Interval of the periodic balancer in nanoseconds.
+const BALANCE_INTERVAL_NS: usize = 10_000_000;
+
+percpu! {
+    static NEXT_BALANCE: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Number of tasks waiting on each working hart, as `(hart_id, nr_ready)`.
+fn queue_lengths() -> Vec<(usize, usize)> {
+    get_working_harts()
+        .iter()
+        .map(|hart| {
+            (
+                hart.hart_id,
+                get_scheduler(hart.hart_id).lock_no_irq().nr_ready(),
+            )
+        })
+        .collect()
+}
+
+/// Choose the hart to queue a woken up task on:
+/// an idle hart if there is one, then for a real-time task the hart running the lowest priority below it,
+/// otherwise the hart with the fewest waiting tasks, preferring the current hart on a tie.
+///
+/// Only the harts allowed by the affinity of the task are considered.
+fn select_hart(task: &Arc<Task>) -> usize {
+    let cur_hart = get_current_hart_id();
+    let mut lengths = queue_lengths();
+    lengths.retain(|(hart_id, _)| task.sched.is_allowed(*hart_id));
+    if let Some((hart_id, _)) = lengths
+        .iter()
+        .find(|(hart_id, len)| *len == 0 && HART_IDLE.remote(*hart_id).load(Ordering::SeqCst))
+    {
+        return *hart_id;
+    }
+    let priority = task.sched.get_priority();
+    if priority > 0 {
+        let lowest = lengths
+            .iter()
+            .map(|(hart_id, _)| {
+                (
+                    *hart_id,
+                    RUNNING_PRIORITY.remote(*hart_id).load(Ordering::SeqCst),
+                )
+            })
+            .min_by_key(|(_, running)| *running)
+            .filter(|(_, running)| *running < priority);
+        if let Some((hart_id, _)) = lowest {
+            return hart_id;
+        }
+    }
+    let (mut target, mut min_len) = match lengths.iter().find(|(hart_id, _)| *hart_id == cur_hart) {
+        Some(current) => *current,
+        // No allowed working hart: keep the task where it was.
+        None => match lengths.first() {
+            Some(first) => *first,
+            None => return task.sched.get_hart(),
+        },
+    };
+    for (hart_id, len) in lengths {
+        if len < min_len {
+            target = hart_id;
+            min_len = len;
+        }
+    }
+    target
+}
+
+/// The working hart other than `hart_id` with the most waiting tasks, as `(hart_id, nr_ready)`.
+fn busiest_hart(hart_id: usize) -> Option<(usize, usize)> {
+    queue_lengths()
+        .into_iter()
+        .filter(|(id, _)| *id != hart_id)
+        .max_by_key(|(_, len)| *len)
+}
+
+/// Steal a waiting task from the busiest hart for the idle hart `hart_id`.
+fn steal_task(hart_id: usize) -> Option<Arc<Task>> {
+    let mut lengths = queue_lengths();
+    lengths.retain(|(id, len)| *id != hart_id && *len > 0);
+    lengths.sort_unstable_by_key(|(_, len)| Reverse(*len));
+    // The busiest hart may only have tasks bound to it, so go on with the next ones.
+    lengths
+        .into_iter()
+        .find_map(|(busiest, _)| get_scheduler(busiest).lock_no_irq().steal(hart_id))
+}
+
+fn balance_due(hart_id: usize) -> bool {
+    let now = get_time();
+    if now < NEXT_BALANCE.remote(hart_id).load(Ordering::Relaxed) {
+        return false;
+    }
+    NEXT_BALANCE
+        .remote(hart_id)
+        .store(now + ns_to_time(BALANCE_INTERVAL_NS), Ordering::Relaxed);
+    true
+}
+
+/// Even out the queue lengths of hart `hart_id` and the busiest hart,
+/// then kick an idle hart if this hart still has tasks to spare.
+///
+/// Never holds two run queues at the same time, so that balancing harts can't deadlock.
+fn balance(hart_id: usize) {
+    if let Some((busiest, len)) = busiest_hart(hart_id) {
+        let own_len = get_scheduler(hart_id).lock_no_irq().nr_ready();
+        if len > own_len + 1 {
+            let count = (len - own_len) / 2;
+            let mut pulled = Vec::with_capacity(count);
+            let mut scheduler = get_scheduler(busiest).lock_no_irq();
+            while pulled.len() < count {
+                match scheduler.steal(hart_id) {
+                    Some(task) => pulled.push(task),
+                    None => break,
+                }
+            }
+            drop(scheduler);
+            let mut scheduler = get_scheduler(hart_id).lock_no_irq();
+            for task in pulled {
+                scheduler.attach(task);
+            }
+        }
+    }
+    if get_scheduler(hart_id).lock_no_irq().nr_ready() > 1 {
+        let idle = get_working_harts()
+            .iter()
+            .map(|hart| hart.hart_id)
+            .find(|id| *id != hart_id && HART_IDLE.remote(*id).load(Ordering::SeqCst));
+        if let Some(idle) = idle {
+            send_reschedule(idle);
+        }
+    }
+}
+
+// endregion
@@ -0,0 +1,474 @@
+//! Signals
+//!
+//! Signals are sent to a process or to one of its threads, and taken by a thread
+//! on its way back to user mode with [handle_signals]:
+//! - threads have their own pending signals and mask of blocked ones, and the process has
+//!   pending signals that any thread not blocking them may take;
+//! - the threads of a process share one [SigAction] per signal;
+//! - a signal with a handler gets a frame pushed on the user stack, which `sigreturn` pops;
+//! - the others take their [DefaultAction]: terminate the process, stop it until `SIGCONT`, or nothing.
+//!
+//! Numbers, structures and flags are those of Linux, for both personalities.
+//! Signals are not queued: a signal sent again while pending is merged.
+
+use crate::{
+    arch::trap::{
+        context::TrapContext,
+        signal::{restore_signal_frame, setup_signal_frame},
+    },
+    mm::config::SIGNAL_TRAMPOLINE,
+    task::{
+        get_current_task,
+        process::{ExitStatus, INIT_PID, Process},
+        scheduler::interrupt,
+        task::Task,
+    },
+};
+use alloc::sync::Arc;
+
+// region: Numbers
+
+/// Number of signals. Signals are numbered from 1.
+pub const NSIG: usize = 64;
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGSTKFLT: usize = 16;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+pub const SIGTTIN: usize = 21;
+pub const SIGTTOU: usize = 22;
+pub const SIGURG: usize = 23;
+pub const SIGXCPU: usize = 24;
+pub const SIGXFSZ: usize = 25;
+pub const SIGVTALRM: usize = 26;
+pub const SIGPROF: usize = 27;
+pub const SIGWINCH: usize = 28;
+pub const SIGIO: usize = 29;
+pub const SIGPWR: usize = 30;
+pub const SIGSYS: usize = 31;
+
+/// Handlers of [SigAction].
+pub const SIG_DFL: usize = 0;
+pub const SIG_IGN: usize = 1;
+
+/// Flags of [SigAction]. Handlers always get the `siginfo_t` and `ucontext_t` arguments,
+/// and interrupted system calls fail with `EINTR` rather than restart. Other flags are ignored.
+pub const SA_SIGINFO: usize = 0x4;
+pub const SA_RESTART: usize = 0x1000_0000;
+pub const SA_NODEFER: usize = 0x4000_0000;
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// Operations on the signal mask.
+pub const SIG_BLOCK: u32 = 0;
+pub const SIG_UNBLOCK: u32 = 1;
+pub const SIG_SETMASK: u32 = 2;
+
+// endregion
+
+/// Errors returned by signal operations.
+#[derive(Debug)]
+pub enum SignalError {
+    /// The action of the signal can't be changed.
+    BadSignal,
+    /// The mask operation is unknown.
+    BadOperation,
+}
+
+/// Whether `sig` is a valid signal number.
+pub fn is_valid_signal(sig: usize) -> bool {
+    (1..=NSIG).contains(&sig)
+}
+
+/// A set of signals, with bit `sig - 1` for signal `sig` like the Linux `sigset_t`.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const EMPTY: SigSet = SigSet(0);
+    /// Signals that can't be caught, blocked or ignored.
+    pub const UNBLOCKABLE: SigSet = SigSet(1 << (SIGKILL - 1) | 1 << (SIGSTOP - 1));
+    /// Signals whose default action stops the process.
+    pub const STOP: SigSet =
+        SigSet(1 << (SIGSTOP - 1) | 1 << (SIGTSTP - 1) | 1 << (SIGTTIN - 1) | 1 << (SIGTTOU - 1));
+
+    /// The set of the valid signal `sig`.
+    pub const fn of(sig: usize) -> SigSet {
+        SigSet(1 << (sig - 1))
+    }
+
+    pub fn contains(self, sig: usize) -> bool {
+        self.0 & SigSet::of(sig).0 != 0
+    }
+
+    pub fn insert(&mut self, sig: usize) {
+        self.0 |= SigSet::of(sig).0;
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !SigSet::of(sig).0;
+    }
+
+    pub fn union(self, other: SigSet) -> SigSet {
+        SigSet(self.0 | other.0)
+    }
+
+    pub fn difference(self, other: SigSet) -> SigSet {
+        SigSet(self.0 & !other.0)
+    }
+
+    /// The lowest signal of the set.
+    pub fn first(self) -> Option<usize> {
+        (self.0 != 0).then(|| self.0.trailing_zeros() as usize + 1)
+    }
+}
+
+/// `struct sigaction` of the Linux riscv64 system calls.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    /// [SIG_DFL], [SIG_IGN], or the user address of the handler.
+    pub handler: usize,
+    pub flags: usize,
+    /// Signals blocked while the handler runs, along with the signal itself unless [SA_NODEFER] is set.
+    pub mask: SigSet,
+}
+
+/// What a signal does without a handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DefaultAction {
+    /// Terminate the process. No core is dumped for the signals that would.
+    Terminate,
+    Ignore,
+    /// Stop the process until it gets `SIGCONT`.
+    Stop,
+    /// Resume the process if it's stopped, which happens when the signal is sent.
+    Continue,
+}
+
+pub fn default_action(sig: usize) -> DefaultAction {
+    match sig {
+        SIGCHLD | SIGURG | SIGWINCH => DefaultAction::Ignore,
+        SIGSTOP | SIGTSTP | SIGTTIN | SIGTTOU => DefaultAction::Stop,
+        SIGCONT => DefaultAction::Continue,
+        _ => DefaultAction::Terminate,
+    }
+}
+
+impl SigAction {
+    /// Whether the action discards `sig`. Continuing is done when the signal is sent.
+    fn ignores(&self, sig: usize) -> bool {
+        match self.handler {
+            SIG_IGN => true,
+            SIG_DFL => matches!(
+                default_action(sig),
+                DefaultAction::Ignore | DefaultAction::Continue
+            ),
+            _ => false,
+        }
+    }
+}
+
+/// Signal state shared by the threads of a process.
+#[derive(Debug)]
+pub struct ProcessSignals {
+    actions: [SigAction; NSIG],
+    /// Signals sent to the process, for any thread to take.
+    pending: SigSet,
+}
+
+impl ProcessSignals {
+    pub const fn new() -> ProcessSignals {
+        ProcessSignals {
+            actions: [SigAction {
+                handler: SIG_DFL,
+                flags: 0,
+                mask: SigSet::EMPTY,
+            }; NSIG],
+            pending: SigSet::EMPTY,
+        }
+    }
+
+    /// The state of a forked child: the same actions, with nothing pending.
+    pub fn fork(&self) -> ProcessSignals {
+        ProcessSignals {
+            actions: self.actions,
+            ..ProcessSignals::new()
+        }
+    }
+
+    /// Reset the handled signals to their default action, for a new program.
+    /// Ignored signals stay ignored.
+    pub fn reset_handlers(&mut self) {
+        for action in &mut self.actions {
+            if action.handler != SIG_IGN {
+                *action = SigAction::default();
+            }
+        }
+    }
+}
+
+/// Signal state of a thread.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TaskSignals {
+    /// Signals sent to the thread itself.
+    pub pending: SigSet,
+    /// Signals left pending until they are unblocked.
+    pub blocked: SigSet,
+}
+
+/// Remove `sigs` from the pending signals of `process` and its threads.
+fn discard_pending(process: &Process, signals: &mut ProcessSignals, sigs: SigSet) {
+    signals.pending = signals.pending.difference(sigs);
+    for thread in process.get_threads() {
+        let mut task_signals = thread.signals.lock_no_irq();
+        task_signals.pending = task_signals.pending.difference(sigs);
+    }
+}
+
+/// Whether `sig`, sent to `process`, must be dropped at once.
+/// Signals sent to init are dropped unless it handles them, so that it can't be killed.
+fn is_discarded(process: &Process, signals: &ProcessSignals, sig: usize) -> bool {
+    let action = &signals.actions[sig - 1];
+    action.ignores(sig) || (process.get_pid() == INIT_PID && action.handler == SIG_DFL)
+}
+
+/// Carry out the effects of sending `sig` to `process` that don't wait for its delivery:
+/// `SIGKILL` kills it, `SIGCONT` resumes it, and both cancel pending stops, or the other way round.
+/// Return `false` if the signal is dropped.
+fn prepare_signal(process: &Arc<Process>, signals: &mut ProcessSignals, sig: usize) -> bool {
+    if process.get_pid() == INIT_PID && sig == SIGKILL {
+        return false;
+    }
+    match sig {
+        SIGKILL | SIGCONT => {
+            discard_pending(process, signals, SigSet::STOP);
+            process.resume();
+        }
+        sig if SigSet::STOP.contains(sig) => {
+            discard_pending(process, signals, SigSet::of(SIGCONT));
+        }
+        _ => {}
+    }
+    if sig == SIGKILL {
+        process.kill(ExitStatus::Signaled(SIGKILL));
+        return false;
+    }
+    !is_discarded(process, signals, sig)
+}
+
+/// Send the valid signal `sig` to `process`, and wake up a thread that doesn't block it.
+pub fn send_signal(process: &Arc<Process>, sig: usize) {
+    let mut signals = process.signals.lock_no_irq();
+    if !prepare_signal(process, &mut signals, sig) {
+        return;
+    }
+    signals.pending.insert(sig);
+    let thread = process
+        .get_threads()
+        .into_iter()
+        .find(|thread| !thread.signals.lock_no_irq().blocked.contains(sig));
+    drop(signals);
+    if let Some(thread) = thread {
+        interrupt(thread);
+    }
+}
+
+/// Send the valid signal `sig` to the thread `task`, and wake it up.
+pub fn send_thread_signal(task: &Arc<Task>, sig: usize) {
+    let Some(process) = task.process.get() else {
+        return;
+    };
+    let mut signals = process.signals.lock_no_irq();
+    if !prepare_signal(process, &mut signals, sig) {
+        return;
+    }
+    task.signals.lock_no_irq().pending.insert(sig);
+    drop(signals);
+    interrupt(task.clone());
+}
+
+/// Send `sig` to the current thread for a fault it caused.
+/// The signal can't be blocked or ignored: it then takes its default action.
+pub fn force_signal(sig: usize) {
+    let task = get_current_task();
+    let Some(process) = task.process.get() else {
+        return;
+    };
+    let mut signals = process.signals.lock_no_irq();
+    let mut task_signals = task.signals.lock_no_irq();
+    let action = &mut signals.actions[sig - 1];
+    if action.handler == SIG_IGN || task_signals.blocked.contains(sig) {
+        *action = SigAction::default();
+        task_signals.blocked.remove(sig);
+    }
+    task_signals.pending.insert(sig);
+}
+
+/// Whether `task` has a pending signal it doesn't block, which interrupts its blocking calls.
+pub fn has_pending_signal(task: &Task) -> bool {
+    let Some(process) = task.process.get() else {
+        return false;
+    };
+    let signals = process.signals.lock_no_irq();
+    let task_signals = task.signals.lock_no_irq();
+    let pending = signals.pending.union(task_signals.pending);
+    pending.difference(task_signals.blocked) != SigSet::EMPTY
+}
+
+/// Replace the action of the valid signal `sig` of `process` with `action`, if given,
+/// and return the previous one. Pending signals that become ignored are discarded.
+pub fn set_action(
+    process: &Process,
+    sig: usize,
+    action: Option<SigAction>,
+) -> Result<SigAction, SignalError> {
+    let mut signals = process.signals.lock_no_irq();
+    let old = signals.actions[sig - 1];
+    let Some(mut action) = action else {
+        return Ok(old);
+    };
+    if SigSet::UNBLOCKABLE.contains(sig) {
+        return Err(SignalError::BadSignal);
+    }
+    action.mask = action.mask.difference(SigSet::UNBLOCKABLE);
+    signals.actions[sig - 1] = action;
+    if action.ignores(sig) {
+        discard_pending(process, &mut signals, SigSet::of(sig));
+    }
+    Ok(old)
+}
+
+/// Change the signal mask of the current thread with `how` and `set`, if given,
+/// and return the previous one. `SIGKILL` and `SIGSTOP` can't be blocked.
+pub fn set_blocked(how: u32, set: Option<SigSet>) -> Result<SigSet, SignalError> {
+    let task = get_current_task();
+    let mut task_signals = task.signals.lock_no_irq();
+    let old = task_signals.blocked;
+    let Some(set) = set else {
+        return Ok(old);
+    };
+    let blocked = match how {
+        SIG_BLOCK => old.union(set),
+        SIG_UNBLOCK => old.difference(set),
+        SIG_SETMASK => set,
+        _ => return Err(SignalError::BadOperation),
+    };
+    task_signals.blocked = blocked.difference(SigSet::UNBLOCKABLE);
+    Ok(old)
+}
+
+/// Give the new thread `task` the signal mask of the current one.
+pub fn inherit_signal_mask(task: &Task) {
+    let blocked = get_current_task().signals.lock_no_irq().blocked;
+    task.signals.lock_no_irq().blocked = blocked;
+}
+
+/// Take the next pending signal `task` doesn't block, its own before those of the process,
+/// with the action to take.
+fn take_signal(process: &Process, task: &Task) -> Option<(usize, SigAction)> {
+    let mut signals = process.signals.lock_no_irq();
+    let mut task_signals = task.signals.lock_no_irq();
+    let blocked = task_signals.blocked;
+    let sig = match task_signals.pending.difference(blocked).first() {
+        Some(sig) => {
+            task_signals.pending.remove(sig);
+            sig
+        }
+        None => {
+            let sig = signals.pending.difference(blocked).first()?;
+            signals.pending.remove(sig);
+            sig
+        }
+    };
+    let action = signals.actions[sig - 1];
+    if action.handler > SIG_IGN && action.flags & SA_RESETHAND != 0 {
+        signals.actions[sig - 1] = SigAction::default();
+    }
+    Some((sig, action))
+}
+
+/// Deliver the pending signals of the current thread before it returns to user mode with `context`.
+///
+/// Ignored signals are dropped, and at most one handler is entered, through a signal frame.
+/// The thread waits here while its process is stopped, and doesn't return if the process terminates.
+/// It returns early if the thread is killed, which must then exit.
+///
+/// **Preemption must be enabled**, as the thread may block or exit.
+pub fn handle_signals(context: &mut TrapContext) {
+    let task = get_current_task();
+    let Some(process) = task.process.get().cloned() else {
+        return;
+    };
+    loop {
+        process.wait_while_stopped();
+        if task.is_killed() {
+            return;
+        }
+        let Some((sig, action)) = take_signal(&process, &task) else {
+            return;
+        };
+        match action.handler {
+            SIG_IGN => {}
+            SIG_DFL => match default_action(sig) {
+                DefaultAction::Ignore | DefaultAction::Continue => {}
+                DefaultAction::Stop => process.stop(),
+                DefaultAction::Terminate => {
+                    drop(task);
+                    process.exit_group(ExitStatus::Signaled(sig));
+                }
+            },
+            handler => {
+                // Only the thread changes its mask, so it can't change while the frame is written.
+                let blocked = task.signals.lock_no_irq().blocked;
+                let fpu = unsafe { &mut *task.get_fpu_context_mut_ptr() };
+                let res =
+                    setup_signal_frame(context, fpu, sig, handler, SIGNAL_TRAMPOLINE, blocked);
+                if res.is_err() {
+                    // The stack can't take the frame, so the handler can't run either.
+                    drop(task);
+                    process.exit_group(ExitStatus::Signaled(SIGSEGV));
+                }
+                let mut mask = action.mask;
+                if action.flags & SA_NODEFER == 0 {
+                    mask.insert(sig);
+                }
+                task.signals.lock_no_irq().blocked =
+                    blocked.union(mask).difference(SigSet::UNBLOCKABLE);
+                return;
+            }
+        }
+    }
+}
+
+/// Restore the user context saved in the signal frame of the handler returning with `context`,
+/// along with the signal mask. The thread gets `SIGSEGV` if the frame can't be read.
+pub fn return_from_handler(context: &mut TrapContext) {
+    let task = get_current_task();
+    let fpu = unsafe { &mut *task.get_fpu_context_mut_ptr() };
+    match restore_signal_frame(context, fpu) {
+        Ok(blocked) => {
+            task.signals.lock_no_irq().blocked = blocked.difference(SigSet::UNBLOCKABLE);
+        }
+        Err(_) => {
+            drop(task);
+            force_signal(SIGSEGV);
+        }
+    }
+}
@@ -0,0 +1,181 @@
+//! Scheduling Statistics
+//!
+//! Every task accounts its CPU time, context switches and run queue wait time in its [TaskStats],
+//! and every hart its idle and busy time. The scheduler updates them when it switches tasks,
+//! so the running slice of a task is only counted once it's switched out.
+//!
+//! The trap handler tells the time spent in user mode apart from the rest of the CPU time.
+
+use crate::{
+    dev::{get_present_hart_ids, is_working_hart},
+    percpu,
+    timer::{get_time, time_to_ns},
+};
+use core::sync::atomic::{AtomicUsize, Ordering};
+use log::{Level, log};
+
+/// Value of [TaskStats::last_hart] before the task first runs.
+const NO_HART: usize = usize::MAX;
+
+#[derive(Debug)]
+pub struct TaskStats {
+    /// Time spent running in nanoseconds.
+    cpu_time: AtomicUsize,
+    /// Part of the CPU time spent in user mode, in nanoseconds.
+    user_time: AtomicUsize,
+    /// Switches out because the task blocked or exited.
+    nr_voluntary_switches: AtomicUsize,
+    /// Switches out because the task was preempted.
+    nr_involuntary_switches: AtomicUsize,
+    /// Time spent ready in run queues in nanoseconds.
+    wait_time: AtomicUsize,
+    /// Time the task last became ready.
+    ready_since: AtomicUsize,
+    /// Time the task last returned to user mode, or was switched in.
+    user_since: AtomicUsize,
+    last_hart: AtomicUsize,
+}
+
+impl TaskStats {
+    /// Statistics of a new task, which is ready from now on.
+    pub fn new() -> TaskStats {
+        TaskStats {
+            cpu_time: AtomicUsize::new(0),
+            user_time: AtomicUsize::new(0),
+            nr_voluntary_switches: AtomicUsize::new(0),
+            nr_involuntary_switches: AtomicUsize::new(0),
+            wait_time: AtomicUsize::new(0),
+            ready_since: AtomicUsize::new(get_time()),
+            user_since: AtomicUsize::new(0),
+            last_hart: AtomicUsize::new(NO_HART),
+        }
+    }
+
+    /// Called when the task becomes ready at `now`.
+    pub fn mark_ready(&self, now: usize) {
+        self.ready_since.store(now, Ordering::Relaxed);
+    }
+
+    /// Called when the task is switched in on hart `hart_id` at `now`.
+    pub fn switch_in(&self, hart_id: usize, now: usize) {
+        let ready_since = self.ready_since.load(Ordering::Relaxed);
+        self.wait_time.fetch_add(
+            time_to_ns(now.saturating_sub(ready_since)),
+            Ordering::Relaxed,
+        );
+        // A task switched in returns to user mode without going through the trap handler.
+        self.user_since.store(now, Ordering::Relaxed);
+        self.last_hart.store(hart_id, Ordering::Relaxed);
+    }
+
+    /// Called when the task is switched out after running `runtime_ns` nanoseconds.
+    pub fn switch_out(&self, runtime_ns: usize, voluntary: bool) {
+        self.cpu_time.fetch_add(runtime_ns, Ordering::Relaxed);
+        if voluntary {
+            self.nr_voluntary_switches.fetch_add(1, Ordering::Relaxed);
+        } else {
+            self.nr_involuntary_switches.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+
+    /// Called by the trap handler before the task returns to user mode.
+    pub fn enter_user(&self) {
+        self.user_since.store(get_time(), Ordering::Relaxed);
+    }
+
+    /// Called by the trap handler when the task traps from user mode.
+    pub fn leave_user(&self) {
+        let user_since = self.user_since.load(Ordering::Relaxed);
+        self.user_time.fetch_add(
+            time_to_ns(get_time().saturating_sub(user_since)),
+            Ordering::Relaxed,
+        );
+    }
+
+    pub fn get_cpu_time(&self) -> usize {
+        self.cpu_time.load(Ordering::Relaxed)
+    }
+
+    pub fn get_user_time(&self) -> usize {
+        self.user_time.load(Ordering::Relaxed)
+    }
+
+    /// The CPU time spent in the kernel, in nanoseconds.
+    pub fn get_system_time(&self) -> usize {
+        self.get_cpu_time().saturating_sub(self.get_user_time())
+    }
+
+    pub fn get_voluntary_switches(&self) -> usize {
+        self.nr_voluntary_switches.load(Ordering::Relaxed)
+    }
+
+    pub fn get_involuntary_switches(&self) -> usize {
+        self.nr_involuntary_switches.load(Ordering::Relaxed)
+    }
+
+    pub fn get_wait_time(&self) -> usize {
+        self.wait_time.load(Ordering::Relaxed)
+    }
+
+    /// The hart the task last ran on, or [None] if it never ran.
+    pub fn get_last_hart(&self) -> Option<usize> {
+        match self.last_hart.load(Ordering::Relaxed) {
+            NO_HART => None,
+            hart_id => Some(hart_id),
+        }
+    }
+}
+
+// region: Hart Times
+
+percpu! {
+    /// Time the hart spent running its idle task, in nanoseconds.
+    static IDLE_TIME: AtomicUsize = AtomicUsize::new(0);
+
+    /// Time the hart spent running other tasks, in nanoseconds.
+    static BUSY_TIME: AtomicUsize = AtomicUsize::new(0);
+}
+
+/// Called when hart `hart_id` switches out a task which ran `runtime_ns` nanoseconds.
+pub fn account_hart_time(hart_id: usize, runtime_ns: usize, idle: bool) {
+    let time = if idle { &IDLE_TIME } else { &BUSY_TIME };
+    time.remote(hart_id)
+        .fetch_add(runtime_ns, Ordering::Relaxed);
+}
+
+pub fn get_hart_idle_time(hart_id: usize) -> usize {
+    IDLE_TIME.remote(hart_id).load(Ordering::Relaxed)
+}
+
+pub fn get_hart_busy_time(hart_id: usize) -> usize {
+    BUSY_TIME.remote(hart_id).load(Ordering::Relaxed)
+}
+
+/// Log the state, idle and busy time of every present hart at `level`.
+pub fn dump_hart_times(level: Level) {
+    log!(
+        level,
+        "{:>4} {:>8} {:>12} {:>12}",
+        "HART",
+        "STATE",
+        "IDLE(us)",
+        "BUSY(us)"
+    );
+    for hart_id in get_present_hart_ids() {
+        let state = if is_working_hart(hart_id) {
+            "online"
+        } else {
+            "offline"
+        };
+        log!(
+            level,
+            "{:>4} {:>8} {:>12} {:>12}",
+            hart_id,
+            state,
+            get_hart_idle_time(hart_id) / 1000,
+            get_hart_busy_time(hart_id) / 1000
+        );
+    }
+}
+
+// endregion
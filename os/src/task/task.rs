@@ -1,13 +1,31 @@
 use crate::{
-    arch::{KERNEL_OFFSET, MAX_HARTS, task::context::TaskContext, trap::context::TrapContext},
+    arch::{
+        KERNEL_OFFSET, MAX_HARTS, MAX_USPACE_ADDR,
+        hart::get_current_hart_id,
+        task::{
+            context::TaskContext,
+            fpu::{self, FpuContext},
+        },
+        trap::context::TrapContext,
+    },
     mm::{frame::FrameAllocatorError, space::MemSpace, stack::KernelStack},
+    mutex::SpinLock,
+    sched::SchedEntity,
     task::{
-        processor::{PROCESSORS, Processor},
+        get_current_task,
+        process::Process,
+        registry::{register_task, unregister_task},
+        signal::{TaskSignals, has_pending_signal},
+        stats::TaskStats,
         tid::{TaskId, alloc_tid},
     },
 };
-use alloc::sync::Arc;
-use spin::RwLock;
+use alloc::{
+    string::{String, ToString},
+    sync::Arc,
+};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::{Once, RwLock};
 use utils::sync::LocalCell;
 
 #[derive(Debug)]
@@ -19,7 +37,26 @@ pub struct Task {
 
     // Basic Info
     pub tid: TaskId,
+    /// Name for diagnostics, not necessarily unique.
+    pub name: String,
     pub status: RwLock<TaskStatus>,
+    /// Whether the task is running on a hart, or its context is still being saved there.
+    /// A hart must wait for it to be cleared before switching to the task.
+    pub on_cpu: AtomicBool,
+    pub sched: SchedEntity,
+    pub stats: TaskStats,
+
+    /// Process the task is a thread of, set once. Kernel tasks have none.
+    pub process: Once<Arc<Process>>,
+    /// Set when the thread must exit on its way back to user mode.
+    pub killed: AtomicBool,
+    /// Set by [super::scheduler::interrupt] if the task was not blocked yet,
+    /// so that its next [super::scheduler::block_current] returns at once.
+    pub interrupt_pending: AtomicBool,
+    /// User address of a word zeroed, and futex woken, when the thread exits. 0 if none.
+    pub clear_child_tid: AtomicUsize,
+    /// Signals sent to the thread, and those it blocks. Locked after those of its process.
+    pub signals: SpinLock<TaskSignals>,
 
     // Memory Management
     /// Memspace of current task. For kernel tasks, the value is [None].
@@ -35,30 +72,51 @@ pub struct Task {
 pub struct TaskInner {
     pub task_context: TaskContext,
     pub trap_context: TrapContext,
+    /// Floating-point and vector registers, saved lazily.
+    pub fpu_context: FpuContext,
     pub hart_id: usize,
 }
 
 impl Task {
-    pub fn new_kernel_from_entry(
+    /// Create a task entering `entry` on hart `hart_id`.
+    /// Tasks with a memspace run in user mode on the stack `user_sp`, the others on their kernel stack.
+    fn new_from_entry(
+        name: &str,
         entry: *const (),
         hart_id: usize,
+        memsp: Option<Arc<MemSpace>>,
+        user_sp: usize,
     ) -> Result<Arc<Task>, FrameAllocatorError> {
         debug_assert!(hart_id < MAX_HARTS);
-        debug_assert!(entry as usize >= KERNEL_OFFSET);
         let tid = alloc_tid();
         let kstack = KernelStack::new()?;
         let kstack_top = kstack.get_stack_top();
+        let trap_context = match memsp {
+            None => {
+                TrapContext::zero_from_entry(entry, hart_id, true, kstack_top, kstack_top, hart_id)
+            }
+            // The user tp starts at 0, the hart id is kept in the trap context.
+            Some(_) => TrapContext::zero_from_entry(entry, hart_id, false, user_sp, kstack_top, 0),
+        };
         let inner = TaskInner {
             task_context: TaskContext::uninitialized(),
-            trap_context: TrapContext::zero_from_entry(
-                entry, hart_id, true, kstack_top, kstack_top, hart_id,
-            ),
+            trap_context,
+            fpu_context: FpuContext::default(),
             hart_id,
         };
         let res = Arc::new(Task {
             tid: tid,
+            name: name.to_string(),
             status: RwLock::new(TaskStatus::Ready),
-            memsp: None,
+            on_cpu: AtomicBool::new(false),
+            sched: SchedEntity::new(),
+            stats: TaskStats::new(),
+            process: Once::new(),
+            killed: AtomicBool::new(false),
+            interrupt_pending: AtomicBool::new(false),
+            clear_child_tid: AtomicUsize::new(0),
+            signals: SpinLock::new(TaskSignals::default()),
+            memsp,
             kstack_top: kstack.get_stack_top(),
             kstack,
             inner: unsafe { LocalCell::new(inner) },
@@ -67,6 +125,81 @@ impl Task {
         let mut inner_exc = unsafe { res.inner.exclusive_access() };
         inner_exc.task_context = TaskContext::return_to_task(trap_ctx, kstack_top);
         drop(inner_exc);
+        register_task(&res);
+        Ok(res)
+    }
+
+    pub fn new_kernel_from_entry(
+        name: &str,
+        entry: *const (),
+        hart_id: usize,
+    ) -> Result<Arc<Task>, FrameAllocatorError> {
+        debug_assert!(entry as usize >= KERNEL_OFFSET);
+        Self::new_from_entry(name, entry, hart_id, None, 0)
+    }
+
+    /// Create a task named `name` running `entry(arg)` in user mode in `memsp`,
+    /// with the stack pointer at `user_sp`.
+    /// It's queued on the current hart until it's added to the scheduler.
+    pub fn new_user(
+        name: &str,
+        memsp: Arc<MemSpace>,
+        entry: usize,
+        user_sp: usize,
+        arg: usize,
+    ) -> Result<Arc<Task>, FrameAllocatorError> {
+        debug_assert!(entry < MAX_USPACE_ADDR && user_sp <= MAX_USPACE_ADDR);
+        let res = Self::new_from_entry(
+            name,
+            entry as *const (),
+            get_current_hart_id(),
+            Some(memsp),
+            user_sp,
+        )?;
+        unsafe { res.inner.exclusive_access() }.trap_context.x[10] = arg; // a0
+        Ok(res)
+    }
+
+    /// Create a task resuming user mode at `pc` in `memsp` with the general registers `regs`,
+    /// as a clone of the current thread, whose floating-point and vector registers it gets too.
+    pub fn new_user_with_regs(
+        name: &str,
+        memsp: Arc<MemSpace>,
+        pc: usize,
+        regs: &[usize; 32],
+    ) -> Result<Arc<Task>, FrameAllocatorError> {
+        let res = Self::new_user(name, memsp, pc, regs[2], 0)?;
+        let current = get_current_task();
+        let (current_fpu, current_context) = unsafe {
+            (
+                &mut *current.get_fpu_context_mut_ptr(),
+                &mut *current.get_trap_context_mut_ptr(),
+            )
+        };
+        // The registers of the current thread may be newer than its saved ones.
+        fpu::save(current_fpu, current_context);
+        let mut inner = unsafe { res.inner.exclusive_access() };
+        inner.trap_context.x = *regs;
+        inner
+            .trap_context
+            .set_fp_state(current_context.get_fp_state());
+        inner
+            .trap_context
+            .set_vector_state(current_context.get_vector_state());
+        inner.fpu_context = current_fpu.fork();
+        drop(inner);
+        Ok(res)
+    }
+
+    /// Create a kernel task running `entry(arg)`.
+    pub fn new_kernel(
+        name: &str,
+        entry: extern "C" fn(usize) -> !,
+        arg: usize,
+        hart_id: usize,
+    ) -> Result<Arc<Task>, FrameAllocatorError> {
+        let res = Self::new_kernel_from_entry(name, entry as *const (), hart_id)?;
+        unsafe { res.inner.exclusive_access() }.trap_context.x[10] = arg; // a0
         Ok(res)
     }
 }
@@ -101,11 +234,22 @@ impl Task {
         unsafe { &mut self.inner.exclusive_access().trap_context }
     }
 
-    /// Get the hart info of this task.
+    /// Get the mutable floating-point and vector context ptr of this task.
     /// **This function is UP-Safe and cannot be preempted.
     ///   Wrap the function in [super::preempt::disable_preempt()] and [super::preempt::restore_preempt()] if needed**
-    pub unsafe fn get_hart_info(&self) -> &'static Processor {
-        unsafe { &PROCESSORS[self.inner.exclusive_access().hart_id] }
+    pub unsafe fn get_fpu_context_mut_ptr(&self) -> *mut FpuContext {
+        unsafe { &mut self.inner.exclusive_access().fpu_context }
+    }
+
+    /// Move the task to hart `hart_id` before it's switched to there.
+    /// **This function is UP-Safe and cannot be preempted.
+    ///   The task must not be running on any hart.**
+    pub unsafe fn set_hart_id(&self, hart_id: usize) {
+        let mut inner = unsafe { self.inner.exclusive_access() };
+        if inner.hart_id != hart_id {
+            inner.hart_id = hart_id;
+            inner.trap_context.set_hart_id(hart_id);
+        }
     }
 }
 
@@ -113,13 +257,82 @@ impl Task {
     pub fn get_tid(&self) -> usize {
         self.tid.value()
     }
+
+    pub fn is_killed(&self) -> bool {
+        self.killed.load(Ordering::Acquire)
+    }
+
+    /// Whether the task is killed or has a signal to take, so that it must give up blocking calls.
+    pub fn is_interrupted(&self) -> bool {
+        self.is_killed() || has_pending_signal(self)
+    }
+
+    /// Take a snapshot of the task for diagnostics.
+    pub fn get_info(&self) -> TaskInfo {
+        TaskInfo {
+            tid: self.get_tid(),
+            pid: self.process.get().map(|process| process.get_pid()),
+            name: self.name.clone(),
+            status: *self.status.read(),
+            hart_id: self.sched.get_hart(),
+            last_hart: self.stats.get_last_hart(),
+            user_time_ns: self.stats.get_user_time(),
+            system_time_ns: self.stats.get_system_time(),
+            wait_time_ns: self.stats.get_wait_time(),
+            nr_voluntary_switches: self.stats.get_voluntary_switches(),
+            nr_involuntary_switches: self.stats.get_involuntary_switches(),
+            kstack_used: self.kstack.get_max_usage(),
+        }
+    }
 }
 
-#[derive(Debug)]
+impl Drop for Task {
+    fn drop(&mut self) {
+        // The tid is freed after this, so no other task can be registered under it yet.
+        unregister_task(self.get_tid());
+    }
+}
+
+/// Snapshot of a task, returned by [Task::get_info].
+#[derive(Debug, Clone)]
+pub struct TaskInfo {
+    pub tid: usize,
+    /// Pid of the process of the task, [None] for kernel tasks.
+    pub pid: Option<usize>,
+    pub name: String,
+    pub status: TaskStatus,
+    /// The hart the task is queued or running on.
+    pub hart_id: usize,
+    /// The hart the task last ran on, if it ever ran.
+    pub last_hart: Option<usize>,
+    pub user_time_ns: usize,
+    pub system_time_ns: usize,
+    /// Time spent ready in run queues.
+    pub wait_time_ns: usize,
+    pub nr_voluntary_switches: usize,
+    pub nr_involuntary_switches: usize,
+    /// Maximum number of bytes of its kernel stack ever used.
+    pub kstack_used: usize,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub enum TaskStatus {
     Running,
     Ready,
     Blocked,
+    /// The task has finished and will never run again.
+    Exited,
+}
+
+impl TaskStatus {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TaskStatus::Running => "Running",
+            TaskStatus::Ready => "Ready",
+            TaskStatus::Blocked => "Blocked",
+            TaskStatus::Exited => "Exited",
+        }
+    }
 }
 
 pub fn init() {}
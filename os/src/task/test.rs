@@ -5,7 +5,7 @@ use log::info;
 use crate::{
     arch::hart::get_current_hart_id,
     sched::Scheduler,
-    task::{get_current_task, scheduler::SCHEDULERS, task::Task},
+    task::{get_current_task, scheduler::get_scheduler, task::Task},
 };
 
 pub fn add_test_tasks() {
@@ -18,8 +18,8 @@ pub fn add_test_tasks() {
 
 pub unsafe fn add_to_current(entry: *const ()) {
     let hart_id = get_current_hart_id();
-    let mut scheduler = unsafe { SCHEDULERS[hart_id].exclusive_access() };
-    scheduler.add_to_ready(Task::new_kernel_from_entry(entry, hart_id).unwrap());
+    let mut scheduler = get_scheduler(hart_id).lock_no_irq();
+    scheduler.attach(Task::new_kernel_from_entry("test", entry, hart_id).unwrap());
 }
 
 static COUNTER: AtomicUsize = AtomicUsize::new(1);
@@ -15,6 +15,14 @@ impl TaskIdAllocator {
         }
     }
 
+    /// An allocator handing out ids from `first` on.
+    pub const fn starting_from(first: usize) -> TaskIdAllocator {
+        TaskIdAllocator {
+            current: first,
+            recycled: vec![],
+        }
+    }
+
     /// Allocate an tid.
     ///
     /// Use [alloc_tid] for safety.
@@ -55,8 +63,7 @@ impl Drop for TaskId {
     }
 }
 
-pub static TID_ALLOC: SpinLock<TaskIdAllocator> =
-    SpinLock::new(TaskIdAllocator::new());
+pub static TID_ALLOC: SpinLock<TaskIdAllocator> = SpinLock::new(TaskIdAllocator::new());
 
 pub fn alloc_tid() -> TaskId {
     TaskId {
@@ -0,0 +1,113 @@
+use crate::{
+    arch::trap::intr::{disable_intr, restore_intr},
+    mutex::SpinLock,
+    task::{
+        scheduler::{block_current, wake_up},
+        task::Task,
+    },
+};
+use alloc::{collections::vec_deque::VecDeque, sync::Arc};
+use core::{
+    pin::Pin,
+    task::{Context, Poll, Waker},
+};
+
+/// Queue of tasks blocked until a condition holds.
+///
+/// The waker makes the condition true before calling [WaitQueue::wake_all] or [WaitQueue::wake_one].
+/// The condition is checked with the queue locked, so a wake up can't slip in between the check and blocking.
+///
+/// Futures wait on it with [WaitQueue::wait_until_async].
+pub struct WaitQueue {
+    queue: SpinLock<VecDeque<Waiter>>,
+}
+
+enum Waiter {
+    Task(Arc<Task>),
+    Waker(Waker),
+}
+
+impl Waiter {
+    fn wake(self) {
+        match self {
+            Waiter::Task(task) => wake_up(task),
+            Waiter::Waker(waker) => waker.wake(),
+        }
+    }
+}
+
+impl WaitQueue {
+    pub const fn new() -> WaitQueue {
+        WaitQueue {
+            queue: SpinLock::new(VecDeque::new()),
+        }
+    }
+
+    /// Block the current task until `cond` returns true.
+    ///
+    /// **Preemption must be enabled.**
+    pub fn wait_until(&self, cond: impl Fn() -> bool) {
+        loop {
+            let intr = disable_intr();
+            let mut queue = self.queue.lock();
+            if cond() {
+                drop(queue);
+                restore_intr(intr);
+                return;
+            }
+            block_current(move |task| {
+                queue.push_back(Waiter::Task(task.clone()));
+                drop(queue);
+            });
+            restore_intr(intr);
+        }
+    }
+
+    /// A future resolving once `cond` returns true.
+    ///
+    /// A future dropped before it resolves leaves its waker behind, which may absorb a [WaitQueue::wake_one].
+    pub fn wait_until_async<F: Fn() -> bool>(&self, cond: F) -> WaitUntil<'_, F> {
+        WaitUntil { queue: self, cond }
+    }
+
+    /// Wake up the first waiting task. Return `false` if there was none.
+    pub fn wake_one(&self) -> bool {
+        let waiter = self.queue.lock_no_irq().pop_front();
+        match waiter {
+            Some(waiter) => {
+                waiter.wake();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Wake up all the waiting tasks and return their number.
+    pub fn wake_all(&self) -> usize {
+        let waiters = core::mem::take(&mut *self.queue.lock_no_irq());
+        let count = waiters.len();
+        for waiter in waiters {
+            waiter.wake();
+        }
+        count
+    }
+}
+
+/// Future returned by [WaitQueue::wait_until_async].
+pub struct WaitUntil<'a, F> {
+    queue: &'a WaitQueue,
+    cond: F,
+}
+
+impl<F: Fn() -> bool> Future for WaitUntil<'_, F> {
+    type Output = ();
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<()> {
+        let mut queue = self.queue.queue.lock_no_irq();
+        if (self.cond)() {
+            return Poll::Ready(());
+        }
+        queue.push_back(Waiter::Waker(cx.waker().clone()));
+        Poll::Pending
+    }
+}
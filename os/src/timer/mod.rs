@@ -0,0 +1,298 @@
+//! Timer Module
+//!
+//! Every hart owns a queue of timer events ordered by deadline, plus an optional periodic
+//! scheduling tick. The hardware timer of a hart is always programmed to the earlier of the two.
+//!
+//! When a hart switches to its idle task, the scheduler stops the tick with [stop_tick]:
+//! only real deadlines are programmed, and the hart sleeps in `wfi` until a deadline expires
+//! or another hart sends it an IPI. The tick is restarted with [restart_tick] as soon as the
+//! hart runs a task again.
+//!
+//! A hart going offline moves its pending events to a working hart, see [hart_offline].
+//!
+//! All times are in timebase units as returned by [get_time].
+
+use crate::{
+    arch::{
+        hart::get_current_hart_id,
+        timer::{DEFAULT_TIMEBASE_FREQ, TIMER_TICK, clear_event, set_next_event},
+        trap::intr::{disable_intr, restore_intr},
+    },
+    dev::{get_present_hart_ids, get_working_hart_ids},
+    mutex::SpinLock,
+    percpu,
+    smp::smp_call_on,
+    task::scheduler::{block_current, schedule, scheduler_tick, wake_up},
+};
+use alloc::{boxed::Box, collections::btree_map::BTreeMap};
+use core::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use spin::Once;
+
+pub use crate::arch::timer::get_time;
+
+pub const NSEC_PER_SEC: usize = 1_000_000_000;
+
+type TimerCallback = Box<dyn FnOnce() + Send>;
+
+/// Timer state of a hart.
+struct HartTimer {
+    /// Pending events, keyed by `(deadline, sequence number)`.
+    events: BTreeMap<(usize, usize), TimerCallback>,
+    /// Deadline of the next scheduling tick. [None] if the tick is stopped.
+    next_tick: Option<usize>,
+}
+
+impl HartTimer {
+    const fn new() -> HartTimer {
+        HartTimer {
+            events: BTreeMap::new(),
+            next_tick: None,
+        }
+    }
+
+    /// The earliest time the hardware timer has to fire at.
+    fn next_event(&self) -> Option<usize> {
+        let deadline = self.events.first_key_value().map(|(key, _)| key.0);
+        match (deadline, self.next_tick) {
+            (Some(deadline), Some(tick)) => Some(deadline.min(tick)),
+            (deadline, tick) => deadline.or(tick),
+        }
+    }
+
+    /// Program the hardware timer. **Only call it on the hart owning this state.**
+    fn reprogram(&self) {
+        match self.next_event() {
+            Some(deadline) => set_next_event(deadline),
+            None => clear_event(),
+        }
+    }
+}
+
+percpu! {
+    static TIMER: SpinLock<HartTimer> = SpinLock::new(HartTimer::new());
+}
+
+static TIMER_SEQ: AtomicUsize = AtomicUsize::new(0);
+
+/// Whether events were ever moved to another hart by [hart_offline].
+static EVENTS_MOVED: AtomicBool = AtomicBool::new(false);
+
+/// Run `f` on the timer state of the current hart with interrupts disabled.
+fn with_local_timer<R>(f: impl FnOnce(&mut HartTimer) -> R) -> R {
+    let intr = disable_intr();
+    let res = f(&mut TIMER.remote(get_current_hart_id()).lock());
+    restore_intr(intr);
+    res
+}
+
+// region: Events
+
+/// Handle to a pending timer event.
+#[derive(Debug, Clone, Copy)]
+pub struct TimerHandle {
+    hart_id: usize,
+    key: (usize, usize),
+}
+
+impl TimerHandle {
+    /// Cancel the event. Return `false` if it has already fired or been canceled.
+    pub fn cancel(&self) -> bool {
+        if remove_event(self.hart_id, &self.key) {
+            return true;
+        }
+        // The hart may have gone offline and handed the event over. The keys are unique to all harts.
+        EVENTS_MOVED.load(Ordering::Acquire)
+            && get_present_hart_ids()
+                .into_iter()
+                .filter(|hart_id| *hart_id != self.hart_id)
+                .any(|hart_id| remove_event(hart_id, &self.key))
+    }
+}
+
+fn remove_event(hart_id: usize, key: &(usize, usize)) -> bool {
+    TIMER
+        .remote(hart_id)
+        .lock_no_irq()
+        .events
+        .remove(key)
+        .is_some()
+}
+
+/// Run `callback` on the current hart once [get_time] reaches `deadline`.
+///
+/// **The callback runs in interrupt context; it must not block.**
+pub fn add_timer(deadline: usize, callback: impl FnOnce() + Send + 'static) -> TimerHandle {
+    let key = (deadline, TIMER_SEQ.fetch_add(1, Ordering::Relaxed));
+    let hart_id = with_local_timer(|timer| {
+        let reprogram = timer.next_event().is_none_or(|next| deadline < next);
+        timer.events.insert(key, Box::new(callback));
+        if reprogram {
+            timer.reprogram();
+        }
+        get_current_hart_id()
+    });
+    TimerHandle { hart_id, key }
+}
+
+/// Handle a timer interrupt of the current hart.
+///
+/// Run the expired events, advance the tick and program the next event.
+/// The scheduler is notified when a tick elapsed, and called when the hart is tickless (idle),
+/// so that the tasks woken up by the events get to run.
+pub fn handle_timer_intr() {
+    loop {
+        let callback = with_local_timer(|timer| {
+            let now = get_time();
+            let expired = timer
+                .events
+                .first_key_value()
+                .is_some_and(|(key, _)| key.0 <= now);
+            if expired {
+                timer.events.pop_first().map(|(_, callback)| callback)
+            } else {
+                None
+            }
+        });
+        match callback {
+            Some(callback) => callback(),
+            None => break,
+        }
+    }
+    let (tick, tickless) = with_local_timer(|timer| {
+        let now = get_time();
+        let next_tick = timer.next_tick;
+        let tick = match next_tick {
+            Some(next) if next <= now => {
+                timer.next_tick = Some(now + TIMER_TICK);
+                true
+            }
+            _ => false,
+        };
+        timer.reprogram();
+        (tick, timer.next_tick.is_none())
+    });
+    if tick {
+        scheduler_tick();
+    } else if tickless {
+        schedule();
+    }
+}
+
+// endregion
+
+// region: Tick
+
+/// Stop the periodic tick of the current hart; only pending events keep the timer armed.
+///
+/// Called by the scheduler when the hart switches to its idle task.
+pub fn stop_tick() {
+    with_local_timer(|timer| {
+        if timer.next_tick.take().is_some() {
+            timer.reprogram();
+        }
+    });
+}
+
+/// Restart the periodic tick of the current hart if it's stopped.
+pub fn restart_tick() {
+    with_local_timer(|timer| {
+        if timer.next_tick.is_none() {
+            timer.next_tick = Some(get_time() + TIMER_TICK);
+            timer.reprogram();
+        }
+    });
+}
+
+/// Whether the periodic tick of the current hart is stopped.
+pub fn is_tick_stopped() -> bool {
+    with_local_timer(|timer| timer.next_tick.is_none())
+}
+
+// endregion
+
+// region: Hotplug
+
+/// Stop the tick and the timer of hart `hart_id`, going offline, and move its pending events
+/// to a working hart. Called on the hart itself by [crate::hotplug].
+pub fn hart_offline(hart_id: usize) {
+    let mut timer = TIMER.remote(hart_id).lock_no_irq();
+    timer.next_tick = None;
+    clear_event();
+    let Some(target) = get_working_hart_ids().first().copied() else {
+        return;
+    };
+    if timer.events.is_empty() {
+        return;
+    }
+    // Set before the events leave, so that a canceler failing to find them here looks elsewhere.
+    EVENTS_MOVED.store(true, Ordering::Release);
+    TIMER
+        .remote(target)
+        .lock_no_irq()
+        .events
+        .append(&mut timer.events);
+    drop(timer);
+    // Only the target programs its own timer.
+    let res = smp_call_on(
+        target,
+        || with_local_timer(|timer| timer.reprogram()),
+        false,
+    );
+    if let Err(err) = res {
+        log::warn!(
+            "Unable to reprogram the timer of hart #{:}: {:?}",
+            target,
+            err
+        );
+    }
+}
+
+// endregion
+
+// region: Time Conversion
+
+static TIMEBASE_FREQ: Once<usize> = Once::new();
+
+/// Record the timebase frequency found in the device tree.
+pub fn set_timebase_freq(freq: usize) {
+    TIMEBASE_FREQ.call_once(|| freq);
+}
+
+/// Get the frequency of [get_time] in Hz.
+pub fn get_timebase_freq() -> usize {
+    *TIMEBASE_FREQ.get().unwrap_or(&DEFAULT_TIMEBASE_FREQ)
+}
+
+/// Convert nanoseconds to timebase units, rounding up.
+pub fn ns_to_time(ns: usize) -> usize {
+    (ns as u128 * get_timebase_freq() as u128).div_ceil(NSEC_PER_SEC as u128) as usize
+}
+
+/// Convert timebase units to nanoseconds.
+pub fn time_to_ns(time: usize) -> usize {
+    (time as u128 * NSEC_PER_SEC as u128 / get_timebase_freq() as u128) as usize
+}
+
+// endregion
+
+// region: Sleep
+
+/// Block the current task until [get_time] reaches `deadline`.
+pub fn sleep_until(deadline: usize) {
+    block_current(|task| {
+        let task = task.clone();
+        add_timer(deadline, move || wake_up(task));
+    });
+}
+
+/// Block the current task for at least `ns` nanoseconds.
+pub fn sleep_ns(ns: usize) {
+    sleep_until(get_time() + ns_to_time(ns));
+}
+
+// endregion
+
+/// Start the tick of the current hart.
+pub fn init() {
+    restart_tick();
+}
@@ -0,0 +1,8 @@
+#!/bin/sh
+# Usage: mkinitrd.sh ROOT_DIR OUTPUT
+# Pack ROOT_DIR into a newc cpio archive; an empty archive is created if ROOT_DIR is missing.
+set -e
+
+mkdir -p "$1"
+out=$(realpath "$2")
+cd "$1" && find . | cpio -o -H newc --quiet > "$out"
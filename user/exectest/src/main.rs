@@ -0,0 +1,53 @@
+#![no_std]
+#![no_main]
+
+use karox_api::{
+    Errno, println,
+    process::{self, Fork},
+    syscall, testing,
+};
+
+/// Run `/hello` with arguments and environment, and check the number of arguments it saw.
+fn test_exec() -> Result<(), &'static str> {
+    let pid = match process::fork().map_err(|_| "fork failed")? {
+        Fork::Parent(pid) => pid,
+        Fork::Child => {
+            let errno = process::exec("/hello", &["hello", "a", "b"], &["TEST=exectest"]);
+            println!("exectest: exec failed: {:}", errno);
+            syscall::exit(-1)
+        }
+    };
+    match process::wait(Some(pid)) {
+        Ok((_, 3)) => Ok(()),
+        _ => Err("wrong exit status"),
+    }
+}
+
+/// A spawned program runs in the child, which keeps its pid across exec.
+fn test_same_pid() -> Result<(), &'static str> {
+    let pid = process::spawn("/hello", &["hello"]).map_err(|_| "spawn failed")?;
+    match process::wait(Some(pid)) {
+        Ok((reaped, 1)) if reaped == pid => Ok(()),
+        _ => Err("wrong child reaped"),
+    }
+}
+
+/// A failed exec returns to the caller.
+fn test_not_found() -> Result<(), &'static str> {
+    match process::exec("/no/such/program", &["none"], &[]) {
+        Errno::ENOENT => Ok(()),
+        _ => Err("wrong error"),
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    testing::run(
+        "exectest",
+        &[
+            ("exec", test_exec),
+            ("same_pid", test_same_pid),
+            ("not_found", test_not_found),
+        ],
+    )
+}
@@ -0,0 +1,129 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{vec, vec::Vec};
+use core::{
+    hint::black_box,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use karox_api::{
+    Errno, process,
+    testing::{self, fork_with},
+    thread,
+};
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// The child exits with a code its parent gets back.
+fn test_exit_status() -> Result<(), &'static str> {
+    let pid = fork_with(|| 42);
+    match process::wait(Some(pid)) {
+        Ok((reaped, 42)) if reaped == pid => Ok(()),
+        _ => Err("wrong exit status"),
+    }
+}
+
+/// Writes after the fork stay private to the process doing them, on the stack, heap and data.
+fn test_copy_on_write() -> Result<(), &'static str> {
+    let mut on_stack = [1u8; 64];
+    let mut on_heap = vec![1u8; 3 * 4096];
+    COUNTER.store(1, Ordering::Relaxed);
+    let pid = fork_with(|| {
+        let seen_parent = on_stack[0] == 1 && on_heap[4096] == 1;
+        on_stack[0] = 2;
+        on_heap[4096] = 2;
+        COUNTER.store(2, Ordering::Relaxed);
+        // Give the parent time to write its own copy.
+        thread::sleep(Duration::from_millis(20));
+        let own = black_box(on_stack[0]) == 2
+            && black_box(on_heap[4096]) == 2
+            && COUNTER.load(Ordering::Relaxed) == 2;
+        if seen_parent && own { 0 } else { 1 }
+    });
+    on_heap[2 * 4096] = 3;
+    thread::sleep(Duration::from_millis(40));
+    let untouched = black_box(on_stack[0]) == 1
+        && black_box(on_heap[4096]) == 1
+        && on_heap[2 * 4096] == 3
+        && COUNTER.load(Ordering::Relaxed) == 1;
+    match process::wait(Some(pid)) {
+        Ok((_, 0)) if untouched => Ok(()),
+        Ok((_, 0)) => Err("the child wrote to the memory of its parent"),
+        _ => Err("the parent wrote to the memory of its child"),
+    }
+}
+
+/// Every child is reaped once, then there is nothing left to wait for.
+fn test_wait_any() -> Result<(), &'static str> {
+    let mut pids: Vec<usize> = (0..8).map(|index| fork_with(move || index)).collect();
+    let mut codes = Vec::new();
+    while let Ok((pid, code)) = process::wait(None) {
+        let index = pids
+            .iter()
+            .position(|other| *other == pid)
+            .ok_or("reaped an unknown child")?;
+        pids.swap_remove(index);
+        codes.push(code);
+    }
+    codes.sort_unstable();
+    if !pids.is_empty() || codes != (0..8).collect::<Vec<i32>>() {
+        return Err("children were lost");
+    }
+    match process::wait(None) {
+        Err(Errno::ECHILD) => Ok(()),
+        _ => Err("waited without children"),
+    }
+}
+
+/// A running child is not reaped by a non-blocking wait.
+fn test_try_wait() -> Result<(), &'static str> {
+    let pid = fork_with(|| {
+        thread::sleep(Duration::from_millis(20));
+        7
+    });
+    if process::try_wait(Some(pid)) != Ok(None) {
+        return Err("reaped a running child");
+    }
+    loop {
+        match process::try_wait(Some(pid)) {
+            Ok(None) => thread::yield_now(),
+            Ok(Some((_, 7))) => return Ok(()),
+            _ => return Err("wrong exit status"),
+        }
+    }
+}
+
+/// A grandchild outliving its parent goes to init, so its grandparent can't wait for it.
+fn test_orphan() -> Result<(), &'static str> {
+    let pid = fork_with(|| {
+        let grandchild = fork_with(|| {
+            thread::sleep(Duration::from_millis(20));
+            0
+        });
+        grandchild as i32
+    });
+    let Ok((_, grandchild)) = process::wait(Some(pid)) else {
+        return Err("lost the child");
+    };
+    match process::wait(Some(grandchild as usize)) {
+        Err(Errno::ECHILD) => Ok(()),
+        _ => Err("waited for a grandchild"),
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    testing::run(
+        "forktest",
+        &[
+            ("exit_status", test_exit_status),
+            ("copy_on_write", test_copy_on_write),
+            ("wait_any", test_wait_any),
+            ("try_wait", test_try_wait),
+            ("orphan", test_orphan),
+        ],
+    )
+}
@@ -0,0 +1,64 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use core::hint::black_box;
+use karox_api::{
+    process,
+    testing::{self, fork_with},
+    thread,
+};
+
+const THREADS: usize = 4;
+const ROUNDS: usize = 2000;
+
+/// A floating-point computation seeded with `seed`, yielding now and then so that others run meanwhile.
+fn compute(seed: usize, yield_every: usize) -> u64 {
+    let mut x = black_box(seed as f64 + 0.5);
+    let mut y = black_box(1.0 / (seed as f64 + 3.0));
+    for round in 0..ROUNDS {
+        x = x * 1.000_001 + y;
+        y = y * 0.999 + 0.001;
+        if yield_every != 0 && round.is_multiple_of(yield_every) {
+            thread::yield_now();
+        }
+    }
+    (x + y).to_bits()
+}
+
+/// Threads switched back and forth get their own registers back.
+fn test_threads() -> Result<(), &'static str> {
+    let expected: Vec<u64> = (0..THREADS).map(|seed| compute(seed, 0)).collect();
+    let handles: Vec<_> = (0..THREADS)
+        .map(|seed| thread::spawn(move || compute(seed, 7)).expect("spawn failed"))
+        .collect();
+    let results: Vec<u64> = handles.into_iter().map(|handle| handle.join()).collect();
+    if results == expected {
+        Ok(())
+    } else {
+        Err("a thread got registers of another")
+    }
+}
+
+/// A forked child starts with the registers of its parent.
+fn test_fork() -> Result<(), &'static str> {
+    let value = black_box(1.0f64 / 3.0);
+    let pid = fork_with(|| {
+        if value.to_bits() == (1.0f64 / 3.0).to_bits() {
+            0
+        } else {
+            1
+        }
+    });
+    match process::wait(Some(pid)) {
+        Ok((_, 0)) => Ok(()),
+        _ => Err("the child lost the registers of its parent"),
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    testing::run("fputest", &[("threads", test_threads), ("fork", test_fork)])
+}
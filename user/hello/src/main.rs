@@ -0,0 +1,17 @@
+#![no_std]
+#![no_main]
+
+use karox_api::{env, println, process};
+
+/// Print the arguments and exit with their number, for `exectest` to check.
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    println!("Hello from pid {:}!", process::id());
+    for (index, arg) in env::args().enumerate() {
+        println!("argv[{:}] = {:?}", index, arg);
+    }
+    for (key, value) in env::vars() {
+        println!("env {:} = {:?}", key, value);
+    }
+    env::args().count() as i32
+}
@@ -1,18 +1,41 @@
 #![no_std]
 #![no_main]
 
-use core::panic::PanicInfo;
+use core::time::Duration;
+use karox_api::{Errno, env, eprintln, println, process, thread};
 
-use karox_api::syscall;
+/// Test programs run at boot, in order.
+const TESTS: &[&str] = &["/forktest", "/exectest", "/synctest", "/sigtest", "/fputest"];
 
+/// Interval between two checks for orphans once init has no child.
+const IDLE_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Init runs the test programs, then reaps the orphans it adopts. It must never exit.
 #[unsafe(no_mangle)]
-pub fn main() {
-    unsafe{
-        syscall::syscall(123, [234, 555, 666]);
+fn main() -> i32 {
+    println!(
+        "Hello from init (pid {:}, tid {:}).",
+        process::id(),
+        thread::current_tid()
+    );
+    for arg in env::args() {
+        println!("arg: {:}", arg);
+    }
+    for path in TESTS {
+        match process::spawn(path, &[path]) {
+            Ok(pid) => println!("init: {:} (pid {:}) started.", path, pid),
+            Err(errno) => eprintln!("init: failed to start {:}: {:}.", path, errno),
+        }
+    }
+    loop {
+        match process::wait(None) {
+            Ok((pid, code)) => println!("init: pid {:} exited with code {:}.", pid, code),
+            // Orphans may still come.
+            Err(Errno::ECHILD) => thread::sleep(IDLE_INTERVAL),
+            Err(errno) => {
+                eprintln!("init: wait failed: {:}.", errno);
+                thread::sleep(IDLE_INTERVAL);
+            }
+        }
     }
-}
-
-#[panic_handler]
-pub fn panic_handler(_pinfo: &PanicInfo) -> ! {
-    loop {}
 }
@@ -0,0 +1,61 @@
+//! Program arguments and environment.
+
+use core::{
+    ffi::{CStr, c_char},
+    slice,
+    sync::atomic::{AtomicPtr, AtomicUsize, Ordering},
+};
+
+static ARGC: AtomicUsize = AtomicUsize::new(0);
+static ARGV: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+static ENVP: AtomicPtr<*const c_char> = AtomicPtr::new(core::ptr::null_mut());
+
+/// Record the arguments and environment from the initial stack at `sp`.
+///
+/// **`sp` must point to the initial stack laid out by the kernel.**
+pub(crate) unsafe fn init(sp: *const usize) {
+    unsafe {
+        let argc = *sp;
+        let argv = sp.add(1) as *mut *const c_char;
+        ARGC.store(argc, Ordering::Relaxed);
+        ARGV.store(argv, Ordering::Relaxed);
+        ENVP.store(argv.add(argc + 1), Ordering::Relaxed);
+    }
+}
+
+/// The strings of a NULL-terminated pointer array. Strings which are not UTF-8 read as empty.
+fn strings(array: *const *const c_char) -> impl Iterator<Item = &'static str> {
+    let len = if array.is_null() {
+        0
+    } else {
+        (0..)
+            .take_while(|index| unsafe { !(*array.add(*index)).is_null() })
+            .count()
+    };
+    let ptrs: &'static [*const c_char] = unsafe {
+        if len == 0 {
+            &[]
+        } else {
+            slice::from_raw_parts(array, len)
+        }
+    };
+    ptrs.iter()
+        .map(|ptr| unsafe { CStr::from_ptr(*ptr) }.to_str().unwrap_or(""))
+}
+
+/// The arguments of the program, starting with its path.
+pub fn args() -> impl Iterator<Item = &'static str> {
+    strings(ARGV.load(Ordering::Relaxed)).take(ARGC.load(Ordering::Relaxed))
+}
+
+/// The environment of the program, as `KEY=value` strings.
+pub fn vars() -> impl Iterator<Item = (&'static str, &'static str)> {
+    strings(ENVP.load(Ordering::Relaxed)).map(|var| var.split_once('=').unwrap_or((var, "")))
+}
+
+/// The value of the environment variable `key`.
+pub fn var(key: &str) -> Option<&'static str> {
+    vars()
+        .find(|(name, _)| *name == key)
+        .map(|(_, value)| value)
+}
@@ -0,0 +1,25 @@
+//! Heap allocator.
+//!
+//! A buddy allocator takes its memory from the program break,
+//! which it moves up whenever it runs out.
+
+use crate::syscall::brk;
+use buddy_system_allocator::{Heap, LockedHeapWithRescue};
+use core::alloc::Layout;
+
+/// Minimum growth of the heap, to keep `brk` calls rare.
+const HEAP_GROW_SIZE: usize = 64 * 1024;
+
+#[global_allocator]
+static HEAP: LockedHeapWithRescue<32> = LockedHeapWithRescue::new(grow_heap);
+
+/// Move the break up to fit `layout`. The allocation fails if the break doesn't move.
+fn grow_heap(heap: &mut Heap<32>, layout: &Layout) {
+    let start = brk(0);
+    // Enough room for an aligned block whatever the alignment of the break.
+    let size = (layout.size() + layout.align()).max(HEAP_GROW_SIZE);
+    let end = brk(start + size);
+    if end > start {
+        unsafe { heap.add_to_heap(start, end) };
+    }
+}
@@ -0,0 +1,71 @@
+//! Console output.
+
+use crate::{error::Errno, sync::Mutex, syscall::write};
+use core::fmt::{Arguments, Write};
+
+pub const STDIN_FD: usize = 0;
+pub const STDOUT_FD: usize = 1;
+pub const STDERR_FD: usize = 2;
+
+/// Keeps the output of concurrent prints from interleaving.
+static OUTPUT_LOCK: Mutex<()> = Mutex::new(());
+
+/// Write all of `buf` to the file `fd`.
+pub fn write_all(fd: usize, mut buf: &[u8]) -> Result<(), Errno> {
+    while !buf.is_empty() {
+        match write(fd, buf)? {
+            0 => return Err(Errno::EIO),
+            count => buf = &buf[count..],
+        }
+    }
+    Ok(())
+}
+
+struct FdWriter(usize);
+
+impl Write for FdWriter {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        write_all(self.0, s.as_bytes()).map_err(|_| core::fmt::Error)
+    }
+}
+
+#[doc(hidden)]
+pub fn _print(fd: usize, args: Arguments) {
+    let _guard = OUTPUT_LOCK.lock();
+    // There is nowhere to report the failure of a print.
+    let _ = FdWriter(fd).write_fmt(args);
+}
+
+#[macro_export]
+macro_rules! print {
+    ($($arg: tt)*) => {
+        $crate::io::_print($crate::io::STDOUT_FD, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! println {
+    () => {
+        $crate::print!("\n")
+    };
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::io::_print($crate::io::STDOUT_FD, format_args!(concat!($fmt, "\n") $(, $($arg)+)?))
+    };
+}
+
+#[macro_export]
+macro_rules! eprint {
+    ($($arg: tt)*) => {
+        $crate::io::_print($crate::io::STDERR_FD, format_args!($($arg)*))
+    };
+}
+
+#[macro_export]
+macro_rules! eprintln {
+    () => {
+        $crate::eprint!("\n")
+    };
+    ($fmt: literal $(, $($arg: tt)+)?) => {
+        $crate::io::_print($crate::io::STDERR_FD, format_args!(concat!($fmt, "\n") $(, $($arg)+)?))
+    };
+}
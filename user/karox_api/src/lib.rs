@@ -1,3 +1,21 @@
+//! Runtime and system interface of karox user programs.
+//!
+//! Linking this crate provides the program entry, a panic handler and a heap,
+//! so that a program only defines `fn main() -> i32`, see [rt].
 #![no_std]
 
+extern crate alloc;
+
+pub mod env;
+pub mod error;
+mod heap;
+pub mod io;
+pub mod process;
+pub mod rt;
+pub mod signal;
+pub mod sync;
 pub mod syscall;
+pub mod testing;
+pub mod thread;
+
+pub use error::Errno;
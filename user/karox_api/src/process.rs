@@ -0,0 +1,105 @@
+//! Processes: creating them, running programs and waiting for them.
+
+use crate::{
+    error::Errno,
+    syscall::{self, WNOHANG},
+};
+use alloc::{ffi::CString, vec::Vec};
+use core::ptr;
+
+/// Pid of init, the first process, which adopts the orphans.
+pub const INIT_PID: usize = 1;
+
+/// The pid of the calling process.
+pub fn id() -> usize {
+    syscall::getpid()
+}
+
+/// The two ways out of [fork].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Fork {
+    /// In the parent, with the pid of the child.
+    Parent(usize),
+    Child,
+}
+
+/// Create a child process running a copy of the caller, which both resume from here.
+///
+/// Only the calling thread is copied: locks held by other threads stay locked in the child.
+pub fn fork() -> Result<Fork, Errno> {
+    match syscall::fork()? {
+        0 => Ok(Fork::Child),
+        pid => Ok(Fork::Parent(pid)),
+    }
+}
+
+fn to_cstring(s: &str) -> Result<CString, Errno> {
+    CString::new(s).map_err(|_| Errno::EINVAL)
+}
+
+fn to_cstrings(strings: &[&str]) -> Result<Vec<CString>, Errno> {
+    strings.iter().map(|s| to_cstring(s)).collect()
+}
+
+/// A NULL-terminated array of pointers to `strings`, which must outlive it.
+fn to_ptr_array(strings: &[CString]) -> Vec<*const u8> {
+    strings
+        .iter()
+        .map(|s| s.as_ptr() as *const u8)
+        .chain([ptr::null()])
+        .collect()
+}
+
+/// Replace the program of the process with the executable `path`, run with the arguments `args`,
+/// starting with its name by convention, and the environment `vars` of `KEY=value` strings.
+/// Only return on failure.
+pub fn exec(path: &str, args: &[&str], vars: &[&str]) -> Errno {
+    let (path, args, vars) = match (to_cstring(path), to_cstrings(args), to_cstrings(vars)) {
+        (Ok(path), Ok(args), Ok(vars)) => (path, args, vars),
+        (Err(errno), _, _) | (_, Err(errno), _) | (_, _, Err(errno)) => return errno,
+    };
+    let argv = to_ptr_array(&args);
+    let envp = to_ptr_array(&vars);
+    unsafe { syscall::execve(path.as_ptr() as *const u8, argv.as_ptr(), envp.as_ptr()) }
+}
+
+fn to_wait_pid(pid: Option<usize>) -> isize {
+    pid.map_or(-1, |pid| pid as isize)
+}
+
+/// Block until a child exits, `pid` or any child if [None], then reap it
+/// and return its pid and exit code, which is 128 plus the signal number if a signal killed it.
+/// Fail with [Errno::ECHILD] if there is no such child.
+pub fn wait(pid: Option<usize>) -> Result<(usize, i32), Errno> {
+    let mut status = 0;
+    let pid = syscall::waitpid(to_wait_pid(pid), Some(&mut status), 0)?;
+    Ok((pid, status))
+}
+
+/// Reap an exited child like [wait], or return [None] if the matching children are all running.
+pub fn try_wait(pid: Option<usize>) -> Result<Option<(usize, i32)>, Errno> {
+    let mut status = 0;
+    match syscall::waitpid(to_wait_pid(pid), Some(&mut status), WNOHANG)? {
+        0 => Ok(None),
+        pid => Ok(Some((pid, status))),
+    }
+}
+
+/// Run the program `path` with the arguments `args` in a child process, and return its pid.
+pub fn spawn(path: &str, args: &[&str]) -> Result<usize, Errno> {
+    let path = to_cstring(path)?;
+    let args = to_cstrings(args)?;
+    // Everything is prepared before forking, so that the child doesn't allocate.
+    let argv = to_ptr_array(&args);
+    let envp = [ptr::null()];
+    match fork()? {
+        Fork::Parent(pid) => Ok(pid),
+        Fork::Child => {
+            let errno = unsafe {
+                syscall::execve(path.as_ptr() as *const u8, argv.as_ptr(), envp.as_ptr())
+            };
+            crate::eprintln!("Failed to run {:?}: {:}.", path, errno);
+            syscall::exit(127)
+        }
+    }
+}
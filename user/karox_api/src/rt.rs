@@ -0,0 +1,68 @@
+//! Program entry and exit.
+//!
+//! The kernel enters `_start` with the initial stack laid out by the System V ABI:
+//! `argc`, then the argv and envp pointer arrays. The runtime records them, then calls
+//! the `main` of the program and exits the whole process with the code it returns:
+//!
+//! ```ignore
+//! #![no_std]
+//! #![no_main]
+//!
+//! #[unsafe(no_mangle)]
+//! fn main() -> i32 {
+//!     karox_api::println!("Hello!");
+//!     0
+//! }
+//! ```
+//!
+//! Programs linked with the runtime carry a `karox` ELF note, telling the kernel to
+//! run them with the karox system calls rather than the Linux ones.
+
+use crate::{env, eprintln, syscall::exit_group};
+use core::{arch::global_asm, panic::PanicInfo};
+
+/// Exit code of a panicking program.
+const PANIC_EXIT_CODE: i32 = 101;
+
+unsafe extern "Rust" {
+    fn main() -> i32;
+}
+
+global_asm! {
+    "
+        .section .text
+        .globl _start
+        _start:
+        mv a0, sp
+        andi sp, sp, -16
+        call __karox_start
+    "
+}
+
+// Note of owner "karox", type `NT_KAROX_ABI` (1), holding the ABI version 1.
+global_asm! {
+    "
+        .pushsection .note.karox, \"a\", @note
+        .balign 4
+        .word 6
+        .word 4
+        .word 1
+        .asciz \"karox\"
+        .balign 4
+        .word 1
+        .popsection
+    "
+}
+
+#[unsafe(no_mangle)]
+extern "C" fn __karox_start(sp: *const usize) -> ! {
+    unsafe { env::init(sp) };
+    let code = unsafe { main() };
+    exit_group(code)
+}
+
+#[panic_handler]
+fn panic_handler(info: &PanicInfo) -> ! {
+    eprintln!("Panicked: {}", info);
+    exit_group(PANIC_EXIT_CODE)
+}
@@ -0,0 +1,146 @@
+//! Signals: sending them, handling them and blocking them.
+
+use crate::{
+    error::Errno,
+    process,
+    syscall::{self, SIG_BLOCK, SIG_SETMASK, SIG_UNBLOCK},
+};
+
+pub const SIGHUP: usize = 1;
+pub const SIGINT: usize = 2;
+pub const SIGQUIT: usize = 3;
+pub const SIGILL: usize = 4;
+pub const SIGTRAP: usize = 5;
+pub const SIGABRT: usize = 6;
+pub const SIGBUS: usize = 7;
+pub const SIGFPE: usize = 8;
+pub const SIGKILL: usize = 9;
+pub const SIGUSR1: usize = 10;
+pub const SIGSEGV: usize = 11;
+pub const SIGUSR2: usize = 12;
+pub const SIGPIPE: usize = 13;
+pub const SIGALRM: usize = 14;
+pub const SIGTERM: usize = 15;
+pub const SIGCHLD: usize = 17;
+pub const SIGCONT: usize = 18;
+pub const SIGSTOP: usize = 19;
+pub const SIGTSTP: usize = 20;
+
+const SIG_DFL: usize = 0;
+const SIG_IGN: usize = 1;
+
+/// Don't block the signal while its handler runs.
+pub const SA_NODEFER: usize = 0x4000_0000;
+/// Reset the action to the default once the handler is entered.
+pub const SA_RESETHAND: usize = 0x8000_0000;
+
+/// A set of signals, bit `sig - 1` standing for `sig`.
+#[repr(transparent)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SigSet(pub u64);
+
+impl SigSet {
+    pub const EMPTY: SigSet = SigSet(0);
+
+    pub const fn of(sig: usize) -> SigSet {
+        SigSet(1 << (sig - 1))
+    }
+
+    pub fn contains(&self, sig: usize) -> bool {
+        self.0 & Self::of(sig).0 != 0
+    }
+
+    pub fn insert(&mut self, sig: usize) {
+        self.0 |= Self::of(sig).0;
+    }
+
+    pub fn remove(&mut self, sig: usize) {
+        self.0 &= !Self::of(sig).0;
+    }
+}
+
+/// The action of a signal, as passed to the kernel.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SigAction {
+    pub handler: usize,
+    pub flags: usize,
+    /// Signals blocked while the handler runs, along with the signal itself.
+    pub mask: SigSet,
+}
+
+/// What a signal does when it arrives.
+#[derive(Debug, Clone, Copy)]
+pub enum SigHandler {
+    /// The default action of the signal: terminating, stopping or continuing the process,
+    /// or nothing.
+    Default,
+    Ignore,
+    /// Call the function with the signal number.
+    Function(extern "C" fn(usize)),
+}
+
+impl SigHandler {
+    fn from_raw(handler: usize) -> SigHandler {
+        match handler {
+            SIG_DFL => SigHandler::Default,
+            SIG_IGN => SigHandler::Ignore,
+            // SAFETY: the kernel only returns what was set, from a `SigHandler`.
+            address => SigHandler::Function(unsafe {
+                core::mem::transmute::<usize, extern "C" fn(usize)>(address)
+            }),
+        }
+    }
+
+    fn to_raw(self) -> usize {
+        match self {
+            SigHandler::Default => SIG_DFL,
+            SigHandler::Ignore => SIG_IGN,
+            SigHandler::Function(f) => f as usize,
+        }
+    }
+}
+
+/// Set what `sig` does, and return what it did. The handler of [SigHandler::Function] runs
+/// with `sig` blocked, on the stack of the interrupted thread.
+///
+/// [SIGKILL] and [SIGSTOP] can't be caught or ignored.
+pub fn set_handler(sig: usize, handler: SigHandler) -> Result<SigHandler, Errno> {
+    let action = SigAction {
+        handler: handler.to_raw(),
+        ..Default::default()
+    };
+    let old = syscall::sigaction(sig, Some(&action))?;
+    Ok(SigHandler::from_raw(old.handler))
+}
+
+/// Block the signals of `set` in the calling thread, and return the previous mask.
+/// Blocked signals stay pending until unblocked.
+pub fn block(set: SigSet) -> Result<SigSet, Errno> {
+    syscall::sigprocmask(SIG_BLOCK, Some(&set))
+}
+
+/// Unblock the signals of `set` in the calling thread, and return the previous mask.
+pub fn unblock(set: SigSet) -> Result<SigSet, Errno> {
+    syscall::sigprocmask(SIG_UNBLOCK, Some(&set))
+}
+
+/// Set the signal mask of the calling thread, and return the previous one.
+pub fn set_mask(set: SigSet) -> Result<SigSet, Errno> {
+    syscall::sigprocmask(SIG_SETMASK, Some(&set))
+}
+
+/// The signal mask of the calling thread.
+pub fn mask() -> SigSet {
+    syscall::sigprocmask(SIG_BLOCK, None).expect("sigprocmask failed")
+}
+
+/// Send `sig` to the process `pid`.
+pub fn kill(pid: usize, sig: usize) -> Result<(), Errno> {
+    syscall::kill(pid, sig)
+}
+
+/// Send `sig` to the calling process.
+pub fn raise(sig: usize) -> Result<(), Errno> {
+    kill(process::id(), sig)
+}
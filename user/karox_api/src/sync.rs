@@ -0,0 +1,207 @@
+//! Synchronization primitives, built on private futexes.
+
+use crate::{
+    error::Errno,
+    syscall::{futex_requeue, futex_wait, futex_wake},
+};
+use core::{
+    cell::UnsafeCell,
+    ops::{Deref, DerefMut},
+    ptr,
+    sync::atomic::{AtomicPtr, AtomicU32, Ordering},
+    time::Duration,
+};
+
+/// States of the futex word of a [Mutex].
+const UNLOCKED: u32 = 0;
+const LOCKED: u32 = 1;
+/// Locked, and threads may be waiting for it.
+const CONTENDED: u32 = 2;
+
+/// Attempts to take a lock before sleeping on it.
+const SPIN_LIMIT: usize = 64;
+
+/// A mutual exclusion lock. Waiters spin for a while, then sleep on a futex
+/// until the owner wakes them up, so the kernel is only entered under contention.
+pub struct Mutex<T: ?Sized> {
+    state: AtomicU32,
+    data: UnsafeCell<T>,
+}
+
+unsafe impl<T: ?Sized + Send> Send for Mutex<T> {}
+unsafe impl<T: ?Sized + Send> Sync for Mutex<T> {}
+
+impl<T> Mutex<T> {
+    pub const fn new(data: T) -> Mutex<T> {
+        Mutex {
+            state: AtomicU32::new(UNLOCKED),
+            data: UnsafeCell::new(data),
+        }
+    }
+
+    pub fn into_inner(self) -> T {
+        self.data.into_inner()
+    }
+}
+
+impl<T: ?Sized> Mutex<T> {
+    pub fn try_lock(&self) -> Option<MutexGuard<'_, T>> {
+        self.state
+            .compare_exchange(UNLOCKED, LOCKED, Ordering::Acquire, Ordering::Relaxed)
+            .ok()
+            .map(|_| MutexGuard { mutex: self })
+    }
+
+    pub fn lock(&self) -> MutexGuard<'_, T> {
+        for _ in 0..SPIN_LIMIT {
+            if let Some(guard) = self.try_lock() {
+                return guard;
+            }
+            core::hint::spin_loop();
+        }
+        self.lock_contended()
+    }
+
+    /// Take the lock marking it contended, as other threads may sleep on it.
+    fn lock_contended(&self) -> MutexGuard<'_, T> {
+        while self.state.swap(CONTENDED, Ordering::Acquire) != UNLOCKED {
+            let _ = futex_wait(&self.state, CONTENDED, None);
+        }
+        MutexGuard { mutex: self }
+    }
+
+    fn unlock(&self) {
+        if self.state.swap(UNLOCKED, Ordering::Release) == CONTENDED {
+            futex_wake(&self.state, 1);
+        }
+    }
+
+    pub fn get_mut(&mut self) -> &mut T {
+        self.data.get_mut()
+    }
+}
+
+impl<T: Default> Default for Mutex<T> {
+    fn default() -> Self {
+        Mutex::new(T::default())
+    }
+}
+
+pub struct MutexGuard<'a, T: ?Sized> {
+    mutex: &'a Mutex<T>,
+}
+
+impl<T: ?Sized> Deref for MutexGuard<'_, T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        unsafe { &*self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> DerefMut for MutexGuard<'_, T> {
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.mutex.data.get() }
+    }
+}
+
+impl<T: ?Sized> Drop for MutexGuard<'_, T> {
+    fn drop(&mut self) {
+        self.mutex.unlock();
+    }
+}
+
+/// A condition variable, waited on with a [Mutex] locked.
+///
+/// Waiters sleep on a sequence number bumped by each notification. [Condvar::notify_all]
+/// wakes one of them and moves the others to the futex of the mutex, so that they
+/// don't all rush for it at once. A condition variable must always be used with the same mutex.
+pub struct Condvar {
+    seq: AtomicU32,
+    /// Futex word of the mutex of the waiters, null before the first wait.
+    mutex: AtomicPtr<AtomicU32>,
+}
+
+impl Condvar {
+    pub const fn new() -> Condvar {
+        Condvar {
+            seq: AtomicU32::new(0),
+            mutex: AtomicPtr::new(ptr::null_mut()),
+        }
+    }
+
+    /// Unlock the mutex of `guard`, block until notified, then lock it again.
+    /// Wake ups may be spurious, so the condition must be checked in a loop.
+    pub fn wait<'a, T: ?Sized>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        self.wait_timeout(guard, None).0
+    }
+
+    /// Like [Condvar::wait], but give up once `timeout` has expired.
+    /// Also return whether it has, and the condition must be checked without waiting again.
+    pub fn wait_timeout<'a, T: ?Sized>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Option<Duration>,
+    ) -> (MutexGuard<'a, T>, bool) {
+        let mutex = guard.mutex;
+        let state = &mutex.state as *const AtomicU32;
+        self.mutex.store(state as *mut AtomicU32, Ordering::Relaxed);
+        // Read before unlocking, so that a notification sent meanwhile changes it.
+        let seq = self.seq.load(Ordering::Relaxed);
+        drop(guard);
+        let res = futex_wait(&self.seq, seq, timeout);
+        // Waiters may have been moved to the futex of the mutex, and must be woken on unlock.
+        (mutex.lock_contended(), res == Err(Errno::ETIMEDOUT))
+    }
+
+    /// Wake up one waiter.
+    pub fn notify_one(&self) {
+        self.seq.fetch_add(1, Ordering::Relaxed);
+        futex_wake(&self.seq, 1);
+    }
+
+    /// Wake up all waiters.
+    pub fn notify_all(&self) {
+        let seq = self.seq.fetch_add(1, Ordering::Relaxed).wrapping_add(1);
+        // Read after the bump: a first waiter storing the mutex meanwhile may already sleep
+        // on the new sequence number, so it's woken directly.
+        let mutex = self.mutex.load(Ordering::Relaxed);
+        if mutex.is_null() {
+            futex_wake(&self.seq, u32::MAX);
+            return;
+        }
+        let target = unsafe { &*mutex };
+        // A notification racing with this one changes the sequence number: wake everyone then.
+        if futex_requeue(&self.seq, target, 1, i32::MAX as usize, Some(seq)).is_err() {
+            futex_wake(&self.seq, u32::MAX);
+            return;
+        }
+        // The moved waiters are only woken by an unlock of a contended mutex, or here if it's free.
+        loop {
+            match target.load(Ordering::Relaxed) {
+                UNLOCKED => {
+                    futex_wake(target, 1);
+                    return;
+                }
+                CONTENDED => return,
+                _ => {
+                    let res = target.compare_exchange(
+                        LOCKED,
+                        CONTENDED,
+                        Ordering::Relaxed,
+                        Ordering::Relaxed,
+                    );
+                    if res.is_ok() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Default for Condvar {
+    fn default() -> Self {
+        Condvar::new()
+    }
+}
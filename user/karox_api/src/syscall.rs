@@ -1,4 +1,48 @@
-use core::arch::asm;
+//! Raw system calls and their typed wrappers.
+//!
+//! The number goes in `a7` and the arguments in `a0`-`a5`. The result comes back in `a0`:
+//! a negative value is a negated [Errno].
+
+use crate::{
+    error::Errno,
+    signal::{SigAction, SigSet},
+};
+use core::{arch::asm, sync::atomic::AtomicU32, time::Duration};
+
+pub const SYS_EXIT: usize = 1;
+pub const SYS_YIELD: usize = 2;
+pub const SYS_GETPID: usize = 3;
+pub const SYS_SLEEP: usize = 4;
+pub const SYS_WRITE: usize = 5;
+pub const SYS_BRK: usize = 6;
+pub const SYS_THREAD_CREATE: usize = 7;
+pub const SYS_GETTID: usize = 8;
+pub const SYS_FORK: usize = 9;
+pub const SYS_EXECVE: usize = 10;
+pub const SYS_WAITPID: usize = 11;
+pub const SYS_FUTEX: usize = 12;
+pub const SYS_KILL: usize = 13;
+pub const SYS_SIGACTION: usize = 14;
+pub const SYS_SIGPROCMASK: usize = 15;
+pub const SYS_SIGRETURN: usize = 16;
+pub const SYS_EXIT_GROUP: usize = 17;
+
+/// Option of [waitpid]: return at once if no child has exited.
+pub const WNOHANG: u32 = 1;
+
+/// Futex operations, and the flag keying futexes by memspace rather than physical address.
+pub const FUTEX_WAIT: u32 = 0;
+pub const FUTEX_WAKE: u32 = 1;
+pub const FUTEX_REQUEUE: u32 = 3;
+pub const FUTEX_CMP_REQUEUE: u32 = 4;
+pub const FUTEX_PRIVATE_FLAG: u32 = 128;
+/// Timeout of futex waits that never expire.
+pub const FUTEX_NO_TIMEOUT: usize = usize::MAX;
+
+/// Operations of [sigprocmask].
+pub const SIG_BLOCK: u32 = 0;
+pub const SIG_UNBLOCK: u32 = 1;
+pub const SIG_SETMASK: u32 = 2;
 
 pub unsafe fn syscall(id: usize, args: [usize; 3]) -> isize {
     let mut ret: isize;
@@ -14,3 +58,170 @@ pub unsafe fn syscall(id: usize, args: [usize; 3]) -> isize {
     ret
 }
 
+pub unsafe fn syscall6(id: usize, args: [usize; 6]) -> isize {
+    let mut ret: isize;
+    unsafe {
+        asm!(
+        "ecall",
+        inlateout("x10") args[0] => ret,
+        in("x11") args[1],
+        in("x12") args[2],
+        in("x13") args[3],
+        in("x14") args[4],
+        in("x15") args[5],
+        in("x17") id
+        );
+    }
+    ret
+}
+
+fn check(ret: isize) -> Result<usize, Errno> {
+    if ret < 0 {
+        Err(Errno(-ret))
+    } else {
+        Ok(ret as usize)
+    }
+}
+
+/// Exit the calling thread. The process exits with `code` once no other thread is left.
+pub fn exit(code: i32) -> ! {
+    unsafe { syscall(SYS_EXIT, [code as usize, 0, 0]) };
+    unreachable!("The thread survived its exit.");
+}
+
+/// Exit every thread of the calling process, which exits with `code`.
+pub fn exit_group(code: i32) -> ! {
+    unsafe { syscall(SYS_EXIT_GROUP, [code as usize, 0, 0]) };
+    unreachable!("The process survived its exit.");
+}
+
+/// Give up the rest of the time slice.
+pub fn yield_now() {
+    unsafe { syscall(SYS_YIELD, [0; 3]) };
+}
+
+pub fn getpid() -> usize {
+    unsafe { syscall(SYS_GETPID, [0; 3]) as usize }
+}
+
+pub fn gettid() -> usize {
+    unsafe { syscall(SYS_GETTID, [0; 3]) as usize }
+}
+
+/// Block for at least `ns` nanoseconds.
+pub fn sleep_ns(ns: usize) {
+    unsafe { syscall(SYS_SLEEP, [ns, 0, 0]) };
+}
+
+/// Write `buf` to the file `fd`, and return the number of bytes written.
+pub fn write(fd: usize, buf: &[u8]) -> Result<usize, Errno> {
+    check(unsafe { syscall(SYS_WRITE, [fd, buf.as_ptr() as usize, buf.len()]) })
+}
+
+/// Move the program break to `addr`, and return the new break.
+/// The break doesn't move if the request can't be met; `brk(0)` queries it.
+pub fn brk(addr: usize) -> usize {
+    unsafe { syscall(SYS_BRK, [addr, 0, 0]) as usize }
+}
+
+/// Start a thread of the process running `entry(arg)` on the stack `sp`, and return its tid.
+///
+/// **The stack must stay valid until the thread has exited.**
+pub unsafe fn thread_create(
+    entry: extern "C" fn(usize) -> !,
+    sp: usize,
+    arg: usize,
+) -> Result<usize, Errno> {
+    check(unsafe { syscall(SYS_THREAD_CREATE, [entry as usize, sp, arg]) })
+}
+
+/// Create a child process running a copy of the caller. Return the pid of the child,
+/// or 0 in the child.
+pub fn fork() -> Result<usize, Errno> {
+    check(unsafe { syscall(SYS_FORK, [0; 3]) })
+}
+
+/// Replace the program of the process with the executable `path`, run with the arguments
+/// `argv` and the environment `envp`. Only return on failure.
+///
+/// **`path` must be a NUL-terminated string, and `argv` and `envp` NULL-terminated arrays of them.**
+pub unsafe fn execve(path: *const u8, argv: *const *const u8, envp: *const *const u8) -> Errno {
+    let ret = unsafe { syscall(SYS_EXECVE, [path as usize, argv as usize, envp as usize]) };
+    Errno(-ret)
+}
+
+/// Wait for a child to exit, `pid` or any child if it's -1, reap it and return its pid.
+/// Its exit code is stored in `status`. With [WNOHANG], return 0 if no matching child has exited.
+pub fn waitpid(pid: isize, status: Option<&mut i32>, options: u32) -> Result<usize, Errno> {
+    let status = status.map_or(0, |status| status as *mut i32 as usize);
+    check(unsafe { syscall(SYS_WAITPID, [pid as usize, status, options as usize]) })
+}
+
+fn futex(word: &AtomicU32, op: u32, val: u32, arg: usize, word2: usize, val3: u32) -> isize {
+    let args = [
+        word.as_ptr() as usize,
+        (op | FUTEX_PRIVATE_FLAG) as usize,
+        val as usize,
+        arg,
+        word2,
+        val3 as usize,
+    ];
+    unsafe { syscall6(SYS_FUTEX, args) }
+}
+
+/// Block while the private futex `word` holds `expected`, until it's woken up or `timeout` expires.
+/// Fail with [Errno::EAGAIN] if the word doesn't hold `expected`, and [Errno::ETIMEDOUT] on timeout.
+/// Wake ups may be spurious.
+pub fn futex_wait(word: &AtomicU32, expected: u32, timeout: Option<Duration>) -> Result<(), Errno> {
+    let timeout = timeout.map_or(FUTEX_NO_TIMEOUT, |timeout| {
+        timeout.as_nanos().min((FUTEX_NO_TIMEOUT - 1) as u128) as usize
+    });
+    check(futex(word, FUTEX_WAIT, expected, timeout, 0, 0)).map(|_| ())
+}
+
+/// Wake up to `count` threads waiting on the private futex `word`, and return their number.
+pub fn futex_wake(word: &AtomicU32, count: u32) -> usize {
+    check(futex(word, FUTEX_WAKE, count, 0, 0, 0)).unwrap_or(0)
+}
+
+/// Wake up to `nr_wake` threads waiting on the private futex `word`, and move up to `nr_requeue`
+/// others to `target`. Return the number of threads woken or moved.
+/// With `expected`, fail with [Errno::EAGAIN] if `word` doesn't hold it.
+pub fn futex_requeue(
+    word: &AtomicU32,
+    target: &AtomicU32,
+    nr_wake: u32,
+    nr_requeue: usize,
+    expected: Option<u32>,
+) -> Result<usize, Errno> {
+    let (op, val3) = match expected {
+        Some(expected) => (FUTEX_CMP_REQUEUE, expected),
+        None => (FUTEX_REQUEUE, 0),
+    };
+    let target = target.as_ptr() as usize;
+    check(futex(word, op, nr_wake, nr_requeue, target, val3))
+}
+
+/// Send the signal `sig` to the process `pid`. Signal 0 only checks that the process exists.
+pub fn kill(pid: usize, sig: usize) -> Result<(), Errno> {
+    check(unsafe { syscall(SYS_KILL, [pid, sig, 0]) }).map(|_| ())
+}
+
+/// Set the action of `sig` to `act`, and return the previous one.
+/// Handlers return through the signal trampoline of the kernel.
+pub fn sigaction(sig: usize, act: Option<&SigAction>) -> Result<SigAction, Errno> {
+    let act = act.map_or(0, |act| act as *const SigAction as usize);
+    let mut old = SigAction::default();
+    let oldact = &mut old as *mut SigAction as usize;
+    check(unsafe { syscall(SYS_SIGACTION, [sig, act, oldact]) })?;
+    Ok(old)
+}
+
+/// Change the signal mask of the calling thread with `how` and `set`, and return the previous one.
+pub fn sigprocmask(how: u32, set: Option<&SigSet>) -> Result<SigSet, Errno> {
+    let set = set.map_or(0, |set| set as *const SigSet as usize);
+    let mut old = SigSet::EMPTY;
+    let oldset = &mut old as *mut SigSet as usize;
+    check(unsafe { syscall(SYS_SIGPROCMASK, [how as usize, set, oldset]) })?;
+    Ok(old)
+}
@@ -0,0 +1,43 @@
+//! A small harness for the test programs.
+//!
+//! A program lists its cases, each returning why it failed, and exits with the number of failures:
+//!
+//! ```ignore
+//! #[unsafe(no_mangle)]
+//! fn main() -> i32 {
+//!     testing::run("forktest", &[("exit_status", test_exit_status)])
+//! }
+//! ```
+
+use crate::{
+    println,
+    process::{self, Fork},
+    syscall,
+};
+
+/// A test case returns why it failed.
+pub type Test = fn() -> Result<(), &'static str>;
+
+/// Run the `cases` of program `name` in order, print the outcome of each,
+/// and return the number of failed ones, as the exit code of the program.
+pub fn run(name: &str, cases: &[(&str, Test)]) -> i32 {
+    let mut failed = 0;
+    for (case, test) in cases {
+        match test() {
+            Ok(()) => println!("{:}: {:} ok", name, case),
+            Err(reason) => {
+                println!("{:}: {:} FAILED: {:}", name, case, reason);
+                failed += 1;
+            }
+        }
+    }
+    failed
+}
+
+/// Fork a child running `f`, which exits with the code it returns. Panic if the fork fails.
+pub fn fork_with(f: impl FnOnce() -> i32) -> usize {
+    match process::fork().expect("fork failed") {
+        Fork::Parent(pid) => pid,
+        Fork::Child => syscall::exit(f()),
+    }
+}
@@ -0,0 +1,147 @@
+//! Threads.
+//!
+//! A thread runs on a stack owned by its [JoinHandle]. The thread flags the end of its work
+//! from code that doesn't touch the stack, right before exiting,
+//! so that the stack can be freed as soon as the flag is seen.
+
+use crate::{
+    error::Errno,
+    syscall::{self, SYS_EXIT, thread_create},
+};
+use alloc::{boxed::Box, sync::Arc, vec};
+use core::{
+    arch::asm,
+    cell::UnsafeCell,
+    mem,
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+
+/// Stack size of spawned threads.
+pub const THREAD_STACK_SIZE: usize = 64 * 1024;
+
+/// Shared by a thread and its handle. The thread holds a reference until it has exited,
+/// which is dropped on its behalf by the handle.
+struct Packet<T> {
+    finished: AtomicBool,
+    result: UnsafeCell<Option<T>>,
+}
+
+unsafe impl<T: Send> Sync for Packet<T> {}
+
+/// The work of a thread, returning the address of its `finished` flag.
+type ThreadMain = Box<dyn FnOnce() -> usize + Send>;
+
+extern "C" fn thread_start(arg: usize) -> ! {
+    let main = unsafe { Box::from_raw(arg as *mut ThreadMain) };
+    let finished = main();
+    unsafe {
+        asm!(
+            "fence rw, w",
+            "sb {one}, 0({finished})",
+            "ecall",
+            one = in(reg) 1,
+            finished = in(reg) finished,
+            in("a0") 0,
+            in("a7") SYS_EXIT,
+            options(noreturn, nostack)
+        )
+    }
+}
+
+/// Owned permission to join a thread. Dropping it detaches the thread.
+pub struct JoinHandle<T> {
+    tid: usize,
+    packet: Arc<Packet<T>>,
+    /// `None` once the thread's reference to the packet is dropped.
+    stack: Option<Box<[u8]>>,
+}
+
+impl<T> JoinHandle<T> {
+    pub fn tid(&self) -> usize {
+        self.tid
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.packet.finished.load(Ordering::Acquire)
+    }
+
+    /// Wait for the thread to finish and return its result.
+    ///
+    /// A thread which panics exits without finishing, so joining it never returns.
+    pub fn join(mut self) -> T {
+        while !self.is_finished() {
+            yield_now();
+        }
+        let result = unsafe { (*self.packet.result.get()).take() };
+        self.release();
+        result.unwrap()
+    }
+
+    /// Drop the reference of the finished thread to the packet, and free its stack.
+    fn release(&mut self) {
+        if self.stack.take().is_some() {
+            unsafe { Arc::decrement_strong_count(Arc::as_ptr(&self.packet)) };
+        }
+    }
+}
+
+impl<T> Drop for JoinHandle<T> {
+    fn drop(&mut self) {
+        if self.is_finished() {
+            self.release();
+        } else if let Some(stack) = self.stack.take() {
+            // A detached thread keeps its stack and packet for good.
+            mem::forget(stack);
+        }
+    }
+}
+
+/// Spawn a thread running `f`.
+pub fn spawn<F, T>(f: F) -> Result<JoinHandle<T>, Errno>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let packet = Arc::new(Packet {
+        finished: AtomicBool::new(false),
+        result: UnsafeCell::new(None),
+    });
+    let their_packet = Arc::into_raw(packet.clone()) as usize;
+    let main: ThreadMain = Box::new(move || {
+        let packet = unsafe { &*(their_packet as *const Packet<T>) };
+        let result = f();
+        unsafe { *packet.result.get() = Some(result) };
+        &packet.finished as *const AtomicBool as usize
+    });
+    let arg = Box::into_raw(Box::new(main));
+    let stack = vec![0u8; THREAD_STACK_SIZE].into_boxed_slice();
+    let sp = (stack.as_ptr() as usize + stack.len()) & !0xf;
+    match unsafe { thread_create(thread_start, sp, arg as usize) } {
+        Ok(tid) => Ok(JoinHandle {
+            tid,
+            packet,
+            stack: Some(stack),
+        }),
+        Err(errno) => {
+            unsafe {
+                drop(Box::from_raw(arg));
+                Arc::decrement_strong_count(their_packet as *const Packet<T>);
+            }
+            Err(errno)
+        }
+    }
+}
+
+/// The tid of the calling thread.
+pub fn current_tid() -> usize {
+    syscall::gettid()
+}
+
+pub fn yield_now() {
+    syscall::yield_now();
+}
+
+pub fn sleep(duration: Duration) {
+    syscall::sleep_ns(duration.as_nanos().min(usize::MAX as u128) as usize);
+}
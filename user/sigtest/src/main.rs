@@ -0,0 +1,150 @@
+#![no_std]
+#![no_main]
+
+use core::{
+    hint::black_box,
+    ptr,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+use karox_api::{
+    process,
+    signal::{self, SIGCONT, SIGKILL, SIGSEGV, SIGSTOP, SIGTERM, SIGUSR1, SigHandler, SigSet},
+    testing::{self, fork_with},
+    thread,
+};
+
+/// Number of signals the handler caught.
+static CAUGHT: AtomicUsize = AtomicUsize::new(0);
+
+extern "C" fn on_signal(_sig: usize) {
+    CAUGHT.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Fork a child sleeping until a signal ends it.
+fn fork_sleeper() -> usize {
+    fork_with(|| {
+        loop {
+            thread::sleep(Duration::from_secs(1));
+        }
+    })
+}
+
+/// Exit code of a process killed by `sig`.
+fn killed_by(sig: usize) -> i32 {
+    128 + sig as i32
+}
+
+/// A signal raised by the process runs its handler before `raise` returns.
+fn test_handler() -> Result<(), &'static str> {
+    signal::set_handler(SIGUSR1, SigHandler::Function(on_signal))
+        .map_err(|_| "sigaction failed")?;
+    let before = CAUGHT.load(Ordering::Relaxed);
+    signal::raise(SIGUSR1).map_err(|_| "kill failed")?;
+    let caught = CAUGHT.load(Ordering::Relaxed) - before;
+    signal::set_handler(SIGUSR1, SigHandler::Default).map_err(|_| "sigaction failed")?;
+    match caught {
+        1 => Ok(()),
+        0 => Err("the handler didn't run"),
+        _ => Err("the handler ran more than once"),
+    }
+}
+
+/// A blocked signal stays pending, and is handled once unblocked.
+fn test_blocked() -> Result<(), &'static str> {
+    signal::set_handler(SIGUSR1, SigHandler::Function(on_signal))
+        .map_err(|_| "sigaction failed")?;
+    let before = CAUGHT.load(Ordering::Relaxed);
+    signal::block(SigSet::of(SIGUSR1)).map_err(|_| "sigprocmask failed")?;
+    signal::raise(SIGUSR1).map_err(|_| "kill failed")?;
+    signal::raise(SIGUSR1).map_err(|_| "kill failed")?;
+    let while_blocked = CAUGHT.load(Ordering::Relaxed) - before;
+    signal::unblock(SigSet::of(SIGUSR1)).map_err(|_| "sigprocmask failed")?;
+    let caught = CAUGHT.load(Ordering::Relaxed) - before;
+    signal::set_handler(SIGUSR1, SigHandler::Default).map_err(|_| "sigaction failed")?;
+    match (while_blocked, caught) {
+        (0, 1) => Ok(()),
+        (0, _) => Err("the pending signal was not handled once"),
+        _ => Err("the handler ran while blocked"),
+    }
+}
+
+/// A signal without a handler terminates its target, and the parent sees which one.
+fn test_terminate() -> Result<(), &'static str> {
+    let pid = fork_sleeper();
+    signal::kill(pid, SIGTERM).map_err(|_| "kill failed")?;
+    match process::wait(Some(pid)) {
+        Ok((_, code)) if code == killed_by(SIGTERM) => Ok(()),
+        _ => Err("wrong exit status"),
+    }
+}
+
+/// An access to an unmapped address kills the process with SIGSEGV.
+fn test_fault() -> Result<(), &'static str> {
+    let pid = fork_with(|| {
+        unsafe { ptr::write_volatile(black_box(ptr::null_mut::<u8>()), 1) };
+        0
+    });
+    match process::wait(Some(pid)) {
+        Ok((_, code)) if code == killed_by(SIGSEGV) => Ok(()),
+        _ => Err("wrong exit status"),
+    }
+}
+
+/// An ignored signal does nothing.
+fn test_ignored() -> Result<(), &'static str> {
+    let pid = fork_with(|| {
+        if signal::set_handler(SIGTERM, SigHandler::Ignore).is_err() {
+            return 1;
+        }
+        match signal::raise(SIGTERM) {
+            Ok(()) => 0,
+            Err(_) => 2,
+        }
+    });
+    match process::wait(Some(pid)) {
+        Ok((_, 0)) => Ok(()),
+        _ => Err("the ignored signal ended the child"),
+    }
+}
+
+/// A stopped process can still be killed, and resumes on SIGCONT.
+fn test_stop() -> Result<(), &'static str> {
+    let pid = fork_sleeper();
+    signal::kill(pid, SIGSTOP).map_err(|_| "kill failed")?;
+    thread::sleep(Duration::from_millis(20));
+    signal::kill(pid, SIGKILL).map_err(|_| "kill failed")?;
+    match process::wait(Some(pid)) {
+        Ok((_, code)) if code == killed_by(SIGKILL) => {}
+        _ => return Err("the stopped child was not killed"),
+    }
+    let pid = fork_with(|| {
+        thread::sleep(Duration::from_millis(40));
+        5
+    });
+    signal::kill(pid, SIGSTOP).map_err(|_| "kill failed")?;
+    thread::sleep(Duration::from_millis(80));
+    if process::try_wait(Some(pid)) != Ok(None) {
+        return Err("the stopped child kept running");
+    }
+    signal::kill(pid, SIGCONT).map_err(|_| "kill failed")?;
+    match process::wait(Some(pid)) {
+        Ok((_, 5)) => Ok(()),
+        _ => Err("the continued child didn't finish"),
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    testing::run(
+        "sigtest",
+        &[
+            ("handler", test_handler),
+            ("blocked", test_blocked),
+            ("terminate", test_terminate),
+            ("fault", test_fault),
+            ("ignored", test_ignored),
+            ("stop", test_stop),
+        ],
+    )
+}
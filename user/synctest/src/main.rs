@@ -0,0 +1,133 @@
+#![no_std]
+#![no_main]
+
+extern crate alloc;
+
+use alloc::{sync::Arc, vec::Vec};
+use core::time::Duration;
+use karox_api::{
+    sync::{Condvar, Mutex},
+    testing, thread,
+};
+
+const THREADS: usize = 4;
+
+/// Spawn `THREADS` threads running `f` with their index, and join them.
+fn run_threads<T: Send + 'static>(f: impl Fn(usize) -> T + Send + Sync + 'static) -> Vec<T> {
+    let f = Arc::new(f);
+    let handles: Vec<_> = (0..THREADS)
+        .map(|index| {
+            let f = f.clone();
+            thread::spawn(move || f(index)).expect("spawn failed")
+        })
+        .collect();
+    handles.into_iter().map(|handle| handle.join()).collect()
+}
+
+/// Increments under the lock are never lost, even once the waiters sleep on it.
+fn test_mutex() -> Result<(), &'static str> {
+    const INCREMENTS: usize = 2000;
+    let counter = Arc::new(Mutex::new(0usize));
+    let shared = counter.clone();
+    run_threads(move |_| {
+        for _ in 0..INCREMENTS {
+            let mut value = shared.lock();
+            let seen = *value;
+            // Yield with the lock held now and then, so that the others block on it.
+            if seen.is_multiple_of(100) {
+                thread::yield_now();
+            }
+            *value = seen + 1;
+        }
+    });
+    if *counter.lock() == THREADS * INCREMENTS {
+        Ok(())
+    } else {
+        Err("lost increments")
+    }
+}
+
+/// Items sent one by one through a condition variable all arrive, in order.
+fn test_condvar() -> Result<(), &'static str> {
+    const ITEMS: usize = 200;
+    let slot = Arc::new((Mutex::new(None::<usize>), Condvar::new(), Condvar::new()));
+    let consumer_slot = slot.clone();
+    let consumer = thread::spawn(move || {
+        let (slot, filled, emptied) = &*consumer_slot;
+        let mut received = Vec::new();
+        while received.len() < ITEMS {
+            let mut item = slot.lock();
+            while item.is_none() {
+                item = filled.wait(item);
+            }
+            received.push(item.take().unwrap());
+            emptied.notify_one();
+        }
+        received
+    })
+    .map_err(|_| "spawn failed")?;
+    let (mutex, filled, emptied) = &*slot;
+    for index in 0..ITEMS {
+        let mut item = mutex.lock();
+        while item.is_some() {
+            item = emptied.wait(item);
+        }
+        *item = Some(index);
+        filled.notify_one();
+    }
+    if consumer.join() == (0..ITEMS).collect::<Vec<_>>() {
+        Ok(())
+    } else {
+        Err("items were lost or reordered")
+    }
+}
+
+/// A broadcast wakes up every waiter, moved to the mutex or not.
+fn test_notify_all() -> Result<(), &'static str> {
+    let state = Arc::new((Mutex::new((false, 0usize)), Condvar::new()));
+    let waiters_state = state.clone();
+    let waiters = thread::spawn(move || {
+        run_threads(move |_| {
+            let (mutex, condvar) = &*waiters_state;
+            let mut guard = mutex.lock();
+            guard.1 += 1;
+            while !guard.0 {
+                guard = condvar.wait(guard);
+            }
+        })
+    })
+    .map_err(|_| "spawn failed")?;
+    let (mutex, condvar) = &*state;
+    while mutex.lock().1 < THREADS {
+        thread::sleep(Duration::from_millis(5));
+    }
+    mutex.lock().0 = true;
+    condvar.notify_all();
+    waiters.join();
+    Ok(())
+}
+
+/// A wait nobody notifies times out.
+fn test_wait_timeout() -> Result<(), &'static str> {
+    let mutex = Mutex::new(());
+    let condvar = Condvar::new();
+    let (_guard, timed_out) = condvar.wait_timeout(mutex.lock(), Some(Duration::from_millis(20)));
+    if timed_out {
+        Ok(())
+    } else {
+        Err("woken without notification")
+    }
+}
+
+#[unsafe(no_mangle)]
+fn main() -> i32 {
+    testing::run(
+        "synctest",
+        &[
+            ("mutex", test_mutex),
+            ("condvar", test_condvar),
+            ("notify_all", test_notify_all),
+            ("wait_timeout", test_wait_timeout),
+        ],
+    )
+}